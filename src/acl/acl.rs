@@ -0,0 +1,630 @@
+// users and what they are allowed to run - checked before every command
+//
+// like redis a user is a set of rules: on/off, passwords (kept as sha256),
+// commands and categories, key patterns with read/write access and pub/sub
+// channel patterns. keys and channels are lowercased here the same way the
+// arguments are, so patterns are lowercased as well.
+use crate::commands::table;
+use crate::utils::sha256;
+use crate::utils::utils;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_USER: &str = "default";
+const LOG_MAX_LEN: usize = 128;
+// denials of the same kind within this window are counted in one entry
+const LOG_GROUPING_MS: u64 = 60000;
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct KeyPattern {
+    pattern: String,
+    read: bool,
+    write: bool,
+}
+
+impl KeyPattern {
+    fn describe(&self) -> String {
+        match (self.read, self.write) {
+            (true, true) => format!("~{}", self.pattern),
+            (true, false) => format!("%R~{}", self.pattern),
+            _ => format!("%W~{}", self.pattern),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    nopass: bool,
+    passwords: Vec<String>, // sha256 hex
+    allowed: BTreeSet<String>, // commands, "command|subcommand" for containers
+    command_rules: Vec<String>, // applied since the last +@all/-@all - describes the user
+    keys: Vec<KeyPattern>,
+    channels: Vec<String>,
+}
+
+// why a command was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum Denial {
+    Command,
+    Key(String),
+    Channel(String),
+}
+
+impl Denial {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Denial::Command => "command",
+            Denial::Key(_) => "key",
+            Denial::Channel(_) => "channel",
+        }
+    }
+
+    // key or channel refused, the command otherwise
+    pub fn object(&self, command: &str) -> String {
+        match self {
+            Denial::Command => command.to_string(),
+            Denial::Key(k) | Denial::Channel(k) => k.clone(),
+        }
+    }
+
+    pub fn message(&self, user: &str, command: &str) -> String {
+        match self {
+            Denial::Command => format!("NOPERM User {} has no permissions to run the '{}' command", user, command),
+            Denial::Key(_) => "NOPERM No permissions to access a key".to_string(),
+            Denial::Channel(_) => "NOPERM No permissions to access a channel".to_string(),
+        }
+    }
+}
+
+impl User {
+    // new users can not do anything until given rules
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            allowed: BTreeSet::new(),
+            command_rules: vec![],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    fn default_user() -> Self {
+        let mut user = Self::new(DEFAULT_USER);
+        for rule in ["on", "nopass", "~*", "&*", "+@all"] {
+            let _ = user.apply(rule);
+        }
+        user
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // names permissions are kept under - containers per subcommand
+    fn names(spec: &table::CommandSpec) -> Vec<String> {
+        let subcommands = table::subcommands(spec.name).map(|(s, _c)| format!("{}|{}", spec.name, s)).collect::<Vec<_>>();
+        if subcommands.is_empty() { vec![spec.name.to_string()] } else { subcommands }
+    }
+
+    fn set_category(&mut self, category: &str, allow: bool) -> Result<(), String> {
+        let bit = match category {
+            "all" => u32::MAX,
+            _ => table::CATEGORIES.iter().find(|(name, _b)| *name == category)
+                .map(|(_n, b)| *b)
+                .ok_or("Unknown command or category name in ACL")?,
+        };
+        let mut names = vec![];
+        for spec in table::commands() {
+            let subcommands = table::subcommands(spec.name).collect::<Vec<_>>();
+            if subcommands.is_empty() {
+                if spec.categories & bit != 0 {
+                    names.push(spec.name.to_string());
+                }
+            } else {
+                subcommands.iter().filter(|(_s, c)| c & bit != 0)
+                    .for_each(|(s, _c)| names.push(format!("{}|{}", spec.name, s)));
+            }
+        }
+        self.set_commands(names, allow);
+        Ok(())
+    }
+
+    fn set_command(&mut self, command: &str, allow: bool) -> Result<(), String> {
+        let (name, subcommand) = match command.split_once('|') {
+            Some((n, s)) => (n, Some(s)),
+            None => (command, None),
+        };
+        let spec = table::lookup(name).ok_or("Unknown command or category name in ACL")?;
+        match subcommand {
+            None => self.set_commands(Self::names(spec), allow),
+            Some(s) if s.is_empty() || s.contains('|') => return Err("Syntax error".to_string()),
+            Some(s) => self.set_commands(vec![format!("{}|{}", name, s)], allow),
+        }
+        Ok(())
+    }
+
+    fn set_commands(&mut self, names: Vec<String>, allow: bool) {
+        for name in names {
+            if allow {
+                self.allowed.insert(name);
+            } else {
+                self.allowed.remove(&name);
+            }
+        }
+    }
+
+    // applies one rule of ACL SETUSER
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        let lower = rule.to_lowercase();
+        match lower.as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            },
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            },
+            "allkeys" => return self.apply("~*"),
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => return self.apply("&*"),
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => {
+                for rule in ["resetpass", "resetkeys", "resetchannels", "off", "-@all"] {
+                    self.apply(rule)?;
+                }
+            },
+            "+@all" | "-@all" => {
+                self.set_category("all", lower == "+@all")?;
+                self.command_rules = vec![lower];
+            },
+            _ => {
+                if let Some(password) = rule.strip_prefix('>') {
+                    let hash = sha256::hex_digest(password.as_bytes());
+                    if !self.passwords.contains(&hash) {
+                        self.passwords.push(hash);
+                    }
+                    self.nopass = false;
+                } else if let Some(password) = rule.strip_prefix('<') {
+                    let hash = sha256::hex_digest(password.as_bytes());
+                    self.remove_password(&hash)?;
+                } else if let Some(hash) = rule.strip_prefix('#') {
+                    valid_hash(hash)?;
+                    if !self.passwords.iter().any(|p| p == hash) {
+                        self.passwords.push(hash.to_string());
+                    }
+                    self.nopass = false;
+                } else if let Some(hash) = rule.strip_prefix('!') {
+                    valid_hash(hash)?;
+                    self.remove_password(hash)?;
+                } else if let Some(pattern) = lower.strip_prefix('~') {
+                    self.add_key(pattern, true, true)?;
+                } else if let Some((access, pattern)) = lower.strip_prefix('%').and_then(|r| r.split_once('~')) {
+                    let (read, write) = (access.contains('r'), access.contains('w'));
+                    if access.is_empty() || access.chars().any(|c| c != 'r' && c != 'w') {
+                        return Err("Syntax error".to_string());
+                    }
+                    self.add_key(pattern, read, write)?;
+                } else if let Some(pattern) = lower.strip_prefix('&') {
+                    if self.channels.iter().any(|c| c == "*") {
+                        return Err("Adding a pattern after the * pattern (or the 'allchannels' flag) is not valid \
+                            and does not have any effect. Try 'resetchannels' to start with an empty list of channels".to_string());
+                    }
+                    if !self.channels.iter().any(|c| c == pattern) {
+                        self.channels.push(pattern.to_string());
+                    }
+                } else if let Some(category) = lower.strip_prefix("+@") {
+                    self.set_category(category, true)?;
+                    self.command_rules.push(lower.clone());
+                } else if let Some(category) = lower.strip_prefix("-@") {
+                    self.set_category(category, false)?;
+                    self.command_rules.push(lower.clone());
+                } else if let Some(command) = lower.strip_prefix('+') {
+                    self.set_command(command, true)?;
+                    self.command_rules.push(lower.clone());
+                } else if let Some(command) = lower.strip_prefix('-') {
+                    self.set_command(command, false)?;
+                    self.command_rules.push(lower.clone());
+                } else {
+                    return Err("Syntax error".to_string());
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn remove_password(&mut self, hash: &str) -> Result<(), String> {
+        let before = self.passwords.len();
+        self.passwords.retain(|p| p != hash);
+        if before == self.passwords.len() {
+            return Err("The password you are trying to remove from the user does not exist".to_string());
+        }
+        Ok(())
+    }
+
+    fn add_key(&mut self, pattern: &str, read: bool, write: bool) -> Result<(), String> {
+        if self.keys.iter().any(|k| k.pattern == "*" && k.read && k.write) {
+            return Err("Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid \
+                and does not have any effect. Try 'resetkeys' to start with an empty list of patterns".to_string());
+        }
+        if pattern == "*" && read && write {
+            self.keys.clear();
+        }
+        match self.keys.iter_mut().find(|k| k.pattern == pattern) {
+            Some(k) => {
+                k.read |= read;
+                k.write |= write;
+            },
+            None => self.keys.push(KeyPattern { pattern: pattern.to_string(), read, write }),
+        }
+        Ok(())
+    }
+
+    fn check_password(&self, password: &[u8]) -> bool {
+        if self.nopass {
+            return true;
+        }
+        let hash = sha256::hex_digest(password);
+        self.passwords.contains(&hash)
+    }
+
+    fn can_run(&self, spec: &table::CommandSpec, args: &[String]) -> bool {
+        if self.allowed.contains(spec.name) {
+            return true;
+        }
+        let Some(subcommand) = args.get(1) else { return false };
+        if self.allowed.contains(&format!("{}|{}", spec.name, subcommand)) {
+            return true;
+        }
+        // unknown subcommands of a container are left to the command itself
+        let names = Self::names(spec);
+        table::subcommands(spec.name).next().is_some()
+            && !names.contains(&format!("{}|{}", spec.name, subcommand))
+            && names.iter().all(|n| self.allowed.contains(n))
+    }
+
+    fn can_access_key(&self, key: &str, read: bool, write: bool) -> bool {
+        // keys neither read nor written need any access
+        self.keys.iter().any(|k| {
+            let access = if read || write { (k.read || !read) && (k.write || !write) } else { k.read || k.write };
+            access && utils::glob_match(&k.pattern, key)
+        })
+    }
+
+    fn can_publish(&self, channel: &str) -> bool {
+        self.channels.iter().any(|c| utils::glob_match(c, channel))
+    }
+
+    // pattern subscriptions have to be allowed as they are
+    fn can_subscribe_pattern(&self, pattern: &str) -> bool {
+        self.channels.iter().any(|c| c == "*" || c == pattern)
+    }
+
+    pub fn check(&self, args: &[String]) -> Result<(), Denial> {
+        let Some(spec) = args.first().and_then(|name| table::lookup(name)) else { return Ok(()) };
+        if !self.can_run(spec, args) {
+            return Err(Denial::Command);
+        }
+        let denied = spec.key_access(args).into_iter().find(|(k, read, write)| !self.can_access_key(k, *read, *write));
+        if let Some((key, _read, _write)) = denied {
+            return Err(Denial::Key(key.clone()));
+        }
+        let denied = match spec.name {
+            "publish" | "spublish" => args.get(1).filter(|c| !self.can_publish(c)),
+            "subscribe" | "ssubscribe" => args[1..].iter().find(|c| !self.can_publish(c)),
+            "psubscribe" => args[1..].iter().find(|p| !self.can_subscribe_pattern(p)),
+            _ => None,
+        };
+        match denied {
+            Some(channel) => Err(Denial::Channel(channel.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn commands_description(&self) -> String {
+        match self.command_rules.first().map(|r| r.as_str()) {
+            None => "-@all".to_string(),
+            Some("+@all" | "-@all") => self.command_rules.join(" "),
+            Some(_) => format!("-@all {}", self.command_rules.join(" ")),
+        }
+    }
+
+    fn keys_description(&self) -> String {
+        self.keys.iter().map(|k| k.describe()).collect::<Vec<_>>().join(" ")
+    }
+
+    fn channels_description(&self) -> String {
+        self.channels.iter().map(|c| format!("&{}", c)).collect::<Vec<_>>().join(" ")
+    }
+
+    // rules recreating the user - as in ACL LIST and the aclfile
+    pub fn describe(&self) -> String {
+        let mut parts = vec![if self.enabled { "on" } else { "off" }.to_string()];
+        if self.nopass {
+            parts.push("nopass".to_string());
+        }
+        self.passwords.iter().for_each(|p| parts.push(format!("#{}", p)));
+        if !self.keys.is_empty() {
+            parts.push(self.keys_description());
+        }
+        if self.channels.iter().any(|c| c == "*") {
+            parts.push("&*".to_string());
+        } else {
+            parts.push("resetchannels".to_string());
+            if !self.channels.is_empty() {
+                parts.push(self.channels_description());
+            }
+        }
+        parts.push(self.commands_description());
+        parts.join(" ")
+    }
+
+    // ACL GETUSER reply
+    pub fn info(&self) -> Vec<u8> {
+        let bulk = |s: &str| format!("${}\r\n{}\r\n", s.len(), s);
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        let mut response = "*12\r\n".to_string();
+        response.push_str(&bulk("flags"));
+        response.push_str(&format!("*{}\r\n", flags.len()));
+        flags.iter().for_each(|f| response.push_str(&bulk(f)));
+        response.push_str(&bulk("passwords"));
+        response.push_str(&format!("*{}\r\n", self.passwords.len()));
+        self.passwords.iter().for_each(|p| response.push_str(&bulk(p)));
+        response.push_str(&bulk("commands"));
+        response.push_str(&bulk(&self.commands_description()));
+        response.push_str(&bulk("keys"));
+        response.push_str(&bulk(&self.keys_description()));
+        response.push_str(&bulk("channels"));
+        response.push_str(&bulk(&self.channels_description()));
+        response.push_str(&bulk("selectors"));
+        response.push_str("*0\r\n");
+        response.into_bytes()
+    }
+}
+
+fn valid_hash(hash: &str) -> Result<(), String> {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)) {
+        return Err("The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters".to_string());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub count: u64,
+    pub reason: String,
+    pub context: String,
+    pub object: String,
+    pub username: String,
+    pub client_info: String,
+    pub entry_id: u64,
+    pub created: u64, // ms
+    pub updated: u64,
+}
+
+impl LogEntry {
+    // ACL LOG reply entry
+    pub fn info(&self) -> Vec<u8> {
+        let bulk = |s: &str| format!("${}\r\n{}\r\n", s.len(), s);
+        let age = format!("{:.3}", now_ms().saturating_sub(self.created) as f64 / 1000.0);
+        let mut response = "*20\r\n".to_string();
+        response.push_str(&format!("{}:{}\r\n", bulk("count"), self.count));
+        for (name, value) in [("reason", &self.reason), ("context", &self.context), ("object", &self.object),
+            ("username", &self.username), ("age-seconds", &age), ("client-info", &self.client_info)] {
+            response.push_str(&bulk(name));
+            response.push_str(&bulk(value));
+        }
+        response.push_str(&format!("{}:{}\r\n", bulk("entry-id"), self.entry_id));
+        response.push_str(&format!("{}:{}\r\n", bulk("timestamp-created"), self.created));
+        response.push_str(&format!("{}:{}\r\n", bulk("timestamp-last-updated"), self.updated));
+        response.into_bytes()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    entries: VecDeque<LogEntry>, // newest first
+    next_id: u64,
+}
+
+#[derive(Debug)]
+pub struct Acl {
+    users: RwLock<BTreeMap<String, User>>,
+    log: Mutex<Log>,
+    aclfile: Option<String>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), User::default_user());
+        Self { users: RwLock::new(users), log: Mutex::new(Log::default()), aclfile: None }
+    }
+}
+
+impl Acl {
+    // requirepass is the password of the default user, aclfile replaces all users
+    pub fn new(requirepass: Option<String>, aclfile: Option<String>) -> Result<Self, String> {
+        let acl = Self { aclfile, ..Default::default() };
+        if let Some(password) = requirepass.filter(|p| !p.is_empty()) {
+            acl.setuser(DEFAULT_USER, &["resetpass".to_string(), format!(">{}", password)])?;
+        }
+        if acl.aclfile.is_some() {
+            acl.load()?;
+        }
+        Ok(acl)
+    }
+
+    pub fn user(&self, name: &str) -> Option<User> {
+        self.users.read().unwrap().get(name).cloned()
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.users.read().unwrap().contains_key(name)
+    }
+
+    // connections are logged in as default on connect while it needs no password
+    pub fn nopass(&self, name: &str) -> bool {
+        self.users.read().unwrap().get(name).map(|u| u.enabled && u.nopass).unwrap_or(false)
+    }
+
+    pub fn authenticate(&self, name: &str, password: &[u8]) -> bool {
+        self.users.read().unwrap().get(name).map(|u| u.enabled && u.check_password(password)).unwrap_or(false)
+    }
+
+    pub fn check(&self, name: &str, args: &[String]) -> Result<(), Denial> {
+        match self.users.read().unwrap().get(name) {
+            Some(user) => user.check(args),
+            None => Err(Denial::Command),
+        }
+    }
+
+    // all rules are applied or none
+    pub fn setuser(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule).map_err(|e| format!("Error in ACL SETUSER modifier '{}': {}", rule, e))?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    pub fn deluser(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|n| n == DEFAULT_USER) {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+        let mut users = self.users.write().unwrap();
+        Ok(names.iter().filter(|n| users.remove(n.as_str()).is_some()).count())
+    }
+
+    pub fn users(&self) -> Vec<User> {
+        self.users.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn log_denial(&self, reason: &str, context: &str, object: &str, username: &str, client_info: &str) {
+        let now = now_ms();
+        let mut log = self.log.lock().unwrap();
+        let similar = log.entries.iter_mut().find(|e| e.reason == reason && e.context == context
+            && e.object == object && e.username == username && now.saturating_sub(e.updated) < LOG_GROUPING_MS);
+        if let Some(entry) = similar {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info.to_string();
+            return;
+        }
+        let entry_id = log.next_id;
+        log.next_id += 1;
+        log.entries.push_front(LogEntry {
+            count: 1,
+            reason: reason.to_string(),
+            context: context.to_string(),
+            object: object.to_string(),
+            username: username.to_string(),
+            client_info: client_info.to_string(),
+            entry_id,
+            created: now,
+            updated: now,
+        });
+        log.entries.truncate(LOG_MAX_LEN);
+    }
+
+    pub fn log(&self, count: usize) -> Vec<LogEntry> {
+        self.log.lock().unwrap().entries.iter().take(count).cloned().collect()
+    }
+
+    pub fn reset_log(&self) {
+        self.log.lock().unwrap().entries.clear();
+    }
+
+    fn aclfile(&self) -> Result<&str, String> {
+        self.aclfile.as_deref().ok_or_else(|| "This Redis instance is not configured to use an ACL file. \
+            You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE \
+            (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.".to_string())
+    }
+
+    // replaces all users with the ones in aclfile - nothing changes if any line is wrong
+    pub fn load(&self) -> Result<(), String> {
+        let path = self.aclfile()?;
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error loading ACLs, opening file '{}': {}", path, e))?;
+        let mut users = BTreeMap::new();
+        for (idx, line) in content.lines().enumerate() {
+            let fail = |e: &str| format!("{}:{}: {}", path, idx + 1, e);
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] => continue,
+                ["user", name, rules @ ..] => {
+                    if users.contains_key(*name) {
+                        return Err(fail(&format!("Duplicate user '{}' found", name)));
+                    }
+                    let mut user = User::new(name);
+                    for rule in rules {
+                        user.apply(rule).map_err(|e| fail(&e))?;
+                    }
+                    users.insert(name.to_string(), user);
+                },
+                ["user"] => return Err(fail("user name is missing")),
+                _ => return Err(fail("should start with user keyword")),
+            }
+        }
+        users.entry(DEFAULT_USER.to_string()).or_insert_with(User::default_user);
+        *self.users.write().unwrap() = users;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = self.aclfile()?;
+        let mut content = String::new();
+        for user in self.users() {
+            content.push_str(&format!("user {} {}\n", user.name, user.describe()));
+        }
+        let temp_path = format!("{}.tmp-{}", path, std::process::id());
+        std::fs::write(&temp_path, content).map_err(|e| format!("There was an error trying to save the ACLs: {}", e))?;
+        std::fs::rename(&temp_path, path).map_err(|e| format!("There was an error trying to save the ACLs: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(acl: &Acl, command: &str) -> Result<(), String> {
+        let args = command.split(' ').map(|a| a.to_string()).collect::<Vec<_>>();
+        acl.check("mover", &args).map_err(|d| d.message("mover", &args[0]))
+    }
+
+    #[test]
+    fn source_and_destination_keys() {
+        let acl = Acl::default();
+        let rules = ["on", "nopass", "+@all", "%R~src*", "%W~dst*"].map(|r| r.to_string());
+        acl.setuser("mover", &rules).unwrap();
+        assert!(check(&acl, "copy src1 dst1").is_ok());
+        assert!(check(&acl, "copy dst1 src1").is_err());
+        assert!(check(&acl, "bitop and dst1 src1 src2").is_ok());
+        assert!(check(&acl, "geosearchstore dst1 src1 fromlonlat 0 0 byradius 1 km").is_ok());
+        // source of a rename is deleted and destination of a merge read
+        assert!(check(&acl, "rename src1 dst1").is_err());
+        assert!(check(&acl, "pfmerge dst1 src1").is_err());
+        acl.setuser("mover", &["~dst*".to_string(), "%RW~src*".to_string()]).unwrap();
+        assert!(check(&acl, "rename src1 dst1").is_ok());
+        assert!(check(&acl, "pfmerge dst1 src1").is_ok());
+    }
+}
@@ -0,0 +1,214 @@
+// per connection state
+use crate::acl::acl;
+use crate::client::connection::Connection;
+use crate::commands::resp;
+use crate::commands::table;
+use crate::pubsub::pubsub;
+use crate::store::db;
+use bytes::BytesMut;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// commands queued after MULTI - along with the raw bytes for replication
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub commands: Vec<(resp::Args, BytesMut)>,
+    aborted: bool, // a command failed validation, EXEC will be refused
+}
+
+#[derive(Debug, Default)]
+pub struct Client {
+    id: u64,
+    user: String,
+    authenticated: bool,
+    multi: Option<Transaction>,
+    watched: Vec<(String, u64)>, // key and its version at WATCH
+    pusher: Option<pubsub::Pusher>, // created on first subscription
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    shards: Vec<String>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst),
+            user: acl::DEFAULT_USER.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub fn authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub fn authenticate(&mut self, user: &str) {
+        self.user = user.to_string();
+        self.authenticated = true;
+    }
+
+    // as recorded in ACL LOG
+    pub fn info(&self, stream: &Connection) -> String {
+        format!("id={} addr={} laddr={} user={} multi={}", self.id, stream.peer_addr().unwrap_or_default(),
+            stream.local_addr().unwrap_or_default(), self.user, self.multi.as_ref().map(|t| t.commands.len() as i64).unwrap_or(-1))
+    }
+
+    pub fn in_multi(&self) -> bool {
+        self.multi.is_some()
+    }
+
+    pub fn multi(&mut self) -> Result<(), String> {
+        if self.multi.is_some() {
+            return Err("ERR MULTI calls can not be nested".to_string());
+        }
+        self.multi = Some(Transaction::default());
+        Ok(())
+    }
+
+    // queues command inside MULTI, returns the response to send
+    pub fn queue(&mut self, args: &resp::Args, raw: &[u8]) -> String {
+        let transaction = match self.multi.as_mut() {
+            Some(t) => t,
+            None => return "-ERR queueing command without MULTI\r\n".to_string(),
+        };
+        match table::validate(args) {
+            Ok(_) => {
+                transaction.commands.push((args.clone(), BytesMut::from(raw)));
+                "+QUEUED\r\n".to_string()
+            },
+            Err(e) => {
+                transaction.aborted = true;
+                format!("-{}\r\n", e)
+            }
+        }
+    }
+
+    // a command was refused while queueing, EXEC will be refused
+    pub fn abort(&mut self) {
+        if let Some(t) = self.multi.as_mut() {
+            t.aborted = true;
+        }
+    }
+
+    // ends the transaction - returns queued commands to run
+    pub fn exec(&mut self) -> Result<Vec<(resp::Args, BytesMut)>, String> {
+        match self.multi.take() {
+            Some(t) if t.aborted => Err("EXECABORT Transaction discarded because of previous errors.".to_string()),
+            Some(t) => Ok(t.commands),
+            None => Err("ERR EXEC without MULTI".to_string()),
+        }
+    }
+
+    pub fn discard(&mut self) -> Result<(), String> {
+        match self.multi.take() {
+            Some(_) => Ok(()),
+            None => Err("ERR DISCARD without MULTI".to_string()),
+        }
+    }
+
+    pub fn watch(&mut self, db: &db::DB, key: &str) {
+        if self.watched.iter().any(|(k, _v)| k == key) {
+            return;
+        }
+        let version = db.watch(key);
+        self.watched.push((key.to_string(), version));
+    }
+
+    // true if any of the watched keys changed since WATCH
+    pub fn watched_changed(&self, db: &db::DB) -> bool {
+        self.watched.iter().any(|(key, version)| db.watched_version(key) != *version)
+    }
+
+    pub fn unwatch(&mut self, db: &db::DB) {
+        for (key, _version) in self.watched.drain(..) {
+            db.unwatch(&key);
+        }
+    }
+
+    // pusher for writing to this client asynchronously
+    pub fn pusher(&mut self, stream: &Connection) -> std::io::Result<pubsub::Pusher> {
+        if let Some(p) = &self.pusher {
+            return Ok(p.clone());
+        }
+        let p = pubsub::Pusher::new(self.id, stream)?;
+        self.pusher = Some(p.clone());
+        Ok(p)
+    }
+
+    // queues reply behind any pushed messages
+    pub fn push(&mut self, stream: &Connection, db: &db::DB, buf: BytesMut) -> std::io::Result<()> {
+        let p = self.pusher(stream)?;
+        p.push(buf, &db.pubsub().limit());
+        Ok(())
+    }
+
+    // before writing on the stream directly nothing can be left in pusher
+    pub fn drain(&self) {
+        if let Some(p) = &self.pusher {
+            p.drain();
+        }
+    }
+
+    // in subscribed mode only subscription commands are accepted
+    pub fn subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shards.is_empty()
+    }
+
+    // count reported in subscribe replies - sharded channels are counted apart
+    pub fn subscriptions(&self, kind: pubsub::Kind) -> usize {
+        match kind {
+            pubsub::Kind::Shard => self.shards.len(),
+            _ => self.channels.len() + self.patterns.len(),
+        }
+    }
+
+    fn list(&mut self, kind: pubsub::Kind) -> &mut Vec<String> {
+        match kind {
+            pubsub::Kind::Channel => &mut self.channels,
+            pubsub::Kind::Pattern => &mut self.patterns,
+            pubsub::Kind::Shard => &mut self.shards,
+        }
+    }
+
+    // returns false if already subscribed
+    pub fn subscribe(&mut self, channel: &str, kind: pubsub::Kind) -> bool {
+        let subs = self.list(kind);
+        if subs.iter().any(|c| c == channel) {
+            return false;
+        }
+        subs.push(channel.to_string());
+        true
+    }
+
+    // returns false if not subscribed
+    pub fn unsubscribe(&mut self, channel: &str, kind: pubsub::Kind) -> bool {
+        let subs = self.list(kind);
+        let before = subs.len();
+        subs.retain(|c| c != channel);
+        before != subs.len()
+    }
+
+    pub fn channels(&mut self, kind: pubsub::Kind) -> Vec<String> {
+        self.list(kind).clone()
+    }
+
+    // connection is going away
+    pub fn close(&mut self, db: &db::DB) {
+        self.multi = None;
+        self.unwatch(db);
+        db.pubsub().remove_client(self.id);
+        self.channels.clear();
+        self.patterns.clear();
+        self.shards.clear();
+        self.pusher = None;
+    }
+}
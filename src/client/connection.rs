@@ -0,0 +1,147 @@
+// client connections - over TCP or a unix domain socket
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Connection {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            Connection::Tcp(s) => s.try_clone().map(Connection::Tcp),
+            Connection::Unix(s) => s.try_clone().map(Connection::Unix),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.shutdown(how),
+            Connection::Unix(s) => s.shutdown(how),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.set_read_timeout(timeout),
+            Connection::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    // unix socket peers have no address - socket path and descriptor tell them apart
+    pub fn peer_addr(&self) -> std::io::Result<String> {
+        match self {
+            Connection::Tcp(s) => s.peer_addr().map(|a| a.to_string()),
+            Connection::Unix(s) => Ok(format!("{}:{}", Self::path(s)?, s.as_raw_fd())),
+        }
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<String> {
+        match self {
+            Connection::Tcp(s) => s.local_addr().map(|a| a.to_string()),
+            Connection::Unix(s) => Ok(format!("{}:0", Self::path(s)?)),
+        }
+    }
+
+    fn path(s: &UnixStream) -> std::io::Result<String> {
+        Ok(s.local_addr()?.as_pathname().map(|p| p.display().to_string()).unwrap_or_default())
+    }
+
+    // loopback and unix socket clients - the ones allowed in protected mode
+    pub fn is_local(&self) -> bool {
+        match self {
+            Connection::Tcp(s) => s.peer_addr().map(|a| a.ip().is_loopback()).unwrap_or(false),
+            Connection::Unix(_) => true,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.read(buf),
+            Connection::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tcp(s) => s.write(buf),
+            Connection::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(s) => s.flush(),
+            Connection::Unix(s) => s.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    // bind list as in redis - * for all IPv4, ::* for all IPv6 addresses
+    // and a leading - for addresses which may not be available
+    pub fn bind_tcp(addresses: &str, port: u16) -> Result<Vec<Self>, String> {
+        let mut listeners = vec![];
+        for address in addresses.split_whitespace() {
+            let (optional, address) = match address.strip_prefix('-') {
+                Some(a) => (true, a),
+                None => (false, address),
+            };
+            let ip = match address {
+                "*" => "0.0.0.0",
+                "::*" => "::",
+                a => a,
+            };
+            let target = if ip.contains(':') { format!("[{}]:{}", ip, port) } else { format!("{}:{}", ip, port) };
+            match TcpListener::bind(&target) {
+                Ok(l) => listeners.push(Listener::Tcp(l)),
+                Err(e) if optional => println!("Skipping optional bind address {}: {}", target, e),
+                Err(e) => return Err(format!("Could not create server TCP listening socket {}: {}", target, e)),
+            }
+        }
+        Ok(listeners)
+    }
+
+    // a stale socket file left behind is replaced, perm 0 leaves the default permissions
+    pub fn bind_unix(path: &str, perm: u32) -> Result<Self, String> {
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path).map_err(|e| format!("Failed opening Unix socket {}: {}", path, e))?;
+        if perm != 0 {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(perm))
+                .map_err(|e| format!("Failed setting permissions of Unix socket {}: {}", path, e))?;
+        }
+        Ok(Listener::Unix(listener))
+    }
+
+    pub fn accept(&self) -> std::io::Result<Connection> {
+        match self {
+            Listener::Tcp(l) => l.accept().map(|(s, _a)| Connection::Tcp(s)),
+            Listener::Unix(l) => l.accept().map(|(s, _a)| Connection::Unix(s)),
+        }
+    }
+
+    pub fn address(&self) -> String {
+        match self {
+            Listener::Tcp(l) => l.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+            Listener::Unix(l) => l.local_addr().ok()
+                .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+                .unwrap_or_default(),
+        }
+    }
+}
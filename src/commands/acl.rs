@@ -0,0 +1,202 @@
+use crate::client::client;
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::commands::table;
+use crate::store::db;
+use crate::utils::utils;
+use std::io::Read;
+use std::sync::Arc;
+
+const ACL_HELP: [&str; 29] = [
+    "ACL <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CAT [<category>]",
+    "    List all commands that belong to <category>, or all command categories",
+    "    when no category is specified.",
+    "DELUSER <username> [<username> ...]",
+    "    Delete a list of users.",
+    "DRYRUN <username> <command> [<arg> ...]",
+    "    Returns whether the user can execute the given command without executing the command.",
+    "GETUSER <username>",
+    "    Get the user's details.",
+    "GENPASS [<bits>]",
+    "    Generate a secure 256-bit user password. The optional `bits` argument can",
+    "    be used to specify a different size.",
+    "LIST",
+    "    Show users details in config file format.",
+    "LOAD",
+    "    Reload users from the ACL file.",
+    "LOG [<count> | RESET]",
+    "    Show the ACL log entries.",
+    "SAVE",
+    "    Save the current config to the ACL file.",
+    "SETUSER <username> <attribute> [<attribute> ...]",
+    "    Create or modify a user with the specified attributes.",
+    "USERS",
+    "    List all the registered usernames.",
+    "WHOAMI",
+    "    Return the current connection username.",
+    "HELP",
+    "    Print this help.",
+];
+
+const DEFAULT_LOG_COUNT: usize = 10;
+const DEFAULT_GENPASS_BITS: i64 = 256;
+
+fn bulks(items: &[String]) -> Vec<u8> {
+    let mut response = format!("*{}\r\n", items.len()).into_bytes();
+    items.iter().for_each(|i| response.extend(strings::bulk(i.as_bytes())));
+    response
+}
+
+// hex password with the given number of random bits
+fn genpass(bits: usize) -> String {
+    let mut random = vec![0u8; bits.div_ceil(8)];
+    let filled = std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut random)).is_ok();
+    if !filled {
+        for chunk in random.chunks_mut(8) {
+            let bytes = utils::random().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+    let hex = random.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+    hex[..bits.div_ceil(4)].to_string()
+}
+
+// ACL SETUSER/GETUSER/DELUSER/LIST/USERS/WHOAMI/CAT/LOG/DRYRUN/GENPASS/LOAD/SAVE
+#[derive(Debug)]
+pub struct Acl<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+}
+
+impl<'a> Acl<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    // user names and rules are taken as sent
+    fn raw(&self, idx: usize) -> String {
+        String::from_utf8_lossy(self.cmd.raw(idx)).to_string()
+    }
+
+    fn cat(&self) -> Result<Vec<u8>, String> {
+        let Some(category) = self.cmd.get(2) else {
+            return Ok(bulks(&table::CATEGORIES.iter().map(|(name, _b)| name.to_string()).collect::<Vec<_>>()));
+        };
+        let (_name, bit) = table::CATEGORIES.iter().find(|(name, _b)| name == category)
+            .ok_or_else(|| format!("ERR Unknown category '{}'", category))?;
+        let mut commands = vec![];
+        for spec in table::commands() {
+            if spec.categories & bit != 0 {
+                commands.push(spec.name.to_string());
+            }
+            table::subcommands(spec.name).filter(|(_s, c)| c & bit != 0)
+                .for_each(|(s, _c)| commands.push(format!("{}|{}", spec.name, s)));
+        }
+        Ok(bulks(&commands))
+    }
+
+    fn dryrun(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        let user = self.raw(2);
+        if !db.acl().exists(&user) {
+            return Err(format!("ERR User '{}' not found", user));
+        }
+        let args = &self.cmd[3..];
+        let spec = table::lookup(&args[0]).ok_or_else(|| format!("ERR Command '{}' not found", args[0]))?;
+        let arity = spec.arity;
+        if (arity > 0 && args.len() as i32 != arity) || (arity < 0 && (args.len() as i32) < -arity) {
+            return Err(format!("ERR wrong number of arguments for '{}' command", args[0]));
+        }
+        match db.acl().check(&user, args) {
+            Ok(()) => Ok(b"+OK\r\n".to_vec()),
+            Err(denial) => {
+                let message = denial.message(&user, &spec.full_name(args));
+                Ok(strings::bulk(message.trim_start_matches("NOPERM ").as_bytes()))
+            },
+        }
+    }
+
+    fn log(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        let count = match self.cmd.get(2).map(|s| s.as_str()) {
+            None => DEFAULT_LOG_COUNT,
+            Some("reset") => {
+                db.acl().reset_log();
+                return Ok(b"+OK\r\n".to_vec());
+            },
+            Some(count) => match strings::parse_int(count.as_bytes()) {
+                Ok(n) if n >= 0 => n as usize,
+                _ => return Err("ERR value is out of range, must be positive".to_string()),
+            },
+        };
+        let entries = db.acl().log(count);
+        let mut response = format!("*{}\r\n", entries.len()).into_bytes();
+        entries.iter().for_each(|e| response.extend(e.info()));
+        Ok(response)
+    }
+
+    fn run(&self, db: &Arc<db::DB>, client: &client::Client) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let acl = db.acl();
+        let subcommand = self.cmd[1].as_str();
+        match (subcommand, self.cmd.len()) {
+            ("help", 2) => {
+                let mut response = format!("*{}\r\n", ACL_HELP.len());
+                ACL_HELP.iter().for_each(|line| response.push_str(&format!("+{}\r\n", line)));
+                Ok(response.into())
+            },
+            ("cat", 2..=3) => self.cat(),
+            ("deluser", 3..) => {
+                let names = (2..self.cmd.len()).map(|i| self.raw(i)).collect::<Vec<_>>();
+                let count = acl.deluser(&names).map_err(|e| format!("ERR {}", e))?;
+                Ok(format!(":{}\r\n", count).into_bytes())
+            },
+            ("dryrun", 4..) => self.dryrun(db),
+            ("genpass", 2..=3) => {
+                let bits = match self.cmd.get(2) {
+                    Some(bits) => strings::parse_int(bits.as_bytes()).unwrap_or(0),
+                    None => DEFAULT_GENPASS_BITS,
+                };
+                if !(1..=4096).contains(&bits) {
+                    return Err("ERR ACL GENPASS argument must be the number of bits for the output password, \
+                        a positive number up to 4096".to_string());
+                }
+                Ok(strings::bulk(genpass(bits as usize).as_bytes()))
+            },
+            ("getuser", 3) => match acl.user(&self.raw(2)) {
+                Some(user) => Ok(user.info()),
+                None => Ok(b"$-1\r\n".to_vec()),
+            },
+            ("list", 2) => {
+                let users = acl.users().iter().map(|u| format!("user {} {}", u.name(), u.describe())).collect::<Vec<_>>();
+                Ok(bulks(&users))
+            },
+            ("users", 2) => Ok(bulks(&acl.users().iter().map(|u| u.name().to_string()).collect::<Vec<_>>())),
+            ("setuser", 3..) => {
+                let rules = (3..self.cmd.len()).map(|i| self.raw(i)).collect::<Vec<_>>();
+                acl.setuser(&self.raw(2), &rules).map_err(|e| format!("ERR {}", e))?;
+                Ok(b"+OK\r\n".to_vec())
+            },
+            ("whoami", 2) => Ok(strings::bulk(client.user().as_bytes())),
+            ("log", 2..=3) => self.log(db),
+            ("load", 2) => acl.load().map(|_| b"+OK\r\n".to_vec()).map_err(|e| format!("ERR {}", e)),
+            ("save", 2) => acl.save().map(|_| b"+OK\r\n".to_vec()).map_err(|e| format!("ERR {}", e)),
+            _ => Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try ACL HELP.", subcommand)),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Acl<'a> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    // WHOAMI needs the connection's user
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        let result = self.run(db, client);
+        strings::reply(stream, self.replication_conn, result)
+    }
+}
@@ -1,18 +1,42 @@
+use crate::commands::acl;
+use crate::commands::auth;
+use crate::commands::bitops;
+use crate::commands::dump;
 use crate::commands::echo;
+use crate::commands::geo;
 use crate::commands::getset;
+use crate::commands::hyperloglog;
 use crate::commands::incoming;
 use crate::commands::info;
 use crate::commands::ping;
+use crate::commands::resp;
+use crate::commands::pubsub;
+use crate::pubsub::pubsub::Kind;
 use crate::commands::psync;
 use crate::commands::replcmd;
 use crate::commands::ss;
 use crate::commands::wait;
 use crate::commands::config;
 use crate::commands::keys;
+use crate::commands::keyspace;
+use crate::commands::memory;
+use crate::commands::migrate;
+use crate::commands::multi;
 use crate::commands::ttype;
 use crate::commands::stream;
+use crate::commands::strings;
+use super::xack;
+use super::xclaim;
+use super::xdel;
+use super::xgroup;
+use super::xinfo;
+use super::xlen;
+use super::xpending;
 use super::xrange;
 use super::xread;
+use super::xreadgroup;
+use super::xsetid;
+use super::xtrim;
 
 #[allow(dead_code)]
 
@@ -24,40 +48,96 @@ pub fn get_nth_arg(values: &Vec<String>, id: usize) -> Option<&String> {
 }
 
 pub fn array_type_handler(
-    cmd: &Vec<String>,
+    cmd: &resp::Args,
     replication_conn: bool,
 ) -> Box<dyn incoming::CommandHandler + '_> {
-    if cmd[0].contains("ok") {
-        return Box::new(ss::OkResponse::new(replication_conn));
-    } else if cmd[0].contains("info") {
-        return Box::new(info::Info::new(cmd, replication_conn));
-    } else if cmd[0].contains("echo") {
-        return Box::new(echo::Echo::new(cmd, replication_conn));
-    } else if cmd[0].contains("ping") {
-        return Box::new(ping::Ping::new(replication_conn));
-    } else if cmd[0].contains("set") {
-        return Box::new(getset::SetCommand::new(cmd, replication_conn));
-    } else if cmd[0].contains("get") {
-        return Box::new(getset::GetCommand::new(cmd, replication_conn));
-    } else if cmd[0].contains("replconf") {
-        return Box::new(replcmd::ReplCommand::new(cmd, replication_conn));
-    } else if cmd[0].contains("psync") {
-        return Box::new(psync::PSync::new(cmd, replication_conn));
-    } else if cmd[0].contains("wait") {
-        return Box::new(wait::Wait::new(cmd, replication_conn));
-    } else if cmd[0].contains("config") {
-        return Box::new(config::Config::new(cmd, replication_conn));
-    } else if cmd[0].contains("keys") {
-        return Box::new(keys::Keys::new(cmd, replication_conn));
-    } else if cmd[0].contains("type") {
-        return Box::new(ttype::TType::new(cmd, replication_conn));
-    } else if cmd[0].contains("xadd") {
-        return Box::new(stream::Stream::new(cmd, replication_conn));
-    } else if cmd[0].contains("xrange") {
-        return Box::new(xrange::XRange::new(cmd, replication_conn));
-    } else if cmd[0].contains("xread") {
-        return Box::new(xread::XRead::new(cmd, replication_conn));
+    match cmd[0].as_str() {
+        "ok" => Box::new(ss::OkResponse::new(replication_conn)),
+        "info" => Box::new(info::Info::new(cmd, replication_conn)),
+        "echo" => Box::new(echo::Echo::new(cmd, replication_conn)),
+        "ping" => Box::new(ping::Ping::new(replication_conn)),
+        "set" => Box::new(getset::SetCommand::new(cmd, replication_conn)),
+        "get" => Box::new(getset::GetCommand::new(cmd, replication_conn)),
+        "incr" | "decr" | "incrby" | "decrby" | "incrbyfloat" => Box::new(strings::Incr::new(cmd, replication_conn)),
+        "append" => Box::new(strings::Append::new(cmd, replication_conn)),
+        "strlen" => Box::new(strings::StrLen::new(cmd, replication_conn)),
+        "getrange" => Box::new(strings::GetRange::new(cmd, replication_conn)),
+        "setrange" => Box::new(strings::SetRange::new(cmd, replication_conn)),
+        "getdel" => Box::new(strings::GetDel::new(cmd, replication_conn)),
+        "getex" => Box::new(strings::GetEx::new(cmd, replication_conn)),
+        "mget" => Box::new(strings::MGet::new(cmd, replication_conn)),
+        "mset" => Box::new(strings::MSet::new(cmd, false, replication_conn)),
+        "msetnx" => Box::new(strings::MSet::new(cmd, true, replication_conn)),
+        "setnx" => Box::new(strings::SetNx::new(cmd, replication_conn)),
+        "setex" => Box::new(strings::SetEx::new(cmd, false, replication_conn)),
+        "psetex" => Box::new(strings::SetEx::new(cmd, true, replication_conn)),
+        "setbit" => Box::new(bitops::SetBit::new(cmd, replication_conn)),
+        "getbit" => Box::new(bitops::GetBit::new(cmd, replication_conn)),
+        "bitcount" => Box::new(bitops::BitCount::new(cmd, replication_conn)),
+        "bitpos" => Box::new(bitops::BitPos::new(cmd, replication_conn)),
+        "bitop" => Box::new(bitops::BitOp::new(cmd, replication_conn)),
+        "bitfield" => Box::new(bitops::BitField::new(cmd, false, replication_conn)),
+        "bitfield_ro" => Box::new(bitops::BitField::new(cmd, true, replication_conn)),
+        "pfadd" => Box::new(hyperloglog::PfAdd::new(cmd, replication_conn)),
+        "pfcount" => Box::new(hyperloglog::PfCount::new(cmd, replication_conn)),
+        "pfmerge" => Box::new(hyperloglog::PfMerge::new(cmd, replication_conn)),
+        "geoadd" => Box::new(geo::GeoAdd::new(cmd, replication_conn)),
+        "geodist" => Box::new(geo::GeoDist::new(cmd, replication_conn)),
+        "geopos" => Box::new(geo::GeoPos::new(cmd, false, replication_conn)),
+        "geohash" => Box::new(geo::GeoPos::new(cmd, true, replication_conn)),
+        "geosearch" => Box::new(geo::GeoSearch::new(cmd, false, replication_conn)),
+        "geosearchstore" => Box::new(geo::GeoSearch::new(cmd, true, replication_conn)),
+        "replconf" => Box::new(replcmd::ReplCommand::new(cmd, replication_conn)),
+        "psync" => Box::new(psync::PSync::new(cmd, replication_conn)),
+        "wait" => Box::new(wait::Wait::new(cmd, replication_conn)),
+        "config" => Box::new(config::Config::new(cmd, replication_conn)),
+        "keys" => Box::new(keys::Keys::new(cmd, replication_conn)),
+        "scan" => Box::new(keys::Scan::new(cmd, replication_conn)),
+        "zscan" => Box::new(keys::ZScan::new(cmd, replication_conn)),
+        "type" => Box::new(ttype::TType::new(cmd, replication_conn)),
+        "del" => Box::new(keyspace::Del::new(cmd, false, replication_conn)),
+        "unlink" => Box::new(keyspace::Del::new(cmd, true, replication_conn)),
+        "exists" => Box::new(keyspace::Exists::new(cmd, false, replication_conn)),
+        "touch" => Box::new(keyspace::Exists::new(cmd, true, replication_conn)),
+        "rename" => Box::new(keyspace::Rename::new(cmd, false, replication_conn)),
+        "renamenx" => Box::new(keyspace::Rename::new(cmd, true, replication_conn)),
+        "copy" => Box::new(keyspace::Copy::new(cmd, replication_conn)),
+        "randomkey" => Box::new(keyspace::RandomKey::new(replication_conn)),
+        "object" => Box::new(keyspace::Object::new(cmd, replication_conn)),
+        "dump" => Box::new(dump::Dump::new(cmd, replication_conn)),
+        "restore" | "restore-asking" => Box::new(dump::Restore::new(cmd, replication_conn)),
+        "migrate" => Box::new(migrate::Migrate::new(cmd, replication_conn)),
+        "memory" => Box::new(memory::Memory::new(cmd, replication_conn)),
+        "auth" => Box::new(auth::Auth::new(cmd, replication_conn)),
+        "acl" => Box::new(acl::Acl::new(cmd, replication_conn)),
+        "xadd" => Box::new(stream::Stream::new(cmd, replication_conn)),
+        "xrange" => Box::new(xrange::XRange::new(cmd, false, replication_conn)),
+        "xrevrange" => Box::new(xrange::XRange::new(cmd, true, replication_conn)),
+        "xlen" => Box::new(xlen::XLen::new(cmd, replication_conn)),
+        "xdel" => Box::new(xdel::XDel::new(cmd, replication_conn)),
+        "xtrim" => Box::new(xtrim::XTrim::new(cmd, replication_conn)),
+        "xsetid" => Box::new(xsetid::XSetId::new(cmd, replication_conn)),
+        "xinfo" => Box::new(xinfo::XInfo::new(cmd, replication_conn)),
+        "xread" => Box::new(xread::XRead::new(cmd, replication_conn)),
+        "xgroup" => Box::new(xgroup::XGroup::new(cmd, replication_conn)),
+        "xreadgroup" => Box::new(xreadgroup::XReadGroup::new(cmd, replication_conn)),
+        "xack" => Box::new(xack::XAck::new(cmd, replication_conn)),
+        "xpending" => Box::new(xpending::XPending::new(cmd, replication_conn)),
+        "xclaim" => Box::new(xclaim::XClaim::new(cmd, replication_conn)),
+        "xautoclaim" => Box::new(xclaim::XAutoClaim::new(cmd, replication_conn)),
+        "multi" => Box::new(multi::Multi::new(replication_conn)),
+        "discard" => Box::new(multi::Discard::new(replication_conn)),
+        "watch" => Box::new(multi::Watch::new(cmd, replication_conn)),
+        "unwatch" => Box::new(multi::Unwatch::new(replication_conn)),
+        "subscribe" => Box::new(pubsub::Subscribe::new(cmd, Kind::Channel, replication_conn)),
+        "psubscribe" => Box::new(pubsub::Subscribe::new(cmd, Kind::Pattern, replication_conn)),
+        "ssubscribe" => Box::new(pubsub::Subscribe::new(cmd, Kind::Shard, replication_conn)),
+        "unsubscribe" => Box::new(pubsub::Unsubscribe::new(cmd, Kind::Channel, replication_conn)),
+        "punsubscribe" => Box::new(pubsub::Unsubscribe::new(cmd, Kind::Pattern, replication_conn)),
+        "sunsubscribe" => Box::new(pubsub::Unsubscribe::new(cmd, Kind::Shard, replication_conn)),
+        "publish" => Box::new(pubsub::Publish::new(cmd, false, replication_conn)),
+        "spublish" => Box::new(pubsub::Publish::new(cmd, true, replication_conn)),
+        "pubsub" => Box::new(pubsub::PubSub::new(cmd, replication_conn)),
+        _ => Box::new(ss::InvalidCommand::new(replication_conn)),
     }
-
-    Box::new(ss::InvalidCommand::new(replication_conn))
 }
@@ -0,0 +1,52 @@
+use crate::acl::acl;
+use crate::client::client;
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::store::db;
+use std::sync::Arc;
+
+// AUTH [username] password - without username it is the default user
+#[derive(Debug)]
+pub struct Auth<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+}
+
+impl<'a> Auth<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, stream: &Connection, db: &Arc<db::DB>, client: &mut client::Client) -> Result<Vec<u8>, String> {
+        let (user, password) = match self.cmd.len() {
+            2 => (acl::DEFAULT_USER.to_string(), self.cmd.raw(1)),
+            3 => (String::from_utf8_lossy(self.cmd.raw(1)).to_string(), self.cmd.raw(2)),
+            n if n > 3 => return Err("ERR syntax error".to_string()),
+            _ => return Err(strings::wrong_args(self.cmd)),
+        };
+        if self.cmd.len() == 2 && db.acl().nopass(acl::DEFAULT_USER) {
+            return Err("ERR AUTH <password> called without any password configured for the default user. \
+                Are you sure your configuration is correct?".to_string());
+        }
+        if !db.acl().authenticate(&user, password) {
+            let context = if client.in_multi() { "multi" } else { "toplevel" };
+            db.acl().log_denial("auth", context, "AUTH", &user, &client.info(stream));
+            return Err("WRONGPASS invalid username-password pair or user is disabled.".to_string());
+        }
+        client.authenticate(&user);
+        Ok(b"+OK\r\n".to_vec())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Auth<'a> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        let result = self.run(stream, db, client);
+        strings::reply(stream, self.replication_conn, result)
+    }
+}
@@ -0,0 +1,508 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::strings;
+use crate::store::db;
+use bytes::BytesMut;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+// bit offsets have to stay within a 512MB string
+const MAX_BIT_OFFSET: u64 = 512 * 1024 * 1024 * 8 - 1;
+
+fn parse_offset(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(n) if n <= MAX_BIT_OFFSET => Ok(n),
+        _ => Err("ERR bit offset is not an integer or out of range".to_string()),
+    }
+}
+
+// bits are numbered from the most significant bit of the first byte
+fn get_bit(bytes: &[u8], offset: u64) -> u64 {
+    let byte = bytes.get((offset >> 3) as usize).copied().unwrap_or(0);
+    ((byte >> (7 - (offset & 7))) & 1) as u64
+}
+
+fn set_bit(bytes: &mut Vec<u8>, offset: u64, bit: u64) {
+    let idx = (offset >> 3) as usize;
+    if bytes.len() <= idx {
+        bytes.resize(idx + 1, 0);
+    }
+    let mask = 1 << (7 - (offset & 7));
+    if bit == 1 {
+        bytes[idx] |= mask;
+    } else {
+        bytes[idx] &= !mask;
+    }
+}
+
+// first and last bit of a BITCOUNT/BITPOS range over len bytes, None if empty -
+// negative start and end count from the end like GETRANGE does
+fn bit_range(start: i64, end: i64, in_bits: bool, len: usize) -> Option<(u64, u64)> {
+    let total = if in_bits { len as i64 * 8 } else { len as i64 };
+    if start < 0 && end < 0 && start > end {
+        return None;
+    }
+    let start = if start < 0 { (start + total).max(0) } else { start };
+    let end = if end < 0 { (end + total).max(0) } else { end.min(total - 1) };
+    if total == 0 || start > end {
+        return None;
+    }
+    if in_bits {
+        return Some((start as u64, end as u64));
+    }
+    Some((start as u64 * 8, end as u64 * 8 + 7))
+}
+
+// byte i of the range first..=last with the bits outside of it cleared
+fn masked(byte: u8, i: u64, first: u64, last: u64) -> u8 {
+    let mut byte = byte;
+    if i == first / 8 {
+        byte &= 0xff >> (first % 8);
+    }
+    if i == last / 8 {
+        byte &= 0xff << (7 - last % 8);
+    }
+    byte
+}
+
+// [start end [BYTE|BIT]] starting at idx - None when no range is given
+fn parse_range(cmd: &[String], idx: usize, end_required: bool) -> Result<Option<(i64, Option<i64>, bool)>, String> {
+    let int = |s: &String| strings::parse_int(s.as_bytes());
+    match &cmd[idx..] {
+        [] => Ok(None),
+        [start] if !end_required => Ok(Some((int(start)?, None, false))),
+        [start, end] => Ok(Some((int(start)?, Some(int(end)?), false))),
+        [start, end, unit] if unit == "byte" || unit == "bit" => {
+            Ok(Some((int(start)?, Some(int(end)?), unit == "bit")))
+        },
+        _ => Err("ERR syntax error".to_string()),
+    }
+}
+
+// SETBIT key offset value
+#[derive(Debug)]
+pub struct SetBit<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> SetBit<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 4 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let offset = parse_offset(&self.cmd[2])?;
+        let bit = match self.cmd[3].as_str() {
+            "0" => 0,
+            "1" => 1,
+            _ => return Err("ERR bit is not an integer or out of range".to_string()),
+        };
+        let old = db.update(&self.cmd[1], |v| {
+            let mut bytes = strings::string_value(v)?.unwrap_or_default();
+            let old = get_bit(&bytes, offset);
+            set_bit(&mut bytes, offset, bit);
+            Ok((db::Change::Value(db::KeyValueType::string(bytes)), old))
+        })?;
+        db.notify('$', "setbit", &self.cmd[1]);
+        *self.changed.write().unwrap() = true;
+        Ok(format!(":{}\r\n", old).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for SetBit<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// GETBIT key offset
+#[derive(Debug)]
+pub struct GetBit<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> GetBit<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 3 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let offset = parse_offset(&self.cmd[2])?;
+        let bytes = strings::string_value(db.get(&self.cmd[1]).as_ref())?.unwrap_or_default();
+        Ok(format!(":{}\r\n", get_bit(&bytes, offset)).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for GetBit<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// BITCOUNT key [start end [BYTE|BIT]]
+#[derive(Debug)]
+pub struct BitCount<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> BitCount<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let range = parse_range(self.cmd, 2, true)?;
+        let bytes = strings::string_value(db.get(&self.cmd[1]).as_ref())?.unwrap_or_default();
+        let (start, end, in_bits) = range.map(|(s, e, b)| (s, e.unwrap_or(-1), b)).unwrap_or((0, -1, false));
+        let count = match bit_range(start, end, in_bits, bytes.len()) {
+            Some((first, last)) => (first / 8..=last / 8)
+                .map(|i| masked(bytes[i as usize], i, first, last).count_ones() as u64)
+                .sum(),
+            None => 0,
+        };
+        Ok(format!(":{}\r\n", count).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for BitCount<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// BITPOS key bit [start [end [BYTE|BIT]]]
+#[derive(Debug)]
+pub struct BitPos<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> BitPos<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 3 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let bit = match self.cmd[2].as_str() {
+            "0" => 0,
+            "1" => 1,
+            _ => return Err("ERR The bit argument must be 1 or 0.".to_string()),
+        };
+        let range = parse_range(self.cmd, 3, false)?;
+        let bytes = match strings::string_value(db.get(&self.cmd[1]).as_ref())? {
+            Some(bytes) => bytes,
+            // missing key is all clear bits
+            None => return Ok(format!(":{}\r\n", if bit == 1 { -1 } else { 0 }).into()),
+        };
+        let (start, end, in_bits) = range.unwrap_or((0, None, false));
+        let (first, last) = match bit_range(start, end.unwrap_or(-1), in_bits, bytes.len()) {
+            Some(range) => range,
+            None => return Ok(":-1\r\n".into()),
+        };
+        let found = (first / 8..=last / 8).find_map(|i| {
+            let byte = if bit == 1 { bytes[i as usize] } else { !bytes[i as usize] };
+            match masked(byte, i, first, last) {
+                0 => None,
+                b => Some(i * 8 + b.leading_zeros() as u64),
+            }
+        });
+        let pos = match found {
+            Some(pos) => pos as i64,
+            // without an end the string counts as padded with clear bits
+            None if bit == 0 && end.is_none() => last as i64 + 1,
+            None => -1,
+        };
+        Ok(format!(":{}\r\n", pos).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for BitPos<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// BITOP AND|OR|XOR|NOT destkey key [key ...]
+#[derive(Debug)]
+pub struct BitOp<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> BitOp<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 4 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let op = self.cmd[1].as_str();
+        if !matches!(op, "and" | "or" | "xor" | "not") {
+            return Err("ERR syntax error".to_string());
+        }
+        if op == "not" && self.cmd.len() != 4 {
+            return Err("ERR BITOP NOT must be called with a single source key.".to_string());
+        }
+        let sources = db.mget(&self.cmd[3..]).iter()
+            .map(|v| strings::string_value(v.as_ref()).map(|v| v.unwrap_or_default()))
+            .collect::<Result<Vec<Vec<u8>>, String>>()?;
+        let len = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+        // shorter strings count as padded with zero bytes
+        let result = (0..len).map(|i| {
+            let mut bytes = sources.iter().map(|s| s.get(i).copied().unwrap_or(0));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                "and" => bytes.fold(first, |acc, b| acc & b),
+                "or" => bytes.fold(first, |acc, b| acc | b),
+                "xor" => bytes.fold(first, |acc, b| acc ^ b),
+                _ => !first,
+            }
+        }).collect::<Vec<u8>>();
+        let dest = &self.cmd[2];
+        let removed = db.update(dest, |v| match result.is_empty() {
+            true => Ok((db::Change::Remove, v.is_some())),
+            false => Ok((db::Change::Replace(db::KeyValueType::string(result), None), false)),
+        })?;
+        if len > 0 {
+            db.notify('$', "set", dest);
+        } else if removed {
+            db.notify('g', "del", dest);
+        }
+        *self.changed.write().unwrap() = true;
+        Ok(format!(":{}\r\n", len).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for BitOp<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+// i1..i64 or u1..u63 at a bit offset
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    signed: bool,
+    bits: u32,
+    offset: u64,
+}
+
+impl Field {
+    fn parse(kind: &str, offset: &str) -> Result<Self, String> {
+        let signed = kind.starts_with('i');
+        let bits = match kind.get(1..).map(|b| b.parse::<u32>()) {
+            Some(Ok(bits)) if (kind.starts_with('u') && bits < 64 || signed && bits <= 64) && bits > 0 => bits,
+            _ => return Err("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.".to_string()),
+        };
+        // #n addresses the n-th field of this width
+        let offset = match offset.strip_prefix('#') {
+            Some(n) => n.parse::<u64>().ok().and_then(|n| n.checked_mul(bits as u64)),
+            None => offset.parse::<u64>().ok(),
+        };
+        match offset {
+            Some(offset) if offset <= MAX_BIT_OFFSET => Ok(Self { signed, bits, offset }),
+            _ => Err("ERR bit offset is not an integer or out of range".to_string()),
+        }
+    }
+
+    fn limits(&self) -> (i128, i128) {
+        if self.signed {
+            return (-(1 << (self.bits - 1)), (1 << (self.bits - 1)) - 1);
+        }
+        (0, (1 << self.bits) - 1)
+    }
+
+    fn read(&self, bytes: &[u8]) -> i128 {
+        let value = (0..self.bits as u64).fold(0u64, |acc, i| acc << 1 | get_bit(bytes, self.offset + i));
+        if self.signed && value >> (self.bits - 1) & 1 == 1 {
+            return value as i128 - (1 << self.bits);
+        }
+        value as i128
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>, value: i128) {
+        let value = value as u64;
+        (0..self.bits as u64).for_each(|i| set_bit(bytes, self.offset + i, value >> (self.bits as u64 - 1 - i) & 1));
+    }
+
+    // value brought back into the range of the field, None when it fails
+    fn overflow(&self, value: i128, overflow: Overflow) -> Option<i128> {
+        let (min, max) = self.limits();
+        if value >= min && value <= max {
+            return Some(value);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let wrapped = value.rem_euclid(1 << self.bits);
+                Some(if wrapped > max { wrapped - (1 << self.bits) } else { wrapped })
+            },
+            Overflow::Sat => Some(if value > max { max } else { min }),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum FieldOp {
+    Get(Field),
+    Set(Field, i64, Overflow),
+    IncrBy(Field, i64, Overflow),
+}
+
+fn parse_field_ops(cmd: &[String], read_only: bool) -> Result<Vec<FieldOp>, String> {
+    let mut ops = vec![];
+    let mut overflow = Overflow::Wrap;
+    let mut idx = 2;
+    while idx < cmd.len() {
+        let args = &cmd[idx + 1..];
+        let op = cmd[idx].as_str();
+        if read_only && op != "get" {
+            return Err("ERR BITFIELD_RO only supports the GET subcommand".to_string());
+        }
+        match (op, args) {
+            ("get", [kind, offset, ..]) => {
+                ops.push(FieldOp::Get(Field::parse(kind, offset)?));
+                idx += 3;
+            },
+            ("set" | "incrby", [kind, offset, value, ..]) => {
+                let field = Field::parse(kind, offset)?;
+                let value = strings::parse_int(value.as_bytes())?;
+                ops.push(match op {
+                    "set" => FieldOp::Set(field, value, overflow),
+                    _ => FieldOp::IncrBy(field, value, overflow),
+                });
+                idx += 4;
+            },
+            ("overflow", [kind, ..]) => {
+                overflow = match kind.as_str() {
+                    "wrap" => Overflow::Wrap,
+                    "sat" => Overflow::Sat,
+                    "fail" => Overflow::Fail,
+                    _ => return Err("ERR Invalid OVERFLOW type specified".to_string()),
+                };
+                idx += 2;
+            },
+            _ => return Err("ERR syntax error".to_string()),
+        }
+    }
+    Ok(ops)
+}
+
+// BITFIELD key [GET type offset] [SET type offset value] [INCRBY type offset increment] [OVERFLOW WRAP|SAT|FAIL] ...
+// BITFIELD_RO key [GET type offset] ...
+#[derive(Debug)]
+pub struct BitField<'a> {
+    cmd: &'a Vec<String>,
+    read_only: bool,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> BitField<'a> {
+    pub fn new(cmd: &'a Vec<String>, read_only: bool, replication_conn: bool) -> Self {
+        Self { cmd, read_only, replication_conn, changed: RwLock::new(false) }
+    }
+
+    // results of all ops run in order on bytes - nil for the ones that failed
+    fn apply(ops: &[FieldOp], bytes: &mut Vec<u8>) -> (Vec<Option<i128>>, usize) {
+        let mut changes = 0;
+        let results = ops.iter().map(|op| match *op {
+            FieldOp::Get(field) => Some(field.read(bytes)),
+            FieldOp::Set(field, value, overflow) => {
+                let old = field.read(bytes);
+                // unsigned fields take the value as unsigned 64 bits like redis does
+                let value = if field.signed { value as i128 } else { value as u64 as i128 };
+                let value = field.overflow(value, overflow)?;
+                field.write(bytes, value);
+                changes += 1;
+                Some(old)
+            },
+            FieldOp::IncrBy(field, incr, overflow) => {
+                let value = field.overflow(field.read(bytes) + incr as i128, overflow)?;
+                field.write(bytes, value);
+                changes += 1;
+                Some(value)
+            },
+        }).collect();
+        (results, changes)
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let ops = parse_field_ops(self.cmd, self.read_only)?;
+        let writes = ops.iter().any(|op| !matches!(op, FieldOp::Get(_)));
+        let (results, changes) = if writes {
+            db.update(&self.cmd[1], |v| {
+                let mut bytes = strings::string_value(v)?.unwrap_or_default();
+                let (results, changes) = Self::apply(&ops, &mut bytes);
+                match changes {
+                    0 => Ok((db::Change::Keep, (results, changes))),
+                    _ => Ok((db::Change::Value(db::KeyValueType::string(bytes)), (results, changes))),
+                }
+            })?
+        } else {
+            let mut bytes = strings::string_value(db.get(&self.cmd[1]).as_ref())?.unwrap_or_default();
+            Self::apply(&ops, &mut bytes)
+        };
+        if changes > 0 {
+            db.notify('$', "setbit", &self.cmd[1]);
+            *self.changed.write().unwrap() = true;
+        }
+        let mut response = format!("*{}\r\n", results.len());
+        results.iter().for_each(|r| match r {
+            Some(n) => response.push_str(&format!(":{}\r\n", n)),
+            None => response.push_str("$-1\r\n"),
+        });
+        Ok(response.into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for BitField<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
@@ -1,81 +1,136 @@
-use crate::commands::array;
+use crate::acl::acl;
+use crate::client::connection::Connection;
 use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::pubsub::pubsub;
+use crate::repl::repl;
 use crate::store::db;
-use std::io::Write;
-use std::net::TcpStream;
-use std::sync::Arc;
+use crate::store::evict;
+use crate::utils::memory;
+use std::sync::{Arc, RwLock};
 
-#[allow(dead_code)]
+const CONFIG_HELP: [&str; 11] = [
+    "CONFIG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "GET <pattern>",
+    "    Return parameters matching the glob-like <pattern> and their values.",
+    "SET <directive> <value>",
+    "    Set the configuration <directive> to <value>.",
+    "RESETSTAT",
+    "    Reset statistics, the memory peak reported by MEMORY STATS.",
+    "REWRITE",
+    "    Rewrite the configuration file.",
+    "HELP",
+    "    Print this help.",
+];
 
-#[derive(Debug, Clone)]
+// pushes a changed parameter to the part of the server using it
+// protected-mode and repl-diskless-load are read from the config when needed
+fn apply(name: &str, db: &db::DB, replcfg: &repl::ReplicationConfig) -> Result<(), String> {
+    let config = db.config();
+    match name {
+        "dir" => {
+            let dir = config.string("dir");
+            if !dir.is_empty() && !std::path::Path::new(&dir).is_dir() {
+                return Err("No such file or directory".to_string());
+            }
+            db.rdb().set_rdb_directory(&dir);
+        },
+        "dbfilename" => db.rdb().set_rdb_filename(&config.string("dbfilename")),
+        "min-replicas-to-write" | "min-replicas-max-lag" => replcfg.set_min_replicas(repl::MinReplicas::new(
+            config.int("min-replicas-to-write") as usize, config.int("min-replicas-max-lag") as u64)),
+        "repl-diskless-sync" | "repl-diskless-sync-delay" => replcfg.set_diskless_sync(repl::DisklessSync::new(
+            config.bool("repl-diskless-sync"), config.int("repl-diskless-sync-delay") as u64)),
+        "repl-backlog-size" => replcfg.set_backlog_size(config.int(name) as u64),
+        "client-output-buffer-limit" => db.pubsub().set_limit(pubsub::OutputLimit::parse(&config.string(name))?),
+        "notify-keyspace-events" => db.pubsub().set_notify(pubsub::NotifyFlags::parse(&config.string(name))?),
+        "maxmemory" | "maxmemory-policy" | "maxmemory-samples" => db.set_maxmemory(evict::MaxMemory::new(
+            &config.string("maxmemory"), &config.string("maxmemory-policy"), config.int("maxmemory-samples") as usize)?),
+        "requirepass" => {
+            let password = config.string("requirepass");
+            let rules = match password.is_empty() {
+                true => vec!["nopass".to_string()],
+                false => vec!["resetpass".to_string(), format!(">{}", password)],
+            };
+            db.acl().setuser(acl::DEFAULT_USER, &rules)?;
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+// CONFIG GET pattern [pattern ...] | SET parameter value [parameter value ...] | REWRITE | RESETSTAT | HELP
 pub struct Config<'a> {
-    cmd: &'a Vec<String>,
+    cmd: &'a resp::Args,
     replication_conn: bool,
+    // SET may change replication settings too - db is kept from handle for repl_config
+    db: RwLock<Option<Arc<db::DB>>>,
 }
 
 impl<'a> Config<'a> {
-    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
-        Self { cmd, replication_conn }
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, db: RwLock::new(None) }
     }
-}
 
-impl<'a> incoming::CommandHandler for Config<'a> {
-    fn handle(
-        &self,
-        stream: &mut TcpStream,
-        db: &Arc<db::DB>,
-    ) -> std::io::Result<()> {
-        let mut response = String::new();
-        // how do we know if this is master/slave connection
-
-        let mut num_args = 0;
-        let mut optidx = 1;
-        let mut valid_command = false;
-        if let Some(arg) = array::get_nth_arg(self.cmd, optidx) {
-            if arg.contains("GET") || arg.contains("get") {
-                valid_command = true;
-            }
-        }
-        
-        if !valid_command {
-            return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                format!("unsupported config command: {:?}", self.cmd)));
+    fn get(&self, db: &Arc<db::DB>) -> Vec<u8> {
+        let matched = db.config().get(&self.cmd[2..]);
+        let mut response = format!("*{}\r\n", matched.len() * 2).into_bytes();
+        for (name, value) in matched {
+            response.extend(strings::bulk(name.as_bytes()));
+            response.extend(strings::bulk(value.as_bytes()));
         }
+        response
+    }
 
-        optidx += 1;
+    // parameter names are case insensitive, values are taken as sent
+    fn set(&self, db: &db::DB, replcfg: &repl::ReplicationConfig) -> Result<Vec<u8>, String> {
+        let pairs = (2..self.cmd.len()).step_by(2)
+            .map(|i| (self.cmd[i].clone(), String::from_utf8_lossy(self.cmd.raw(i + 1)).to_string()))
+            .collect::<Vec<_>>();
+        db.config().set(&pairs, |name| apply(name, db, replcfg))?;
+        Ok(b"+OK\r\n".to_vec())
+    }
 
-        while let Some(arg) = array::get_nth_arg(self.cmd, optidx) {
-            match arg.as_str() {
-                 "dir" => {
-                    num_args += 2;
-                    optidx += 1;
-                    let dir = db.rdb_directory();
-                    let _ = std::fmt::write(&mut response,
-                        format_args!("${}\r\n{}\r\n${}\r\n{}\r\n", arg.len(), arg, dir.len(), dir));
-                },
-                "dbfilename" => {
-                    num_args += 2;
-                    optidx += 1;
-                    let filename = db.rdb_filename();
-                    let _ = std::fmt::write(&mut response,
-                        format_args!("${}\r\n{}\r\n${}\r\n{}\r\n", arg.len(), arg, filename.len(), filename));
-                },
-                _ => {
-                    // return error
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                        format!("Invalid arguements to config command: {}", arg)));
-                }
-            }
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
         }
-        if num_args == 0 { 
-            // error command
-            return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                format!("No valid arguements to config command: {:?}", self.cmd)));
+        let subcommand = self.cmd[1].as_str();
+        match (subcommand, self.cmd.len()) {
+            ("help", 2) => {
+                let mut response = format!("*{}\r\n", CONFIG_HELP.len());
+                CONFIG_HELP.iter().for_each(|line| response.push_str(&format!("+{}\r\n", line)));
+                Ok(response.into())
+            },
+            ("get", 3..) => Ok(self.get(db)),
+            ("set", n) if n >= 4 && n % 2 == 0 => {
+                *self.db.write().unwrap() = Some(Arc::clone(db));
+                Ok(vec![])
+            },
+            ("rewrite", 2) => {
+                db.config().rewrite()?;
+                Ok(b"+OK\r\n".to_vec())
+            },
+            ("resetstat", 2) => {
+                memory::reset_peak();
+                Ok(b"+OK\r\n".to_vec())
+            },
+            _ => Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try CONFIG HELP.", subcommand)),
         }
+    }
+}
 
-        let final_response = format!("*{}\r\n{}", num_args, response);
-        // is it dirname or filename -> next parameter
-        stream.write_all(final_response.as_bytes())
+impl<'a> incoming::CommandHandler for Config<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
     }
 
+    fn repl_config(
+        &self,
+        stream: &mut Connection,
+        replcfg: &Arc<repl::ReplicationConfig>
+    ) -> std::io::Result<()> {
+        let Some(db) = self.db.write().unwrap().take() else { return Ok(()) };
+        strings::reply(stream, self.replication_conn, self.set(&db, replcfg))
+    }
 }
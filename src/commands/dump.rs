@@ -0,0 +1,121 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::rdb::rdb;
+use crate::store::db;
+use bytes::BytesMut;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// DUMP key
+#[derive(Debug)]
+pub struct Dump<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> Dump<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        match db.get(&self.cmd[1]) {
+            Some(value) => Ok(strings::bulk(&rdb::RDB::dump_value(&value)?)),
+            None => Ok(b"$-1\r\n".to_vec()),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Dump<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+#[derive(Debug)]
+pub struct Restore<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> Restore<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 4 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let (mut replace, mut absttl) = (false, false);
+        let (mut idle, mut freq) = (None, None);
+        let mut idx = 4;
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "replace" => replace = true,
+                "absttl" => absttl = true,
+                "idletime" if idx + 1 < self.cmd.len() && freq.is_none() => {
+                    idx += 1;
+                    match strings::parse_int(self.cmd[idx].as_bytes())? {
+                        n if n < 0 => return Err("ERR Invalid IDLETIME value, must be >= 0".to_string()),
+                        n => idle = Some(n as u64),
+                    }
+                },
+                "freq" if idx + 1 < self.cmd.len() && idle.is_none() => {
+                    idx += 1;
+                    match strings::parse_int(self.cmd[idx].as_bytes())? {
+                        n if !(0..=255).contains(&n) => return Err("ERR Invalid FREQ value, must be >= 0 and <= 255".to_string()),
+                        n => freq = Some(n as u8),
+                    }
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 1;
+        }
+        let key = &self.cmd[1];
+        if !replace && db.exists(key) {
+            return Err("BUSYKEY Target key name already exists.".to_string());
+        }
+        let ttl = match strings::parse_int(self.cmd[2].as_bytes())? {
+            n if n < 0 => return Err("ERR Invalid TTL value, must be >= 0".to_string()),
+            n => n as u64,
+        };
+        let value = rdb::RDB::restore_value(self.cmd.raw(3))?;
+        let expiry = match (ttl, absttl) {
+            (0, _) => None,
+            (ttl, true) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                Some(ttl.saturating_sub(now))
+            },
+            (ttl, false) => Some(ttl),
+        };
+        // a value restored already expired only takes the old one out
+        if expiry == Some(0) {
+            *self.changed.write().unwrap() = replace && db.remove(key).is_some();
+            return Ok("+OK\r\n".into());
+        }
+        db.restore(key, value, expiry, idle.unwrap_or(0) * 1000, freq, replace)?;
+        *self.changed.write().unwrap() = true;
+        Ok("+OK\r\n".into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Restore<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
@@ -1,7 +1,7 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
@@ -19,7 +19,7 @@ impl<'a> Echo<'a> {
 impl<'a> incoming::CommandHandler for Echo<'a> {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         _db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
         // only be called when data type is appropriate
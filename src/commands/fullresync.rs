@@ -1,23 +1,49 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
+use crate::repl::repl;
 use crate::store::db;
-use std::net::TcpStream;
 use std::sync::Arc;
 
 #[allow(dead_code)]
 
 #[derive(Debug, Clone)]
-pub struct FullResync {
+pub struct FullResync<'a> {
+    cmd: &'a str,
     replication_conn: bool,
 }
 
-impl FullResync {
-    pub fn new(replication_conn: bool) -> Self {
-        Self { replication_conn }
+impl<'a> FullResync<'a> {
+    pub fn new(cmd: &'a str, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
     }
 }
 
-impl incoming::CommandHandler for FullResync {
-    fn handle(&self, _stream: &mut TcpStream, _db: &Arc<db::DB>) -> std::io::Result<()> {
+impl<'a> incoming::CommandHandler for FullResync<'a> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
         Ok(())
     }
+
+    // FULLRESYNC <replid> <offset>
+    // replica serves the same stream (ID and offsets) to its own replicas
+    fn repl_config(
+        &self,
+        _stream: &mut Connection,
+        replcfg: &Arc<repl::ReplicationConfig>
+    ) -> std::io::Result<()> {
+        if !self.replication_conn {
+            return Ok(());
+        }
+        let args = self.cmd.split(' ').collect::<Vec<&str>>();
+        if args.len() != 3 {
+            return Err(std::io::Error::other(format!("invalid fullresync: {}", self.cmd)));
+        }
+        match args[2].parse::<u64>() {
+            Ok(offset) => {
+                println!("synced with master replid: {}, offset: {}", args[1], offset);
+                replcfg.set_upstream(args[1], offset);
+                Ok(())
+            },
+            Err(e) => Err(std::io::Error::other(format!("invalid fullresync offset: {}, error: {}", args[2], e))),
+        }
+    }
 }
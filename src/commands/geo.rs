@@ -0,0 +1,446 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::store::db;
+use crate::store::geohash;
+use crate::store::sortedset;
+use bytes::BytesMut;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+const SYNTAX_ERROR: &str = "ERR syntax error";
+
+// members are kept as given - args are lowercased
+fn member(cmd: &resp::Args, idx: usize) -> String {
+    String::from_utf8_lossy(cmd.raw(idx)).into_owned()
+}
+
+fn zset(value: Option<db::KeyValueType>) -> Result<Option<sortedset::SortedSet>, String> {
+    match value {
+        Some(db::KeyValueType::SortedSetType(z)) => Ok(Some(z)),
+        Some(_) => Err(db::WRONGTYPE.to_string()),
+        None => Ok(None),
+    }
+}
+
+// meters in one unit
+fn unit(name: &str) -> Result<f64, String> {
+    match name {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err("ERR unsupported unit provided. please use M, KM, FT, MI".to_string()),
+    }
+}
+
+fn lon_lat(cmd: &resp::Args, idx: usize) -> Result<(f64, f64), String> {
+    let lon = strings::parse_float(cmd.raw(idx))?;
+    let lat = strings::parse_float(cmd.raw(idx + 1))?;
+    if !geohash::valid(lon, lat) {
+        return Err(format!("ERR invalid longitude,latitude pair {:.6},{:.6}", lon, lat));
+    }
+    Ok((lon, lat))
+}
+
+// coordinates with all the digits they have - trailing zeros dropped
+fn coordinate(value: f64) -> Vec<u8> {
+    let value = format!("{:.17}", value);
+    strings::bulk(value.trim_end_matches('0').trim_end_matches('.').as_bytes())
+}
+
+fn distance(meters: f64, unit: f64) -> Vec<u8> {
+    strings::bulk(format!("{:.4}", meters / unit).as_bytes())
+}
+
+// GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude member ...]
+#[derive(Debug)]
+pub struct GeoAdd<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> GeoAdd<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 5 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let (mut nx, mut xx, mut ch) = (false, false, false);
+        let mut idx = 2;
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "nx" => nx = true,
+                "xx" => xx = true,
+                "ch" => ch = true,
+                _ => break,
+            }
+            idx += 1;
+        }
+        if nx && xx {
+            return Err("ERR XX and NX options at the same time are not compatible".to_string());
+        }
+        if idx == self.cmd.len() || !(self.cmd.len() - idx).is_multiple_of(3) {
+            return Err("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ".to_string());
+        }
+        // nothing is added unless all positions are valid
+        let mut points = vec![];
+        for i in (idx..self.cmd.len()).step_by(3) {
+            let (lon, lat) = lon_lat(self.cmd, i)?;
+            points.push((member(self.cmd, i + 2), geohash::encode(lon, lat) as f64));
+        }
+        let (added, updated) = db.zadd(&self.cmd[1], !xx, |z| {
+            let (mut added, mut updated) = (0, 0);
+            for (member, score) in &points {
+                match z.score(member) {
+                    Some(_) if nx => {},
+                    Some(old) => if old != *score {
+                        z.insert(member, *score);
+                        updated += 1;
+                    },
+                    None if xx => {},
+                    None => {
+                        z.insert(member, *score);
+                        added += 1;
+                    },
+                }
+            }
+            Ok((added, updated))
+        })?.unwrap_or((0, 0));
+        if added + updated > 0 {
+            db.notify('z', "zadd", &self.cmd[1]);
+            *self.changed.write().unwrap() = true;
+        }
+        Ok(format!(":{}\r\n", if ch { added + updated } else { added }).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for GeoAdd<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// GEODIST key member1 member2 [M | KM | FT | MI]
+#[derive(Debug)]
+pub struct GeoDist<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+}
+
+impl<'a> GeoDist<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        let unit = match self.cmd.len() {
+            0..=3 => return Err(strings::wrong_args(self.cmd)),
+            4 => 1.0,
+            5 => unit(&self.cmd[4])?,
+            _ => return Err(SYNTAX_ERROR.to_string()),
+        };
+        let z = match zset(db.get(&self.cmd[1]))? {
+            Some(z) => z,
+            None => return Ok(b"$-1\r\n".to_vec()),
+        };
+        match (z.score(&member(self.cmd, 2)), z.score(&member(self.cmd, 3))) {
+            (Some(a), Some(b)) => {
+                let (lon1, lat1) = geohash::decode(a as u64);
+                let (lon2, lat2) = geohash::decode(b as u64);
+                Ok(distance(geohash::distance(lon1, lat1, lon2, lat2), unit))
+            },
+            _ => Ok(b"$-1\r\n".to_vec()),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for GeoDist<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// GEOPOS key [member [member ...]] | GEOHASH key [member [member ...]]
+#[derive(Debug)]
+pub struct GeoPos<'a> {
+    cmd: &'a resp::Args,
+    hash: bool,
+    replication_conn: bool,
+}
+
+impl<'a> GeoPos<'a> {
+    pub fn new(cmd: &'a resp::Args, hash: bool, replication_conn: bool) -> Self {
+        Self { cmd, hash, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let z = zset(db.get(&self.cmd[1]))?.unwrap_or_default();
+        let mut response = format!("*{}\r\n", self.cmd.len() - 2).into_bytes();
+        for idx in 2..self.cmd.len() {
+            match z.score(&member(self.cmd, idx)) {
+                Some(score) if self.hash => {
+                    response.extend(strings::bulk(geohash::to_string(score as u64).as_bytes()));
+                },
+                Some(score) => {
+                    let (lon, lat) = geohash::decode(score as u64);
+                    response.extend_from_slice(b"*2\r\n");
+                    response.extend(coordinate(lon));
+                    response.extend(coordinate(lat));
+                },
+                None if self.hash => response.extend_from_slice(b"$-1\r\n"),
+                None => response.extend_from_slice(b"*-1\r\n"),
+            }
+        }
+        Ok(response)
+    }
+}
+
+impl<'a> incoming::CommandHandler for GeoPos<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+#[derive(Debug)]
+enum Shape {
+    Radius(f64),
+    Box(f64, f64),
+}
+
+#[derive(Debug, Default)]
+struct SearchOptions {
+    member: Option<String>,
+    center: Option<(f64, f64)>,
+    shape: Option<Shape>, // in meters
+    unit: f64,
+    desc: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+    withcoord: bool,
+    withdist: bool,
+    withhash: bool,
+    storedist: bool,
+}
+
+// GEOSEARCH key <FROMMEMBER member | FROMLONLAT longitude latitude>
+//   <BYRADIUS radius <M | KM | FT | MI> | BYBOX width height <M | KM | FT | MI>>
+//   [ASC | DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+// GEOSEARCHSTORE destination source <FROM..> <BY..> [ASC | DESC] [COUNT count [ANY]] [STOREDIST]
+#[derive(Debug)]
+pub struct GeoSearch<'a> {
+    cmd: &'a resp::Args,
+    store: bool,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> GeoSearch<'a> {
+    pub fn new(cmd: &'a resp::Args, store: bool, replication_conn: bool) -> Self {
+        Self { cmd, store, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn options(&self) -> Result<SearchOptions, String> {
+        let mut options = SearchOptions::default();
+        let (mut from, mut by) = (0, 0);
+        let mut idx = if self.store { 3 } else { 2 };
+        let args = |idx: usize, n: usize| match idx + n < self.cmd.len() {
+            true => Ok(()),
+            false => Err(SYNTAX_ERROR.to_string()),
+        };
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "frommember" => {
+                    args(idx, 1)?;
+                    options.member = Some(member(self.cmd, idx + 1));
+                    from += 1;
+                    idx += 1;
+                },
+                "fromlonlat" => {
+                    args(idx, 2)?;
+                    options.center = Some(lon_lat(self.cmd, idx + 1)?);
+                    from += 1;
+                    idx += 2;
+                },
+                "byradius" => {
+                    args(idx, 2)?;
+                    let radius = strings::parse_float(self.cmd.raw(idx + 1))?;
+                    if radius < 0.0 {
+                        return Err("ERR radius cannot be negative".to_string());
+                    }
+                    options.unit = unit(&self.cmd[idx + 2])?;
+                    options.shape = Some(Shape::Radius(radius * options.unit));
+                    by += 1;
+                    idx += 2;
+                },
+                "bybox" => {
+                    args(idx, 3)?;
+                    let width = strings::parse_float(self.cmd.raw(idx + 1))?;
+                    let height = strings::parse_float(self.cmd.raw(idx + 2))?;
+                    if width < 0.0 || height < 0.0 {
+                        return Err("ERR height or width cannot be negative".to_string());
+                    }
+                    options.unit = unit(&self.cmd[idx + 3])?;
+                    options.shape = Some(Shape::Box(width * options.unit, height * options.unit));
+                    by += 1;
+                    idx += 3;
+                },
+                "asc" => options.desc = Some(false),
+                "desc" => options.desc = Some(true),
+                "count" => {
+                    args(idx, 1)?;
+                    let count = strings::parse_int(self.cmd.raw(idx + 1))?;
+                    if count <= 0 {
+                        return Err("ERR COUNT must be > 0".to_string());
+                    }
+                    options.count = Some(count as usize);
+                    idx += 1;
+                },
+                "any" => options.any = true,
+                "withcoord" => options.withcoord = true,
+                "withdist" => options.withdist = true,
+                "withhash" => options.withhash = true,
+                "storedist" if self.store => options.storedist = true,
+                _ => return Err(SYNTAX_ERROR.to_string()),
+            }
+            idx += 1;
+        }
+        if from != 1 {
+            return Err("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH".to_string());
+        }
+        if by != 1 {
+            return Err("ERR exactly one of BYRADIUS and BYBOX arguments should be provided".to_string());
+        }
+        if options.any && options.count.is_none() {
+            return Err("ERR the ANY argument requires COUNT argument".to_string());
+        }
+        if self.store && (options.withcoord || options.withdist || options.withhash) {
+            return Err("ERR STORE option in GEOSEARCHSTORE is not compatible with WITHDIST, WITHHASH and WITHCOORD options".to_string());
+        }
+        // the closest ones are returned when COUNT cuts the result
+        if options.count.is_some() && !options.any && options.desc.is_none() {
+            options.desc = Some(false);
+        }
+        Ok(options)
+    }
+
+    // members within the shape as (member, distance, score)
+    fn search(&self, z: &sortedset::SortedSet, options: &SearchOptions) -> Result<Vec<(String, f64, f64)>, String> {
+        let center = match (&options.member, options.center) {
+            (Some(member), _) => match z.score(member) {
+                Some(score) => geohash::decode(score as u64),
+                None => return Err("ERR could not decode requested zset member".to_string()),
+            },
+            (None, Some(center)) => center,
+            (None, None) => unreachable!(),
+        };
+        let mut found = vec![];
+        for (member, score) in z.iter() {
+            let point = geohash::decode(score as u64);
+            let dist = match options.shape {
+                Some(Shape::Radius(radius)) => Some(geohash::distance(center.0, center.1, point.0, point.1))
+                    .filter(|d| *d <= radius),
+                Some(Shape::Box(width, height)) => geohash::distance_in_box(width, height, center, point),
+                None => None,
+            };
+            if let Some(dist) = dist {
+                found.push((member.to_string(), dist, score));
+                if options.any && Some(found.len()) == options.count {
+                    break;
+                }
+            }
+        }
+        match options.desc {
+            Some(false) => found.sort_by(|a, b| a.1.total_cmp(&b.1)),
+            Some(true) => found.sort_by(|a, b| b.1.total_cmp(&a.1)),
+            None => {},
+        }
+        if let Some(count) = options.count {
+            found.truncate(count);
+        }
+        Ok(found)
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < if self.store { 8 } else { 7 } {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let options = self.options()?;
+        let source = if self.store { &self.cmd[2] } else { &self.cmd[1] };
+        let found = match zset(db.get(source))? {
+            Some(z) => self.search(&z, &options)?,
+            None => vec![],
+        };
+        if self.store {
+            return self.save(db, &found, &options);
+        }
+        let mut response = format!("*{}\r\n", found.len()).into_bytes();
+        let fields = 1 + options.withdist as usize + options.withhash as usize + options.withcoord as usize;
+        for (member, dist, score) in found {
+            if fields > 1 {
+                response.extend(format!("*{}\r\n", fields).into_bytes());
+            }
+            response.extend(strings::bulk(member.as_bytes()));
+            if options.withdist {
+                response.extend(distance(dist, options.unit));
+            }
+            if options.withhash {
+                response.extend(format!(":{}\r\n", score as u64).into_bytes());
+            }
+            if options.withcoord {
+                let (lon, lat) = geohash::decode(score as u64);
+                response.extend_from_slice(b"*2\r\n");
+                response.extend(coordinate(lon));
+                response.extend(coordinate(lat));
+            }
+        }
+        Ok(response)
+    }
+
+    // destination is replaced by the result - removed when nothing was found
+    fn save(&self, db: &Arc<db::DB>, found: &[(String, f64, f64)], options: &SearchOptions) -> Result<Vec<u8>, String> {
+        let dest = &self.cmd[1];
+        let existed = db.update(dest, |v| {
+            if found.is_empty() {
+                return Ok((db::Change::Remove, v.is_some()));
+            }
+            let mut z = sortedset::SortedSet::new();
+            for (member, dist, score) in found {
+                z.insert(member, if options.storedist { dist / options.unit } else { *score });
+            }
+            Ok((db::Change::Replace(db::KeyValueType::SortedSetType(z), None), v.is_some()))
+        })?;
+        if !found.is_empty() {
+            db.notify('z', "geosearchstore", dest);
+        } else if existed {
+            db.notify('g', "del", dest);
+        }
+        *self.changed.write().unwrap() = !found.is_empty() || existed;
+        Ok(format!(":{}\r\n", found.len()).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for GeoSearch<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
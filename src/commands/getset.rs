@@ -1,12 +1,16 @@
+use crate::client::connection::Connection;
 use crate::commands::array;
 use crate::commands::incoming;
+use crate::commands::resp;
 use crate::commands::ss;
+use crate::commands::strings;
 use crate::store::db;
 use bytes::BytesMut;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Default, Debug)]
 pub struct SetOptions {
@@ -20,65 +24,127 @@ impl SetOptions {
         }
     }
 }
-#[derive(Debug, Clone)]
+// how long a key set with EX|PX|EXAT|PXAT keeps living
+#[derive(Debug, Clone, Copy)]
+pub enum Expiry {
+    In(u64), // ms from now
+    Passed,  // absolute time already gone - the key is deleted
+}
+
+// parses the time given to an expiry option of command
+pub fn parse_expiry(option: &str, value: &str, command: &str) -> Result<Expiry, String> {
+    let invalid = || format!("ERR invalid expire time in '{}' command", command);
+    let n = value.parse::<i64>().map_err(|_| "ERR value is not an integer or out of range".to_string())?;
+    if n <= 0 {
+        return Err(invalid());
+    }
+    let mut ms = match option {
+        "ex" | "exat" => (n as i128) * 1000,
+        _ => n as i128,
+    };
+    if ms > i64::MAX as i128 {
+        return Err(invalid());
+    }
+    if option == "exat" || option == "pxat" {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i128;
+        ms -= now;
+    }
+    if ms <= 0 {
+        return Ok(Expiry::Passed);
+    }
+    Ok(Expiry::In(ms as u64))
+}
+
+// SET key value [NX|XX] [GET] [EX seconds|PX ms|EXAT ts|PXAT ts-ms|KEEPTTL]
+#[derive(Debug, Default)]
+struct SetRequest {
+    nx: bool,
+    xx: bool,
+    get: bool,
+    keepttl: bool,
+    expiry: Option<Expiry>,
+}
+
+impl SetRequest {
+    fn parse(cmd: &[String]) -> Result<Self, String> {
+        let mut request = Self::default();
+        let mut idx = 3;
+        while idx < cmd.len() {
+            match cmd[idx].as_str() {
+                "nx" if !request.xx => request.nx = true,
+                "xx" if !request.nx => request.xx = true,
+                "get" => request.get = true,
+                "keepttl" if request.expiry.is_none() => request.keepttl = true,
+                opt @ ("ex" | "px" | "exat" | "pxat") if !request.keepttl && request.expiry.is_none() && idx + 1 < cmd.len() => {
+                    request.expiry = Some(parse_expiry(opt, &cmd[idx + 1], "set")?);
+                    idx += 1;
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 1;
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Debug)]
 pub struct SetCommand <'a>{
-    cmd: &'a Vec<String>,
+    cmd: &'a resp::Args,
     replication_conn: bool,
+    applied: RwLock<bool>,
 }
 
 impl<'a> SetCommand<'a> {
-    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
-        Self { cmd, replication_conn }
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, applied: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 3 {
+            return Err("ERR wrong number of arguments for 'set' command".to_string());
+        }
+        let request = SetRequest::parse(self.cmd)?;
+        let key = &self.cmd[1];
+        let value = db::KeyValueType::string(self.cmd.raw(2).to_vec());
+        let (applied, old) = db.update(key, |current| {
+            let old = match current {
+                Some(v) if request.get => Some(v.as_bytes().ok_or(db::WRONGTYPE.to_string())?),
+                _ => None,
+            };
+            if (request.nx && current.is_some()) || (request.xx && current.is_none()) {
+                return Ok((db::Change::Keep, (false, old)));
+            }
+            let change = match request.expiry {
+                _ if request.keepttl => db::Change::Value(value),
+                Some(Expiry::In(ms)) => db::Change::Replace(value, Some(ms)),
+                Some(Expiry::Passed) => db::Change::Remove,
+                None => db::Change::Replace(value, None),
+            };
+            Ok((change, (true, old)))
+        })?;
+        *self.applied.write().unwrap() = applied;
+        if applied {
+            match request.expiry {
+                Some(Expiry::Passed) => db.notify('g', "del", key),
+                _ => db.notify('$', "set", key),
+            }
+        }
+        Ok(match (request.get, old) {
+            (true, Some(old)) => strings::bulk(&old),
+            (true, None) => b"$-1\r\n".to_vec(),
+            _ if applied => b"+OK\r\n".to_vec(),
+            _ => b"$-1\r\n".to_vec(),
+        })
     }
 }
 
 impl<'a> incoming::CommandHandler for SetCommand<'a> {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         db: &Arc<db::DB>
     ) -> std::io::Result<()> {
-        let cmd = &self.cmd;
-        let mut response = String::new();
-        let key_option = array::get_nth_arg(cmd, 1);
-        let val_option = array::get_nth_arg(cmd, 2);
-        if key_option.is_none() || val_option.is_none() {
-            return ss::invalid(stream);
-        }
-        let key = key_option.unwrap();
-        let val = val_option.unwrap();
-        let mut options = SetOptions::new();
-        // search for expiry option
-        let mut argidx = 3;
-        while let Some(opt) = array::get_nth_arg(cmd, argidx) {
-            match opt.as_str() {
-                "px" => {
-                    argidx += 1;
-                    if let Some(expiry) = array::get_nth_arg(cmd, argidx) {
-                        if let Ok(expiry_ms) = expiry.parse::<u64>() {
-                            options.expiry_in_ms = expiry_ms;
-                        }
-                    }
-                }
-                _ => {}
-            };
-            argidx += 1;
-        }
-        let db_result = db.add(key.clone(), db::KeyValueType::StringType(val.clone()), &options);
-        if db_result.is_err() {
-            println!("Error writing into the DB");
-            return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                format!("failed set command: {:?}", self.cmd)));
-        }
-        if self.replication_conn { return Ok(()); }
-
-        if let Ok(_o) =  db_result {
-            // replicate it now
-            let _ = std::fmt::write(&mut response, format_args!("+OK\r\n"));
-        } else {
-            let _ = std::fmt::write(&mut response, format_args!("-Error writing to DB\r\n"));
-        }
-        stream.write_all(response.as_bytes())
+        strings::reply(stream, self.replication_conn, self.run(db))
     }
 
     fn replicate(
@@ -86,7 +152,7 @@ impl<'a> incoming::CommandHandler for SetCommand<'a> {
             buf: &BytesMut,
             tx_ch: &Sender<BytesMut>
         ) -> std::io::Result<()> {
-        if self.replication_conn { return Ok(()); }
+        if self.replication_conn || !*self.applied.read().unwrap() { return Ok(()); }
         match tx_ch.send(buf.clone()) {
             Ok(_) => Ok(()),
             Err(e) => 
@@ -112,37 +178,26 @@ impl<'a> GetCommand<'a> {
 impl<'a> incoming::CommandHandler for GetCommand<'a> {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
         if self.replication_conn { return Ok(()); }
         
         let cmd = &self.cmd;
-        let mut response = String::new();
+        let response;
         if let Some(key) = array::get_nth_arg(cmd, 1) {
             if let Some(value) = db.get(key) {
-                match value {
-                    db::KeyValueType::StringType(val) => {
-                        let _ = std::fmt::write(
-                            &mut response,
-                            format_args!("${}\r\n{}\r\n", val.chars().count(), val),
-                        );
-                    },
-                    db::KeyValueType::StreamType(_val) => {
-                        // FILL ME UP PLEASE
-                        let _ = std::fmt::write(
-                            &mut response,
-                            format_args!("${}\r\n{}\r\n", "FIX-ME-STREAM-KEY-VALUE".chars().count(), "FIX-ME-STREAM-KEY-VALUE"),
-                        );
-                    },
+                response = match value.as_bytes() {
+                    Some(val) => strings::bulk(&val),
+                    None => format!("-{}\r\n", db::WRONGTYPE).into_bytes(),
                 };
             } else {
                 // did not find
-                let _ = std::fmt::write(&mut response, format_args!("$-1\r\n"));
+                response = b"$-1\r\n".to_vec();
             }
         } else {
             return ss::invalid(stream);
         }
-        stream.write_all(response.as_bytes())
+        stream.write_all(&response)
     }
 }
\ No newline at end of file
@@ -0,0 +1,168 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::store::db;
+use crate::store::hll;
+use bytes::BytesMut;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+fn parse(value: Option<&db::KeyValueType>) -> Result<Option<hll::Hll>, String> {
+    match strings::string_value(value)? {
+        Some(bytes) => Ok(Some(hll::Hll::parse(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+// PFADD key [element [element ...]]
+#[derive(Debug)]
+pub struct PfAdd<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> PfAdd<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let changed = db.update(&self.cmd[1], |v| {
+            let (mut hll, mut changed) = match parse(v)? {
+                Some(hll) => (hll, false),
+                None => (hll::Hll::new(), true),
+            };
+            for idx in 2..self.cmd.len() {
+                changed |= hll.add(self.cmd.raw(idx));
+            }
+            match changed {
+                true => Ok((db::Change::Value(db::KeyValueType::string(hll.encode())), true)),
+                false => Ok((db::Change::Keep, false)),
+            }
+        })?;
+        if changed {
+            db.notify('$', "pfadd", &self.cmd[1]);
+        }
+        *self.changed.write().unwrap() = changed;
+        Ok(format!(":{}\r\n", changed as u8).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for PfAdd<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// PFCOUNT key [key ...] - the union of all keys when given more than one
+#[derive(Debug)]
+pub struct PfCount<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    cached: RwLock<bool>,
+}
+
+impl<'a> PfCount<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, cached: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        if self.cmd.len() == 2 {
+            // a stale cardinality is computed once and cached in the header
+            let (card, cached) = db.update(&self.cmd[1], |v| {
+                let mut hll = match parse(v)? {
+                    Some(hll) => hll,
+                    None => return Ok((db::Change::Keep, (0, false))),
+                };
+                if let Some(card) = hll.cached() {
+                    return Ok((db::Change::Keep, (card, false)));
+                }
+                let card = hll.count();
+                let mut bytes = strings::string_value(v)?.unwrap_or_default();
+                hll::Hll::cache_into(&mut bytes, card);
+                Ok((db::Change::Value(db::KeyValueType::string(bytes)), (card, true)))
+            })?;
+            *self.cached.write().unwrap() = cached;
+            return Ok(format!(":{}\r\n", card).into());
+        }
+        let mut union = hll::Hll::new();
+        for value in db.mget(&self.cmd[1..]) {
+            if let Some(hll) = parse(value.as_ref())? {
+                union.merge(&hll);
+            }
+        }
+        Ok(format!(":{}\r\n", union.count()).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for PfCount<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    // replicas get the cardinality cached as well
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.cached.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// PFMERGE destkey [sourcekey [sourcekey ...]]
+#[derive(Debug)]
+pub struct PfMerge<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> PfMerge<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let mut sources = vec![];
+        for value in db.mget(&self.cmd[2..]) {
+            if let Some(hll) = parse(value.as_ref())? {
+                sources.push(hll);
+            }
+        }
+        db.update(&self.cmd[1], |v| {
+            let mut hll = parse(v)?.unwrap_or_else(hll::Hll::new);
+            sources.iter().for_each(|s| hll.merge(s));
+            Ok((db::Change::Value(db::KeyValueType::string(hll.encode())), ()))
+        })?;
+        db.notify('$', "pfadd", &self.cmd[1]);
+        *self.changed.write().unwrap() = true;
+        Ok("+OK\r\n".into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for PfMerge<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
@@ -1,21 +1,25 @@
 // incoming command formatting
+use crate::client::client;
+use crate::client::connection::Connection;
 use crate::commands::array;
 use crate::commands::bulk;
+use crate::commands::pubsub;
 use crate::commands::resp;
 use crate::commands::ss;
+use crate::commands::table;
 use crate::repl::repl;
 use crate::slave::slave;
 use crate::store::db;
 use bytes::BytesMut;
 use std::io::Write;
-use std::net::TcpStream;
+use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 
 pub trait CommandHandler {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         db: &Arc<db::DB>,
     ) -> std::io::Result<()>;
 
@@ -28,10 +32,15 @@ pub trait CommandHandler {
         Ok(())
     }
 
+    // write commands are refused over maxmemory - the ones which only free memory can still run
+    fn deny_oom(&self) -> bool {
+        true
+    }
+
     // if command is setting up replication config, add its implementation
     fn repl_config(
         &self,
-        _stream: &mut TcpStream,
+        _stream: &mut Connection,
         _replcfg: &Arc<repl::ReplicationConfig>
     ) -> std::io::Result<()> {
         Ok(())
@@ -39,14 +48,41 @@ pub trait CommandHandler {
 
     // for tracking slave offset - per received buffer, its done in main thread
     // this is for any further processing or specific response
-    fn track_offset(&self, slavecfg: &Option<slave::Config>, _stream: &mut TcpStream, length: usize) -> std::io::Result<()>{
+    fn track_offset(&self, slavecfg: &Option<slave::Config>, _stream: &mut Connection, length: usize) -> std::io::Result<()>{
         if let Some(cfg) = slavecfg {
             cfg.track_offset(length as u64);
         }
         Ok(())
     }
+
+    // for commands working on per connection state (MULTI/WATCH etc)
+    fn client_state(
+        &self,
+        _stream: &mut Connection,
+        _db: &Arc<db::DB>,
+        _client: &mut client::Client,
+    ) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// hands buf over to the replication thread
+pub fn send_replication(buf: BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+    match tx_ch.send(buf) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(std::io::Error::other(format!("failed replication: {:?}", e))),
+    }
 }
 
+// commands not queued inside MULTI
+const TRANSACTION_CONTROL: [&str; 4] = ["exec", "discard", "multi", "watch"];
+
+// commands accepted before the connection authenticates
+const NO_AUTH: [&str; 1] = ["auth"];
+
+// writes refused while min-replicas-to-write is not met
+const NOREPLICAS: &str = "NOREPLICAS Not enough good replicas to write.";
+
 pub struct Incoming<'a> {
     pub buf: &'a BytesMut,
     pub commands: Vec<resp::DataType>,
@@ -68,21 +104,71 @@ impl<'a, 'b> Incoming<'b> {
 
     pub fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         db: &Arc<db::DB>,
         replcfg: &Arc<repl::ReplicationConfig>,
         repl_ch: &Sender<BytesMut>,
         slavecfg: &Option<slave::Config>,
+        client: &mut client::Client,
     ) -> std::io::Result<()> {
         for command in &self.commands {
             println!("processing command: {}", command);
+            let raw = BytesMut::from(command.raw(self.buf));
+            if !client.subscribed() {
+                client.drain();
+            }
+            if let resp::DataType::Array(ref cmd, _start, _end) = command {
+                if !self.replication_conn {
+                    if let Some(e) = self.authorize(stream, db, client, cmd, false)? {
+                        client.abort();
+                        let reply = BytesMut::from(format!("-{}\r\n", e).as_bytes());
+                        if client.subscribed() {
+                            client.push(stream, db, reply)?;
+                        } else {
+                            stream.write_all(&reply)?;
+                        }
+                        continue;
+                    }
+                }
+                if client.subscribed() {
+                    if let Some(reply) = pubsub::subscribed_reply(cmd) {
+                        client.push(stream, db, reply)?;
+                        continue;
+                    }
+                }
+                // inside MULTI everything except transaction control is queued
+                if client.in_multi() && !TRANSACTION_CONTROL.contains(&cmd[0].as_str()) {
+                    let response = client.queue(cmd, &raw);
+                    if !self.replication_conn {
+                        stream.write_all(response.as_bytes())?;
+                    }
+                    if let Err(e) = self.forward(&raw, repl_ch, slavecfg) {
+                        println!("Error forwarding queued command: {:?}", e);
+                    }
+                    if let Some(cfg) = slavecfg {
+                        cfg.track_offset(command.len() as u64);
+                    }
+                    continue;
+                }
+                if cmd[0] == "exec" {
+                    if let Err(e) = self.exec(stream, db, replcfg, repl_ch, slavecfg, client, &raw) {
+                        println!("Error executing transaction: {:?}", e);
+                    }
+                    if let Some(cfg) = slavecfg {
+                        cfg.track_offset(command.len() as u64);
+                    }
+                    continue;
+                }
+            }
             let mut handler = None;
+            let mut write = false;
             match command {
                 resp::DataType::SimpleString(ref cmd, _start, _end) => {
                     handler = Some(ss::simple_string_command_handler(cmd, self.replication_conn));
                 },
                 resp::DataType::Array(ref cmd, _start, _end) => {
                     handler = Some(array::array_type_handler(cmd, self.replication_conn));
+                    write = table::is_write(cmd);
                 },
                 resp::DataType::BulkString(ref cmd, _start, _end) => {
                     handler = Some(bulk::bulk_string_type_handler(cmd, self.replication_conn));
@@ -97,32 +183,189 @@ impl<'a, 'b> Incoming<'b> {
                 }
             }
             if let Some(f) = handler {
-                let result1 = f.handle(stream, db);
-                let result2 = f.replicate(self.buf, repl_ch);
-                let result3 = f.repl_config(stream, replcfg);
-                let result4 = f.track_offset(slavecfg, stream, command.len());
-
-                if result1.is_err() { 
-                    println!("Error processing command for {} - result1: {:?}", command, result1);
-                    // return result1; 
-                }
-                if result2.is_err() { 
-                    println!("Error processing replication for {} - result2: {:?}", command, result2);
-                    //return result2; 
-                }
-                if result3.is_err() {
-                    println!("Error updating repl configuration for {} - result3: {:?}", command, result3);
-                    //return result3;
+                self.execute(f.as_ref(), write, &raw, stream, db, replcfg, repl_ch, slavecfg, client, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    // refuses commands before the connection authenticated and the ones its user is not permitted
+    // connections of a deleted user are closed
+    fn authorize(
+        &self,
+        stream: &Connection,
+        db: &Arc<db::DB>,
+        client: &client::Client,
+        cmd: &resp::Args,
+        in_exec: bool,
+    ) -> std::io::Result<Option<String>> {
+        if !client.authenticated() {
+            if NO_AUTH.contains(&cmd[0].as_str()) {
+                return Ok(None);
+            }
+            return Ok(Some("NOAUTH Authentication required.".to_string()));
+        }
+        let acl = db.acl();
+        if !acl.exists(client.user()) {
+            return Err(std::io::Error::other(format!("user {} was deleted", client.user())));
+        }
+        let Err(denial) = acl.check(client.user(), cmd) else { return Ok(None) };
+        let name = table::lookup(&cmd[0]).map(|spec| spec.full_name(cmd)).unwrap_or(cmd[0].clone());
+        let context = if in_exec || client.in_multi() { "multi" } else { "toplevel" };
+        acl.log_denial(denial.reason(), context, &denial.object(&name), client.user(), &client.info(stream));
+        Ok(Some(denial.message(client.user(), &name)))
+    }
+
+    // runs all the hooks of a command handler, write is from the command table
+    // within EXEC - db is already locked and replication is collected by the caller
+    #[allow(clippy::too_many_arguments)]
+    fn execute(
+        &self,
+        f: &dyn CommandHandler,
+        write: bool,
+        raw: &BytesMut,
+        stream: &mut Connection,
+        db: &Arc<db::DB>,
+        replcfg: &Arc<repl::ReplicationConfig>,
+        repl_ch: &Sender<BytesMut>,
+        slavecfg: &Option<slave::Config>,
+        client: &mut client::Client,
+        in_exec: bool,
+    ) -> std::io::Result<()> {
+        // a transaction is checked once as a whole by exec
+        if !in_exec && !self.replication_conn && write && db.role_master() && !replcfg.writes_allowed() {
+            return stream.write_all(format!("-{}\r\n", NOREPLICAS).as_bytes());
+        }
+        // command and its replication are one step for a full sync snapshot
+        let guard = if in_exec { None } else { Some(db.command_guard()) };
+        // replicas leave eviction to their master and get its DELs
+        if !self.replication_conn && db.role_master() {
+            let (evicted, fits) = db.evict(replcfg.buffered_memory());
+            for key in evicted {
+                send_replication(BytesMut::from(&resp::command(&[b"DEL", key.as_bytes()])[..]), repl_ch)?;
+            }
+            if !fits && write && f.deny_oom() {
+                return stream.write_all(b"-OOM command not allowed when used memory > 'maxmemory'.\r\n");
+            }
+        }
+        let result1 = f.handle(stream, db);
+        let mut result2 = Ok(());
+        if !in_exec && self.forwarding(slavecfg) {
+            result2 = self.forward(raw, repl_ch, slavecfg);
+        } else if db.role_master() {
+            result2 = f.replicate(raw, repl_ch);
+        }
+        drop(guard);
+        let result3 = f.repl_config(stream, replcfg);
+        let result4 = f.client_state(stream, db, client);
+        let result5 = if in_exec { Ok(()) } else { f.track_offset(slavecfg, stream, raw.len()) };
+
+        if result1.is_err() {
+            println!("Error processing command - result1: {:?}", result1);
+        }
+        if result2.is_err() {
+            println!("Error processing replication - result2: {:?}", result2);
+        }
+        if result3.is_err() {
+            println!("Error updating repl configuration - result3: {:?}", result3);
+        }
+        if result4.is_err() {
+            println!("Error updating client state - result4: {:?}", result4);
+        }
+        if result5.is_err() {
+            println!("Error updating slave offset - result5: {:?}", result5);
+        }
+        Ok(())
+    }
+
+    // runs queued commands of the transaction as one step
+    // nothing else runs on db meanwhile, replicas get them wrapped in MULTI/EXEC
+    #[allow(clippy::too_many_arguments)]
+    fn exec(
+        &self,
+        stream: &mut Connection,
+        db: &Arc<db::DB>,
+        replcfg: &Arc<repl::ReplicationConfig>,
+        repl_ch: &Sender<BytesMut>,
+        slavecfg: &Option<slave::Config>,
+        client: &mut client::Client,
+        raw: &BytesMut,
+    ) -> std::io::Result<()> {
+        // replica proxies the transaction as received
+        if self.forwarding(slavecfg) {
+            self.forward(raw, repl_ch, slavecfg)?;
+        }
+        let guard = db.exclusive_guard();
+        let queued = client.exec();
+        let changed = client.watched_changed(db);
+        client.unwatch(db);
+        let queued = match queued {
+            Ok(q) => q,
+            Err(e) => {
+                if !self.replication_conn {
+                    stream.write_all(format!("-{}\r\n", e).as_bytes())?;
                 }
-                if result4.is_err() {
-                    println!("Error updating slave offset for {} for result4: {:?}", command, result4);
-                    //return result3;
+                return Ok(());
+            }
+        };
+        // any write refuses the whole transaction, not only the writes in it
+        if !self.replication_conn && db.role_master() && !replcfg.writes_allowed()
+            && queued.iter().any(|(cmd, _raw)| table::is_write(cmd)) {
+            return stream.write_all(format!("-EXECABORT Transaction discarded because of: {}\r\n", NOREPLICAS).as_bytes());
+        }
+        if changed {
+            if !self.replication_conn {
+                stream.write_all(b"*-1\r\n")?;
+            }
+            return Ok(());
+        }
+        if !self.replication_conn {
+            stream.write_all(format!("*{}\r\n", queued.len()).as_bytes())?;
+        }
+        let (tx, rx) = mpsc::channel();
+        for (cmd, cmd_raw) in queued.iter() {
+            // permissions may have changed since the command was queued
+            if !self.replication_conn {
+                if let Some(e) = self.authorize(stream, db, client, cmd, true)? {
+                    stream.write_all(format!("-{}\r\n", e).as_bytes())?;
+                    continue;
                 }
             }
+            let f = array::array_type_handler(cmd, self.replication_conn);
+            self.execute(f.as_ref(), table::is_write(cmd), cmd_raw, stream, db, replcfg, &tx, slavecfg, client, true)?;
         }
+        let mut block = BytesMut::new();
+        while let Ok(buf) = rx.try_recv() {
+            block.extend_from_slice(&buf);
+        }
+        if !block.is_empty() {
+            let mut wrapped = BytesMut::from(&b"*1\r\n$5\r\nMULTI\r\n"[..]);
+            wrapped.extend_from_slice(&block);
+            wrapped.extend_from_slice(b"*1\r\n$4\r\nEXEC\r\n");
+            if let Err(e) = repl_ch.send(wrapped) {
+                println!("failed replicating transaction: {:?}", e);
+            }
+        }
+        drop(guard);
         Ok(())
     }
 
+    // replica proxies everything it processes from its master to sub-replicas
+    fn forwarding(&self, slavecfg: &Option<slave::Config>) -> bool {
+        self.replication_conn
+            && slavecfg.as_ref().map(|cfg| cfg.in_sync()).unwrap_or(false)
+    }
+
+    fn forward(&self, raw: &BytesMut, repl_ch: &Sender<BytesMut>, slavecfg: &Option<slave::Config>) -> std::io::Result<()> {
+        if !self.forwarding(slavecfg) {
+            return Ok(());
+        }
+        match repl_ch.send(raw.clone()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(std::io::Error::other(format!("failed replication: {:?}", e))),
+        }
+    }
+
     // returns first token - could be command or a response
     pub fn get_command(&self, id: usize) -> String {
         if id >= self.commands.len() {
@@ -1,44 +1,73 @@
+use crate::client::connection::Connection;
 use crate::commands::array;
 use crate::commands::incoming;
+use crate::repl::repl;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
+use std::sync::RwLock;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Info<'a> {
     cmd: &'a Vec<String>,
     replication_conn: bool,
+    role: RwLock<Option<String>>, // built in handle, sent out along with replication state
 }
 
 impl<'a> Info<'a> {
     pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
-        Self {cmd, replication_conn}
+        Self {cmd, replication_conn, role: RwLock::new(None)}
     }
 }
 
 impl<'a> incoming::CommandHandler for Info<'a> {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        _stream: &mut Connection,
         db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
         if self.replication_conn { return Ok(()); }
         
         // only be called when data type is appropriate
-        let mut response = String::new();
-        let mut replication = false;
+        let mut replication = true;
         if let Some(info_type) = array::get_nth_arg(self.cmd, 1) {
-            if info_type.contains("replication") {
-                replication = true;
+            if !info_type.contains("replication") {
+                replication = false;
             }
         }
         if replication {
             let mut role = "role:slave".to_string();
             if db.role_master() {
                 role = "role:master".to_string();
-                role.push_str("\r\nmaster_replid:8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb");
-                role.push_str("\r\nmaster_repl_offset:0");
+            }
+            *self.role.write().unwrap() = Some(role);
+        }
+        Ok(())
+    }
+
+    // replication section needs the replica states - so its sent from here
+    fn repl_config(
+        &self,
+        stream: &mut Connection,
+        replcfg: &Arc<repl::ReplicationConfig>
+    ) -> std::io::Result<()> {
+        let mut response = String::new();
+        if let Some(mut role) = self.role.write().unwrap().take() {
+            if role == "role:master" {
+                let _ = std::fmt::write(&mut role,
+                    format_args!("\r\nconnected_slaves:{}", replcfg.num_replicas()));
+                let min_replicas = replcfg.min_replicas();
+                if min_replicas.to_write > 0 && min_replicas.max_lag > 0 {
+                    let _ = std::fmt::write(&mut role,
+                        format_args!("\r\nmin_slaves_good_slaves:{}", replcfg.good_replicas()));
+                }
+            } else {
+                let _ = std::fmt::write(&mut role,
+                    format_args!("\r\nconnected_slaves:{}", replcfg.num_replicas()));
+            }
+            if let Some((replid, offset)) = replcfg.replication_info() {
+                let _ = std::fmt::write(&mut role,
+                    format_args!("\r\nmaster_replid:{}\r\nmaster_repl_offset:{}", replid, offset));
             }
             let _ = std::fmt::write(
                 &mut response,
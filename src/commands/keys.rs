@@ -1,7 +1,9 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
 use crate::store::db;
-use std::io::Write;
-use std::net::TcpStream;
+use crate::utils::utils;
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -21,11 +23,142 @@ impl<'a> Keys<'a> {
 impl<'a> incoming::CommandHandler for Keys<'a> {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
-        // read all the keys from DB and send them via an array.
-        let (response, _count) = db.keys();
-        stream.write_all(response.as_bytes())
+        if self.cmd.len() != 2 {
+            return strings::reply(stream, self.replication_conn, Err(strings::wrong_args(self.cmd)));
+        }
+        // read the matching keys from DB and send them via an array.
+        let keys = db.keys(&self.cmd[1]);
+        let mut response = format!("*{}\r\n", keys.len()).into_bytes();
+        keys.iter().for_each(|k| response.extend(strings::bulk(k.as_bytes())));
+        strings::reply(stream, self.replication_conn, Ok(response))
+    }
+}
+
+#[derive(Debug)]
+struct ScanOptions {
+    pattern: Option<String>,
+    count: usize,
+    kind: Option<String>,
+}
+
+fn cursor(value: &str) -> Result<u64, String> {
+    value.parse::<u64>().map_err(|_| "ERR invalid cursor".to_string())
+}
+
+// [MATCH pattern] [COUNT count] [TYPE type] from start on
+// members keep their case unlike keys, and there is no TYPE for them
+fn scan_options(cmd: &resp::Args, start: usize, members: bool) -> Result<ScanOptions, String> {
+    let mut options = ScanOptions { pattern: None, count: 10, kind: None };
+    let mut idx = start;
+    while idx < cmd.len() {
+        if idx + 1 == cmd.len() {
+            return Err("ERR syntax error".to_string());
+        }
+        match cmd[idx].as_str() {
+            "match" if members => options.pattern = Some(String::from_utf8_lossy(cmd.raw(idx + 1)).into_owned()),
+            "match" => options.pattern = Some(cmd[idx + 1].clone()),
+            "count" => {
+                options.count = match strings::parse_int(cmd[idx + 1].as_bytes())? {
+                    n if n < 1 => return Err("ERR syntax error".to_string()),
+                    n => n as usize,
+                };
+            },
+            "type" if !members => options.kind = Some(cmd[idx + 1].clone()),
+            _ => return Err("ERR syntax error".to_string()),
+        }
+        idx += 2;
+    }
+    Ok(options)
+}
+
+fn scan_reply(cursor: u64, items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut response = b"*2\r\n".to_vec();
+    response.extend(strings::bulk(cursor.to_string().as_bytes()));
+    response.extend(format!("*{}\r\n", items.len()).into_bytes());
+    items.into_iter().for_each(|item| response.extend(item));
+    response
+}
+
+// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+// MATCH and TYPE filter what COUNT picked so fewer keys may come back
+#[derive(Debug)]
+pub struct Scan<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+}
+
+impl<'a> Scan<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let cursor = cursor(&self.cmd[1])?;
+        let options = scan_options(self.cmd, 2, false)?;
+        let (next, keys) = db.scan(cursor, options.count);
+        let keys = keys.into_iter()
+            .filter(|(key, _t)| options.pattern.as_ref().map(|p| utils::glob_match(p, key)).unwrap_or(true))
+            .filter(|(_k, kind)| options.kind.as_ref().map(|t| t == kind).unwrap_or(true))
+            .map(|(key, _t)| strings::bulk(key.as_bytes()))
+            .collect();
+        Ok(scan_reply(next, keys))
+    }
+}
+
+impl<'a> incoming::CommandHandler for Scan<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// ZSCAN key cursor [MATCH pattern] [COUNT count]
+// small sets come back whole in one call like redis does for listpack ones
+#[derive(Debug)]
+pub struct ZScan<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+}
+
+impl<'a> ZScan<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 3 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let cursor = cursor(&self.cmd[2])?;
+        let options = scan_options(self.cmd, 3, true)?;
+        let value = db.get(&self.cmd[1]);
+        let z = match &value {
+            Some(db::KeyValueType::SortedSetType(z)) => z,
+            Some(_) => return Err(db::WRONGTYPE.to_string()),
+            None => return Ok(scan_reply(0, vec![])),
+        };
+        let (next, members) = match value.as_ref().map(|v| v.encoding()) {
+            Some("listpack") => (0, z.iter().collect()),
+            _ => z.scan(cursor, options.count),
+        };
+        let mut items = vec![];
+        for (member, score) in members {
+            if options.pattern.as_ref().map(|p| utils::glob_match(p, member)).unwrap_or(true) {
+                items.push(strings::bulk(member.as_bytes()));
+                items.push(strings::bulk(score.to_string().as_bytes()));
+            }
+        }
+        Ok(scan_reply(next, items))
+    }
+}
+
+impl<'a> incoming::CommandHandler for ZScan<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
     }
 }
@@ -0,0 +1,275 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::strings;
+use crate::store::db;
+use bytes::BytesMut;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread;
+
+// values with more allocations than this are freed in the background by UNLINK
+const LAZYFREE_THRESHOLD: usize = 64;
+
+const OBJECT_HELP: [&str; 14] = [
+    "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "ENCODING <key>",
+    "    Return the kind of internal representation used in order to store the value",
+    "    associated with a <key>.",
+    "FREQ <key>",
+    "    Return the access logarithmic access frequency counter of a <key>.",
+    "IDLETIME <key>",
+    "    Return the idle time of a <key>, that is the approximated number of",
+    "    seconds elapsed since the last access to the key.",
+    "REFCOUNT <key>",
+    "    Return the number of references of the value associated with the specified",
+    "    <key>.",
+    "HELP",
+    "    Print this help.",
+];
+
+// DEL key [key ...] | UNLINK key [key ...]
+#[derive(Debug)]
+pub struct Del<'a> {
+    cmd: &'a Vec<String>,
+    unlink: bool,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> Del<'a> {
+    pub fn new(cmd: &'a Vec<String>, unlink: bool, replication_conn: bool) -> Self {
+        Self { cmd, unlink, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let mut removed = 0;
+        let mut lazy = vec![];
+        for key in &self.cmd[1..] {
+            if let Some(value) = db.remove(key) {
+                removed += 1;
+                if self.unlink && value.effort() > LAZYFREE_THRESHOLD {
+                    lazy.push(value);
+                }
+            }
+        }
+        // keys are gone already - only the memory is given back later
+        if !lazy.is_empty() {
+            thread::spawn(move || drop(lazy));
+        }
+        *self.changed.write().unwrap() = removed > 0;
+        Ok(format!(":{}\r\n", removed).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Del<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// EXISTS key [key ...] | TOUCH key [key ...] - a key given twice counts twice
+#[derive(Debug)]
+pub struct Exists<'a> {
+    cmd: &'a Vec<String>,
+    touch: bool,
+    replication_conn: bool,
+}
+
+impl<'a> Exists<'a> {
+    pub fn new(cmd: &'a Vec<String>, touch: bool, replication_conn: bool) -> Self {
+        Self { cmd, touch, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let count = self.cmd[1..].iter()
+            .filter(|key| if self.touch { db.access(key) } else { db.exists(key) })
+            .count();
+        Ok(format!(":{}\r\n", count).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Exists<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// RENAME key newkey | RENAMENX key newkey
+#[derive(Debug)]
+pub struct Rename<'a> {
+    cmd: &'a Vec<String>,
+    nx: bool,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> Rename<'a> {
+    pub fn new(cmd: &'a Vec<String>, nx: bool, replication_conn: bool) -> Self {
+        Self { cmd, nx, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 3 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let renamed = db.rename(&self.cmd[1], &self.cmd[2], self.nx)?;
+        *self.changed.write().unwrap() = renamed;
+        match self.nx {
+            true => Ok(format!(":{}\r\n", renamed as u8).into()),
+            false => Ok("+OK\r\n".into()),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Rename<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// COPY source destination [DB destination-db] [REPLACE] - there is only db 0
+#[derive(Debug)]
+pub struct Copy<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> Copy<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 3 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let mut replace = false;
+        let mut idx = 3;
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "replace" => replace = true,
+                "db" if idx + 1 < self.cmd.len() => {
+                    if strings::parse_int(self.cmd[idx + 1].as_bytes())? != 0 {
+                        return Err("ERR DB index is out of range".to_string());
+                    }
+                    idx += 1;
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 1;
+        }
+        if self.cmd[1] == self.cmd[2] {
+            return Err("ERR source and destination objects are the same".to_string());
+        }
+        let copied = db.copy(&self.cmd[1], &self.cmd[2], replace);
+        *self.changed.write().unwrap() = copied;
+        Ok(format!(":{}\r\n", copied as u8).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Copy<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// RANDOMKEY
+#[derive(Debug)]
+pub struct RandomKey {
+    replication_conn: bool,
+}
+
+impl RandomKey {
+    pub fn new(replication_conn: bool) -> Self {
+        Self { replication_conn }
+    }
+}
+
+impl incoming::CommandHandler for RandomKey {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match db.random_key() {
+            Some(key) => strings::bulk(key.as_bytes()),
+            None => b"$-1\r\n".to_vec(),
+        };
+        strings::reply(stream, self.replication_conn, Ok(response))
+    }
+}
+
+// OBJECT ENCODING | FREQ | IDLETIME | REFCOUNT key
+// OBJECT HELP
+#[derive(Debug)]
+pub struct Object<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> Object<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let subcommand = self.cmd[1].as_str();
+        let key = match (subcommand, &self.cmd[2..]) {
+            ("help", []) => {
+                let mut response = format!("*{}\r\n", OBJECT_HELP.len());
+                OBJECT_HELP.iter().for_each(|line| response.push_str(&format!("+{}\r\n", line)));
+                return Ok(response.into());
+            },
+            ("encoding" | "freq" | "idletime" | "refcount", [key]) => key,
+            _ => return Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try OBJECT HELP.", subcommand)),
+        };
+        let lfu = db.maxmemory().policy.lfu();
+        let response = db.object(key, |value, idle, freq| match subcommand {
+            "encoding" => Ok(strings::bulk(value.encoding().as_bytes())),
+            "idletime" if !lfu => Ok(format!(":{}\r\n", idle / 1000).into_bytes()),
+            "idletime" => Err("ERR An LFU maxmemory policy is selected, idle time not tracked. \
+                Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".to_string()),
+            // small integers are shared objects in redis
+            "refcount" => match value {
+                db::KeyValueType::IntegerType(0..=9999) => Ok(format!(":{}\r\n", i32::MAX).into_bytes()),
+                _ => Ok(b":1\r\n".to_vec()),
+            },
+            _ if lfu => Ok(format!(":{}\r\n", freq).into_bytes()),
+            _ => Err("ERR An LFU maxmemory policy is not selected, access frequency not tracked. \
+                Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".to_string()),
+        });
+        response.unwrap_or(Ok(b"$-1\r\n".to_vec()))
+    }
+}
+
+impl<'a> incoming::CommandHandler for Object<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+}
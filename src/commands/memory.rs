@@ -0,0 +1,237 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::repl::repl;
+use crate::store::db;
+use crate::utils::memory;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+const MEMORY_HELP: [&str; 14] = [
+    "MEMORY <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "DOCTOR",
+    "    Return memory problems reports.",
+    "MALLOC-STATS",
+    "    Return internal statistics report from the memory allocator.",
+    "PURGE",
+    "    Attempt to purge dirty pages for reclamation by the allocator.",
+    "STATS",
+    "    Return information about the memory usage of the server.",
+    "USAGE <key> [SAMPLES <count>]",
+    "    Return memory in bytes used by <key> and its value. Nested values are",
+    "    sampled up to <count> times (default: 5, 0 means sample all).",
+    "HELP",
+    "    Print this help.",
+];
+
+// below this much memory in use the doctor has nothing to say
+const DOCTOR_MIN_MEMORY: usize = 5 << 20;
+// peak above this percentage of the current usage is reported
+const DOCTOR_PEAK_PERCENTAGE: usize = 150;
+const DOCTOR_REPLICA_BUFFER: usize = 10 << 20;
+const DOCTOR_CLIENT_BUFFER: usize = 200 << 10;
+
+// where the memory goes - as much of it as the server can account for
+#[derive(Debug)]
+struct Overhead {
+    peak: usize,
+    total: usize,
+    startup: usize,
+    replication_backlog: usize,
+    clients_slaves: usize,
+    replicas: usize,
+    clients_normal: usize,
+    subscribers: usize,
+    hashtable: usize,
+    keys: usize,
+}
+
+impl Overhead {
+    fn total_overhead(&self) -> usize {
+        self.startup + self.replication_backlog + self.clients_slaves + self.clients_normal + self.hashtable
+    }
+
+    fn dataset(&self) -> usize {
+        self.total.saturating_sub(self.total_overhead())
+    }
+
+    fn stats(&self) -> Vec<u8> {
+        let float = |value: f64| {
+            let value = value.to_string();
+            format!("${}\r\n{}\r\n", value.len(), value)
+        };
+        let mut fields = vec![
+            ("peak.allocated", format!(":{}\r\n", self.peak)),
+            ("total.allocated", format!(":{}\r\n", self.total)),
+            ("startup.allocated", format!(":{}\r\n", self.startup)),
+            ("replication.backlog", format!(":{}\r\n", self.replication_backlog)),
+            ("clients.slaves", format!(":{}\r\n", self.clients_slaves)),
+            ("clients.normal", format!(":{}\r\n", self.clients_normal)),
+        ];
+        // only dbs with keys are listed
+        if self.keys > 0 {
+            fields.push(("db.0", format!("*4\r\n$23\r\noverhead.hashtable.main\r\n:{}\r\n\
+                $26\r\noverhead.hashtable.expires\r\n:0\r\n", self.hashtable)));
+        }
+        let net = self.total.saturating_sub(self.startup);
+        let dataset_percentage = if net > 0 { self.dataset() as f64 * 100.0 / net as f64 } else { 0.0 };
+        let peak_percentage = if self.peak > 0 { self.total as f64 * 100.0 / self.peak as f64 } else { 0.0 };
+        let bytes_per_key = net.checked_div(self.keys).unwrap_or(0);
+        fields.extend([
+            ("overhead.total", format!(":{}\r\n", self.total_overhead())),
+            ("keys.count", format!(":{}\r\n", self.keys)),
+            ("keys.bytes-per-key", format!(":{}\r\n", bytes_per_key)),
+            ("dataset.bytes", format!(":{}\r\n", self.dataset())),
+            ("dataset.percentage", float(dataset_percentage)),
+            ("peak.percentage", float(peak_percentage)),
+        ]);
+        let mut response = format!("*{}\r\n", fields.len() * 2);
+        for (name, value) in fields {
+            response.push_str(&format!("${}\r\n{}\r\n{}", name.len(), name, value));
+        }
+        response.into_bytes()
+    }
+
+    fn doctor(&self) -> Vec<u8> {
+        let report = if self.total < DOCTOR_MIN_MEMORY {
+            "Hi Sam, this instance is empty or is using very little memory, my issues detector \
+                can't be used in these conditions. Please, leave for your mission on Earth and fill it \
+                with some data. The new Sam and I will be back to our programming as soon as I finished \
+                rebooting.".to_string()
+        } else {
+            let mut issues = vec![];
+            if self.peak * 100 > self.total * DOCTOR_PEAK_PERCENTAGE {
+                issues.push(" * Peak memory: In the past this instance used more than 150% the memory \
+                    that is currently using. The allocator is normally not able to release memory after \
+                    a peak, so you can expect to see a big fragmentation ratio, however this is actually \
+                    harmless and is only due to the memory peak, and if the Redis instance Resident Set \
+                    Size (RSS) is currently bigger than expected, the memory will be used as soon as you \
+                    fill the Redis instance with more data. If the memory peak was only occasional and \
+                    you want to try to reclaim memory, please try the MEMORY PURGE command, otherwise \
+                    the only other option is to shutdown and restart the instance.\n\n");
+            }
+            if self.replicas > 0 && self.clients_slaves / self.replicas > DOCTOR_REPLICA_BUFFER {
+                issues.push(" * Big replica buffers: The replica output buffers in this instance are \
+                    greater than 10MB for each replica (on average). This likely means that there is \
+                    some replica instance that is struggling receiving data, either because it is too \
+                    slow or because of networking issues. As a result, data piles on the master output \
+                    buffers. Please try to identify what replica is not receiving data correctly and \
+                    why. You can use the INFO output in order to check the replicas delays and the \
+                    CLIENT LIST command to check the output buffers of each replica.\n\n");
+            }
+            if self.subscribers > 0 && self.clients_normal / self.subscribers > DOCTOR_CLIENT_BUFFER {
+                issues.push(" * Big client buffers: The clients output buffers in this instance are \
+                    greater than 200K per client (on average). This may result from different causes, \
+                    like Pub/Sub clients subscribed to channels but not receiving data fast enough, so \
+                    that data piles on the Redis instance output buffer, or clients sending commands \
+                    with large replies or very large sequences of commands in the same pipeline. Please \
+                    use the CLIENT LIST command in order to investigate the issue if it causes problems \
+                    in your instance, or to understand better why certain clients are using a big \
+                    amount of memory.\n\n");
+            }
+            if issues.is_empty() {
+                "Hi Sam, I can't find any memory issue in your instance. \
+                    I can only account for what occurs on this base.".to_string()
+            } else {
+                format!("Sam, I detected a few issues in this Redis instance memory implants:\n\n{}\
+                    I'm here to keep you safe, Sam. I want to help you.\n", issues.concat())
+            }
+        };
+        strings::bulk(report.as_bytes())
+    }
+}
+
+// MEMORY USAGE key [SAMPLES count] | STATS | DOCTOR | MALLOC-STATS | PURGE | HELP
+#[derive(Debug)]
+pub struct Memory<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    // STATS and DOCTOR need the replication state - built in handle, sent from repl_config
+    overhead: RwLock<Option<Overhead>>,
+}
+
+impl<'a> Memory<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, overhead: RwLock::new(None) }
+    }
+
+    fn usage(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        let mut samples = memory::DEFAULT_SAMPLES;
+        match &self.cmd[3..] {
+            [] => {},
+            [option, count] if option == "samples" => {
+                samples = match strings::parse_int(count.as_bytes())? {
+                    n if n < 0 => return Err("ERR syntax error".to_string()),
+                    n => n as usize,
+                };
+            },
+            _ => return Err("ERR syntax error".to_string()),
+        }
+        match db.memory_usage(&self.cmd[2], samples) {
+            Some(bytes) => Ok(format!(":{}\r\n", bytes).into_bytes()),
+            None => Ok(b"$-1\r\n".to_vec()),
+        }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let subcommand = self.cmd[1].as_str();
+        match (subcommand, self.cmd.len()) {
+            ("help", 2) => {
+                let mut response = format!("*{}\r\n", MEMORY_HELP.len());
+                MEMORY_HELP.iter().for_each(|line| response.push_str(&format!("+{}\r\n", line)));
+                Ok(response.into())
+            },
+            ("usage", 3..) => self.usage(db),
+            ("stats" | "doctor", 2) => {
+                let (subscribers, clients_normal) = db.pubsub().pending_output();
+                *self.overhead.write().unwrap() = Some(Overhead {
+                    peak: memory::peak(),
+                    total: memory::allocated(),
+                    startup: memory::startup(),
+                    replication_backlog: 0,
+                    clients_slaves: 0,
+                    replicas: 0,
+                    clients_normal,
+                    subscribers,
+                    hashtable: db.table_overhead(),
+                    keys: db.len(),
+                });
+                Ok(vec![])
+            },
+            ("malloc-stats", 2) => Ok(strings::bulk(b"Stats not supported for the current allocator")),
+            // freed memory goes back to the system allocator right away
+            ("purge", 2) => Ok(b"+OK\r\n".to_vec()),
+            _ => Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try MEMORY HELP.", subcommand)),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Memory<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn repl_config(
+        &self,
+        stream: &mut Connection,
+        replcfg: &Arc<repl::ReplicationConfig>
+    ) -> std::io::Result<()> {
+        let Some(mut overhead) = self.overhead.write().unwrap().take() else { return Ok(()) };
+        if self.replication_conn { return Ok(()); }
+        overhead.replication_backlog = replcfg.buffered_memory();
+        overhead.clients_slaves = replcfg.replica_output();
+        overhead.replicas = replcfg.num_replicas();
+        // replica output is part of the replication log here, count it once
+        overhead.replication_backlog = overhead.replication_backlog.saturating_sub(overhead.clients_slaves);
+        let response = match self.cmd[1].as_str() {
+            "stats" => overhead.stats(),
+            _ => overhead.doctor(),
+        };
+        stream.write_all(&response)
+    }
+}
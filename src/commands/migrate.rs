@@ -0,0 +1,225 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::commands::strings;
+use crate::rdb::rdb;
+use crate::store::db;
+use bytes::BytesMut;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+// connections to targets stay open for later MIGRATE calls this long
+const SOCKET_IDLE: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct CachedSocket {
+    target: String,
+    stream: TcpStream,
+    last_use: Instant,
+}
+
+static SOCKETS: Mutex<Vec<CachedSocket>> = Mutex::new(Vec::new());
+
+// takes the cached connection to target out of the cache - idle ones are closed on the way
+fn cached_socket(target: &str) -> Option<TcpStream> {
+    let mut sockets = SOCKETS.lock().unwrap();
+    sockets.retain(|s| s.last_use.elapsed() < SOCKET_IDLE);
+    let idx = sockets.iter().position(|s| s.target == target)?;
+    Some(sockets.swap_remove(idx).stream)
+}
+
+fn cache_socket(target: &str, stream: TcpStream) {
+    SOCKETS.lock().unwrap().push(CachedSocket { target: target.to_string(), stream, last_use: Instant::now() });
+}
+
+fn connect(target: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let failed = || "IOERR error or timeout connecting to the client".to_string();
+    let addr = target.to_socket_addrs().map_err(|_| failed())?.next().ok_or_else(failed)?;
+    TcpStream::connect_timeout(&addr, timeout).map_err(|_| failed())
+}
+
+// MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+//   [AUTH password | AUTH2 username password] [KEYS key [key ...]]
+// keys are sent as RESTORE-ASKING and only removed here once the target took them
+#[derive(Debug)]
+pub struct Migrate<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    removed: RwLock<Vec<String>>,
+}
+
+impl<'a> Migrate<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, removed: RwLock::new(vec![]) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 6 {
+            return Err(strings::wrong_args(self.cmd));
+        }
+        let (mut copy, mut replace) = (false, false);
+        let mut auth: Vec<&[u8]> = vec![];
+        let mut keys = vec![];
+        let mut idx = 6;
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "copy" => copy = true,
+                "replace" => replace = true,
+                "auth" if idx + 1 < self.cmd.len() => {
+                    auth = vec![self.cmd.raw(idx + 1)];
+                    idx += 1;
+                },
+                "auth2" if idx + 2 < self.cmd.len() => {
+                    auth = vec![self.cmd.raw(idx + 1), self.cmd.raw(idx + 2)];
+                    idx += 2;
+                },
+                "keys" => {
+                    if !self.cmd[3].is_empty() {
+                        return Err("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".to_string());
+                    }
+                    keys.extend(self.cmd[idx + 1..].iter().cloned());
+                    break;
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 1;
+        }
+        if keys.is_empty() && !self.cmd[3].is_empty() {
+            keys.push(self.cmd[3].clone());
+        }
+        let port = strings::parse_int(self.cmd[2].as_bytes())?;
+        // there is only db 0 here and on other instances of this server
+        if strings::parse_int(self.cmd[4].as_bytes())? != 0 {
+            return Err("ERR DB index is out of range".to_string());
+        }
+        let timeout = match strings::parse_int(self.cmd[5].as_bytes())? {
+            n if n <= 0 => Duration::from_millis(1000),
+            n => Duration::from_millis(n as u64),
+        };
+
+        let mut entries = vec![];
+        for key in keys {
+            if let Some((value, ttl)) = db.get_with_ttl(&key) {
+                let payload = rdb::RDB::dump_value(&value)?;
+                entries.push((key, payload, ttl));
+            }
+        }
+        if entries.is_empty() {
+            return Ok("+NOKEY\r\n".into());
+        }
+
+        let authenticate = !auth.is_empty();
+        let mut request = vec![];
+        if authenticate {
+            let mut args: Vec<&[u8]> = vec![b"AUTH"];
+            args.extend(auth);
+            request.extend(resp::command(&args));
+        }
+        for (key, payload, ttl) in entries.iter() {
+            let ttl = ttl.unwrap_or(0).to_string();
+            let mut args: Vec<&[u8]> = vec![b"RESTORE-ASKING", key.as_bytes(), ttl.as_bytes(), payload];
+            if replace {
+                args.push(b"REPLACE");
+            }
+            request.extend(resp::command(&args));
+        }
+
+        let target = format!("{}:{}", self.cmd[1], port);
+        let replies = self.exchange(&target, &request, entries.len() + authenticate as usize, timeout)?;
+        let mut replies = replies.into_iter();
+        if authenticate {
+            if let Some(Err(e)) = replies.next() {
+                return Err(format!("ERR Target instance replied with error: {}", e));
+            }
+        }
+        let mut error = None;
+        let mut removed = vec![];
+        for ((key, _p, _t), reply) in entries.into_iter().zip(replies) {
+            match reply {
+                Ok(_) if !copy => {
+                    if db.remove(&key).is_some() {
+                        removed.push(key);
+                    }
+                },
+                Ok(_) => {},
+                Err(e) => error = Some(format!("ERR Target instance replied with error: {}", e)),
+            }
+        }
+        *self.removed.write().unwrap() = removed;
+        match error {
+            Some(e) => Err(e),
+            None => Ok("+OK\r\n".into()),
+        }
+    }
+
+    // sends the request and reads a status reply for each command in it
+    // a cached connection which went stale is given one more try on a new one
+    fn exchange(&self, target: &str, request: &[u8], count: usize, timeout: Duration)
+        -> Result<Vec<Result<String, String>>, String> {
+        let cached = cached_socket(target);
+        let retry = cached.is_some();
+        let stream = match cached {
+            Some(stream) => stream,
+            None => connect(target, timeout)?,
+        };
+        match send(&stream, request, count, timeout) {
+            Ok(replies) => {
+                cache_socket(target, stream);
+                Ok(replies)
+            },
+            Err(_) if retry => {
+                let stream = connect(target, timeout)?;
+                let replies = send(&stream, request, count, timeout)?;
+                cache_socket(target, stream);
+                Ok(replies)
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn send(mut stream: &TcpStream, request: &[u8], count: usize, timeout: Duration)
+    -> Result<Vec<Result<String, String>>, String> {
+    let _ = stream.set_write_timeout(Some(timeout));
+    let _ = stream.set_read_timeout(Some(timeout));
+    stream.write_all(request).map_err(|_| "IOERR error or timeout writing to target instance".to_string())?;
+    let mut reader = BufReader::new(stream);
+    let mut replies = vec![];
+    for _ in 0..count {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(n) if n > 0 => {},
+            _ => return Err("IOERR error or timeout reading to target instance".to_string()),
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        match line.strip_prefix('-') {
+            Some(e) => replies.push(Err(e.to_string())),
+            None => replies.push(Ok(line.to_string())),
+        }
+    }
+    Ok(replies)
+}
+
+impl<'a> incoming::CommandHandler for Migrate<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        strings::reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    // replicas drop the keys which moved away
+    fn replicate(&self, _buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        let removed = self.removed.read().unwrap();
+        if self.replication_conn || removed.is_empty() { return Ok(()); }
+        let mut args: Vec<&[u8]> = vec![b"DEL"];
+        removed.iter().for_each(|key| args.push(key.as_bytes()));
+        incoming::send_replication(BytesMut::from(&resp::command(&args)[..]), tx_ch)
+    }
+}
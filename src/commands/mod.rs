@@ -1,20 +1,43 @@
+pub mod acl;
 pub mod array;
+pub mod auth;
+pub mod bitops;
 pub mod bulk;
 pub mod config;
+pub mod dump;
 pub mod echo;
 pub mod fullresync;
+pub mod geo;
 pub mod getset;
+pub mod hyperloglog;
 pub mod incoming;
 pub mod info;
 pub mod keys;
+pub mod keyspace;
+pub mod memory;
+pub mod migrate;
+pub mod multi;
 pub mod ping;
 pub mod psync;
+pub mod pubsub;
 pub mod rdbfile;
 pub mod replcmd;
 pub mod resp;
 pub mod ss;
 pub mod stream;
+pub mod strings;
+pub mod table;
 pub mod ttype;
 pub mod wait;
+pub mod xack;
+pub mod xclaim;
+pub mod xdel;
+pub mod xgroup;
+pub mod xinfo;
+pub mod xlen;
+pub mod xpending;
 pub mod xrange;
 pub mod xread;
+pub mod xreadgroup;
+pub mod xsetid;
+pub mod xtrim;
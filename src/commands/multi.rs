@@ -0,0 +1,112 @@
+use crate::client::client;
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use std::io::Write;
+use std::sync::Arc;
+
+// MULTI/DISCARD/WATCH/UNWATCH work on per connection state
+// EXEC is run by incoming as it needs to execute the queued commands
+
+fn respond(stream: &mut Connection, replication_conn: bool, result: Result<(), String>) -> std::io::Result<()> {
+    if replication_conn { return Ok(()); }
+    match result {
+        Ok(()) => stream.write_all(b"+OK\r\n"),
+        Err(e) => stream.write_all(format!("-{}\r\n", e).as_bytes()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Multi {
+    replication_conn: bool,
+}
+
+impl Multi {
+    pub fn new(replication_conn: bool) -> Self {
+        Self { replication_conn }
+    }
+}
+
+impl incoming::CommandHandler for Multi {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, _db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        respond(stream, self.replication_conn, client.multi())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Discard {
+    replication_conn: bool,
+}
+
+impl Discard {
+    pub fn new(replication_conn: bool) -> Self {
+        Self { replication_conn }
+    }
+}
+
+impl incoming::CommandHandler for Discard {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        let result = client.discard();
+        client.unwatch(db);
+        respond(stream, self.replication_conn, result)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Watch<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> Watch<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Watch<'a> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        if client.in_multi() {
+            return respond(stream, self.replication_conn, Err("ERR WATCH inside MULTI is not allowed".to_string()));
+        }
+        if self.cmd.len() < 2 {
+            return respond(stream, self.replication_conn, Err("ERR wrong number of arguments for 'watch' command".to_string()));
+        }
+        self.cmd.iter().skip(1).for_each(|key| client.watch(db, key));
+        respond(stream, self.replication_conn, Ok(()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Unwatch {
+    replication_conn: bool,
+}
+
+impl Unwatch {
+    pub fn new(replication_conn: bool) -> Self {
+        Self { replication_conn }
+    }
+}
+
+impl incoming::CommandHandler for Unwatch {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        client.unwatch(db);
+        respond(stream, self.replication_conn, Ok(()))
+    }
+}
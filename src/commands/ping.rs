@@ -1,7 +1,7 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
@@ -18,7 +18,7 @@ impl Ping {
 impl incoming::CommandHandler for Ping {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         _db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
         if self.replication_conn { return Ok(()); }
@@ -1,10 +1,9 @@
+use crate::client::connection::Connection;
 use crate::commands::array;
 use crate::commands::incoming;
-use crate::rdb::rdb;
 use crate::repl::repl;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -24,27 +23,22 @@ impl<'a> PSync<'a> {
 impl<'a> incoming::CommandHandler for PSync<'a> {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        _stream: &mut Connection,
         _db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
-        let mut response = vec![];
-        response
-            .extend_from_slice("+FULLRESYNC 75cd7bc10c49047e0d163660f3b90625b1af31dc 0\r\n".as_bytes());
-        if let Ok(rdb_content) = rdb::RDB::empty() {
-            response.extend_from_slice(&rdb_content);
-        } else {
-            println!("---------- Error decoding RDB content ----------");
-        }
-
-        // how do we know if this is master/slave connection
-        stream.write_all(&response)
+        Ok(())
     }
 
+    // replicator responds with FULLRESYNC and the RDB snapshot - replica gets its
+    // master's replication ID and offset so that sub-replicas follow the same stream
     fn repl_config(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         replcfg: &Arc<repl::ReplicationConfig>
     ) -> std::io::Result<()> {
+        if replcfg.replication_info().is_none() {
+            return stream.write_all(b"-NOMASTERLINK Can't SYNC while not connected with my master\r\n");
+        }
         if let Err(e) = parse_psync_options(self.cmd, stream, replcfg) {
             println!("Error updating replication node sync!!: {}", e);
             return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
@@ -55,12 +49,11 @@ impl<'a> incoming::CommandHandler for PSync<'a> {
 
 fn parse_psync_options(
     cmd: &Vec<String>,
-    stream: &mut TcpStream,
+    stream: &mut Connection,
     replcfg: &Arc<repl::ReplicationConfig>,
 ) -> Result<(), String> {
-    let peer_addr_complete = format!("{}", stream.peer_addr().unwrap());
-    let peer_addr = peer_addr_complete.split(":").collect::<Vec<&str>>();
-    if peer_addr.len() != 2 {
+    let peer_addr_complete = stream.peer_addr().unwrap();
+    if !peer_addr_complete.contains(':') {
         return Err(format!(
             "Invalid peer address format: {}",
             peer_addr_complete
@@ -0,0 +1,198 @@
+use crate::client::client;
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::pubsub::pubsub;
+use crate::store::db;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+// commands a client can run while subscribed to a channel or pattern
+const SUBSCRIBED_ALLOWED: [&str; 7] = ["subscribe", "psubscribe", "ssubscribe", "unsubscribe", "punsubscribe", "sunsubscribe", "ping"];
+
+fn bulk(s: &str) -> String {
+    format!("${}\r\n{}\r\n", s.len(), s)
+}
+
+// channels, patterns and messages are kept as given - args are lowercased
+fn channel(cmd: &resp::Args, idx: usize) -> String {
+    String::from_utf8_lossy(cmd.raw(idx)).into_owned()
+}
+
+// reply for commands not executed as usual in subscribed mode, None if command should run
+pub fn subscribed_reply(cmd: &resp::Args) -> Option<BytesMut> {
+    if cmd[0] == "ping" {
+        let message = if cmd.len() > 1 { cmd.raw(1) } else { b"" };
+        let mut reply = BytesMut::from(format!("*2\r\n{}${}\r\n", bulk("pong"), message.len()).as_bytes());
+        reply.extend_from_slice(message);
+        reply.extend_from_slice(b"\r\n");
+        return Some(reply);
+    }
+    if SUBSCRIBED_ALLOWED.contains(&cmd[0].as_str()) {
+        return None;
+    }
+    Some(BytesMut::from(format!("-ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context\r\n",
+        cmd[0]).as_bytes()))
+}
+
+// SUBSCRIBE, PSUBSCRIBE and SSUBSCRIBE - replies go through the pusher to stay in order with messages
+#[derive(Debug, Clone)]
+pub struct Subscribe<'a> {
+    cmd: &'a resp::Args,
+    kind: pubsub::Kind,
+    replication_conn: bool,
+}
+
+impl<'a> Subscribe<'a> {
+    pub fn new(cmd: &'a resp::Args, kind: pubsub::Kind, replication_conn: bool) -> Self {
+        Self { cmd, kind, replication_conn }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Subscribe<'a> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        if self.cmd.len() < 2 {
+            return stream.write_all(format!("-ERR wrong number of arguments for '{}' command\r\n", self.cmd[0]).as_bytes());
+        }
+        let pusher = client.pusher(stream)?;
+        for channel in (1..self.cmd.len()).map(|idx| channel(self.cmd, idx)) {
+            if client.subscribe(&channel, self.kind) {
+                db.pubsub().subscribe(self.kind, &channel, &pusher);
+            }
+            let reply = format!("*3\r\n{}{}:{}\r\n", bulk(&self.cmd[0]), bulk(&channel), client.subscriptions(self.kind));
+            client.push(stream, db, BytesMut::from(reply.as_bytes()))?;
+        }
+        Ok(())
+    }
+}
+
+// UNSUBSCRIBE, PUNSUBSCRIBE and SUNSUBSCRIBE - without arguments drops all subscriptions of the kind
+#[derive(Debug, Clone)]
+pub struct Unsubscribe<'a> {
+    cmd: &'a resp::Args,
+    kind: pubsub::Kind,
+    replication_conn: bool,
+}
+
+impl<'a> Unsubscribe<'a> {
+    pub fn new(cmd: &'a resp::Args, kind: pubsub::Kind, replication_conn: bool) -> Self {
+        Self { cmd, kind, replication_conn }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Unsubscribe<'a> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn client_state(&self, stream: &mut Connection, db: &Arc<db::DB>, client: &mut client::Client) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        let channels = if self.cmd.len() > 1 {
+            (1..self.cmd.len()).map(|idx| channel(self.cmd, idx)).collect()
+        } else {
+            client.channels(self.kind)
+        };
+        if channels.is_empty() {
+            let reply = format!("*3\r\n{}$-1\r\n:{}\r\n", bulk(&self.cmd[0]), client.subscriptions(self.kind));
+            return client.push(stream, db, BytesMut::from(reply.as_bytes()));
+        }
+        for channel in channels.iter() {
+            if client.unsubscribe(channel, self.kind) {
+                db.pubsub().unsubscribe(self.kind, channel, client.id());
+            }
+            let reply = format!("*3\r\n{}{}:{}\r\n", bulk(&self.cmd[0]), bulk(channel), client.subscriptions(self.kind));
+            client.push(stream, db, BytesMut::from(reply.as_bytes()))?;
+        }
+        Ok(())
+    }
+}
+
+// PUBLISH and SPUBLISH
+#[derive(Debug, Clone)]
+pub struct Publish<'a> {
+    cmd: &'a resp::Args,
+    sharded: bool,
+    replication_conn: bool,
+}
+
+impl<'a> Publish<'a> {
+    pub fn new(cmd: &'a resp::Args, sharded: bool, replication_conn: bool) -> Self {
+        Self { cmd, sharded, replication_conn }
+    }
+}
+
+impl<'a> incoming::CommandHandler for Publish<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        if self.cmd.len() != 3 {
+            if self.replication_conn { return Ok(()); }
+            return stream.write_all(format!("-ERR wrong number of arguments for '{}' command\r\n", self.cmd[0]).as_bytes());
+        }
+        let receivers = if self.sharded {
+            db.pubsub().spublish(&channel(self.cmd, 1), self.cmd.raw(2))
+        } else {
+            db.pubsub().publish(&channel(self.cmd, 1), self.cmd.raw(2))
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(format!(":{}\r\n", receivers).as_bytes())
+    }
+
+    // subscribers on replicas get the message as well
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || self.cmd.len() != 3 { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// PUBSUB CHANNELS [pattern] | NUMSUB [channel ...] | NUMPAT
+// SHARDCHANNELS [pattern] | SHARDNUMSUB [channel ...]
+#[derive(Debug, Clone)]
+pub struct PubSub<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+}
+
+impl<'a> PubSub<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+}
+
+impl<'a> incoming::CommandHandler for PubSub<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        let subcommand = self.cmd.get(1).map(|s| s.as_str()).unwrap_or("");
+        let mut response = String::new();
+        match subcommand {
+            "channels" | "shardchannels" if self.cmd.len() <= 3 => {
+                let kind = if subcommand == "channels" { pubsub::Kind::Channel } else { pubsub::Kind::Shard };
+                let pattern = (self.cmd.len() == 3).then(|| channel(self.cmd, 2));
+                let channels = db.pubsub().channels(kind, pattern.as_deref());
+                let _ = std::fmt::write(&mut response, format_args!("*{}\r\n", channels.len()));
+                channels.iter().for_each(|c| response.push_str(&bulk(c)));
+            },
+            "numsub" | "shardnumsub" => {
+                let kind = if subcommand == "numsub" { pubsub::Kind::Channel } else { pubsub::Kind::Shard };
+                let _ = std::fmt::write(&mut response, format_args!("*{}\r\n", (self.cmd.len() - 2) * 2));
+                for channel in (2..self.cmd.len()).map(|idx| channel(self.cmd, idx)) {
+                    let _ = std::fmt::write(&mut response,
+                        format_args!("{}:{}\r\n", bulk(&channel), db.pubsub().numsub(kind, &channel)));
+                }
+            },
+            "numpat" if self.cmd.len() == 2 => {
+                let _ = std::fmt::write(&mut response, format_args!(":{}\r\n", db.pubsub().numpat()));
+            },
+            _ => {
+                let _ = std::fmt::write(&mut response,
+                    format_args!("-ERR unknown subcommand or wrong number of arguments for '{}'. Try PUBSUB HELP.\r\n", subcommand));
+            }
+        }
+        stream.write_all(response.as_bytes())
+    }
+}
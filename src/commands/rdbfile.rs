@@ -1,11 +1,12 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
-use crate::slave::slave;
 use crate::store::db;
-use std::net::TcpStream;
 use std::sync::Arc;
 
 #[allow(dead_code)]
 
+// RDB of a full sync is received and loaded by the slave thread before
+// command processing starts - bulk strings seen here are ignored
 pub struct RDBFile<'a> {
     cmd: &'a Vec<u8>,
     replication_conn: bool,
@@ -18,15 +19,7 @@ impl<'a> RDBFile<'a> {
 }
 
 impl<'a> incoming::CommandHandler for RDBFile<'a> {
-    fn handle(&self, _stream: &mut TcpStream, _db: &Arc<db::DB>) -> std::io::Result<()> {
-        Ok(())
-    }
-
-    fn track_offset(&self, slavecfg: &Option<slave::Config>, _stream: &mut TcpStream, _length: usize) -> std::io::Result<()>{
-        if let Some(cfg) = slavecfg.as_ref() {
-            println!("Started tracking offsets now!!");
-            cfg.synced_in();
-        } 
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
         Ok(())
     }
 }
@@ -1,10 +1,10 @@
+use crate::client::connection::Connection;
 use crate::commands::array;
 use crate::commands::incoming;
 use crate::slave::slave;
 use crate::repl::repl;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 
 #[allow(dead_code)]
@@ -23,7 +23,7 @@ impl<'a> ReplCommand<'a> {
 impl<'a> incoming::CommandHandler for ReplCommand<'a> {
     fn handle(
         &self,
-        _stream: &mut TcpStream,
+        _stream: &mut Connection,
         _db: &Arc<db::DB>,
     ) -> std::io::Result<()> {
 
@@ -37,7 +37,7 @@ impl<'a> incoming::CommandHandler for ReplCommand<'a> {
     // should be done only if this is master node
     fn repl_config(
             &self,
-            stream: &mut TcpStream,
+            stream: &mut Connection,
             replcfg: &Arc<repl::ReplicationConfig>
         ) -> std::io::Result<()> {
             // we should receive these commands only over replication connection
@@ -47,14 +47,14 @@ impl<'a> incoming::CommandHandler for ReplCommand<'a> {
                 println!("Error creating replication node!!: {}", e);
                 return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
             }
-            let peer_addr = format!("{}", stream.peer_addr().unwrap());
+            let peer_addr = stream.peer_addr().unwrap();
             if replcfg.replication_connection(&peer_addr) || self.replication_conn {
                 return Ok(());
             }
             stream.write_all(b"+OK\r\n")
         }
 
-    fn track_offset(&self, slavecfg: &Option<slave::Config>, stream: &mut TcpStream, length: usize) -> std::io::Result<()>{
+    fn track_offset(&self, slavecfg: &Option<slave::Config>, stream: &mut Connection, length: usize) -> std::io::Result<()>{
         let offset;
         if let Some(cfg) = slavecfg.as_ref() {
             offset = cfg.get_offset();
@@ -76,29 +76,34 @@ impl<'a> incoming::CommandHandler for ReplCommand<'a> {
 
 fn parse_repl_options(
     cmd: &Vec<String>,
-    stream: &TcpStream,
+    stream: &Connection,
     replcfg: &Arc<repl::ReplicationConfig>,
 ) -> Result<(), String> {
-    let peer_addr_complete = format!("{}", stream.peer_addr().unwrap());
-    let peer_addr = peer_addr_complete.split(":").collect::<Vec<&str>>();
-    if peer_addr.len() != 2 {
+    let peer_addr_complete = stream.peer_addr().unwrap();
+    // port follows the last colon - IPv6 hosts have colons of their own
+    let Some((peer_host, _port)) = peer_addr_complete.rsplit_once(':') else {
         return Err(format!(
             "Invalid peer address format: {}",
             peer_addr_complete
         ));
-    }
-    println!("peer address: {:?}", peer_addr);
+    };
+    let peer_host = peer_host.trim_start_matches('[').trim_end_matches(']');
+    println!("peer address: {}", peer_addr_complete);
     if let Some(o) = array::get_nth_arg(cmd, 1) {
         if o.contains("listening-port") {
             if let Some(port) = array::get_nth_arg(cmd, 2) {
                 if let Ok(pp) = port.parse::<u16>() {
-                    if let Ok(_) = replcfg.add_node(peer_addr[0], pp, &peer_addr_complete) {
+                    if let Ok(_) = replcfg.add_node(peer_host, pp, &peer_addr_complete) {
                         return Ok(());
                     }
                 }
             }
         } else if o.contains("capa") {
-            println!("its second replconf - we should update slave capabilities");
+            let capabilities = cmd[1..].chunks(2)
+                .filter(|pair| pair.len() == 2 && pair[0] == "capa")
+                .map(|pair| pair[1].clone())
+                .collect::<Vec<String>>();
+            replcfg.add_capabilities(&peer_addr_complete, &capabilities);
             return Ok(());
         } else if o.contains("getack") || o.contains("GETACK") {
             return Ok(());
@@ -1,4 +1,5 @@
 use bytes::BytesMut;
+use std::ops::Deref;
 
 const ARRAY_MARKER: char = '*';
 const SIMPLE_STRING_MARKER: char = '+';
@@ -6,9 +7,93 @@ const INTEGER_MARKER: char = ':';
 const SIMPLE_ERROR_MARKER: char = '-';
 const BULK_STRING_MARKER: char = '$';
 
+// arguments of a command - lowercased for matching names and options, the
+// bytes as sent are kept for values that have to be stored binary safe
+#[derive(Debug, Clone)]
+pub struct Args {
+    args: Vec<String>,
+    raw: Vec<Vec<u8>>,
+}
+
+impl Args {
+    pub fn raw(&self, idx: usize) -> &[u8] {
+        &self.raw[idx]
+    }
+}
+
+impl Deref for Args {
+    type Target = Vec<String>;
+
+    fn deref(&self) -> &Vec<String> {
+        &self.args
+    }
+}
+
+// command as sent over the wire - an array of bulk strings
+pub fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        buf.extend(format!("${}\r\n", arg.len()).into_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf
+}
+
+// length of the complete frames at the start of buf - what follows them is a
+// frame still arriving over the connection. bytes which are not RESP at all
+// are all counted in, for the parser to report
+pub fn complete_len(buf: &[u8]) -> usize {
+    let mut idx = 0;
+    while idx < buf.len() {
+        match frame_end(buf, idx) {
+            Ok(Some(end)) => idx = end,
+            Ok(None) => break,
+            Err(_) => return buf.len(),
+        }
+    }
+    idx
+}
+
+// index right after the frame starting at start, None if it's not all there yet
+// nested arrays only add to the elements still to come, so any depth is fine
+fn frame_end(buf: &[u8], start: usize) -> Result<Option<usize>, String> {
+    let mut idx = start;
+    let mut pending: u64 = 1;
+    while pending > 0 {
+        let Some(pos) = buf[idx..].windows(2).position(|w| w == b"\r\n") else { return Ok(None) };
+        let line_end = idx + pos + 2;
+        let header = || String::from_utf8_lossy(&buf[idx + 1..line_end - 2]).parse::<i64>()
+            .map_err(|_| "invalid length".to_string());
+        pending -= 1;
+        idx = match buf[idx] as char {
+            SIMPLE_STRING_MARKER | SIMPLE_ERROR_MARKER | INTEGER_MARKER => line_end,
+            BULK_STRING_MARKER => {
+                // null bulk string $-1 has no data
+                let len = header()?;
+                if len < 0 {
+                    line_end
+                } else {
+                    let end = line_end + len as usize + 2;
+                    if end > buf.len() {
+                        return Ok(None);
+                    }
+                    end
+                }
+            },
+            ARRAY_MARKER => {
+                pending = pending.saturating_add(header()?.max(0) as u64);
+                line_end
+            },
+            _ => return Err("not a RESP frame".to_string()),
+        };
+    }
+    Ok(Some(idx))
+}
+
 #[derive(Debug)]
 pub enum DataType {
-    Array(Vec<String>, usize, usize),
+    Array(Args, usize, usize),
     SimpleString(String, usize, usize),
     SimpleError(String, usize, usize),
     Integers(i64, usize, usize),
@@ -150,14 +235,11 @@ impl DataType {
         for i in start + 1..=end - 2 {
             num_args = num_args * 10 + (buf[i] - '0' as u8) as usize;
         }
-        let mut result: Vec<String> = Vec::with_capacity(num_args);
+        let mut result = Args { args: Vec::with_capacity(num_args), raw: Vec::with_capacity(num_args) };
         while num_args > 0 {
             let (binary, new_end) = Self::_parse_bulk_string(buf, end + 1)?;
-            if let Ok(cmd) = String::from_utf8(binary) {
-                result.push(cmd.to_lowercase());
-            } else {
-                return Err("Unble to parse string from binary!".to_string());
-            }
+            result.args.push(String::from_utf8_lossy(&binary).to_lowercase());
+            result.raw.push(binary);
             end = new_end;
             num_args -= 1;
         }
@@ -217,6 +299,18 @@ impl DataType {
         }
     }
     
+    // raw bytes of this token as received on the wire
+    pub fn raw<'a>(&self, buf: &'a BytesMut) -> &'a [u8] {
+        match self {
+            DataType::SimpleString(_s, start, end) => &buf[*start..=*end],
+            DataType::SimpleError(_s, start, end) => &buf[*start..=*end],
+            DataType::Integers(_val, start, end) => &buf[*start..=*end],
+            DataType::BulkString(_s, start, end) => &buf[*start..=*end],
+            DataType::Array(_s, start, end) => &buf[*start..=*end],
+            DataType::Invalid(_s) => &[],
+        }
+    }
+
     pub fn len(&self) -> usize {
         match self {
             DataType::SimpleString(_s, start, end) => 1+end-start,
@@ -241,4 +335,29 @@ impl std::fmt::Display for DataType {
             DataType::Invalid(s) => write!(f, "invalid: {:?}", s),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_len_waits_for_whole_frames() {
+        let set = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$-1\r\n";
+        for split in 0..set.len() {
+            assert_eq!(complete_len(&set[..split]), 0);
+        }
+        let mut two = set.to_vec();
+        two.extend_from_slice(b"+OK\r\n:1\r\n*2\r\n$1\r\na");
+        assert_eq!(complete_len(&two), set.len() + 9);
+        assert_eq!(complete_len(b"garbage\r\n"), 9);
+    }
+
+    #[test]
+    fn complete_len_deeply_nested() {
+        let depth = 1_000_000;
+        let mut nested = b"*1\r\n".repeat(depth);
+        assert_eq!(complete_len(&nested), 0);
+        nested.extend_from_slice(b":1\r\n");
+        assert_eq!(complete_len(&nested), nested.len());
+    }
+}
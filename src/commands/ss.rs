@@ -1,3 +1,4 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
 use crate::commands::ping;
 use crate::commands::resp;
@@ -5,11 +6,10 @@ use crate::commands::fullresync;
 use crate::repl::repl;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 use std::io::ErrorKind;
 
-pub fn invalid(stream: &mut TcpStream) -> std::io::Result<()> {
+pub fn invalid(stream: &mut Connection) -> std::io::Result<()> {
     println!("---------- ************* sending invalid command ***********-----------");
     let d = resp::DataType::Invalid("invalid command\r\n".to_string());
     stream.write_all(format!("{}", d).as_bytes())
@@ -28,7 +28,7 @@ impl InvalidCommand {
 impl incoming::CommandHandler for InvalidCommand {
     fn handle(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         _store: &Arc<db::DB>,
     ) -> std::io::Result<()> {
         invalid(stream)
@@ -48,7 +48,7 @@ impl OkResponse {
 impl incoming::CommandHandler for OkResponse {
     fn handle(
         &self,
-        _stream: &mut TcpStream,
+        _stream: &mut Connection,
         _store: &Arc<db::DB>,
     ) -> std::io::Result<()> {
         println!("is it on replication connection: {}, if not master side??", self.replication_conn);
@@ -58,11 +58,11 @@ impl incoming::CommandHandler for OkResponse {
         // should be done only if this is master node
     fn repl_config(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         replcfg: &Arc<repl::ReplicationConfig>
     ) -> std::io::Result<()> {
         if replcfg.num_replicas() > 0 {
-            let peer_addr = format!("{}", stream.peer_addr().unwrap());
+            let peer_addr = stream.peer_addr().unwrap();
             println!("peer address for replication ack: {:?}", peer_addr);
             if let Err(e) = replcfg.replication_acked(&peer_addr, 0) {
                 println!("Error updating ackid from slave node!!, error: {}", e);
@@ -79,14 +79,14 @@ impl incoming::CommandHandler for OkResponse {
 pub fn simple_string_command_handler(
     cmd: &String,
     replication_conn: bool,
-) -> Box<dyn incoming::CommandHandler> 
+) -> Box<dyn incoming::CommandHandler + '_> 
 {
     if cmd.contains("ping") {
         return Box::new(ping::Ping::new(replication_conn));
     } else if cmd.contains("ok") {
         return Box::new(OkResponse::new(replication_conn));
     } else if cmd.contains("fullresync") {
-        return Box::new(fullresync::FullResync::new(replication_conn));
+        return Box::new(fullresync::FullResync::new(cmd, replication_conn));
     }
 
     Box::new(InvalidCommand::new(replication_conn))
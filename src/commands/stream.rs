@@ -1,205 +1,196 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
-use crate::commands::getset;
 use crate::store::db;
+use bytes::BytesMut;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
-use crate::commands::array;
+use std::sync::RwLock;
+use std::sync::mpsc::Sender;
 use crate::store::streams;
 use std::fmt;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-#[allow(dead_code)]
 enum XADDErrors {
-    TimeStampOlder(u128),
-    TimeStampInvalid(u128),
+    TimeStampOlder,
+    TimeStampInvalid,
     InvalidArgs,
 }
 
 impl fmt::Display for XADDErrors {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            XADDErrors::TimeStampOlder(_val) => write!(f, "ERR The ID specified in XADD is equal or smaller than the target stream top item"),
-            XADDErrors::TimeStampInvalid(val) => write!(f, "ERR The ID specified in XADD must be greater than {}-0", val),
-            XADDErrors::InvalidArgs => write!(f, "-invalid arguments"),
+            XADDErrors::TimeStampOlder => write!(f, "ERR The ID specified in XADD is equal or smaller than the target stream top item"),
+            XADDErrors::TimeStampInvalid => write!(f, "ERR The ID specified in XADD must be greater than 0-0"),
+            XADDErrors::InvalidArgs => write!(f, "ERR Invalid stream ID specified as stream command argument"),
         }
     }
 }
 
-#[allow(dead_code)]
+// MAXLEN|MINID [=|~] threshold [LIMIT count] starting at idx - returns the
+// trim and index of the first argument after it
+pub fn parse_trim(cmd: &[String], mut idx: usize) -> Result<(streams::Trim, usize), String> {
+    let strategy = cmd[idx].clone();
+    idx += 1;
+    let mut approx = false;
+    match cmd.get(idx).map(|s| s.as_str()) {
+        Some("~") => { approx = true; idx += 1; },
+        Some("=") => idx += 1,
+        _ => {},
+    }
+    let threshold = match cmd.get(idx) {
+        Some(v) => v,
+        None => return Err("ERR syntax error".to_string()),
+    };
+    let strategy = match strategy.as_str() {
+        "maxlen" => match threshold.parse::<i64>() {
+            Ok(n) if n >= 0 => streams::TrimStrategy::MaxLen(n as usize),
+            Ok(_) => return Err("ERR The MAXLEN argument must be >= 0.".to_string()),
+            Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+        },
+        _ => streams::TrimStrategy::MinId(streams::parse_id(threshold, 0)?),
+    };
+    idx += 1;
+    let mut limit = None;
+    if cmd.get(idx).map(|s| s.as_str()) == Some("limit") {
+        match cmd.get(idx + 1).map(|v| v.parse::<usize>()) {
+            Some(Ok(n)) => limit = Some(n),
+            _ => return Err("ERR value is not an integer or out of range".to_string()),
+        }
+        if !approx {
+            return Err("ERR syntax error, LIMIT cannot be used without the special ~ option".to_string());
+        }
+        idx += 2;
+    }
+    Ok((streams::Trim { strategy, approx, limit }, idx))
+}
 
-#[derive(Debug, Clone)]
+// XADD key [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] id|* field value [field value ...]
+#[derive(Debug)]
+struct AddRequest {
+    nomkstream: bool,
+    trim: Option<streams::Trim>,
+    id_idx: usize,
+}
+
+impl AddRequest {
+    fn parse(cmd: &[String]) -> Result<Self, String> {
+        let mut request = Self { nomkstream: false, trim: None, id_idx: 2 };
+        while request.id_idx < cmd.len() {
+            match cmd[request.id_idx].as_str() {
+                "nomkstream" => {
+                    request.nomkstream = true;
+                    request.id_idx += 1;
+                },
+                "maxlen" | "minid" => {
+                    let (trim, idx) = parse_trim(cmd, request.id_idx)?;
+                    request.trim = Some(trim);
+                    request.id_idx = idx;
+                },
+                _ => break,
+            }
+        }
+        let fields = cmd.len().saturating_sub(request.id_idx + 1);
+        if fields == 0 || !fields.is_multiple_of(2) {
+            return Err("ERR wrong number of arguments for 'xadd' command".to_string());
+        }
+        Ok(request)
+    }
+}
+
+#[derive(Debug)]
 pub struct Stream<'a> {
     cmd: &'a Vec<String>,
     replication_conn: bool,
+    added: RwLock<Option<(usize, String)>>, // id of added entry and its index - replicated in place of *
 }
 
 impl<'a> Stream<'a> {
     pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
-        Self {cmd, replication_conn}
+        Self {cmd, replication_conn, added: RwLock::new(None)}
     }
 
-    fn extract_timestamp(&self) -> Result<(Option<u128>, Option<u64>), String> {
-        if let Some(stamp) = array::get_nth_arg(self.cmd, 2) {
-            let ss = stamp.split('-').collect::<Vec<&str>>();
-            let base: Option<u128>;
-            let seq: Option<u64>;
-            if ss[0] == "*" {
-                base = None;
-            } else {
-                if let Ok(_base) = ss[0].parse::<u128>() {
-                    base = Some(_base);
-                } else {
-                    return Err("Invalid timestamp".to_string());
-                }
-            }
-            
-            if ss.len() >= 2 {
-                if ss[1] == "*" {
-                    seq = None;
-                } else {
-                    if let Ok(_seq) = ss[1].parse::<u64>() {
-                        seq = Some(_seq);
-                    } else {
-                        return Err("Invalid Sequence number".to_string());
-                    }
-                }
-            } else {
-                seq = None;
-            }
-            return Ok((base, seq));
-        }
-        Err("Insufficient number of arguements to XADD command".to_string())
+    // id|* ms-* or ms-seq - ms alone picks the next sequence as well
+    fn extract_timestamp(&self, idx: usize) -> Result<(Option<u128>, Option<u64>), XADDErrors> {
+        let stamp = self.cmd[idx].as_str();
+        if stamp == "*" { return Ok((None, None)); }
+        let (base, seq) = match stamp.split_once('-') {
+            Some((base, seq)) => (base, Some(seq)),
+            None => (stamp, None),
+        };
+        let base = base.parse::<u128>().map_err(|_| XADDErrors::InvalidArgs)?;
+        let seq = match seq {
+            None | Some("*") => None,
+            Some(seq) => Some(seq.parse::<u64>().map_err(|_| XADDErrors::InvalidArgs)?),
+        };
+        Ok((Some(base), seq))
     }
 
-    fn build(&self, existing_stream: Option<&streams::Streams>) -> Result<(u128, u64, Vec<String>, String), XADDErrors> {
-        // XADD stream_key 1526919030474-0 temperature 36 humidity 95
-        // split 1526919030474-0 (time stamp and seq-id)
-        if let Ok((in_timestamp, in_seq)) = self.extract_timestamp() {
-            let timestamp = match in_timestamp {
-                Some(v) => v,
-                _ => {
-                    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
-                }
-            };
-
-            let seq = match in_seq {
-                Some(v) => v,
-                _ => { // we need to find the last sequence number
-                    match existing_stream {
-                        Some(v) => {
-                            let (last_tstamp, last_seq) = v.last_entry_key();
-                            if last_tstamp == timestamp { last_seq+ 1 } else { 0 }
-                        },
-                        None => { if timestamp == 0 { 1 } else { 0 } },
-                    }
-                }
-            };
-
-            // if the stream is empty - only then we reach here
-            if timestamp == 0 && seq == 0 { return Err(XADDErrors::TimeStampInvalid(timestamp)); }
-
-            // gather everything else and build an vector of strings
-            let kvpairs = self.cmd.iter()
-                .skip(3)
-                .fold(Vec::<String>::new(), |mut acc, s| {
-                    acc.push(s.clone()); // create a copy for now
-                    acc
-                });
-
-            return Ok((timestamp, seq, kvpairs, format!("{}-{}", timestamp, seq)));
-        }
-        Err(XADDErrors::InvalidArgs)
+    // id of the new entry - has to be greater than the last id the stream ever had
+    fn build(&self, idx: usize, last: streams::StreamId) -> Result<streams::StreamId, XADDErrors> {
+        let (in_timestamp, in_seq) = self.extract_timestamp(idx)?;
+        let timestamp = match in_timestamp {
+            Some(v) => v,
+            None => streams::now_ms().max(last.0),
+        };
+        let id = match in_seq {
+            Some(seq) => (timestamp, seq),
+            None if timestamp == last.0 => match last.1.checked_add(1) {
+                Some(seq) => (timestamp, seq),
+                None => return Err(XADDErrors::TimeStampOlder),
+            },
+            None => (timestamp, 0),
+        };
+        if id == (0, 0) { return Err(XADDErrors::TimeStampInvalid); }
+        if id <= last { return Err(XADDErrors::TimeStampOlder); }
+        Ok(id)
     }
 
-    fn validate_timetamp(&self, value: &streams::Streams) -> Result<(), XADDErrors> {
-        if let Ok((in_timestamp, in_sequence)) = self.extract_timestamp() {
-            if in_timestamp.is_none() { return Ok(()); }
-            let in_tstamp = in_timestamp.unwrap();
-
-            if in_sequence.is_some() {
-                let in_seq = in_sequence.unwrap();
-                // If the stream is empty, the ID should be greater than 0-0
-                if in_tstamp == 0 && in_seq == 0 { return Err(XADDErrors::TimeStampInvalid(in_tstamp)) }; 
-            }
-            for (tstamp, seq) in value.streams.keys() {
-                if in_tstamp < *tstamp { return Err(XADDErrors::TimeStampOlder(in_tstamp)); }
-                if in_sequence.is_some() {
-                    let in_seq = in_sequence.unwrap();
-                    if in_tstamp <= *tstamp && in_seq <= *seq {
-                            return Err(XADDErrors::TimeStampOlder(in_tstamp));
-                    }
+    fn run(&self, db: &Arc<db::DB>) -> Result<String, String> {
+        let request = AddRequest::parse(self.cmd)?;
+        let key = &self.cmd[1];
+        let kvpairs = self.cmd[request.id_idx + 1..].to_vec();
+        let added = db.xadd(key, !request.nomkstream, |s| {
+            let (timestamp, seq) = self.build(request.id_idx, s.last_entry_key()).map_err(|e| e.to_string())?;
+            s.add(timestamp, seq, kvpairs);
+            let trimmed = request.trim.map(|t| s.trim(&t)).unwrap_or(0);
+            Ok((streams::format_id(&(timestamp, seq)), trimmed))
+        })?;
+        match added {
+            Some((keyid, trimmed)) => {
+                if trimmed > 0 {
+                    db.notify('t', "xtrim", key);
                 }
-            }
-
-            // If the stream is empty, the ID should be greater than 0-0
-            //if in_tstamp == 0 { return Err(XADDErrors::TimeStampInvalid(in_tstamp)); }
+                let response = format!("${}\r\n{}\r\n", keyid.len(), keyid);
+                *self.added.write().unwrap() = Some((request.id_idx, keyid));
+                Ok(response)
+            },
+            None => Ok("$-1\r\n".to_string()), // NOMKSTREAM and no such key
         }
-        Ok(())
     }
-
 }
 
 impl<'a> incoming::CommandHandler for Stream<'a> {
-    fn handle(&self, stream: &mut TcpStream, db: &Arc<db::DB>) -> std::io::Result<()> {
-        let mut response = String::new();
-        if let Some(skey) = array::get_nth_arg(self.cmd, 1) {
-            let mut existing_stream: Option<streams::Streams> = None;
-            if let Some(skey_id) = array::get_nth_arg(self.cmd, 2) {
-                // check if key already exists
-                let mut valid = true;
-                if let Some(existing_key) = db.get(skey) {
-                    match existing_key {
-                        db::KeyValueType::StreamType(value) => {
-                            // found one - lets validate the timestamp and seq
-                            match self.validate_timetamp(&value) {
-                                Ok(()) => {},
-                                Err(e) => {
-                                    valid = false;
-                                    let _ = std::fmt::write(&mut response,
-                                        format_args!("-{}\r\n", e));
-                                }
-                            };
-                            existing_stream = Some(value);
-                        },
-                        _ => {
-                            valid = false;
-                            let _ = std::fmt::write(&mut response,
-                                format_args!("-Invalid Command {}\r\n{}\r\n", skey_id.len(), skey_id));
-                        }
-                    }
-                    // validate
-                } else {
-
-                }
-                // save the key with value
-                if valid {
-                    match self.build(existing_stream.as_ref()) {
-                        Ok((timestamp, seq, kvpairs, keyid)) => {
-                            let options = getset::SetOptions::new();
-                            let db_result = db.xadd(skey.clone(),
-                                db::KeyValueType::StringType("".to_string()), &options, timestamp, seq, kvpairs);
-                            if db_result.is_err() {
-                                println!("Error writing into the DB");
-                                return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                                format!("failed set command: {:?}", self.cmd)));
-                            }
-
-                            let _ = std::fmt::write(&mut response,
-                                format_args!("${}\r\n{}\r\n", keyid.len(), keyid));
-                        },
-                        Err(e) => {
-                            let _ = std::fmt::write(&mut response,
-                                format_args!("-{}\r\n", e));
-                        }
-                    }
-                }
-            }
-        } else {
-            let _ = std::fmt::write(&mut response,
-                format_args!("-invalid command\r\n"));
-        }
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(r) => r,
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
         stream.write_all(response.as_bytes())
     }
 
+    fn replicate(&self, _buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        let (idx, id) = match self.added.read().unwrap().clone() {
+            Some(added) => added,
+            None => return Ok(()),
+        };
+        let mut args = self.cmd.clone();
+        args[0] = "XADD".to_string();
+        args[idx] = id;
+        let mut buf = format!("*{}\r\n", args.len());
+        args.iter().for_each(|a| {
+            let _ = std::fmt::write(&mut buf, format_args!("${}\r\n{}\r\n", a.len(), a));
+        });
+        incoming::send_replication(BytesMut::from(buf.as_bytes()), tx_ch)
+    }
 }
@@ -0,0 +1,586 @@
+use crate::client::connection::Connection;
+use crate::commands::getset;
+use crate::commands::incoming;
+use crate::commands::resp;
+use crate::store::db;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+// strings can not grow past 512MB
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+pub fn bulk(value: &[u8]) -> Vec<u8> {
+    let mut response = format!("${}\r\n", value.len()).into_bytes();
+    response.extend_from_slice(value);
+    response.extend_from_slice(b"\r\n");
+    response
+}
+
+pub fn reply(stream: &mut Connection, replication_conn: bool, result: Result<Vec<u8>, String>) -> std::io::Result<()> {
+    let response = match result {
+        Ok(r) => r,
+        Err(e) => format!("-{}\r\n", e).into_bytes(),
+    };
+    if replication_conn { return Ok(()); }
+    stream.write_all(&response)
+}
+
+pub fn wrong_args(cmd: &[String]) -> String {
+    format!("ERR wrong number of arguments for '{}' command", cmd[0])
+}
+
+// integer the way redis reads it - no sign or leading zeros it would not print back
+pub fn parse_int(value: &[u8]) -> Result<i64, String> {
+    match std::str::from_utf8(value).ok().and_then(|s| s.parse::<i64>().ok()) {
+        Some(n) if n.to_string().as_bytes() == value => Ok(n),
+        _ => Err("ERR value is not an integer or out of range".to_string()),
+    }
+}
+
+pub fn parse_float(value: &[u8]) -> Result<f64, String> {
+    let value = std::str::from_utf8(value).unwrap_or("");
+    match value.parse::<f64>() {
+        Ok(n) if !n.is_nan() && value.trim() == value => Ok(n),
+        _ => Err("ERR value is not a valid float".to_string()),
+    }
+}
+
+// string value of a key, None if it does not exist
+pub fn string_value(value: Option<&db::KeyValueType>) -> Result<Option<Vec<u8>>, String> {
+    match value {
+        Some(v) => v.as_bytes().map(Some).ok_or(db::WRONGTYPE.to_string()),
+        None => Ok(None),
+    }
+}
+
+// INCR key | DECR key | INCRBY key increment | DECRBY key decrement | INCRBYFLOAT key increment
+#[derive(Debug)]
+pub struct Incr<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    result: RwLock<Option<String>>, // new value - INCRBYFLOAT replicates it as SET
+}
+
+impl<'a> Incr<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, result: RwLock::new(None) }
+    }
+
+    fn float(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        let by = parse_float(self.cmd.raw(2))?;
+        let value = db.update(&self.cmd[1], |v| {
+            let current = match v {
+                Some(db::KeyValueType::IntegerType(n)) => *n as f64,
+                v => match string_value(v)? {
+                    Some(s) => parse_float(&s)?,
+                    None => 0.0,
+                },
+            };
+            let value = current + by;
+            if !value.is_finite() {
+                return Err("ERR increment would produce NaN or Infinity".to_string());
+            }
+            let value = value.to_string();
+            Ok((db::Change::Value(db::KeyValueType::string(value.clone().into_bytes())), value))
+        })?;
+        db.notify('$', "incrbyfloat", &self.cmd[1]);
+        *self.result.write().unwrap() = Some(value.clone());
+        Ok(bulk(value.as_bytes()))
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        let name = self.cmd[0].as_str();
+        let arity = if name == "incr" || name == "decr" { 2 } else { 3 };
+        if self.cmd.len() != arity {
+            return Err(wrong_args(self.cmd));
+        }
+        if name == "incrbyfloat" {
+            return self.float(db);
+        }
+        let by = match name {
+            "incr" => 1,
+            "decr" => -1,
+            "incrby" => parse_int(self.cmd[2].as_bytes())?,
+            _ => parse_int(self.cmd[2].as_bytes())?.checked_neg().ok_or("ERR decrement would overflow".to_string())?,
+        };
+        let value = db.update(&self.cmd[1], |v| {
+            let current = match v {
+                Some(db::KeyValueType::IntegerType(n)) => *n,
+                v => match string_value(v)? {
+                    Some(s) => parse_int(&s)?,
+                    None => 0,
+                },
+            };
+            let value = current.checked_add(by).ok_or("ERR increment or decrement would overflow".to_string())?;
+            Ok((db::Change::Value(db::KeyValueType::IntegerType(value)), value))
+        })?;
+        db.notify('$', "incrby", &self.cmd[1]);
+        *self.result.write().unwrap() = Some(value.to_string());
+        Ok(format!(":{}\r\n", value).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Incr<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        let value = match self.result.read().unwrap().clone() {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        if self.cmd[0] != "incrbyfloat" {
+            return incoming::send_replication(buf.clone(), tx_ch);
+        }
+        // float formatting may differ on the replica - send the result instead
+        let mut set = BytesMut::from(&b"*4\r\n$3\r\nSET\r\n"[..]);
+        set.extend_from_slice(&bulk(self.cmd.raw(1)));
+        set.extend_from_slice(&bulk(value.as_bytes()));
+        set.extend_from_slice(b"$7\r\nKEEPTTL\r\n");
+        incoming::send_replication(set, tx_ch)
+    }
+}
+
+// APPEND key value
+#[derive(Debug)]
+pub struct Append<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> Append<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 3 {
+            return Err(wrong_args(self.cmd));
+        }
+        let len = db.update(&self.cmd[1], |v| {
+            let mut value = string_value(v)?.unwrap_or_default();
+            value.extend_from_slice(self.cmd.raw(2));
+            if value.len() > MAX_STRING_LEN {
+                return Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string());
+            }
+            let len = value.len();
+            Ok((db::Change::Value(db::KeyValueType::string(value)), len))
+        })?;
+        db.notify('$', "append", &self.cmd[1]);
+        *self.changed.write().unwrap() = true;
+        Ok(format!(":{}\r\n", len).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for Append<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// STRLEN key
+#[derive(Debug)]
+pub struct StrLen<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> StrLen<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 2 {
+            return Err(wrong_args(self.cmd));
+        }
+        let value = string_value(db.get(&self.cmd[1]).as_ref())?;
+        Ok(format!(":{}\r\n", value.map(|v| v.len()).unwrap_or(0)).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for StrLen<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// GETRANGE key start end - negative offsets count from the end
+#[derive(Debug)]
+pub struct GetRange<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> GetRange<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 4 {
+            return Err(wrong_args(self.cmd));
+        }
+        let mut start = parse_int(self.cmd[2].as_bytes())?;
+        let mut end = parse_int(self.cmd[3].as_bytes())?;
+        let value = string_value(db.get(&self.cmd[1]).as_ref())?.unwrap_or_default();
+        let len = value.len() as i64;
+        if start < 0 && end < 0 && start > end {
+            return Ok(bulk(b""));
+        }
+        if start < 0 { start += len; }
+        if end < 0 { end += len; }
+        start = start.max(0);
+        end = end.max(0).min(len - 1);
+        if len == 0 || start > end {
+            return Ok(bulk(b""));
+        }
+        Ok(bulk(&value[start as usize..=end as usize]))
+    }
+}
+
+impl<'a> incoming::CommandHandler for GetRange<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// SETRANGE key offset value - pads with zero bytes up to offset
+#[derive(Debug)]
+pub struct SetRange<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> SetRange<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 4 {
+            return Err(wrong_args(self.cmd));
+        }
+        let offset = match parse_int(self.cmd[2].as_bytes())? {
+            n if n < 0 => return Err("ERR offset is out of range".to_string()),
+            n => n as usize,
+        };
+        let patch = self.cmd.raw(3);
+        let (len, changed) = db.update(&self.cmd[1], |v| {
+            let current = string_value(v)?;
+            if patch.is_empty() {
+                // nothing to write - not even a missing key gets created
+                return Ok((db::Change::Keep, (current.map(|c| c.len()).unwrap_or(0), false)));
+            }
+            if offset + patch.len() > MAX_STRING_LEN {
+                return Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".to_string());
+            }
+            let mut bytes = current.unwrap_or_default();
+            if bytes.len() < offset + patch.len() {
+                bytes.resize(offset + patch.len(), 0);
+            }
+            bytes[offset..offset + patch.len()].copy_from_slice(patch);
+            let len = bytes.len();
+            Ok((db::Change::Value(db::KeyValueType::string(bytes)), (len, true)))
+        })?;
+        if changed {
+            db.notify('$', "setrange", &self.cmd[1]);
+        }
+        *self.changed.write().unwrap() = changed;
+        Ok(format!(":{}\r\n", len).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for SetRange<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// GETDEL key
+#[derive(Debug)]
+pub struct GetDel<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> GetDel<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 2 {
+            return Err(wrong_args(self.cmd));
+        }
+        let value = db.update(&self.cmd[1], |v| match string_value(v)? {
+            Some(value) => Ok((db::Change::Remove, Some(value))),
+            None => Ok((db::Change::Keep, None)),
+        })?;
+        match value {
+            Some(value) => {
+                db.notify('g', "del", &self.cmd[1]);
+                *self.changed.write().unwrap() = true;
+                Ok(bulk(&value))
+            },
+            None => Ok("$-1\r\n".into()),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for GetDel<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// GETEX key [EX seconds|PX ms|EXAT ts|PXAT ts-ms|PERSIST]
+#[derive(Debug)]
+pub struct GetEx<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> GetEx<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(wrong_args(self.cmd));
+        }
+        // None leaves the expiry alone, Some(None) is PERSIST
+        let expiry = match &self.cmd[2..] {
+            [] => None,
+            [persist] if persist == "persist" => Some(None),
+            [option, value] if matches!(option.as_str(), "ex" | "px" | "exat" | "pxat") => {
+                Some(Some(getset::parse_expiry(option, value, "getex")?))
+            },
+            _ => return Err("ERR syntax error".to_string()),
+        };
+        let value = db.update(&self.cmd[1], |v| {
+            let value = match string_value(v)? {
+                Some(value) => value,
+                None => return Ok((db::Change::Keep, None)),
+            };
+            let change = match expiry {
+                None => db::Change::Keep,
+                Some(None) => db::Change::Expiry(None),
+                Some(Some(getset::Expiry::In(ms))) => db::Change::Expiry(Some(ms)),
+                Some(Some(getset::Expiry::Passed)) => db::Change::Remove,
+            };
+            Ok((change, Some(value)))
+        })?;
+        let value = match value {
+            Some(value) => value,
+            None => return Ok("$-1\r\n".into()),
+        };
+        if let Some(expiry) = expiry {
+            let event = match expiry {
+                None => "persist",
+                Some(getset::Expiry::In(_)) => "expire",
+                Some(getset::Expiry::Passed) => "del",
+            };
+            db.notify('g', event, &self.cmd[1]);
+            *self.changed.write().unwrap() = true;
+        }
+        Ok(bulk(&value))
+    }
+}
+
+impl<'a> incoming::CommandHandler for GetEx<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// MGET key [key ...]
+#[derive(Debug)]
+pub struct MGet<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> MGet<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 2 {
+            return Err(wrong_args(self.cmd));
+        }
+        let values = db.mget(&self.cmd[1..]);
+        let mut response = format!("*{}\r\n", values.len()).into_bytes();
+        values.iter().for_each(|v| match v.as_ref().and_then(|v| v.as_bytes()) {
+            Some(s) => response.extend_from_slice(&bulk(&s)),
+            None => response.extend_from_slice(b"$-1\r\n"), // missing or not a string
+        });
+        Ok(response)
+    }
+}
+
+impl<'a> incoming::CommandHandler for MGet<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+}
+
+// MSET key value [key value ...] | MSETNX key value [key value ...]
+#[derive(Debug)]
+pub struct MSet<'a> {
+    cmd: &'a resp::Args,
+    nx: bool,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> MSet<'a> {
+    pub fn new(cmd: &'a resp::Args, nx: bool, replication_conn: bool) -> Self {
+        Self { cmd, nx, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() < 3 || self.cmd.len().is_multiple_of(2) {
+            return Err(wrong_args(self.cmd));
+        }
+        let pairs = (1..self.cmd.len()).step_by(2)
+            .map(|idx| (self.cmd[idx].clone(), self.cmd.raw(idx + 1).to_vec()))
+            .collect::<Vec<(String, Vec<u8>)>>();
+        let set = db.mset(&pairs, self.nx);
+        *self.changed.write().unwrap() = set;
+        if !self.nx {
+            return Ok("+OK\r\n".into());
+        }
+        Ok(format!(":{}\r\n", set as u8).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for MSet<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// SETNX key value
+#[derive(Debug)]
+pub struct SetNx<'a> {
+    cmd: &'a resp::Args,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> SetNx<'a> {
+    pub fn new(cmd: &'a resp::Args, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 3 {
+            return Err(wrong_args(self.cmd));
+        }
+        let value = db::KeyValueType::string(self.cmd.raw(2).to_vec());
+        let set = db.update(&self.cmd[1], |v| match v {
+            Some(_) => Ok((db::Change::Keep, false)),
+            None => Ok((db::Change::Replace(value, None), true)),
+        })?;
+        if set {
+            db.notify('$', "set", &self.cmd[1]);
+        }
+        *self.changed.write().unwrap() = set;
+        Ok(format!(":{}\r\n", set as u8).into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for SetNx<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
+
+// SETEX key seconds value | PSETEX key ms value
+#[derive(Debug)]
+pub struct SetEx<'a> {
+    cmd: &'a resp::Args,
+    millis: bool,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> SetEx<'a> {
+    pub fn new(cmd: &'a resp::Args, millis: bool, replication_conn: bool) -> Self {
+        Self { cmd, millis, replication_conn, changed: RwLock::new(false) }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<Vec<u8>, String> {
+        if self.cmd.len() != 4 {
+            return Err(wrong_args(self.cmd));
+        }
+        let unit = if self.millis { "px" } else { "ex" };
+        let ms = match getset::parse_expiry(unit, &self.cmd[2], &self.cmd[0])? {
+            getset::Expiry::In(ms) => ms,
+            getset::Expiry::Passed => 0, // only absolute times can be in the past
+        };
+        let value = db::KeyValueType::string(self.cmd.raw(3).to_vec());
+        db.update(&self.cmd[1], |_v| Ok((db::Change::Replace(value, Some(ms)), ())))?;
+        db.notify('$', "set", &self.cmd[1]);
+        db.notify('g', "expire", &self.cmd[1]);
+        *self.changed.write().unwrap() = true;
+        Ok("+OK\r\n".into())
+    }
+}
+
+impl<'a> incoming::CommandHandler for SetEx<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        reply(stream, self.replication_conn, self.run(db))
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
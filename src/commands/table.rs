@@ -0,0 +1,274 @@
+// command table - arity follows redis convention:
+// positive is exact number of arguments (including command name),
+// negative is the minimum number of arguments
+// keys are (first, last, step) argument positions - last -1 is the last argument
+
+// ACL categories
+pub const KEYSPACE: u32 = 1 << 0;
+pub const READ: u32 = 1 << 1;
+pub const WRITE: u32 = 1 << 2;
+pub const SET: u32 = 1 << 3;
+pub const SORTEDSET: u32 = 1 << 4;
+pub const LIST: u32 = 1 << 5;
+pub const HASH: u32 = 1 << 6;
+pub const STRING: u32 = 1 << 7;
+pub const BITMAP: u32 = 1 << 8;
+pub const HYPERLOGLOG: u32 = 1 << 9;
+pub const GEO: u32 = 1 << 10;
+pub const STREAM: u32 = 1 << 11;
+pub const PUBSUB: u32 = 1 << 12;
+pub const ADMIN: u32 = 1 << 13;
+pub const FAST: u32 = 1 << 14;
+pub const SLOW: u32 = 1 << 15;
+pub const BLOCKING: u32 = 1 << 16;
+pub const DANGEROUS: u32 = 1 << 17;
+pub const CONNECTION: u32 = 1 << 18;
+pub const TRANSACTION: u32 = 1 << 19;
+pub const SCRIPTING: u32 = 1 << 20;
+
+pub const CATEGORIES: &[(&str, u32)] = &[
+    ("keyspace", KEYSPACE), ("read", READ), ("write", WRITE), ("set", SET),
+    ("sortedset", SORTEDSET), ("list", LIST), ("hash", HASH), ("string", STRING),
+    ("bitmap", BITMAP), ("hyperloglog", HYPERLOGLOG), ("geo", GEO), ("stream", STREAM),
+    ("pubsub", PUBSUB), ("admin", ADMIN), ("fast", FAST), ("slow", SLOW),
+    ("blocking", BLOCKING), ("dangerous", DANGEROUS), ("connection", CONNECTION),
+    ("transaction", TRANSACTION), ("scripting", SCRIPTING),
+];
+
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub arity: i32,
+    pub categories: u32,
+    keys: (i32, i32, i32),
+}
+
+const fn spec(name: &'static str, arity: i32, categories: u32, keys: (i32, i32, i32)) -> CommandSpec {
+    CommandSpec { name, arity, categories, keys }
+}
+
+// subcommands which differ in categories from their container command
+// permissions are kept per subcommand for these
+const SUBCOMMANDS: &[(&str, &str, u32)] = &[
+    ("acl", "cat", SLOW),
+    ("acl", "deluser", ADMIN|SLOW|DANGEROUS),
+    ("acl", "dryrun", ADMIN|SLOW|DANGEROUS),
+    ("acl", "genpass", SLOW),
+    ("acl", "getuser", ADMIN|SLOW|DANGEROUS),
+    ("acl", "help", SLOW),
+    ("acl", "list", ADMIN|SLOW|DANGEROUS),
+    ("acl", "load", ADMIN|SLOW|DANGEROUS),
+    ("acl", "log", ADMIN|SLOW|DANGEROUS),
+    ("acl", "save", ADMIN|SLOW|DANGEROUS),
+    ("acl", "setuser", ADMIN|SLOW|DANGEROUS),
+    ("acl", "users", ADMIN|SLOW|DANGEROUS),
+    ("acl", "whoami", SLOW),
+    ("config", "get", ADMIN|SLOW|DANGEROUS),
+    ("config", "help", SLOW),
+    ("config", "resetstat", ADMIN|SLOW|DANGEROUS),
+    ("config", "rewrite", ADMIN|SLOW|DANGEROUS),
+    ("config", "set", ADMIN|SLOW|DANGEROUS),
+];
+
+const COMMANDS: &[CommandSpec] = &[
+    spec("ping", -1, FAST|CONNECTION, (0, 0, 0)),
+    spec("echo", 2, FAST|CONNECTION, (0, 0, 0)),
+    spec("info", -1, SLOW|DANGEROUS, (0, 0, 0)),
+    spec("set", -3, WRITE|STRING|SLOW, (1, 1, 1)),
+    spec("get", 2, READ|STRING|FAST, (1, 1, 1)),
+    spec("incr", 2, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("decr", 2, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("incrby", 3, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("decrby", 3, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("incrbyfloat", 3, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("append", 3, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("strlen", 2, READ|STRING|FAST, (1, 1, 1)),
+    spec("getrange", 4, READ|STRING|SLOW, (1, 1, 1)),
+    spec("setrange", 4, WRITE|STRING|SLOW, (1, 1, 1)),
+    spec("getdel", 2, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("getex", -2, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("mget", -2, READ|STRING|FAST, (1, -1, 1)),
+    spec("mset", -3, WRITE|STRING|SLOW, (1, -1, 2)),
+    spec("msetnx", -3, WRITE|STRING|SLOW, (1, -1, 2)),
+    spec("setnx", 3, WRITE|STRING|FAST, (1, 1, 1)),
+    spec("setex", 4, WRITE|STRING|SLOW, (1, 1, 1)),
+    spec("psetex", 4, WRITE|STRING|SLOW, (1, 1, 1)),
+    spec("setbit", 4, WRITE|BITMAP|SLOW, (1, 1, 1)),
+    spec("getbit", 3, READ|BITMAP|FAST, (1, 1, 1)),
+    spec("bitcount", -2, READ|BITMAP|SLOW, (1, 1, 1)),
+    spec("bitpos", -3, READ|BITMAP|SLOW, (1, 1, 1)),
+    spec("bitop", -4, WRITE|BITMAP|SLOW, (2, -1, 1)),
+    spec("bitfield", -2, WRITE|BITMAP|SLOW, (1, 1, 1)),
+    spec("bitfield_ro", -2, READ|BITMAP|FAST, (1, 1, 1)),
+    spec("pfadd", -2, WRITE|HYPERLOGLOG|FAST, (1, 1, 1)),
+    spec("pfcount", -2, READ|HYPERLOGLOG|SLOW, (1, -1, 1)),
+    spec("pfmerge", -2, WRITE|HYPERLOGLOG|SLOW, (1, -1, 1)),
+    spec("geoadd", -5, WRITE|GEO|SLOW, (1, 1, 1)),
+    spec("geodist", -4, READ|GEO|SLOW, (1, 1, 1)),
+    spec("geopos", -2, READ|GEO|SLOW, (1, 1, 1)),
+    spec("geohash", -2, READ|GEO|SLOW, (1, 1, 1)),
+    spec("geosearch", -7, READ|GEO|SLOW, (1, 1, 1)),
+    spec("geosearchstore", -8, WRITE|GEO|SLOW, (1, 2, 1)),
+    spec("replconf", -1, ADMIN|SLOW|DANGEROUS, (0, 0, 0)),
+    spec("psync", -3, ADMIN|SLOW|DANGEROUS, (0, 0, 0)),
+    spec("wait", 3, SLOW|CONNECTION, (0, 0, 0)),
+    spec("config", -2, SLOW, (0, 0, 0)),
+    spec("keys", 2, KEYSPACE|READ|SLOW|DANGEROUS, (0, 0, 0)),
+    spec("scan", -2, KEYSPACE|READ|SLOW, (0, 0, 0)),
+    spec("zscan", -3, READ|SORTEDSET|SLOW, (1, 1, 1)),
+    spec("type", 2, KEYSPACE|READ|FAST, (1, 1, 1)),
+    spec("del", -2, KEYSPACE|WRITE|SLOW, (1, -1, 1)),
+    spec("unlink", -2, KEYSPACE|WRITE|FAST, (1, -1, 1)),
+    spec("exists", -2, KEYSPACE|READ|FAST, (1, -1, 1)),
+    spec("touch", -2, KEYSPACE|READ|FAST, (1, -1, 1)),
+    spec("rename", 3, KEYSPACE|WRITE|SLOW, (1, 2, 1)),
+    spec("renamenx", 3, KEYSPACE|WRITE|FAST, (1, 2, 1)),
+    spec("copy", -3, KEYSPACE|WRITE|SLOW, (1, 2, 1)),
+    spec("randomkey", 1, KEYSPACE|READ|SLOW, (0, 0, 0)),
+    spec("object", -2, KEYSPACE|READ|SLOW, (2, 2, 1)),
+    spec("dump", 2, KEYSPACE|READ|SLOW, (1, 1, 1)),
+    spec("restore", -4, KEYSPACE|WRITE|SLOW|DANGEROUS, (1, 1, 1)),
+    spec("restore-asking", -4, KEYSPACE|WRITE|SLOW|DANGEROUS, (1, 1, 1)),
+    spec("migrate", -6, KEYSPACE|WRITE|SLOW|DANGEROUS, (3, 3, 1)),
+    spec("memory", -2, READ|SLOW, (2, 2, 1)),
+    spec("auth", -2, FAST|CONNECTION, (0, 0, 0)),
+    spec("acl", -2, SLOW, (0, 0, 0)),
+    spec("xadd", -5, WRITE|STREAM|FAST, (1, 1, 1)),
+    spec("xrange", -4, READ|STREAM|SLOW, (1, 1, 1)),
+    spec("xrevrange", -4, READ|STREAM|SLOW, (1, 1, 1)),
+    spec("xlen", 2, READ|STREAM|FAST, (1, 1, 1)),
+    spec("xdel", -3, WRITE|STREAM|FAST, (1, 1, 1)),
+    spec("xtrim", -4, WRITE|STREAM|SLOW, (1, 1, 1)),
+    spec("xsetid", -3, WRITE|STREAM|FAST, (1, 1, 1)),
+    spec("xinfo", -2, READ|STREAM|SLOW, (2, 2, 1)),
+    spec("xread", -4, READ|STREAM|SLOW|BLOCKING, (0, 0, 0)),
+    spec("xgroup", -2, WRITE|STREAM|SLOW, (2, 2, 1)),
+    spec("xreadgroup", -7, WRITE|STREAM|SLOW|BLOCKING, (0, 0, 0)),
+    spec("xack", -4, WRITE|STREAM|FAST, (1, 1, 1)),
+    spec("xpending", -3, READ|STREAM|SLOW, (1, 1, 1)),
+    spec("xclaim", -6, WRITE|STREAM|FAST, (1, 1, 1)),
+    spec("xautoclaim", -6, WRITE|STREAM|FAST, (1, 1, 1)),
+    spec("multi", 1, FAST|TRANSACTION, (0, 0, 0)),
+    spec("exec", 1, SLOW|TRANSACTION, (0, 0, 0)),
+    spec("discard", 1, FAST|TRANSACTION, (0, 0, 0)),
+    spec("watch", -2, FAST|TRANSACTION, (1, -1, 1)),
+    spec("unwatch", 1, FAST|TRANSACTION, (0, 0, 0)),
+    spec("subscribe", -2, PUBSUB|SLOW, (0, 0, 0)),
+    spec("psubscribe", -2, PUBSUB|SLOW, (0, 0, 0)),
+    spec("unsubscribe", -1, PUBSUB|SLOW, (0, 0, 0)),
+    spec("punsubscribe", -1, PUBSUB|SLOW, (0, 0, 0)),
+    spec("ssubscribe", -2, PUBSUB|SLOW, (0, 0, 0)),
+    spec("sunsubscribe", -1, PUBSUB|SLOW, (0, 0, 0)),
+    spec("publish", 3, PUBSUB|FAST, (0, 0, 0)),
+    spec("spublish", 3, PUBSUB|FAST, (0, 0, 0)),
+    spec("pubsub", -2, PUBSUB|SLOW, (0, 0, 0)),
+];
+
+pub fn lookup(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+pub fn commands() -> &'static [CommandSpec] {
+    COMMANDS
+}
+
+// write commands are refused when master can not honor min-replicas-to-write
+// and over maxmemory
+pub fn is_write(args: &[String]) -> bool {
+    args.first().and_then(|name| lookup(name)).is_some_and(|spec| spec.categories(args) & WRITE != 0)
+}
+
+// subcommands of a container command along with their categories
+pub fn subcommands(name: &str) -> impl Iterator<Item = (&'static str, u32)> + '_ {
+    SUBCOMMANDS.iter().filter(move |(c, _s, _cat)| *c == name).map(|(_c, s, cat)| (*s, *cat))
+}
+
+impl CommandSpec {
+    // name as reported by ACL - container|subcommand where permissions are per subcommand
+    pub fn full_name(&self, args: &[String]) -> String {
+        match args.get(1) {
+            Some(sub) if subcommands(self.name).any(|(s, _c)| s == sub) => format!("{}|{}", self.name, sub),
+            _ => self.name.to_string(),
+        }
+    }
+
+    // categories of the command as called - subcommand ones where they differ
+    pub fn categories(&self, args: &[String]) -> u32 {
+        args.get(1)
+            .and_then(|sub| subcommands(self.name).find(|(s, _cat)| s == sub))
+            .map(|(_s, cat)| cat)
+            .unwrap_or(self.categories)
+    }
+
+    // key arguments of the command as called along with (read, write) access to them
+    // by default as the categories say, commands taking data from one key into another
+    // only read the source and write the destination
+    pub fn key_access<'a>(&self, args: &'a [String]) -> Vec<(&'a String, bool, bool)> {
+        let categories = self.categories(args);
+        let (read, write) = (categories & READ != 0, categories & WRITE != 0);
+        self.keys(args).into_iter().enumerate().map(|(idx, key)| {
+            let (read, write) = match (self.name, idx) {
+                // source is deleted as well
+                ("rename" | "renamenx", 0) => (true, true),
+                ("copy", 0) => (true, false),
+                ("rename" | "renamenx" | "copy", _) => (false, true),
+                ("geosearchstore" | "bitop", 0) => (false, true),
+                ("geosearchstore" | "bitop", _) => (true, false),
+                // destination is merged into
+                ("pfmerge", 0) => (true, true),
+                ("pfmerge", _) => (true, false),
+                _ => (read, write),
+            };
+            (key, read, write)
+        }).collect()
+    }
+
+    // key arguments of the command as called
+    pub fn keys<'a>(&self, args: &'a [String]) -> Vec<&'a String> {
+        match self.name {
+            // keys and ids follow STREAMS in equal numbers
+            "xread" | "xreadgroup" => {
+                let Some(idx) = args.iter().position(|a| a == "streams") else { return vec![] };
+                let rest = &args[idx + 1..];
+                rest[..rest.len() / 2].iter().collect()
+            },
+            "migrate" if args.get(3).is_some_and(|k| k.is_empty()) => {
+                let Some(idx) = args.iter().position(|a| a == "keys") else { return vec![] };
+                args[idx + 1..].iter().collect()
+            },
+            "memory" if args.get(1).is_none_or(|s| s != "usage") => vec![],
+            _ => {
+                let (first, last, step) = self.keys;
+                if first == 0 || args.len() <= first as usize {
+                    return vec![];
+                }
+                let last = if last < 0 { args.len() as i32 + last } else { last.min(args.len() as i32 - 1) };
+                (first..=last).step_by(step as usize).map(|i| &args[i as usize]).collect()
+            },
+        }
+    }
+}
+
+// validates command exists and has right number of arguments
+// returns the error to send back otherwise
+pub fn validate(args: &[String]) -> Result<&'static CommandSpec, String> {
+    let name = match args.first() {
+        Some(n) => n,
+        None => return Err("ERR empty command".to_string()),
+    };
+    let spec = match lookup(name) {
+        Some(s) => s,
+        None => {
+            let mut args_str = String::new();
+            args.iter().skip(1).for_each(|a| {
+                let _ = std::fmt::write(&mut args_str, format_args!("'{}' ", a));
+            });
+            return Err(format!("ERR unknown command '{}', with args beginning with: {}", name, args_str));
+        }
+    };
+    let num_args = args.len() as i32;
+    if (spec.arity > 0 && num_args != spec.arity) || (spec.arity < 0 && num_args < -spec.arity) {
+        return Err(format!("ERR wrong number of arguments for '{}' command", name));
+    }
+    Ok(spec)
+}
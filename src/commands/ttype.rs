@@ -1,7 +1,7 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 use crate::commands::array;
 
@@ -19,20 +19,12 @@ impl<'a> TType<'a> {
 }
 
 impl<'a> incoming::CommandHandler for TType<'a> {
-    fn handle(&self, stream: &mut TcpStream, db: &Arc<db::DB>) -> std::io::Result<()> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
         let mut response = String::new();
         if let Some(key) = array::get_nth_arg(self.cmd, 1) {
             if let Some(value) = db.get(key) {
-                match value {
-                    db::KeyValueType::StringType(_s) => {
-                        let _ = std::fmt::write(&mut response,
-                            format_args!("+string\r\n"));
-                    },
-                    db::KeyValueType::StreamType(_s) => {
-                        let _ = std::fmt::write(&mut response,
-                            format_args!("+stream\r\n"));
-                    },
-                };
+                let _ = std::fmt::write(&mut response,
+                    format_args!("+{}\r\n", value.type_name()));
             } else {
                 let _ = std::fmt::write(&mut response,
                     format_args!("+none\r\n"));
@@ -1,7 +1,7 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
 use crate::repl::repl;
 use std::thread;
@@ -21,18 +21,21 @@ impl<'a> Wait<'a> {
 }
 
 impl<'a> incoming::CommandHandler for Wait<'a> {
-    fn handle(&self, _stream: &mut TcpStream, _db: &Arc<db::DB>) -> std::io::Result<()> {
+    fn handle(&self, _stream: &mut Connection, _db: &Arc<db::DB>) -> std::io::Result<()> {
         Ok(())
     }
 
     fn repl_config(
         &self,
-        stream: &mut TcpStream,
+        stream: &mut Connection,
         replcfg: &Arc<repl::ReplicationConfig>
     ) -> std::io::Result<()> {
         if self.replication_conn {
             return Ok(());
         }
+        if !replcfg.role_master() {
+            return stream.write_all(b"-ERR WAIT cannot be used with replica instances\r\n");
+        }
         let mut replicas = 0;
         let mut millis = 0;
 
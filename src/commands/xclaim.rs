@@ -0,0 +1,242 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::xreadgroup;
+use crate::store::db;
+use crate::store::streams;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+fn integer<T: std::str::FromStr>(value: Option<&String>) -> Result<T, String> {
+    match value.map(|v| v.parse::<T>()) {
+        Some(Ok(v)) => Ok(v),
+        _ => Err("ERR value is not an integer or out of range".to_string()),
+    }
+}
+
+fn ids_resp(ids: &[streams::StreamId]) -> String {
+    let mut response = format!("*{}\r\n", ids.len());
+    ids.iter().map(streams::format_id).for_each(|id| {
+        let _ = std::fmt::write(&mut response, format_args!("${}\r\n{}\r\n", id.len(), id));
+    });
+    response
+}
+
+fn command(args: &[String]) -> BytesMut {
+    let mut buf = format!("*{}\r\n", args.len());
+    args.iter().for_each(|a| {
+        let _ = std::fmt::write(&mut buf, format_args!("${}\r\n{}\r\n", a.len(), a));
+    });
+    BytesMut::from(buf.as_bytes())
+}
+
+// claimed entries are replicated as forced XCLAIMs carrying the final state
+// so that replicas do not depend on their own clock for idle times
+fn claim_propagation(s: &streams::Streams, key: &str, group: &str, consumer: &str,
+    claimed: &[streams::Delivered]) -> Vec<BytesMut> {
+    let group_state = match s.group(key, group) {
+        Ok(g) => g,
+        Err(_) => return vec![],
+    };
+    claimed.iter().filter_map(|(id, _fields)| {
+        let p = group_state.pending.get(id)?;
+        Some(command(&[
+            "XCLAIM".to_string(), key.to_string(), group.to_string(), consumer.to_string(), "0".to_string(),
+            streams::format_id(id), "TIME".to_string(), p.delivery_time.to_string(),
+            "RETRYCOUNT".to_string(), p.delivery_count.to_string(), "FORCE".to_string(), "JUSTID".to_string(),
+            "LASTID".to_string(), streams::format_id(&group_state.last_delivered),
+        ]))
+    }).collect()
+}
+
+// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-ms]
+//        [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+#[derive(Debug)]
+pub struct XClaim<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    propagate: RwLock<Vec<BytesMut>>,
+}
+
+impl<'a> XClaim<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            propagate: RwLock::new(vec![]),
+        }
+    }
+
+    fn parse(&self) -> Result<(u128, Vec<streams::StreamId>, streams::ClaimOptions), String> {
+        if self.cmd.len() < 6 {
+            return Err("ERR wrong number of arguments for 'xclaim' command".to_string());
+        }
+        let min_idle = integer::<u128>(self.cmd.get(4))?;
+        let mut idx = 5;
+        let mut ids = vec![];
+        while idx < self.cmd.len() {
+            match streams::parse_id(&self.cmd[idx], 0) {
+                Ok(id) => ids.push(id),
+                Err(_) => break, // options start here
+            }
+            idx += 1;
+        }
+        let mut options = streams::ClaimOptions::default();
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "idle" => {
+                    options.idle = Some(integer(self.cmd.get(idx + 1))?);
+                    idx += 1;
+                },
+                "time" => {
+                    options.time = Some(integer(self.cmd.get(idx + 1))?);
+                    idx += 1;
+                },
+                "retrycount" => {
+                    options.retry_count = Some(integer(self.cmd.get(idx + 1))?);
+                    idx += 1;
+                },
+                "lastid" => {
+                    match self.cmd.get(idx + 1) {
+                        Some(v) => options.last_id = Some(streams::parse_id(v, 0)?),
+                        None => return Err("ERR syntax error".to_string()),
+                    }
+                    idx += 1;
+                },
+                "force" => options.force = true,
+                "justid" => options.justid = true,
+                _ => return Err(format!("ERR Unrecognized XCLAIM option '{}'", self.cmd[idx])),
+            }
+            idx += 1;
+        }
+        Ok((min_idle, ids, options))
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<String, String> {
+        let (min_idle, ids, options) = self.parse()?;
+        let (key, group, consumer) = (&self.cmd[1], &self.cmd[2], &self.cmd[3]);
+        let (claimed, propagate) = db.update_stream(key, false, |s| match s {
+            Some(s) => {
+                let claimed = s.claim(key, group, consumer, min_idle, &ids, &options)?;
+                let propagate = claim_propagation(s, key, group, consumer, &claimed);
+                Ok((claimed, propagate))
+            },
+            None => Err(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group)),
+        })?;
+        *self.propagate.write().unwrap() = propagate;
+        if options.justid {
+            let ids = claimed.iter().map(|(id, _f)| *id).collect::<Vec<streams::StreamId>>();
+            return Ok(ids_resp(&ids));
+        }
+        Ok(xreadgroup::entries_resp(&claimed))
+    }
+}
+
+impl<'a> incoming::CommandHandler for XClaim<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(r) => r,
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn replicate(&self, _buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        for buf in self.propagate.write().unwrap().drain(..) {
+            incoming::send_replication(buf, tx_ch)?;
+        }
+        Ok(())
+    }
+}
+
+// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+#[derive(Debug)]
+pub struct XAutoClaim<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    propagate: RwLock<Vec<BytesMut>>,
+}
+
+impl<'a> XAutoClaim<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            propagate: RwLock::new(vec![]),
+        }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<String, String> {
+        if self.cmd.len() < 6 {
+            return Err("ERR wrong number of arguments for 'xautoclaim' command".to_string());
+        }
+        let (key, group, consumer) = (&self.cmd[1], &self.cmd[2], &self.cmd[3]);
+        let min_idle = integer::<u128>(self.cmd.get(4))?;
+        let start = match self.cmd[5].as_str() {
+            "-" => (0, 0),
+            v => streams::parse_id(v, 0)?,
+        };
+        let mut count = 100;
+        let mut justid = false;
+        let mut idx = 6;
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "count" => {
+                    count = integer::<usize>(self.cmd.get(idx + 1))?;
+                    if count == 0 {
+                        return Err("ERR COUNT must be > 0".to_string());
+                    }
+                    idx += 1;
+                },
+                "justid" => justid = true,
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 1;
+        }
+        let (next, claimed, deleted, propagate) = db.update_stream(key, false, |s| match s {
+            Some(s) => {
+                let (next, claimed, deleted) = s.autoclaim(key, group, consumer, min_idle, start, count, justid)?;
+                let mut propagate = claim_propagation(s, key, group, consumer, &claimed);
+                if !deleted.is_empty() {
+                    // gone from the stream - dropped from the PEL on replicas as well
+                    let mut args = vec!["XACK".to_string(), key.to_string(), group.to_string()];
+                    deleted.iter().for_each(|id| args.push(streams::format_id(id)));
+                    propagate.push(command(&args));
+                }
+                Ok((next, claimed, deleted, propagate))
+            },
+            None => Err(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group)),
+        })?;
+        *self.propagate.write().unwrap() = propagate;
+        let next = streams::format_id(&next);
+        let entries = if justid {
+            ids_resp(&claimed.iter().map(|(id, _f)| *id).collect::<Vec<streams::StreamId>>())
+        } else {
+            xreadgroup::entries_resp(&claimed)
+        };
+        Ok(format!("*3\r\n${}\r\n{}\r\n{}{}", next.len(), next, entries, ids_resp(&deleted)))
+    }
+}
+
+impl<'a> incoming::CommandHandler for XAutoClaim<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(r) => r,
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn replicate(&self, _buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        for buf in self.propagate.write().unwrap().drain(..) {
+            incoming::send_replication(buf, tx_ch)?;
+        }
+        Ok(())
+    }
+}
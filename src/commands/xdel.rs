@@ -0,0 +1,66 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use crate::store::streams;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+// XDEL key id [id ...]
+#[derive(Debug)]
+pub struct XDel<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    deleted: RwLock<usize>,
+}
+
+impl<'a> XDel<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            deleted: RwLock::new(0),
+        }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<usize, String> {
+        if self.cmd.len() < 3 {
+            return Err("ERR wrong number of arguments for 'xdel' command".to_string());
+        }
+        let ids = self.cmd[2..].iter()
+            .map(|id| streams::parse_id(id, 0))
+            .collect::<Result<Vec<streams::StreamId>, String>>()?;
+        db.update_stream(&self.cmd[1], false, |s| match s {
+            Some(s) => Ok(s.delete(&ids)),
+            None => Ok(0),
+        })
+    }
+}
+
+impl<'a> incoming::CommandHandler for XDel<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(n) => {
+                if n > 0 {
+                    db.notify('t', "xdel", &self.cmd[1]);
+                }
+                *self.deleted.write().unwrap() = n;
+                format!(":{}\r\n", n)
+            },
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || *self.deleted.read().unwrap() == 0 { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
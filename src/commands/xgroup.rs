@@ -0,0 +1,137 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use crate::store::streams;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+const NO_KEY: &str = "ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+// XGROUP CREATE key group id|$ [MKSTREAM] [ENTRIESREAD n]
+// XGROUP SETID key group id|$ [ENTRIESREAD n]
+// XGROUP DESTROY key group
+// XGROUP CREATECONSUMER key group consumer
+// XGROUP DELCONSUMER key group consumer
+#[derive(Debug)]
+pub struct XGroup<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>, // only successful changes are replicated
+}
+
+impl<'a> XGroup<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            changed: RwLock::new(false),
+        }
+    }
+
+    // id or $ followed by options
+    fn parse_id_options(&self) -> Result<(Option<streams::StreamId>, bool, Option<u64>), String> {
+        let id = match self.cmd[4].as_str() {
+            "$" => None,
+            v => Some(streams::parse_id(v, 0)?),
+        };
+        let mut mkstream = false;
+        let mut entries_read = None;
+        let mut idx = 5;
+        while idx < self.cmd.len() {
+            match self.cmd[idx].as_str() {
+                "mkstream" if self.cmd[1] == "create" => mkstream = true,
+                "entriesread" if idx + 1 < self.cmd.len() => {
+                    match self.cmd[idx + 1].parse::<u64>() {
+                        Ok(n) => entries_read = Some(n),
+                        Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                    }
+                    idx += 1;
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 1;
+        }
+        Ok((id, mkstream, entries_read))
+    }
+
+    // returns the reply and event to notify
+    fn run(&self, db: &Arc<db::DB>) -> Result<(String, Option<&'static str>), String> {
+        let subcommand = self.cmd.get(1).map(|s| s.as_str()).unwrap_or("");
+        let arity_error = || Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try XGROUP HELP.", subcommand));
+        let key = match self.cmd.get(2) {
+            Some(k) => k,
+            None => return arity_error(),
+        };
+        match subcommand {
+            "create" if self.cmd.len() >= 5 => {
+                let (id, mkstream, entries_read) = self.parse_id_options()?;
+                db.update_stream(key, mkstream, |s| match s {
+                    Some(s) => s.create_group(&self.cmd[3], id, entries_read),
+                    None => Err(NO_KEY.to_string()),
+                })?;
+                Ok(("+OK\r\n".to_string(), Some("xgroup-create")))
+            },
+            "setid" if self.cmd.len() >= 5 => {
+                let (id, _mkstream, entries_read) = self.parse_id_options()?;
+                db.update_stream(key, false, |s| match s {
+                    Some(s) => s.set_group_id(key, &self.cmd[3], id, entries_read),
+                    None => Err(NO_KEY.to_string()),
+                })?;
+                Ok(("+OK\r\n".to_string(), Some("xgroup-setid")))
+            },
+            "destroy" if self.cmd.len() == 4 => {
+                let destroyed = db.update_stream(key, false, |s| match s {
+                    Some(s) => Ok(s.destroy_group(&self.cmd[3])),
+                    None => Err(NO_KEY.to_string()),
+                })?;
+                if destroyed {
+                    return Ok((":1\r\n".to_string(), Some("xgroup-destroy")));
+                }
+                Ok((":0\r\n".to_string(), None))
+            },
+            "createconsumer" if self.cmd.len() == 5 => {
+                let created = db.update_stream(key, false, |s| match s {
+                    Some(s) => s.create_consumer(key, &self.cmd[3], &self.cmd[4]),
+                    None => Err(NO_KEY.to_string()),
+                })?;
+                if created {
+                    return Ok((":1\r\n".to_string(), Some("xgroup-createconsumer")));
+                }
+                Ok((":0\r\n".to_string(), None))
+            },
+            "delconsumer" if self.cmd.len() == 5 => {
+                let pending = db.update_stream(key, false, |s| match s {
+                    Some(s) => s.delete_consumer(key, &self.cmd[3], &self.cmd[4]),
+                    None => Err(NO_KEY.to_string()),
+                })?;
+                Ok((format!(":{}\r\n", pending), Some("xgroup-delconsumer")))
+            },
+            _ => arity_error(),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for XGroup<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok((response, event)) => {
+                if let Some(event) = event {
+                    db.notify('t', event, &self.cmd[2]);
+                    *self.changed.write().unwrap() = true;
+                }
+                response
+            },
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
@@ -0,0 +1,207 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use crate::store::streams;
+use std::io::Write;
+use std::sync::Arc;
+
+const HELP: [&str; 8] = [
+    "XINFO <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "CONSUMERS <key> <groupname>",
+    "    Show consumers of <groupname>.",
+    "GROUPS <key>",
+    "    Show the stream consumer groups.",
+    "STREAM <key> [FULL [COUNT <count>]",
+    "    Show information about the stream.",
+    "HELP",
+];
+
+fn bulk(s: &str) -> String {
+    format!("${}\r\n{}\r\n", s.len(), s)
+}
+
+fn id_bulk(id: &streams::StreamId) -> String {
+    bulk(&streams::format_id(id))
+}
+
+fn int_or_nil(value: Option<u64>) -> String {
+    match value {
+        Some(n) => format!(":{}\r\n", n),
+        None => "$-1\r\n".to_string(),
+    }
+}
+
+// flat field/value map of RESP2
+fn map(fields: &[(&str, String)]) -> String {
+    let mut response = format!("*{}\r\n", fields.len() * 2);
+    fields.iter().for_each(|(name, value)| {
+        response.push_str(&bulk(name));
+        response.push_str(value);
+    });
+    response
+}
+
+fn entry(id: &streams::StreamId, fields: &[String]) -> String {
+    let mut response = format!("*2\r\n{}*{}\r\n", id_bulk(id), fields.len());
+    fields.iter().for_each(|f| response.push_str(&bulk(f)));
+    response
+}
+
+fn entry_or_nil(s: &streams::Streams, id: Option<streams::StreamId>) -> String {
+    match id.and_then(|id| s.entries.get(&id).map(|fields| entry(&id, &fields))) {
+        Some(e) => e,
+        None => "$-1\r\n".to_string(),
+    }
+}
+
+// fields shared by the summary and the FULL form
+fn stream_header(s: &streams::Streams) -> Vec<(&'static str, String)> {
+    let (keys, nodes) = s.radix_stats();
+    vec![
+        ("length", format!(":{}\r\n", s.len())),
+        ("radix-tree-keys", format!(":{}\r\n", keys)),
+        ("radix-tree-nodes", format!(":{}\r\n", nodes)),
+        ("last-generated-id", id_bulk(&s.last_entry_key())),
+        ("max-deleted-entry-id", id_bulk(&s.max_deleted_id())),
+        ("entries-added", format!(":{}\r\n", s.entries_added())),
+        ("recorded-first-entry-id", id_bulk(&s.first_id().unwrap_or((0, 0)))),
+    ]
+}
+
+fn stream_summary(s: &streams::Streams) -> String {
+    let mut fields = stream_header(s);
+    fields.push(("groups", format!(":{}\r\n", s.groups.len())));
+    fields.push(("first-entry", entry_or_nil(s, s.first_id())));
+    fields.push(("last-entry", entry_or_nil(s, s.entries.last_id())));
+    map(&fields)
+}
+
+// entries, PELs and consumers up to count each - 0 shows everything
+fn stream_full(s: &streams::Streams, count: usize) -> String {
+    let count = if count == 0 { usize::MAX } else { count };
+    let mut fields = stream_header(s);
+    let entries = s.entries.range((0, 0), streams::MAX_ID).take(count).map(|(id, f)| entry(&id, &f)).collect::<Vec<String>>();
+    fields.push(("entries", format!("*{}\r\n{}", entries.len(), entries.concat())));
+    let groups = s.groups.iter().map(|(name, g)| {
+        let pending = g.pending.iter().take(count).map(|(id, p)| {
+            format!("*4\r\n{}{}:{}\r\n:{}\r\n", id_bulk(id), bulk(&p.consumer), p.delivery_time, p.delivery_count)
+        }).collect::<Vec<String>>();
+        let consumers = g.consumers.iter().map(|(cname, c)| {
+            let own = g.pending.iter().filter(|(_id, p)| p.consumer == *cname);
+            let own_pending = own.clone().take(count).map(|(id, p)| {
+                format!("*3\r\n{}:{}\r\n:{}\r\n", id_bulk(id), p.delivery_time, p.delivery_count)
+            }).collect::<Vec<String>>();
+            map(&[
+                ("name", bulk(cname)),
+                ("seen-time", format!(":{}\r\n", c.seen_time)),
+                ("active-time", format!(":{}\r\n", c.active_time.map(|t| t as i128).unwrap_or(-1))),
+                ("pel-count", format!(":{}\r\n", own.count())),
+                ("pending", format!("*{}\r\n{}", own_pending.len(), own_pending.concat())),
+            ])
+        }).collect::<Vec<String>>();
+        map(&[
+            ("name", bulk(name)),
+            ("last-delivered-id", id_bulk(&g.last_delivered)),
+            ("entries-read", int_or_nil(g.entries_read)),
+            ("lag", int_or_nil(s.lag(g))),
+            ("pel-count", format!(":{}\r\n", g.pending.len())),
+            ("pending", format!("*{}\r\n{}", pending.len(), pending.concat())),
+            ("consumers", format!("*{}\r\n{}", consumers.len(), consumers.concat())),
+        ])
+    }).collect::<Vec<String>>();
+    fields.push(("groups", format!("*{}\r\n{}", groups.len(), groups.concat())));
+    map(&fields)
+}
+
+fn groups(s: &streams::Streams) -> String {
+    let groups = s.groups.iter().map(|(name, g)| map(&[
+        ("name", bulk(name)),
+        ("consumers", format!(":{}\r\n", g.consumers.len())),
+        ("pending", format!(":{}\r\n", g.pending.len())),
+        ("last-delivered-id", id_bulk(&g.last_delivered)),
+        ("entries-read", int_or_nil(g.entries_read)),
+        ("lag", int_or_nil(s.lag(g))),
+    ])).collect::<Vec<String>>();
+    format!("*{}\r\n{}", groups.len(), groups.concat())
+}
+
+fn consumers(key: &str, s: &streams::Streams, group: &str) -> Result<String, String> {
+    let g = s.group(key, group)?;
+    let now = streams::now_ms();
+    let consumers = g.consumers.iter().map(|(name, c)| {
+        let inactive = match c.active_time {
+            Some(t) => now.saturating_sub(t) as i128,
+            None => -1,
+        };
+        map(&[
+            ("name", bulk(name)),
+            ("pending", format!(":{}\r\n", g.pending.values().filter(|p| p.consumer == *name).count())),
+            ("idle", format!(":{}\r\n", now.saturating_sub(c.seen_time))),
+            ("inactive", format!(":{}\r\n", inactive)),
+        ])
+    }).collect::<Vec<String>>();
+    Ok(format!("*{}\r\n{}", consumers.len(), consumers.concat()))
+}
+
+// XINFO STREAM key [FULL [COUNT count]]
+// XINFO GROUPS key
+// XINFO CONSUMERS key group
+// XINFO HELP
+#[derive(Debug)]
+pub struct XInfo<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> XInfo<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {cmd, replication_conn}
+    }
+
+    fn stream(&self, db: &Arc<db::DB>, key: &str) -> Result<streams::Streams, String> {
+        match db.get(key) {
+            Some(db::KeyValueType::StreamType(s)) => Ok(s),
+            Some(_) => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            None => Err("ERR no such key".to_string()),
+        }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<String, String> {
+        let subcommand = self.cmd.get(1).map(|s| s.as_str()).unwrap_or("");
+        let args = &self.cmd[2.min(self.cmd.len())..];
+        match (subcommand, args) {
+            ("help", []) => {
+                let mut response = format!("*{}\r\n", HELP.len());
+                HELP.iter().for_each(|line| response.push_str(&format!("+{}\r\n", line)));
+                Ok(response)
+            },
+            ("stream", [key]) => Ok(stream_summary(&self.stream(db, key)?)),
+            ("stream", [key, full, options @ ..]) if full == "full" => {
+                let count = match options {
+                    [] => 10,
+                    [option, n] if option == "count" => match n.parse::<i64>() {
+                        Ok(n) => n.max(0) as usize,
+                        Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                    },
+                    _ => return Err("ERR syntax error".to_string()),
+                };
+                Ok(stream_full(&self.stream(db, key)?, count))
+            },
+            ("stream", [_key, ..]) => Err("ERR syntax error".to_string()),
+            ("groups", [key]) => Ok(groups(&self.stream(db, key)?)),
+            ("consumers", [key, group]) => consumers(key, &self.stream(db, key)?, group),
+            _ => Err(format!("ERR unknown subcommand or wrong number of arguments for '{}'. Try XINFO HELP.", subcommand)),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for XInfo<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(r) => r,
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+}
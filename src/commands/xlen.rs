@@ -0,0 +1,34 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use std::io::Write;
+use std::sync::Arc;
+
+// XLEN key
+#[derive(Debug)]
+pub struct XLen<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> XLen<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {cmd, replication_conn}
+    }
+}
+
+impl<'a> incoming::CommandHandler for XLen<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        if self.cmd.len() != 2 {
+            if self.replication_conn { return Ok(()); }
+            return stream.write_all(b"-ERR wrong number of arguments for 'xlen' command\r\n");
+        }
+        let response = match db.get(&self.cmd[1]) {
+            Some(db::KeyValueType::StreamType(s)) => format!(":{}\r\n", s.len()),
+            Some(_) => "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n".to_string(),
+            None => ":0\r\n".to_string(),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+}
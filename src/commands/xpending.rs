@@ -0,0 +1,101 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use crate::store::streams;
+use std::io::Write;
+use std::sync::Arc;
+
+fn bulk(s: &str) -> String {
+    format!("${}\r\n{}\r\n", s.len(), s)
+}
+
+// XPENDING key group [[IDLE min-idle] start end count [consumer]]
+#[derive(Debug, Clone)]
+pub struct XPending<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+}
+
+impl<'a> XPending<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self { cmd, replication_conn }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<String, String> {
+        if self.cmd.len() < 3 {
+            return Err("ERR wrong number of arguments for 'xpending' command".to_string());
+        }
+        let (key, group) = (&self.cmd[1], &self.cmd[2]);
+        let value = match db.get(key) {
+            Some(db::KeyValueType::StreamType(s)) => s,
+            Some(_) => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+            None => return Err(format!("NOGROUP No such key '{}' or consumer group '{}'", key, group)),
+        };
+        let group = value.group(key, group)?;
+        if self.cmd.len() == 3 {
+            return Ok(summary(group));
+        }
+        let mut idx = 3;
+        let mut min_idle = 0;
+        if self.cmd[idx] == "idle" {
+            min_idle = match self.cmd.get(idx + 1).map(|v| v.parse::<u128>()) {
+                Some(Ok(v)) => v,
+                _ => return Err("ERR value is not an integer or out of range".to_string()),
+            };
+            idx += 2;
+        }
+        let args = &self.cmd[idx..];
+        if args.len() < 3 || args.len() > 4 {
+            return Err("ERR syntax error".to_string());
+        }
+        let start = streams::parse_range_id(&args[0], true)?;
+        let end = streams::parse_range_id(&args[1], false)?;
+        let count = match args[2].parse::<i64>() {
+            Ok(c) => c.max(0) as usize,
+            Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+        };
+        let consumer = args.get(3);
+        let now = streams::now_ms();
+        let mut response = String::new();
+        let mut n = 0;
+        if start <= end {
+            for (id, p) in group.pending.range(start..=end)
+                .filter(|(_id, p)| consumer.map(|c| *c == p.consumer).unwrap_or(true))
+                .filter(|(_id, p)| now.saturating_sub(p.delivery_time) >= min_idle)
+                .take(count) {
+                let _ = std::fmt::write(&mut response, format_args!("*4\r\n{}{}:{}\r\n:{}\r\n",
+                    bulk(&streams::format_id(id)), bulk(&p.consumer), now.saturating_sub(p.delivery_time), p.delivery_count));
+                n += 1;
+            }
+        }
+        Ok(format!("*{}\r\n{}", n, response))
+    }
+}
+
+// count, smallest and largest pending id and pending count per consumer
+fn summary(group: &streams::ConsumerGroup) -> String {
+    if group.pending.is_empty() {
+        return "*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n".to_string();
+    }
+    let first = group.pending.keys().next().map(streams::format_id).unwrap_or_default();
+    let last = group.pending.keys().last().map(streams::format_id).unwrap_or_default();
+    let mut consumers = std::collections::BTreeMap::<&str, usize>::new();
+    group.pending.values().for_each(|p| *consumers.entry(p.consumer.as_str()).or_default() += 1);
+    let mut response = format!("*4\r\n:{}\r\n{}{}*{}\r\n", group.pending.len(), bulk(&first), bulk(&last), consumers.len());
+    for (name, count) in consumers {
+        let count = count.to_string();
+        let _ = std::fmt::write(&mut response, format_args!("*2\r\n{}{}", bulk(name), bulk(&count)));
+    }
+    response
+}
+
+impl<'a> incoming::CommandHandler for XPending<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        let response = match self.run(db) {
+            Ok(r) => r,
+            Err(e) => format!("-{}\r\n", e),
+        };
+        stream.write_all(response.as_bytes())
+    }
+}
@@ -1,40 +1,102 @@
+use crate::client::connection::Connection;
 use crate::commands::incoming;
+use crate::commands::xrange;
 use crate::store::db;
 use std::io::Write;
-use std::net::TcpStream;
 use std::sync::Arc;
-use crate::commands::array;
 use crate::store::streams;
-use std::sync::RwLock;
 use std::time::{Instant, Duration};
 use std::thread;
 
+// XREAD [COUNT count] [BLOCK ms] STREAMS key [key ...] id|$ [id|$ ...]
 #[derive(Debug, Clone)]
-struct KeyOptions {
-    key: String,
-    placeholder: String,
-    timestamp: u128,
-    seq: u64,
+struct ReadRequest {
+    count: Option<usize>,
+    block: Option<u64>,
+    keys: Vec<(String, Option<streams::StreamId>)>, // None for $ - entries added from now on
 }
 
-impl KeyOptions {
-    fn new(key: String) -> Self {
-        Self {
-            key,
-            placeholder: "".to_string(),
-            timestamp: 0,
-            seq: 0,
+impl ReadRequest {
+    fn parse(cmd: &[String]) -> Result<Self, String> {
+        let mut request = Self { count: None, block: None, keys: vec![] };
+        let mut idx = 1;
+        while idx < cmd.len() {
+            match cmd[idx].as_str() {
+                "count" | "block" if idx + 1 < cmd.len() => {
+                    let value = match cmd[idx + 1].parse::<i64>() {
+                        Ok(v) => v.max(0) as u64,
+                        Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                    };
+                    if cmd[idx] == "count" {
+                        request.count = Some(value as usize);
+                    } else {
+                        request.block = Some(value);
+                    }
+                    idx += 2;
+                },
+                "streams" => {
+                    let rest = &cmd[idx + 1..];
+                    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                        return Err("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.".to_string());
+                    }
+                    let (keys, ids) = rest.split_at(rest.len() / 2);
+                    for (key, id) in keys.iter().zip(ids.iter()) {
+                        let id = match id.as_str() {
+                            "$" => None,
+                            v => Some(streams::parse_id(v, 0)?),
+                        };
+                        request.keys.push((key.clone(), id));
+                    }
+                    return Ok(request);
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
         }
+        Err("ERR syntax error".to_string())
+    }
+
+    // replaces $ with the last id of each stream at the time of the call
+    fn resolve(&mut self, db: &Arc<db::DB>) {
+        for (key, id) in self.keys.iter_mut() {
+            if id.is_none() {
+                *id = match db.get(key) {
+                    Some(db::KeyValueType::StreamType(s)) => Some(s.last_entry_key()),
+                    _ => Some((0, 0)),
+                };
+            }
+        }
+    }
+
+    // entries after the id of every key, None if none of the streams has any
+    fn read(&self, db: &Arc<db::DB>) -> Result<Option<String>, String> {
+        let count = self.count.filter(|n| *n > 0).unwrap_or(usize::MAX);
+        let mut found = vec![];
+        for (key, id) in self.keys.iter() {
+            let s = match db.get(key) {
+                Some(db::KeyValueType::StreamType(s)) => s,
+                Some(_) => return Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()),
+                None => continue,
+            };
+            let after = match streams::next_id(id.unwrap_or(s.last_entry_key())) {
+                Some(after) => after,
+                None => continue,
+            };
+            let mut entries = s.entries.range(after, streams::MAX_ID).take(count).peekable();
+            if entries.peek().is_some() {
+                found.push(format!("*2\r\n${}\r\n{}\r\n{}", key.len(), key, xrange::entries_resp(entries)));
+            }
+        }
+        if found.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!("*{}\r\n{}", found.len(), found.concat())))
     }
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub struct XRead<'a> {
     cmd: &'a Vec<String>,
     replication_conn: bool,
-    keys: RwLock<Vec<KeyOptions>>,
-    block: RwLock<Option<u64>>, // block for how long in seconds
 }
 
 impl<'a> XRead<'a> {
@@ -42,237 +104,57 @@ impl<'a> XRead<'a> {
         Self {
             cmd,
             replication_conn,
-            keys: RwLock::new(vec![]),
-            block: RwLock::new(None),
         }
     }
-
-    fn parse_options(&self) -> Result<(), String> {
-        // XREAD streams stream_key 0-0
-        // XREAD streams stream_key other_stream_key 0-0 0-1
-        // XREAD block 1000 streams some_key 1526985054069-0
-
-        // get all the keys!
-        let mut keys = self.keys.write().unwrap();
-        let mut idx: usize = 1; // start of block or stream name
-        let mut keyidx: usize = 0;
-        let mut block = self.block.write().unwrap();
-        loop {
-            if let Some(v) = array::get_nth_arg(self.cmd, idx) {
-                match v.as_str() {
-                    "streams" => {
-                        // do nothing for now - ideally should validate
-                    },
-                    "block" => {
-                        // read blocking interval
-                        match array::get_nth_arg(self.cmd, idx+1) {
-                            Some(b) => {
-                                if let Ok(bi) = b.parse::<u64>() {
-                                    *block = Some(bi);
-                                } else {
-                                    return Err("Invalid blocking interval number to xread command (key)".to_string());
-                                }
-                                idx += 1;
-                            },
-                            None => {
-                                // invalid - return error
-                                return Err("No time interval provided to blocking xread command".to_string());
-                            }
-                        }
-                    },
-                    _ => {
-                        if v == "-" {  
-                            keys[keyidx].timestamp = u128::MIN;
-                            keyidx += 1;
-                        } else if v == "$" {
-                            keys[keyidx].placeholder = "$".to_string();
-                            keyidx += 1;
-                        } else {
-                            // see if this is complete key
-                            if v.contains("-") {
-                                let ss = v.split('-').collect::<Vec<&str>>();
-                                if let Ok(_base) = ss[0].parse::<u128>() {
-                                    keys[keyidx].timestamp = _base;
-                                } else {
-                                    return Err("Invalid timestamp to xread command".to_string());
-                                }
-                                if ss.len() >= 2 {
-                                    if let Ok(_seq) = ss[1].parse::<u64>() {
-                                        keys[keyidx].seq = _seq;
-                                    } else {
-                                        return Err("Invalid Sequence number to xread command (key)".to_string());
-                                    }
-                                }
-                                keyidx += 1;
-                            } else {
-                                if let Ok(_base) = v.parse::<u128>() {
-                                    keys[keyidx].timestamp = _base;
-                                    keyidx += 1;
-                                } else { // its a key
-                                    keys.push(KeyOptions::new(v.clone()));
-                                }
-                            }
-                        }
-                    }
-                }
-                idx += 1;
-            } else {
-                break;
-            }
-        }
-        Ok(())
-    }
-
-    // update_options
-    //
-    // fills in the replacement for "$" in the timestamp - only new ones
-    fn update_options(&self, key: &str, stream: &streams::Streams) {
-        let mut keys = self.keys.write().unwrap();
-        for i in 0..keys.len() {
-            if keys[i].key == key {
-                if keys[i].placeholder == "$" {
-                    match stream.streams.iter().last() {
-                        Some((k, _)) => {
-                            keys[i].timestamp = k.0;
-                            keys[i].seq = k.1;
-                        },
-                        None => {
-                            keys[i].timestamp = u128::MIN;
-                            keys[i].seq = u64::MIN;
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    fn build_response(&self, stream: &streams::Streams, idx: usize) -> Result<String, String> {
-        let keys = self.keys.read().unwrap();
-        build_response_internal(stream, &keys[idx].key, keys[idx].timestamp, keys[idx].seq)
-    }
 }
 
 impl<'a> incoming::CommandHandler for XRead<'a> {
-    fn handle(&self, stream: &mut TcpStream, db: &Arc<db::DB>) -> std::io::Result<()> {
-        let mut response = String::new();
-        if let Ok(_) = self.parse_options() {
-            // check if key already exists
-            let num_keys = self.keys.read().unwrap().len();
-            let mut i_responses = Vec::with_capacity(num_keys);
-            for i in 0..num_keys {
-                let k = self.keys.read().unwrap()[i].key.clone();
-                if let Some(existing_key) = db.get(&k) {
-                    match existing_key {
-                        db::KeyValueType::StreamType(value) => {
-                            self.update_options(&k, &value);    // populate replacements for $ if any
-                            // found one - lets validate the timestamp and seq
-                            match self.build_response(&value, i) {
-                                Ok(res) => {
-                                    i_responses.push(res);
-                                },
-                                Err(_e) => { // possibly blocking read command
-                                    let block = *self.block.read().unwrap();
-                                    if let Some(interval) = block {
-                                        let db_clone = Arc::clone(&db);
-                                        let stream_cloned: TcpStream;
-                                        match stream.try_clone() {
-                                            Ok(c) => stream_cloned = c,
-                                            Err(e) => {
-                                                return stream.write_all(format!("-Unable to clone stream for xread block: {}\r\n", e).as_bytes());
-                                            }
-                                        };                                        
-                                        let timestamp = self.keys.read().unwrap()[i].timestamp;
-                                        let cmd_seq = self.keys.read().unwrap()[i].seq;
-                                        let _ = thread::spawn(move || 
-                                            blocking_xread_thread(db_clone, k, stream_cloned, interval,
-                                                timestamp, cmd_seq));
-                                        // thread will handle the response - this CLI is returning
-                                        return Ok(());
-                                    };
-                                    return stream.write_all(b"-1\r\n");
-                                }
-                            }
-                        },
-                        db::KeyValueType::StringType(_) => {
-                            return stream.write_all(b"-Wrong type of key for xread command\r\n");
-                        }
-                    };
-                } else {
-                    return stream.write_all(b"-invalid stream key - does not exist (xread command)\r\n");
-                }
-            }
-            let _ = std::fmt::write(&mut response, format_args!("*{}\r\n", num_keys));
-            for i in 0..num_keys {
-                let _ = std::fmt::write(&mut response,
-                    format_args!("{}", i_responses[i]));
-            }
-        } else {
-            let _ = std::fmt::write(&mut response,
-                format_args!("-invalid command\r\n"));
-        }
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let mut request = match ReadRequest::parse(self.cmd) {
+            Ok(r) => r,
+            Err(e) => return stream.write_all(format!("-{}\r\n", e).as_bytes()),
+        };
+        request.resolve(db);
+        let response = match request.read(db) {
+            Ok(Some(response)) => response,
+            Ok(None) if request.block.is_some() && !self.replication_conn => {
+                let stream_cloned = stream.try_clone()?;
+                let db_clone = Arc::clone(db);
+                // thread will handle the response - this CLI is returning
+                let _ = thread::spawn(move || blocking_xread_thread(db_clone, request, stream_cloned));
+                return Ok(());
+            },
+            Ok(None) => "*-1\r\n".to_string(),
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
         stream.write_all(response.as_bytes())
     }
 }
 
-
-// build_response_internal
-//
-// puts together response to xread command
-fn build_response_internal(stream: &streams::Streams, key: &str, timestamp: u128, cmd_seq: u64) -> Result<String, String> {
-    let (count, response) = stream.streams.iter()
-        .filter(|((ts, seq), _value)| (*ts == timestamp && *seq > cmd_seq) || *ts > timestamp)
-        .fold((0, String::new()), |(count, mut acc), ((ts, seq), value)| {
-            let field = format!("{}-{}", ts, seq);
-            let _ = std::fmt::write(&mut acc, format_args!("*2\r\n${}\r\n{}\r\n", field.len(), field));
-            // format the internal array (string)
-            let _ = std::fmt::write(&mut acc, format_args!("*{}\r\n", value.len()));
-            value.iter().for_each(|s| {
-                let _ = std::fmt::write(&mut acc, format_args!("${}\r\n{}\r\n", s.len(), s));
-            });
-            (count+1, acc)
-        });
-    // no entries found
-    if count == 0 {
-        return Err("$-1\r\n".to_string());
-    }
-    Ok(format!("*{}\r\n${}\r\n{}\r\n*{}\r\n{}", count+1, key.len(), key, count, response))
-}
-
-// blocking_read_thread
+// blocking_xread_thread
 //
-// thread that blocks until given timeout 
-pub fn blocking_xread_thread(db: Arc<db::DB>, key: String, mut stream: TcpStream, timeout: u64, timestamp: u128, cmd_seq: u64) {
+// waits until any of the streams gets new entries or timeout
+fn blocking_xread_thread(db: Arc<db::DB>, request: ReadRequest, mut stream: Connection) {
     // TODO - make it channel receiver - publisher sends a message when a new key is added
     let sleep_duration = Duration::from_millis(100); // check every 100 milliseconds
+    let timeout = request.block.unwrap_or(0);
     let wait_time = Duration::from_millis(timeout);
     let now = Instant::now();
-    let mut responded = false;
 
     while now.elapsed() < wait_time || timeout == 0 {
         thread::sleep(sleep_duration);
-
-        // check DB if additional entries are added
-        if let Some(existing_key) = db.get(&key) {
-            match existing_key {
-                db::KeyValueType::StreamType(value) => {
-                    // found one - lets validate the timestamp and seq
-                    match build_response_internal(&value, &key, timestamp, cmd_seq) {
-                        Ok(res) => {
-                            let res = format!("*1\r\n{}", res);
-                            let _ = stream.write_all(res.as_bytes());
-                            responded = true;
-                            break;
-                        },
-                        Err(_e) => {},
-                    };
-                },
-                _ => {},
-            };
-        };
-    }
-
-    if !responded {
-       let _ = stream.write_all(b"$-1\r\n");
+        match request.read(&db) {
+            Ok(Some(response)) => {
+                let _ = stream.write_all(response.as_bytes());
+                return;
+            },
+            Ok(None) => {},
+            Err(e) => {
+                let _ = stream.write_all(format!("-{}\r\n", e).as_bytes());
+                return;
+            }
+        }
     }
-    //println!("xread blocking thread exiting........");
-}
\ No newline at end of file
+    let _ = stream.write_all(b"*-1\r\n");
+}
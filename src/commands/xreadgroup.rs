@@ -0,0 +1,256 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use crate::store::streams;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// formats entries as returned by XREADGROUP/XCLAIM - deleted entries have nil fields
+pub fn entries_resp(entries: &[streams::Delivered]) -> String {
+    let mut response = format!("*{}\r\n", entries.len());
+    for (id, fields) in entries {
+        let id = streams::format_id(id);
+        let _ = std::fmt::write(&mut response, format_args!("*2\r\n${}\r\n{}\r\n", id.len(), id));
+        match fields {
+            Some(fields) => {
+                let _ = std::fmt::write(&mut response, format_args!("*{}\r\n", fields.len()));
+                fields.iter().for_each(|f| {
+                    let _ = std::fmt::write(&mut response, format_args!("${}\r\n{}\r\n", f.len(), f));
+                });
+            },
+            None => response.push_str("*-1\r\n"),
+        }
+    }
+    response
+}
+
+// XREADGROUP GROUP group consumer [COUNT n] [BLOCK ms] [NOACK] STREAMS key [key ...] id [id ...]
+#[derive(Debug, Clone)]
+struct ReadGroupRequest {
+    group: String,
+    consumer: String,
+    count: Option<usize>,
+    block: Option<u64>,
+    noack: bool,
+    keys: Vec<(String, streams::ReadFrom)>,
+}
+
+impl ReadGroupRequest {
+    fn parse(cmd: &[String]) -> Result<Self, String> {
+        if cmd.len() < 7 || cmd[1] != "group" {
+            return Err("ERR syntax error".to_string());
+        }
+        let mut request = Self {
+            group: cmd[2].clone(),
+            consumer: cmd[3].clone(),
+            count: None,
+            block: None,
+            noack: false,
+            keys: vec![],
+        };
+        let mut idx = 4;
+        while idx < cmd.len() {
+            match cmd[idx].as_str() {
+                "count" | "block" if idx + 1 < cmd.len() => {
+                    let value = match cmd[idx + 1].parse::<u64>() {
+                        Ok(v) => v,
+                        Err(_) => return Err("ERR value is not an integer or out of range".to_string()),
+                    };
+                    if cmd[idx] == "count" {
+                        request.count = Some(value as usize);
+                    } else {
+                        request.block = Some(value);
+                    }
+                    idx += 2;
+                },
+                "noack" => {
+                    request.noack = true;
+                    idx += 1;
+                },
+                "streams" => {
+                    let rest = &cmd[idx + 1..];
+                    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                        return Err("ERR Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.".to_string());
+                    }
+                    let (keys, ids) = rest.split_at(rest.len() / 2);
+                    for (key, id) in keys.iter().zip(ids.iter()) {
+                        let from = match id.as_str() {
+                            ">" => streams::ReadFrom::New,
+                            v => streams::ReadFrom::History(streams::parse_id(v, 0)?),
+                        };
+                        request.keys.push((key.clone(), from));
+                    }
+                    return Ok(request);
+                },
+                _ => return Err("ERR syntax error".to_string()),
+            }
+        }
+        Err("ERR syntax error".to_string())
+    }
+
+    // only reads of new entries wait for data
+    fn may_block(&self) -> bool {
+        self.block.is_some() && self.keys.iter().all(|(_k, from)| matches!(from, streams::ReadFrom::New))
+    }
+
+    // non blocking equivalent of this read - replicas run it at the same point of the stream
+    fn to_command(&self) -> BytesMut {
+        let mut args = vec!["XREADGROUP".to_string(), "GROUP".to_string(), self.group.clone(), self.consumer.clone()];
+        if let Some(count) = self.count {
+            args.push("COUNT".to_string());
+            args.push(count.to_string());
+        }
+        if self.noack {
+            args.push("NOACK".to_string());
+        }
+        args.push("STREAMS".to_string());
+        self.keys.iter().for_each(|(k, _from)| args.push(k.clone()));
+        self.keys.iter().for_each(|(_k, from)| args.push(match from {
+            streams::ReadFrom::New => ">".to_string(),
+            streams::ReadFrom::History(id) => streams::format_id(id),
+        }));
+        let mut buf = format!("*{}\r\n", args.len());
+        args.iter().for_each(|a| {
+            let _ = std::fmt::write(&mut buf, format_args!("${}\r\n{}\r\n", a.len(), a));
+        });
+        BytesMut::from(buf.as_bytes())
+    }
+
+    // reads every key, returns None if nothing was delivered to a blocking read
+    fn read(&self, db: &Arc<db::DB>) -> Result<Option<String>, String> {
+        let mut delivered = vec![];
+        for (key, from) in self.keys.iter() {
+            let (entries, created) = db.update_stream(key, false, |s| match s {
+                Some(s) => s.read_group(key, &self.group, &self.consumer, *from, self.count, self.noack),
+                None => Err(format!("NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                    key, self.group)),
+            })?;
+            if created {
+                db.notify('t', "xgroup-createconsumer", key);
+            }
+            // new entries only list streams that got some
+            if !entries.is_empty() || matches!(from, streams::ReadFrom::History(_)) {
+                delivered.push((key, entries));
+            }
+        }
+        if delivered.is_empty() {
+            return Ok(None);
+        }
+        let mut response = format!("*{}\r\n", delivered.len());
+        for (key, entries) in delivered {
+            let _ = std::fmt::write(&mut response,
+                format_args!("*2\r\n${}\r\n{}\r\n{}", key.len(), key, entries_resp(&entries)));
+        }
+        Ok(Some(response))
+    }
+}
+
+// request waiting for new entries
+struct Blocked {
+    request: ReadGroupRequest,
+    stream: Connection,
+    db: Arc<db::DB>,
+}
+
+pub struct XReadGroup<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    request: RwLock<Option<ReadGroupRequest>>, // replicated once read is done
+    blocked: RwLock<Option<Blocked>>,
+}
+
+impl<'a> XReadGroup<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            request: RwLock::new(None),
+            blocked: RwLock::new(None),
+        }
+    }
+}
+
+impl<'a> incoming::CommandHandler for XReadGroup<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let request = match ReadGroupRequest::parse(self.cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                if self.replication_conn { return Ok(()); }
+                return stream.write_all(format!("-{}\r\n", e).as_bytes());
+            }
+        };
+        let response = match request.read(db) {
+            Ok(Some(response)) => response,
+            Ok(None) if request.may_block() && !self.replication_conn => {
+                let blocked = Blocked { request: request.clone(), stream: stream.try_clone()?, db: Arc::clone(db) };
+                *self.request.write().unwrap() = Some(request);
+                if db.role_master() {
+                    // waits for replicate to get the replication channel
+                    *self.blocked.write().unwrap() = Some(blocked);
+                } else {
+                    let _ = thread::spawn(move || blocking_xreadgroup_thread(blocked, None));
+                }
+                return Ok(());
+            },
+            Ok(None) => "*-1\r\n".to_string(),
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if !response.starts_with('-') {
+            *self.request.write().unwrap() = Some(request);
+        }
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn replicate(&self, _buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn { return Ok(()); }
+        let request = self.request.read().unwrap().clone();
+        if let Some(request) = request {
+            // consumer may have been created even if nothing was read
+            incoming::send_replication(request.to_command(), tx_ch)?;
+        }
+        if let Some(blocked) = self.blocked.write().unwrap().take() {
+            let tx = tx_ch.clone();
+            let _ = thread::spawn(move || blocking_xreadgroup_thread(blocked, Some(tx)));
+        }
+        Ok(())
+    }
+}
+
+// blocking_xreadgroup_thread
+//
+// waits for new entries until timeout, the read and its replication happen as one step
+fn blocking_xreadgroup_thread(blocked: Blocked, tx: Option<Sender<BytesMut>>) {
+    let Blocked { request, mut stream, db } = blocked;
+    let sleep_duration = Duration::from_millis(100); // check every 100 milliseconds
+    let timeout = request.block.unwrap_or(0);
+    let wait_time = Duration::from_millis(timeout);
+    let now = Instant::now();
+
+    while now.elapsed() < wait_time || timeout == 0 {
+        thread::sleep(sleep_duration);
+        let guard = db.command_guard();
+        match request.read(&db) {
+            Ok(Some(response)) => {
+                if let Some(tx) = &tx {
+                    let _ = tx.send(request.to_command());
+                }
+                drop(guard);
+                let _ = stream.write_all(response.as_bytes());
+                return;
+            },
+            Ok(None) => {},
+            Err(e) => {
+                drop(guard);
+                let _ = stream.write_all(format!("-{}\r\n", e).as_bytes());
+                return;
+            }
+        }
+    }
+    let _ = stream.write_all(b"*-1\r\n");
+}
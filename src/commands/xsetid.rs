@@ -0,0 +1,76 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::store::db;
+use crate::store::streams;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+// XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]
+#[derive(Debug)]
+pub struct XSetId<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    changed: RwLock<bool>,
+}
+
+impl<'a> XSetId<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            changed: RwLock::new(false),
+        }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<(), String> {
+        if self.cmd.len() < 3 {
+            return Err("ERR wrong number of arguments for 'xsetid' command".to_string());
+        }
+        let id = streams::parse_id(&self.cmd[2], 0)?;
+        let mut entries_added = None;
+        let mut max_deleted_id = None;
+        let mut idx = 3;
+        while idx < self.cmd.len() {
+            let value = match self.cmd.get(idx + 1) {
+                Some(v) => v,
+                None => return Err("ERR syntax error".to_string()),
+            };
+            match self.cmd[idx].as_str() {
+                "entriesadded" => match value.parse::<u64>() {
+                    Ok(n) => entries_added = Some(n),
+                    Err(_) => return Err("ERR entries_added must be positive".to_string()),
+                },
+                "maxdeletedid" => max_deleted_id = Some(streams::parse_id(value, 0)?),
+                _ => return Err("ERR syntax error".to_string()),
+            }
+            idx += 2;
+        }
+        db.update_stream(&self.cmd[1], false, |s| match s {
+            Some(s) => s.set_id(id, entries_added, max_deleted_id),
+            None => Err("ERR no such key".to_string()),
+        })
+    }
+}
+
+impl<'a> incoming::CommandHandler for XSetId<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(()) => {
+                db.notify('t', "xsetid", &self.cmd[1]);
+                *self.changed.write().unwrap() = true;
+                "+OK\r\n".to_string()
+            },
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || !*self.changed.read().unwrap() { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
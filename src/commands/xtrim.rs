@@ -0,0 +1,70 @@
+use crate::client::connection::Connection;
+use crate::commands::incoming;
+use crate::commands::stream;
+use crate::store::db;
+use bytes::BytesMut;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+// XTRIM key MAXLEN|MINID [=|~] threshold [LIMIT count]
+#[derive(Debug)]
+pub struct XTrim<'a> {
+    cmd: &'a Vec<String>,
+    replication_conn: bool,
+    trimmed: RwLock<usize>,
+}
+
+impl<'a> XTrim<'a> {
+    pub fn new(cmd: &'a Vec<String>, replication_conn: bool) -> Self {
+        Self {
+            cmd,
+            replication_conn,
+            trimmed: RwLock::new(0),
+        }
+    }
+
+    fn run(&self, db: &Arc<db::DB>) -> Result<usize, String> {
+        if self.cmd.len() < 4 {
+            return Err("ERR wrong number of arguments for 'xtrim' command".to_string());
+        }
+        if !matches!(self.cmd[2].as_str(), "maxlen" | "minid") {
+            return Err("ERR syntax error".to_string());
+        }
+        let (trim, idx) = stream::parse_trim(self.cmd, 2)?;
+        if idx != self.cmd.len() {
+            return Err("ERR syntax error".to_string());
+        }
+        db.update_stream(&self.cmd[1], false, |s| match s {
+            Some(s) => Ok(s.trim(&trim)),
+            None => Ok(0),
+        })
+    }
+}
+
+impl<'a> incoming::CommandHandler for XTrim<'a> {
+    fn handle(&self, stream: &mut Connection, db: &Arc<db::DB>) -> std::io::Result<()> {
+        let response = match self.run(db) {
+            Ok(n) => {
+                if n > 0 {
+                    db.notify('t', "xtrim", &self.cmd[1]);
+                }
+                *self.trimmed.write().unwrap() = n;
+                format!(":{}\r\n", n)
+            },
+            Err(e) => format!("-{}\r\n", e),
+        };
+        if self.replication_conn { return Ok(()); }
+        stream.write_all(response.as_bytes())
+    }
+
+    fn deny_oom(&self) -> bool {
+        false
+    }
+
+    fn replicate(&self, buf: &BytesMut, tx_ch: &Sender<BytesMut>) -> std::io::Result<()> {
+        if self.replication_conn || *self.trimmed.read().unwrap() == 0 { return Ok(()); }
+        incoming::send_replication(buf.clone(), tx_ch)
+    }
+}
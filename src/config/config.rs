@@ -0,0 +1,450 @@
+// server configuration - every parameter with its default, how its value is
+// checked and if it can change at runtime. values come from the defaults, then
+// the redis.conf file, then the command line, and later from CONFIG SET
+use crate::pubsub::pubsub;
+use crate::utils::utils;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+
+const REWRITE_MARKER: &str = "# Generated by CONFIG REWRITE";
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Memory(u64),
+    Str(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", if *b { "yes" } else { "no" }),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Memory(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+enum Kind {
+    Bool,
+    Int(i64, i64), // inclusive range
+    Memory,        // bytes, with k/kb/m/mb/g/gb units
+    Enum(&'static [&'static str]),
+    Str,
+    // checked and normalized by its own function, which also gets the current value
+    Custom(fn(&str, &str) -> Result<String, String>),
+}
+
+impl Kind {
+    fn parse(&self, value: &str, current: &Value) -> Result<Value, String> {
+        match self {
+            Kind::Bool => match value.to_lowercase().as_str() {
+                "yes" => Ok(Value::Bool(true)),
+                "no" => Ok(Value::Bool(false)),
+                _ => Err("argument must be 'yes' or 'no'".to_string()),
+            },
+            Kind::Int(min, max) => {
+                let n = value.parse::<i64>().map_err(|_| "argument couldn't be parsed into an integer".to_string())?;
+                if n < *min || n > *max {
+                    return Err(format!("argument must be between {} and {} inclusive", min, max));
+                }
+                Ok(Value::Int(n))
+            },
+            Kind::Memory => utils::parse_memory(value).map(Value::Memory)
+                .map_err(|_| "argument must be a memory value".to_string()),
+            Kind::Enum(names) => names.iter().find(|n| n.eq_ignore_ascii_case(value))
+                .map(|n| Value::Str(n.to_string()))
+                .ok_or_else(|| format!("argument(s) must be one of the following: {}", names.join(", "))),
+            Kind::Str => Ok(Value::Str(value.to_string())),
+            Kind::Custom(f) => f(value, &current.to_string()).map(Value::Str),
+        }
+    }
+}
+
+struct Param {
+    name: &'static str,
+    alias: Option<&'static str>,
+    default: &'static str,
+    kind: Kind,
+    mutable: bool,
+    words: bool, // value is a list of space separated arguments
+}
+
+const MUTABLE: bool = true;
+const IMMUTABLE: bool = false;
+
+const fn param(name: &'static str, default: &'static str, kind: Kind, mutable: bool) -> Param {
+    Param { name, alias: None, default, kind, mutable, words: false }
+}
+
+impl Param {
+    const fn alias(self, alias: &'static str) -> Param {
+        Param { alias: Some(alias), ..self }
+    }
+
+    const fn words(self) -> Param {
+        Param { words: true, ..self }
+    }
+
+    fn names(&self) -> impl Iterator<Item = &'static str> {
+        std::iter::once(self.name).chain(self.alias)
+    }
+
+    fn default_value(&self) -> Value {
+        let default = Value::Str(self.default.to_string());
+        self.kind.parse(self.default, &default).unwrap_or(default)
+    }
+}
+
+const PARAMS: &[Param] = &[
+    param("port", "6379", Kind::Int(0, 65535), IMMUTABLE),
+    param("bind", "127.0.0.1 -::1", Kind::Str, IMMUTABLE).words(),
+    param("unixsocket", "", Kind::Str, IMMUTABLE),
+    param("unixsocketperm", "0", Kind::Custom(permissions), IMMUTABLE),
+    param("protected-mode", "yes", Kind::Bool, MUTABLE),
+    param("replicaof", "", Kind::Custom(replicaof), IMMUTABLE).alias("slaveof").words(),
+    param("dir", "", Kind::Str, MUTABLE),
+    param("dbfilename", "", Kind::Custom(filename), MUTABLE),
+    param("min-replicas-to-write", "0", Kind::Int(0, i32::MAX as i64), MUTABLE).alias("min-slaves-to-write"),
+    param("min-replicas-max-lag", "10", Kind::Int(0, i32::MAX as i64), MUTABLE).alias("min-slaves-max-lag"),
+    param("repl-diskless-sync", "yes", Kind::Bool, MUTABLE),
+    param("repl-diskless-sync-delay", "5", Kind::Int(0, i32::MAX as i64), MUTABLE),
+    param("repl-diskless-load", "disabled", Kind::Enum(&["disabled", "on-empty-db", "swapdb"]), MUTABLE),
+    param("repl-backlog-size", "1mb", Kind::Memory, MUTABLE),
+    param("client-output-buffer-limit", "normal 0 0 0 slave 268435456 67108864 60 pubsub 33554432 8388608 60",
+        Kind::Custom(output_limit), MUTABLE).words(),
+    param("notify-keyspace-events", "", Kind::Custom(notify), MUTABLE),
+    param("maxmemory", "0", Kind::Memory, MUTABLE),
+    param("maxmemory-policy", "noeviction", Kind::Enum(&["noeviction", "allkeys-lru", "volatile-lru",
+        "allkeys-lfu", "volatile-lfu", "allkeys-random", "volatile-random", "volatile-ttl"]), MUTABLE),
+    param("maxmemory-samples", "5", Kind::Int(1, 64), MUTABLE),
+    param("requirepass", "", Kind::Str, MUTABLE),
+    param("aclfile", "", Kind::Str, IMMUTABLE),
+    param("masteruser", "", Kind::Str, IMMUTABLE),
+    param("masterauth", "", Kind::Str, IMMUTABLE),
+];
+
+fn lookup(name: &str) -> Option<&'static Param> {
+    PARAMS.iter().find(|p| p.names().any(|n| n.eq_ignore_ascii_case(name)))
+}
+
+// octal file permissions
+fn permissions(value: &str, _current: &str) -> Result<String, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(perm) if perm <= 0o777 => Ok(format!("{:o}", perm)),
+        _ => Err("argument must be an octal permission value".to_string()),
+    }
+}
+
+// "<host> <port>", empty or "no one" for a master
+fn replicaof(value: &str, _current: &str) -> Result<String, String> {
+    let args = value.split_whitespace().collect::<Vec<&str>>();
+    match args.as_slice() {
+        [] => Ok(String::new()),
+        [no, one] if no.eq_ignore_ascii_case("no") && one.eq_ignore_ascii_case("one") => Ok(String::new()),
+        [host, port] if port.parse::<u16>().is_ok() => Ok(format!("{} {}", host, port)),
+        _ => Err("argument must be '<host> <port>' or 'no one'".to_string()),
+    }
+}
+
+fn filename(value: &str, _current: &str) -> Result<String, String> {
+    if value.contains('/') {
+        return Err("dbfilename can't be a path, just a filename".to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn notify(value: &str, _current: &str) -> Result<String, String> {
+    pubsub::NotifyFlags::parse(value).map(|flags| flags.to_string())
+}
+
+// like redis the given classes replace just their own limits, in bytes
+fn output_limit(value: &str, current: &str) -> Result<String, String> {
+    pubsub::OutputLimit::parse(value)?;
+    let mut classes = current.split_whitespace().collect::<Vec<&str>>().chunks(4)
+        .map(|group| (group[0].to_string(), group[1..].join(" ")))
+        .collect::<Vec<_>>();
+    for group in value.split_whitespace().collect::<Vec<&str>>().chunks(4) {
+        let class = match group[0].to_lowercase().as_str() {
+            "replica" => "slave".to_string(),
+            class => class.to_string(),
+        };
+        let limits = format!("{} {} {}", utils::parse_memory(group[1])?, utils::parse_memory(group[2])?, group[3]);
+        match classes.iter_mut().find(|(c, _l)| *c == class) {
+            Some(existing) => existing.1 = limits,
+            None => classes.push((class, limits)),
+        }
+    }
+    Ok(classes.iter().map(|(c, l)| format!("{} {}", c, l)).collect::<Vec<_>>().join(" "))
+}
+
+// splits a config line into arguments like redis does - "double quoted" ones
+// take \n \r \t \b \a \\ \" and \xHH escapes, 'single quoted' ones only \'
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let unbalanced = || "Unbalanced quotes in configuration line".to_string();
+    let chars = line.chars().collect::<Vec<char>>();
+    let mut args = vec![];
+    let mut i = 0;
+    loop {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        if i >= chars.len() {
+            return Ok(args);
+        }
+        let mut arg = String::new();
+        let quote = match chars[i] {
+            '"' | '\'' => Some(chars[i]),
+            _ => None,
+        };
+        if quote.is_some() {
+            i += 1;
+        }
+        loop {
+            let Some(&c) = chars.get(i) else {
+                if quote.is_some() {
+                    return Err(unbalanced());
+                }
+                break;
+            };
+            i += 1;
+            match quote {
+                None if c.is_whitespace() => break,
+                None => arg.push(c),
+                Some(q) if c == q => {
+                    // closing quote must end the argument
+                    if chars.get(i).is_some_and(|n| !n.is_whitespace()) {
+                        return Err(unbalanced());
+                    }
+                    break;
+                },
+                Some('"') if c == '\\' && i < chars.len() => {
+                    let escaped = chars[i];
+                    i += 1;
+                    match escaped {
+                        'n' => arg.push('\n'),
+                        'r' => arg.push('\r'),
+                        't' => arg.push('\t'),
+                        'b' => arg.push('\u{8}'),
+                        'a' => arg.push('\u{7}'),
+                        'x' if i + 1 < chars.len() && chars[i].is_ascii_hexdigit() && chars[i + 1].is_ascii_hexdigit() => {
+                            let hex = chars[i..i + 2].iter().collect::<String>();
+                            arg.push(u8::from_str_radix(&hex, 16).unwrap_or(0) as char);
+                            i += 2;
+                        },
+                        e => arg.push(e),
+                    }
+                },
+                Some('\'') if c == '\\' && chars.get(i) == Some(&'\'') => {
+                    arg.push('\'');
+                    i += 1;
+                },
+                Some(_) => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+// argument as written to the config file - quoted unless it reads back the same without
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars().all(|c| c.is_ascii_graphic() && !"\"'\\".contains(c));
+    if plain {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn directive(param: &Param, value: &Value) -> String {
+    let value = value.to_string();
+    match param.words && !value.is_empty() {
+        true => format!("{} {}", param.name, value.split_whitespace().map(quote).collect::<Vec<_>>().join(" ")),
+        false => format!("{} {}", param.name, quote(&value)),
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    values: RwLock<HashMap<&'static str, Value>>,
+    file: Option<String>, // absolute path, REWRITE writes it back
+    // CONFIG SET runs one at a time so a rollback puts back what it replaced
+    set_lock: Mutex<()>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self {
+            values: RwLock::new(PARAMS.iter().map(|p| (p.name, p.default_value())).collect()),
+            file: None,
+            set_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        self.include(path, 0)?;
+        let absolute = std::fs::canonicalize(path).map_err(|e| format!("can't resolve config file '{}': {}", path, e))?;
+        self.file = Some(absolute.display().to_string());
+        Ok(())
+    }
+
+    fn include(&self, path: &str, depth: usize) -> Result<(), String> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(format!("Too many nested includes at config file '{}'", path));
+        }
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Fatal error, can't open config file '{}': {}", path, e))?;
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fail = |e: String| format!("*** FATAL CONFIG FILE ERROR *** Reading the configuration file {}, \
+                at line {} >>> '{}' {}", path, i + 1, line, e);
+            let args = split_args(line).map_err(fail)?;
+            if args.len() < 2 {
+                return Err(fail("Bad directive or wrong number of arguments".to_string()));
+            }
+            if args[0].eq_ignore_ascii_case("include") {
+                self.include(&args[1], depth + 1)?;
+                continue;
+            }
+            self.set_startup(&args[0], &args[1..].join(" ")).map_err(fail)?;
+        }
+        Ok(())
+    }
+
+    // from the config file or command line - immutable parameters too
+    pub fn set_startup(&self, name: &str, value: &str) -> Result<(), String> {
+        let param = lookup(name).ok_or_else(|| "Bad directive or wrong number of arguments".to_string())?;
+        let parsed = param.kind.parse(value, &self.value(param.name))?;
+        self.values.write().unwrap().insert(param.name, parsed);
+        Ok(())
+    }
+
+    pub fn value(&self, name: &str) -> Value {
+        self.values.read().unwrap().get(name).cloned().unwrap_or(Value::Str(String::new()))
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        self.value(name) == Value::Bool(true)
+    }
+
+    pub fn int(&self, name: &str) -> i64 {
+        match self.value(name) {
+            Value::Int(n) => n,
+            Value::Memory(n) => n as i64,
+            _ => 0,
+        }
+    }
+
+    pub fn string(&self, name: &str) -> String {
+        self.value(name).to_string()
+    }
+
+    // parameters matching any of the glob patterns, aliases match by their own name
+    pub fn get(&self, patterns: &[String]) -> Vec<(&'static str, String)> {
+        let values = self.values.read().unwrap();
+        let mut matched = vec![];
+        for param in PARAMS {
+            for name in param.names().filter(|n| patterns.iter().any(|p| utils::glob_match(p, n))) {
+                matched.push((name, values[param.name].to_string()));
+            }
+        }
+        matched
+    }
+
+    // every value is checked before any is changed, then they are set and applied in order
+    // when apply fails the values set so far are put back and applied again
+    pub fn set(&self, pairs: &[(String, String)], apply: impl Fn(&str) -> Result<(), String>) -> Result<(), String> {
+        let _guard = self.set_lock.lock().unwrap();
+        let failed = |name: &str, e: &str| format!("ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, e);
+        let mut changes: Vec<(&str, &Param, Value)> = vec![];
+        for (name, value) in pairs {
+            let param = lookup(name)
+                .ok_or_else(|| format!("ERR Unknown option or number of arguments for CONFIG SET - '{}'", name))?;
+            if !param.mutable {
+                return Err(failed(name, "can't set immutable config"));
+            }
+            if changes.iter().any(|(_n, p, _v)| p.name == param.name) {
+                return Err(failed(name, "duplicate parameter"));
+            }
+            let parsed = param.kind.parse(value, &self.value(param.name)).map_err(|e| failed(name, &e))?;
+            changes.push((name, param, parsed));
+        }
+        let mut previous: Vec<(&'static str, Value)> = vec![];
+        for (name, param, value) in changes {
+            let old = self.values.write().unwrap().insert(param.name, value).unwrap_or_else(|| param.default_value());
+            previous.push((param.name, old));
+            if let Err(e) = apply(param.name) {
+                let mut values = self.values.write().unwrap();
+                previous.iter().for_each(|(n, v)| { values.insert(n, v.clone()); });
+                drop(values);
+                previous.iter().for_each(|(n, _v)| { let _ = apply(n); });
+                return Err(failed(name, &e));
+            }
+        }
+        Ok(())
+    }
+
+    // puts current values into the config file - lines of known parameters are replaced,
+    // comments and everything else stay, changed parameters not in the file are appended
+    pub fn rewrite(&self) -> Result<(), String> {
+        let Some(path) = &self.file else {
+            return Err("ERR The server is running without a config file".to_string());
+        };
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("ERR Rewriting config file: {}", e)),
+        };
+        let values = self.values.read().unwrap().clone();
+        let mut written = HashSet::new();
+        let mut lines = vec![];
+        for line in content.lines() {
+            let trimmed = line.trim();
+            let param = match split_args(trimmed) {
+                Ok(args) if !trimmed.starts_with('#') => args.first().and_then(|name| lookup(name)),
+                _ => None,
+            };
+            match param {
+                Some(p) if written.insert(p.name) => lines.push(directive(p, &values[p.name])),
+                Some(_p) => {}, // repeated lines of a parameter are folded into the first one
+                None => lines.push(line.to_string()),
+            }
+        }
+        let missing = PARAMS.iter().filter(|p| !written.contains(p.name) && values[p.name] != p.default_value());
+        let mut marked = lines.iter().any(|l| l == REWRITE_MARKER);
+        for param in missing {
+            if !marked {
+                lines.push(REWRITE_MARKER.to_string());
+                marked = true;
+            }
+            lines.push(directive(param, &values[param.name]));
+        }
+        let temp_path = format!("{}.tmp-{}", path, std::process::id());
+        let mut output = lines.join("\n");
+        output.push('\n');
+        std::fs::write(&temp_path, output)
+            .and_then(|_| std::fs::rename(&temp_path, path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&temp_path);
+                format!("ERR Rewriting config file: {}", e)
+            })
+    }
+}
@@ -1,64 +1,166 @@
 use bytes::BytesMut;
 use clap::Parser;
-use std::io::Read;
-use std::net::{Shutdown, TcpListener, TcpStream};
+use std::io::{Read, Write};
+use std::net::Shutdown;
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
 use std::thread;
 
+mod acl;
+mod client;
 mod commands;
+mod config;
+mod pubsub;
 mod rdb;
 mod repl;
 mod slave;
 mod store;
+mod utils;
 
-const EXPIRY_LOOP_TIME: u64 = 500; // 500 milli seconds
-const DEFAULT_LISTENING_PORT: u16 = 6379;
+#[global_allocator]
+static ALLOCATOR: utils::memory::CountingAllocator = utils::memory::CountingAllocator;
 
+const EXPIRY_LOOP_TIME: u64 = 500; // 500 milli seconds
+// command line takes an optional redis.conf file and --<parameter> <value> overrides,
+// defaults and checks of every parameter are in the config module
 #[derive(Debug, Default, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[clap(default_value_t=DEFAULT_LISTENING_PORT, short, long)]
-    port: u16,
+    config_file: Option<String>,
+    #[clap(short, long)]
+    port: Option<String>,
+    // space separated addresses, a leading - marks an address as optional
+    #[clap(long)]
+    bind: Option<String>,
+    #[clap(long)]
+    unixsocket: Option<String>,
+    // octal permissions of the unix socket file
+    #[clap(long)]
+    unixsocketperm: Option<String>,
+    // only loopback and unix socket clients while the default user has no password
+    #[clap(long)]
+    protected_mode: Option<String>,
     #[clap(short, long)]
     replicaof: Option<String>,
     #[clap(long)]
     dir: Option<String>,
     #[clap(long)]
     dbfilename: Option<String>,
+    #[clap(long)]
+    min_replicas_to_write: Option<String>,
+    #[clap(long)]
+    min_replicas_max_lag: Option<String>,
+    #[clap(long)]
+    repl_diskless_sync: Option<String>,
+    #[clap(long)]
+    repl_diskless_sync_delay: Option<String>,
+    #[clap(long)]
+    repl_diskless_load: Option<String>,
+    #[clap(long)]
+    repl_backlog_size: Option<String>,
+    #[clap(long)]
+    client_output_buffer_limit: Option<String>,
+    #[clap(long)]
+    notify_keyspace_events: Option<String>,
+    #[clap(long)]
+    maxmemory: Option<String>,
+    #[clap(long)]
+    maxmemory_policy: Option<String>,
+    #[clap(long)]
+    maxmemory_samples: Option<String>,
+    #[clap(long)]
+    requirepass: Option<String>,
+    #[clap(long)]
+    aclfile: Option<String>,
+    // replica authenticates to its master with these
+    #[clap(long)]
+    masteruser: Option<String>,
+    #[clap(long)]
+    masterauth: Option<String>,
 }
 
+impl Args {
+    // config parameters given on the command line
+    fn overrides(&self) -> Vec<(&'static str, &Option<String>)> {
+        vec![
+            ("port", &self.port),
+            ("bind", &self.bind),
+            ("unixsocket", &self.unixsocket),
+            ("unixsocketperm", &self.unixsocketperm),
+            ("protected-mode", &self.protected_mode),
+            ("replicaof", &self.replicaof),
+            ("dir", &self.dir),
+            ("dbfilename", &self.dbfilename),
+            ("min-replicas-to-write", &self.min_replicas_to_write),
+            ("min-replicas-max-lag", &self.min_replicas_max_lag),
+            ("repl-diskless-sync", &self.repl_diskless_sync),
+            ("repl-diskless-sync-delay", &self.repl_diskless_sync_delay),
+            ("repl-diskless-load", &self.repl_diskless_load),
+            ("repl-backlog-size", &self.repl_backlog_size),
+            ("client-output-buffer-limit", &self.client_output_buffer_limit),
+            ("notify-keyspace-events", &self.notify_keyspace_events),
+            ("maxmemory", &self.maxmemory),
+            ("maxmemory-policy", &self.maxmemory_policy),
+            ("maxmemory-samples", &self.maxmemory_samples),
+            ("requirepass", &self.requirepass),
+            ("aclfile", &self.aclfile),
+            ("masteruser", &self.masteruser),
+            ("masterauth", &self.masterauth),
+        ]
+    }
+}
+
+const PROTECTED_MODE_DENIED: &str = "-DENIED Redis is running in protected mode because protected mode is enabled \
+    and no password is set for the default user. In this mode connections are only accepted from the loopback \
+    interface. If you want to connect from external computers to Redis you may adopt one of the following \
+    solutions: 1) Disable protected mode with the --protected-mode no option and restart the server. \
+    2) Setup a password for the default user with --requirepass or an ACL file. \
+    3) Bind only to loopback addresses with the --bind option.\r\n";
+
 fn handle_connection(
-    stream: TcpStream,
+    stream: client::connection::Connection,
     db: Arc<store::db::DB>,
     replcfg: Arc<repl::repl::ReplicationConfig>,
     repl_ch_tx: Sender<BytesMut>,
 ) {
     let mut stream = stream;
+    if db.config().bool("protected-mode") && !stream.is_local() && db.acl().nopass(acl::acl::DEFAULT_USER) {
+        let _ = stream.write_all(PROTECTED_MODE_DENIED.as_bytes());
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+    let mut client = client::client::Client::new();
+    // no AUTH needed while the default user has no password
+    if db.acl().nopass(acl::acl::DEFAULT_USER) {
+        client.authenticate(acl::acl::DEFAULT_USER);
+    }
     let mut buf = BytesMut::with_capacity(1500);
     unsafe {
         buf.set_len(1500);
     }
+    // bytes read but not handled yet - a command may span several reads
+    let mut input = BytesMut::new();
     // read data from socket
 
     while let Ok(len) = stream.read(&mut buf) {
         if len <= 0 {
             break;
         }
-        unsafe {
-            buf.set_len(len);
+        input.extend_from_slice(&buf[..len]);
+        let complete = commands::resp::complete_len(&input);
+        if complete == 0 {
+            continue;
         }
-        let cmd = commands::incoming::Incoming::new(&buf, false);
-        if let Err(e) = cmd.handle(&mut stream, &db, &replcfg, &repl_ch_tx, &None) {
+        let commands = input.split_to(complete);
+        let cmd = commands::incoming::Incoming::new(&commands, false);
+        if let Err(e) = cmd.handle(&mut stream, &db, &replcfg, &repl_ch_tx, &None, &mut client) {
             println!("error handling incoming command: {}, Error: {}", cmd, e);
             break;
         }
-        unsafe {
-            buf.set_len(1500);
-        }
     }
 
+    client.close(&db);
     let _ = stream.shutdown(Shutdown::Both);
 }
 
@@ -67,41 +169,103 @@ fn main() {
     println!("Logs from your program will appear here!");
 
     let args = Args::parse();
-    println!("Listening port: {}", args.port);
-    println!("replicaoff flag: {:?}", args.replicaof);
+    let mut config = config::config::Config::new();
+    if let Some(path) = &args.config_file {
+        if let Err(e) = config.load(path) {
+            println!("{}... exiting", e);
+            return;
+        }
+    }
+    for (name, value) in args.overrides() {
+        let Some(value) = value else { continue };
+        if let Err(e) = config.set_startup(name, value) {
+            println!("Invalid {} value '{}': {}... exiting", name, value, e);
+            return;
+        }
+    }
+    let port = config.int("port") as u16;
+    let replicaof = config.string("replicaof");
+    println!("Listening port: {}", port);
+    println!("replicaoff flag: {:?}", replicaof);
 
     let mut role_master = true;
     let mut master_ip_addr = "".to_string();
     let mut master_port = 6380;
 
-    if let Some(replica) = args.replicaof {
+    // checked by the config - "<host> <port>" or empty
+    if let Some((host, p)) = replicaof.split_once(' ') {
         role_master = false; // slave node
-        let args = replica
-            .split(" ")
-            .map(|s| {
-                if s == "localhost" {
-                    return "127.0.0.1".to_string();
-                }
-                s.to_string()
-            })
-            .collect::<Vec<String>>();
-        if args.len() != 2 {
-            // problematic
-            println!("Invalid options for replicaof.... exiting");
+        master_ip_addr = if host == "localhost" { "127.0.0.1".to_string() } else { host.to_string() };
+        master_port = p.parse::<u16>().unwrap_or(master_port);
+    }
+
+    let unixsocketperm = u32::from_str_radix(&config.string("unixsocketperm"), 8).unwrap_or(0);
+
+    let output_limit = match pubsub::pubsub::OutputLimit::parse(&config.string("client-output-buffer-limit")) {
+        Ok(l) => l,
+        Err(e) => {
+            println!("{}... exiting", e);
             return;
         }
-        master_ip_addr = args[0].clone();
-        if let Ok(p) = args[1].parse::<u16>() {
-            master_port = p;
-        } else {
-            println!("Invalid master port format!!... exiting");
+    };
+
+    let notify = match pubsub::pubsub::NotifyFlags::parse(&config.string("notify-keyspace-events")) {
+        Ok(n) => n,
+        Err(e) => {
+            println!("{}... exiting", e);
             return;
         }
-    }
+    };
+
+    let maxmemory = match store::evict::MaxMemory::new(&config.string("maxmemory"),
+        &config.string("maxmemory-policy"), config.int("maxmemory-samples") as usize) {
+        Ok(m) => m,
+        Err(e) => {
+            println!("{}... exiting", e);
+            return;
+        }
+    };
+
+    let configured = |name: &str| Some(config.string(name)).filter(|v| !v.is_empty());
+    let acl = match acl::acl::Acl::new(configured("requirepass"), configured("aclfile")) {
+        Ok(a) => a,
+        Err(e) => {
+            println!("{}... exiting", e);
+            return;
+        }
+    };
+    let master_auth = configured("masterauth")
+        .map(|password| slave::slave::MasterAuth::new(configured("masteruser"), password));
 
-    // Uncomment this block to pass the first stage
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", args.port)).unwrap();
-    let db = Arc::new(store::db::DB::new(role_master, args.dir, args.dbfilename));
+    utils::memory::mark_startup();
+
+    // port 0 disables TCP
+    let mut listeners = if port == 0 {
+        vec![]
+    } else {
+        match client::connection::Listener::bind_tcp(&config.string("bind"), port) {
+            Ok(l) => l,
+            Err(e) => {
+                println!("{}... exiting", e);
+                return;
+            }
+        }
+    };
+    if let Some(path) = &configured("unixsocket") {
+        match client::connection::Listener::bind_unix(path, unixsocketperm) {
+            Ok(l) => listeners.push(l),
+            Err(e) => {
+                println!("{}... exiting", e);
+                return;
+            }
+        }
+    }
+    if listeners.is_empty() {
+        println!("Configured to not listen anywhere... exiting");
+        return;
+    }
+    let db = Arc::new(store::db::DB::new(role_master, Some(config.string("dir")), Some(config.string("dbfilename")),
+        pubsub::pubsub::Hub::new(output_limit, notify), acl, config, maxmemory));
 
     // spawn expiry thread
     if true {
@@ -110,7 +274,13 @@ fn main() {
     }
 
     let (repl_tx_ch, repl_rx_ch) = mpsc::channel();
-    let replcfg = Arc::new(repl::repl::ReplicationConfig::new());
+    let config = db.config();
+    let min_replicas = repl::repl::MinReplicas::new(config.int("min-replicas-to-write") as usize,
+        config.int("min-replicas-max-lag") as u64);
+    let diskless = repl::repl::DisklessSync::new(config.bool("repl-diskless-sync"),
+        config.int("repl-diskless-sync-delay") as u64);
+    let replcfg = Arc::new(repl::repl::ReplicationConfig::new(role_master, min_replicas, diskless,
+        config.int("repl-backlog-size") as u64));
 
     // start replication thread - only needed on master
     // but slave can get promoted to a master
@@ -127,21 +297,31 @@ fn main() {
         let replcfg_cp = Arc::clone(&replcfg);
         let _ =
             thread::spawn(move || slave::slave::slave_thread(dbc, replcfg_cp,
-                    tx_ch_clone, master_ip_addr, master_port, args.port));
+                    tx_ch_clone, master_ip_addr, master_port, port, master_auth));
     }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(_stream) => {
-                let dbc = Arc::clone(&db);
-                let tx_ch_clone = repl_tx_ch.clone();
-                let replcfg_cp = Arc::clone(&replcfg);
-                let _ =
-                    thread::spawn(move || handle_connection(_stream, dbc, replcfg_cp, tx_ch_clone));
-            }
-            Err(e) => {
-                println!("error: {}", e);
+    // one accepting thread per listening socket
+    let accepters = listeners.into_iter().map(|listener| {
+        let db = Arc::clone(&db);
+        let repl_tx_ch = repl_tx_ch.clone();
+        let replcfg = Arc::clone(&replcfg);
+        println!("Accepting connections on {}", listener.address());
+        thread::spawn(move || loop {
+            match listener.accept() {
+                Ok(_stream) => {
+                    let dbc = Arc::clone(&db);
+                    let tx_ch_clone = repl_tx_ch.clone();
+                    let replcfg_cp = Arc::clone(&replcfg);
+                    let _ =
+                        thread::spawn(move || handle_connection(_stream, dbc, replcfg_cp, tx_ch_clone));
+                }
+                Err(e) => {
+                    println!("error: {}", e);
+                }
             }
-        }
+        })
+    }).collect::<Vec<_>>();
+    for accepter in accepters {
+        let _ = accepter.join();
     }
 }
@@ -0,0 +1,398 @@
+// publish/subscribe hub - channel and pattern subscriptions
+// messages are pushed to subscribers through their connection pusher
+use crate::client::connection::Connection;
+use crate::utils::utils;
+use bytes::BytesMut;
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// client-output-buffer-limit pubsub <hard> <soft> <soft seconds>
+// subscriber is disconnected once its pending output crosses hard limit
+// or stays above soft limit for soft seconds, 0 disables a limit
+#[derive(Debug, Clone, Copy)]
+pub struct OutputLimit {
+    hard: usize,
+    soft: usize,
+    soft_seconds: u64,
+}
+
+impl Default for OutputLimit {
+    fn default() -> Self {
+        Self {
+            hard: 32 * 1024 * 1024,
+            soft: 8 * 1024 * 1024,
+            soft_seconds: 60,
+        }
+    }
+}
+
+impl OutputLimit {
+    // parses "<class> <hard> <soft> <soft seconds>" groups, only pubsub class applies
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let args = value.split_whitespace().collect::<Vec<&str>>();
+        if args.is_empty() || !args.len().is_multiple_of(4) {
+            return Err("Wrong number of arguments in buffer limit configuration".to_string());
+        }
+        let mut limit = Self::default();
+        for group in args.chunks(4) {
+            let hard = utils::parse_memory(group[1])?;
+            let soft = utils::parse_memory(group[2])?;
+            let soft_seconds = match group[3].parse::<u64>() {
+                Ok(s) => s,
+                Err(_) => return Err(format!("invalid soft seconds '{}'", group[3])),
+            };
+            match group[0].to_lowercase().as_str() {
+                "pubsub" => {
+                    limit = Self { hard: hard as usize, soft: soft as usize, soft_seconds };
+                },
+                "normal" | "replica" | "slave" => {
+                    println!("client-output-buffer-limit for {} is not enforced", group[0]);
+                },
+                _ => return Err(format!("Invalid client class specified in buffer limit configuration '{}'", group[0])),
+            }
+        }
+        Ok(limit)
+    }
+}
+
+// writes to a connection from its own thread - lets others push messages
+// to a client without waiting on it, pending bytes are tracked for output limits
+#[derive(Debug, Clone)]
+pub struct Pusher {
+    id: u64,
+    tx: Sender<BytesMut>,
+    pending: Arc<AtomicUsize>,
+    soft_since: Arc<Mutex<Option<Instant>>>,
+    closed: Arc<AtomicBool>,
+    stream: Arc<Connection>, // for disconnecting a slow client
+}
+
+impl Pusher {
+    pub fn new(id: u64, stream: &Connection) -> std::io::Result<Self> {
+        let mut writer = stream.try_clone()?;
+        let (tx, rx) = mpsc::channel::<BytesMut>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let closed = Arc::new(AtomicBool::new(false));
+        let pending_cp = Arc::clone(&pending);
+        let closed_cp = Arc::clone(&closed);
+        let _ = thread::spawn(move || {
+            // ends once every sender is dropped
+            for buf in rx {
+                if closed_cp.load(Ordering::SeqCst) || writer.write_all(&buf).is_err() {
+                    closed_cp.store(true, Ordering::SeqCst);
+                }
+                pending_cp.fetch_sub(buf.len(), Ordering::SeqCst);
+            }
+        });
+        Ok(Self {
+            id,
+            tx,
+            pending,
+            soft_since: Arc::new(Mutex::new(None)),
+            closed,
+            stream: Arc::new(stream.try_clone()?),
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // bytes waiting to be written to the client
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    // queues buf for the client, returns false if client got disconnected
+    pub fn push(&self, buf: BytesMut, limit: &OutputLimit) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+        let pending = self.pending.fetch_add(buf.len(), Ordering::SeqCst) + buf.len();
+        if limit.hard > 0 && pending > limit.hard {
+            println!("client {} closed for overcoming of output buffer limits", self.id);
+            self.close();
+            return false;
+        }
+        if limit.soft > 0 && pending > limit.soft {
+            let mut since = self.soft_since.lock().unwrap();
+            match *since {
+                Some(t) if t.elapsed() >= Duration::from_secs(limit.soft_seconds) => {
+                    drop(since);
+                    println!("client {} closed for overcoming of output buffer limits", self.id);
+                    self.close();
+                    return false;
+                },
+                Some(_) => {},
+                None => *since = Some(Instant::now()),
+            }
+        } else {
+            *self.soft_since.lock().unwrap() = None;
+        }
+        let len = buf.len();
+        if self.tx.send(buf).is_err() {
+            self.pending.fetch_sub(len, Ordering::SeqCst);
+            self.closed.store(true, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+
+    // waits for everything queued to be written - before writing on stream directly
+    pub fn drain(&self) {
+        while !self.is_closed() && self.pending.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+// notify-keyspace-events - K keyspace, E keyevent channels and classes of events
+// g generic, $ string, l list, s set, h hash, z sorted set, x expired,
+// e evicted, t stream, m key miss, n new key, d module, A alias for g$lshzxetd
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NotifyFlags {
+    keyspace: bool,
+    keyevent: bool,
+    classes: String,
+}
+
+impl NotifyFlags {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let mut flags = Self::default();
+        for c in value.chars() {
+            match c {
+                'K' => flags.keyspace = true,
+                'E' => flags.keyevent = true,
+                'A' => "g$lshzxetd".chars().for_each(|c| flags.add_class(c)),
+                'g' | '$' | 'l' | 's' | 'h' | 'z' | 'x' | 'e' | 't' | 'm' | 'n' | 'd' => flags.add_class(c),
+                _ => return Err(format!("Invalid event class character '{}' in notify-keyspace-events", c)),
+            }
+        }
+        Ok(flags)
+    }
+
+    fn add_class(&mut self, c: char) {
+        if !self.classes.contains(c) {
+            self.classes.push(c);
+        }
+    }
+
+    fn enabled(&self, class: char) -> bool {
+        (self.keyspace || self.keyevent) && self.classes.contains(class)
+    }
+}
+
+impl std::fmt::Display for NotifyFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.keyspace && !self.keyevent {
+            return write!(f, "");
+        }
+        let mut classes = self.classes.clone();
+        if "g$lshzxetd".chars().all(|c| classes.contains(c)) {
+            classes = classes.chars().filter(|c| !"g$lshzxetd".contains(*c)).collect();
+            classes.insert(0, 'A');
+        }
+        write!(f, "{}{}{}", classes, if self.keyspace { "K" } else { "" }, if self.keyevent { "E" } else { "" })
+    }
+}
+
+// kind of subscription - sharded channels are separate from the regular ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+// subscribers of a channel or pattern by client id
+type Subscribers = HashMap<String, HashMap<u64, Pusher>>;
+
+#[derive(Debug, Default)]
+pub struct Hub {
+    channels: RwLock<Subscribers>,
+    patterns: RwLock<Subscribers>,
+    shards: RwLock<Subscribers>,
+    limit: RwLock<OutputLimit>,
+    notify: RwLock<NotifyFlags>,
+}
+
+impl Hub {
+    pub fn new(limit: OutputLimit, notify: NotifyFlags) -> Self {
+        Self {
+            limit: RwLock::new(limit),
+            notify: RwLock::new(notify),
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(&self) -> OutputLimit {
+        *self.limit.read().unwrap()
+    }
+
+    pub fn set_limit(&self, limit: OutputLimit) {
+        *self.limit.write().unwrap() = limit;
+    }
+
+    pub fn set_notify(&self, notify: NotifyFlags) {
+        *self.notify.write().unwrap() = notify;
+    }
+
+    fn subscribers(&self, kind: Kind) -> &RwLock<Subscribers> {
+        match kind {
+            Kind::Channel => &self.channels,
+            Kind::Pattern => &self.patterns,
+            Kind::Shard => &self.shards,
+        }
+    }
+
+    pub fn subscribe(&self, kind: Kind, channel: &str, pusher: &Pusher) {
+        add(self.subscribers(kind), channel, pusher);
+    }
+
+    pub fn unsubscribe(&self, kind: Kind, channel: &str, id: u64) {
+        remove(self.subscribers(kind), channel, id);
+    }
+
+    // sends message to channel and pattern subscribers, returns receivers count
+    pub fn publish(&self, channel: &str, message: &[u8]) -> usize {
+        let mut dropped = vec![];
+        let mut receivers = self.deliver(Kind::Channel, "message", channel, message, &mut dropped);
+        receivers += self.deliver(Kind::Pattern, "pmessage", channel, message, &mut dropped);
+        // disconnected clients are gone from every subscription
+        for id in dropped {
+            self.remove_client(id);
+        }
+        receivers
+    }
+
+    // sharded channels have no pattern subscriptions
+    pub fn spublish(&self, channel: &str, message: &[u8]) -> usize {
+        let mut dropped = vec![];
+        let receivers = self.deliver(Kind::Shard, "smessage", channel, message, &mut dropped);
+        for id in dropped {
+            self.remove_client(id);
+        }
+        receivers
+    }
+
+    fn deliver(&self, kind: Kind, msg_type: &str, channel: &str, message: &[u8], dropped: &mut Vec<u64>) -> usize {
+        let subs = self.subscribers(kind).read().unwrap();
+        if kind != Kind::Pattern {
+            let mut buf = format!("*3\r\n${}\r\n{}\r\n${}\r\n{}\r\n${}\r\n",
+                msg_type.len(), msg_type, channel.len(), channel, message.len()).into_bytes();
+            buf.extend_from_slice(message);
+            buf.extend_from_slice(b"\r\n");
+            return match subs.get(channel) {
+                Some(subscribers) => self.push_all(subscribers, buf, dropped),
+                None => 0,
+            };
+        }
+        let mut receivers = 0;
+        for (pattern, subscribers) in subs.iter() {
+            if !utils::glob_match(pattern, channel) {
+                continue;
+            }
+            let mut buf = format!("*4\r\n${}\r\n{}\r\n${}\r\n{}\r\n${}\r\n{}\r\n${}\r\n",
+                msg_type.len(), msg_type, pattern.len(), pattern, channel.len(), channel, message.len()).into_bytes();
+            buf.extend_from_slice(message);
+            buf.extend_from_slice(b"\r\n");
+            receivers += self.push_all(subscribers, buf, dropped);
+        }
+        receivers
+    }
+
+    fn push_all(&self, subscribers: &HashMap<u64, Pusher>, buf: Vec<u8>, dropped: &mut Vec<u64>) -> usize {
+        let limit = self.limit();
+        let buf = BytesMut::from(&buf[..]);
+        let mut receivers = 0;
+        for pusher in subscribers.values() {
+            if pusher.push(buf.clone(), &limit) {
+                receivers += 1;
+            } else {
+                dropped.push(pusher.id());
+            }
+        }
+        receivers
+    }
+
+    pub fn remove_client(&self, id: u64) {
+        for subs in [&self.channels, &self.patterns, &self.shards] {
+            let mut subs = subs.write().unwrap();
+            subs.values_mut().for_each(|s| { s.remove(&id); });
+            subs.retain(|_k, s| !s.is_empty());
+        }
+    }
+
+    // active channels, optionally matching a pattern
+    pub fn channels(&self, kind: Kind, pattern: Option<&str>) -> Vec<String> {
+        self.subscribers(kind).read().unwrap().keys()
+            .filter(|c| pattern.map(|p| utils::glob_match(p, c)).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+
+    pub fn numsub(&self, kind: Kind, channel: &str) -> usize {
+        self.subscribers(kind).read().unwrap().get(channel).map(|s| s.len()).unwrap_or(0)
+    }
+
+    // subscribed clients and the output they have pending
+    pub fn pending_output(&self) -> (usize, usize) {
+        let mut clients = HashMap::new();
+        for subs in [&self.channels, &self.patterns, &self.shards] {
+            for pusher in subs.read().unwrap().values().flat_map(|s| s.values()) {
+                clients.insert(pusher.id(), pusher.pending());
+            }
+        }
+        (clients.len(), clients.values().sum())
+    }
+
+    pub fn numpat(&self) -> usize {
+        self.patterns.read().unwrap().len()
+    }
+
+    // keyspace notification for event of given class on key
+    pub fn notify(&self, class: char, event: &str, key: &str) {
+        let flags = self.notify.read().unwrap().clone();
+        if !flags.enabled(class) {
+            return;
+        }
+        if flags.keyspace {
+            self.publish(&format!("__keyspace@0__:{}", key), event.as_bytes());
+        }
+        if flags.keyevent {
+            self.publish(&format!("__keyevent@0__:{}", event), key.as_bytes());
+        }
+    }
+}
+
+fn add(subs: &RwLock<Subscribers>, name: &str, pusher: &Pusher) {
+    subs.write().unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .insert(pusher.id(), pusher.clone());
+}
+
+fn remove(subs: &RwLock<Subscribers>, name: &str, id: u64) {
+    let mut subs = subs.write().unwrap();
+    if let Some(s) = subs.get_mut(name) {
+        s.remove(&id);
+        if s.is_empty() {
+            subs.remove(name);
+        }
+    }
+}
@@ -1,34 +1,100 @@
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
 use crate::store::db;
+use crate::store::sortedset;
+use crate::store::streams;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::sync::RwLock;
 use crate::commands::getset;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+const RDB_VERSION: &[u8] = b"REDIS0011";
+// version in the footer of DUMP payloads - the one RDB_VERSION has
+const DUMP_VERSION: u16 = 11;
+const DEFAULT_RDB_FILENAME: &str = "dump.rdb";
+
+// opcodes
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+// value types
+const TYPE_STRING: u8 = 0;
+const TYPE_ZSET: u8 = 3; // scores as strings
+const TYPE_ZSET_2: u8 = 5; // scores as binary doubles
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19; // with first id, max deleted id, entries added and read
+const TYPE_STREAM_LISTPACKS_3: u8 = 21; // with consumer active time
+
+// flags of entries in a stream listpack
+const STREAM_ITEM_DELETED: i64 = 1;
+const STREAM_ITEM_SAME_FIELDS: i64 = 2;
+
+// special string encodings (length byte 11xxxxxx)
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+fn invalid_data(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+// length encoding: either a length or a special encoding of the string that follows
+enum Length {
+    Len(usize),
+    Encoded(u8),
+}
+
+// directory and file name can be changed with CONFIG SET
 pub struct RDB {
-    directory: String,
-    rdb_file: String,
+    directory: RwLock<String>,
+    rdb_file: RwLock<String>,
 }
 
 impl RDB {
     pub fn new(dir: Option<String>, rdb_file: Option<String>) -> Self {
         Self {
-            directory: dir.unwrap_or("".to_string()),
-            rdb_file: rdb_file.unwrap_or("".to_string()),
+            directory: RwLock::new(dir.unwrap_or("".to_string())),
+            rdb_file: RwLock::new(rdb_file.unwrap_or("".to_string())),
         }
     }
 
-    pub fn get_rdb_directory(&self) -> &str {
-        &self.directory
+    pub fn get_rdb_directory(&self) -> String {
+        self.directory.read().unwrap().clone()
+    }
+
+    pub fn get_rdb_filename(&self) -> String {
+        self.rdb_file.read().unwrap().clone()
     }
 
-    pub fn get_rdb_filename(&self) -> &str {
-        &self.rdb_file
+    pub fn set_rdb_directory(&self, dir: &str) {
+        *self.directory.write().unwrap() = dir.to_string();
     }
 
-    fn add_to_db(db: &db::DB, k: &Vec<u8>, v: &Vec<u8>, expiry_in_ms: u64) -> Result<(), String> {
+    pub fn set_rdb_filename(&self, rdb_file: &str) {
+        *self.rdb_file.write().unwrap() = rdb_file.to_string();
+    }
+
+    // path RDB is saved into - falls back to dump.rdb in current directory
+    pub fn path(&self) -> String {
+        let (directory, rdb_file) = (self.get_rdb_directory(), self.get_rdb_filename());
+        let filename = if rdb_file.is_empty() { DEFAULT_RDB_FILENAME } else { &rdb_file };
+        if directory.is_empty() {
+            return filename.to_string();
+        }
+        format!("{}/{}", directory, filename)
+    }
+
+    fn add_to_db(db: &db::DB, k: &[u8], v: db::KeyValueType, expiry_in_ms: u64) -> Result<(), String> {
         // discard if key is already expired!
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
         if expiry_in_ms > 0 && expiry_in_ms as u128 <= now.as_millis() {
@@ -36,250 +102,293 @@ impl RDB {
             return Ok(());
         }
         let key = String::from_utf8_lossy(k);
-        let value = String::from_utf8_lossy(v);
         let mut options = getset::SetOptions::new();
-        options.expiry_in_ms = expiry_in_ms;
-        db.add(key.into_owned(), db::KeyValueType::StringType(value.into_owned()), &options)
+        // RDB stores absolute unix time, DB expects time to live
+        if expiry_in_ms > 0 {
+            options.expiry_in_ms = expiry_in_ms - now.as_millis() as u64;
+        }
+        db.add(key.into_owned(), v, &options)
     }
 
-    fn checksum(&self) -> bool {
-        // open file, read entire file and compute checksum
-        // compare with checksum which is the last byte in the file
-        true
+    fn read_byte<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte)?;
+        Ok(byte[0])
     }
-/*
-    fn int_value(len: usize, value: &Vec<u8>) -> usize {
-        match len {
-            1 => value[0] as usize,
-            2 => u16::from_ne_bytes(value[0..=1]) as usize,
-            4 => u32::from_ne_bytes(value[0..=3]) as usize,
-            _ => 0,
-        }
-    }
-*/
 
-    fn read_key_value(opcode: Option<u8>, reader: &mut BufReader<File>) -> Result<(Vec<u8>, Vec<u8>),  String> {
-        if let Ok(length) = Self::encoded_length(opcode, reader) {
-            let mut key = vec!(0; length);
-            if let Err(e) = reader.read_exact(&mut key) {
-                return Err(format!("Failed to read Key from RDB file, error: {}", e));
-            }
-            if let Ok(val_len) = Self::encoded_length(None, reader) {
-                let mut value = vec!(0; val_len);
-                if let Err(e) = reader.read_exact(&mut value) {
-                    return Err(format!("Failed to read value for key {} from RDB file, error: {}",
-                        String::from_utf8_lossy(&key), e));
+    fn read_length<R: Read>(reader: &mut R) -> std::io::Result<Length> {
+        let first = Self::read_byte(reader)?;
+        match (first & 0xC0) >> 6 {
+            0x0 => Ok(Length::Len(first as usize & 0x3F)),
+            0x1 => {
+                let next = Self::read_byte(reader)?;
+                Ok(Length::Len(((first as usize & 0x3F) << 8) | next as usize))
+            },
+            0x2 => {
+                // 32 or 64 bit length in network byte order
+                if first == 0x80 {
+                    let mut length = [0; 4];
+                    reader.read_exact(&mut length)?;
+                    Ok(Length::Len(u32::from_be_bytes(length) as usize))
+                } else if first == 0x81 {
+                    let mut length = [0; 8];
+                    reader.read_exact(&mut length)?;
+                    Ok(Length::Len(u64::from_be_bytes(length) as usize))
+                } else {
+                    Err(invalid_data(format!("Invalid length encoding: {:x}", first)))
                 }
-                println!("read_key_value, key: {:?}, value: {:?}",
-                    String::from_utf8(key.clone()).unwrap_or("unable to unwrap key".to_string()),
-                    String::from_utf8(value.clone()).unwrap_or("unwrap error value".to_string()));
-                return Ok((key, value));
-            }
+            },
+            _ => Ok(Length::Encoded(first & 0x3F)),
         }
-        Err("invalid string - its all messed up!!".to_string())
     }
 
-    fn read_key_value_with_type(vtype: Option<u8>, reader: &mut BufReader<File>) -> Result<(Vec<u8>, Vec<u8>),  String> {
-        // read 1 byte value type
-        let opcode: u8;
-        if vtype.is_none() {
-            let mut value_type = [0; 1];
-            let result = reader.read_exact(&mut value_type);
-            if result.is_err() {
-                return Err(format!("Unable to read the value type: {:?}", result))
-            }
-            opcode = value_type[0];
-        } else {
-            opcode = vtype.unwrap();
+    fn read_plain_length<R: Read>(reader: &mut R) -> std::io::Result<usize> {
+        match Self::read_length(reader)? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(enc) => Err(invalid_data(format!("Unexpected string encoding {} for a length", enc))),
         }
+    }
 
-        match opcode {
-            0 => {// string encoding
-                // read length, and then key
-                return Self::read_key_value(None, reader);
+    fn read_string<R: Read>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+        match Self::read_length(reader)? {
+            Length::Len(len) => {
+                let mut value = vec![0; len];
+                reader.read_exact(&mut value)?;
+                Ok(value)
             },
-            _ => {
-                Err(format!("Value type: {} is not yet supported!", opcode))
-            }
+            Length::Encoded(ENC_INT8) => {
+                let value = Self::read_byte(reader)? as i8;
+                Ok(value.to_string().into_bytes())
+            },
+            Length::Encoded(ENC_INT16) => {
+                let mut value = [0; 2];
+                reader.read_exact(&mut value)?;
+                Ok(i16::from_le_bytes(value).to_string().into_bytes())
+            },
+            Length::Encoded(ENC_INT32) => {
+                let mut value = [0; 4];
+                reader.read_exact(&mut value)?;
+                Ok(i32::from_le_bytes(value).to_string().into_bytes())
+            },
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = Self::read_plain_length(reader)?;
+                let len = Self::read_plain_length(reader)?;
+                let mut compressed = vec![0; compressed_len];
+                reader.read_exact(&mut compressed)?;
+                lzf_decompress(&compressed, len)
+            },
+            Length::Encoded(enc) => Err(invalid_data(format!("Invalid string encoding: {}", enc))),
         }
     }
 
-    fn encoded_length(opcode_option: Option<u8>, reader: &mut BufReader<File>) -> Result<usize,  String> {
-        let mut opcode = [0; 1];
-        if opcode_option.is_some() {
-            opcode[0] = opcode_option.unwrap();
-        } else {
-            let result = reader.read_exact(&mut opcode);
-            if result.is_err() {
-                return Err(format!("Unable to read the opcode: {:?}", result))
-            }
+    fn read_double<R: Read>(reader: &mut R) -> std::io::Result<f64> {
+        let len = Self::read_byte(reader)?;
+        match len {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let mut value = vec![0; len as usize];
+                reader.read_exact(&mut value)?;
+                parse_double(&value)
+            },
         }
+    }
 
-        match (opcode[0] & 0xC0) >> 6 {
-            0x0 => Ok(opcode[0] as usize & 0x3F),
-            0x1 => {
-                // read one more byte
-                let mut length = [0; 1];
-                if let Ok(_) = reader.read_exact(&mut length) {
-                    return Err("Unable to read next byte from stream!".to_string());
+    // value of the given RDB type - what follows the key in RDB and the type byte in DUMP
+    fn read_object<R: Read>(vtype: u8, reader: &mut R) -> std::io::Result<db::KeyValueType> {
+        match vtype {
+            TYPE_STRING => Ok(db::KeyValueType::string(Self::read_string(reader)?)),
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let mut z = sortedset::SortedSet::new();
+                for _ in 0..Self::read_plain_length(reader)? {
+                    let member = Self::read_string(reader)?;
+                    let score = match vtype {
+                        TYPE_ZSET => Self::read_double(reader)?,
+                        _ => {
+                            let mut score = [0; 8];
+                            reader.read_exact(&mut score)?;
+                            f64::from_le_bytes(score)
+                        },
+                    };
+                    z.insert(&String::from_utf8_lossy(&member), score);
                 }
-                return Ok(((opcode[0] as usize & 0x3F) << 8) | length[0] as usize);
+                Ok(db::KeyValueType::SortedSetType(z))
             },
-            0x2 => {
-                let mut length = [0; 4];
-                if let Ok(_) = reader.read_exact(&mut length) {
-                    return Err("Unable to read next byte from stream!".to_string());
+            TYPE_ZSET_LISTPACK => {
+                let entries = listpack_entries(&Self::read_string(reader)?)?;
+                if entries.len() % 2 != 0 {
+                    return Err(invalid_data("Odd number of entries in a zset listpack".to_string()));
                 }
-                return Ok(u32::from_ne_bytes(length) as usize);
-            },
-            0x3 => {
-                match opcode[0] & 0x3F {
-                    0 => {
-                        Ok(1)
-                    },
-                    1 => { // 2 bytes integer
-                        Ok(2)
-                    },
-                    2 => {  // 4 byte integers
-                        Ok(4)
-                    },
-                    _ => {
-                        return Err("Invalid integer encoding!".to_string());
-                    }
+                let mut z = sortedset::SortedSet::new();
+                for pair in entries.chunks(2) {
+                    z.insert(&String::from_utf8_lossy(&pair[0]), parse_double(&pair[1])?);
                 }
+                Ok(db::KeyValueType::SortedSetType(z))
+            },
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                Ok(db::KeyValueType::StreamType(Self::read_stream(vtype, reader)?))
             },
-            _ => Err(format!("Invaid encoding!!! {}", opcode[0])),
+            _ => Err(invalid_data(format!("Value type: {} is not yet supported!", vtype))),
         }
+    }
 
-        //Err(format!("Invaid encoding!!! {}", byte))
+    // 128 bit id as in stream rax keys and pending entries - ms and seq big endian
+    fn read_stream_id<R: Read>(reader: &mut R) -> std::io::Result<streams::StreamId> {
+        let mut id = [0; 16];
+        reader.read_exact(&mut id)?;
+        Ok((u64::from_be_bytes(id[..8].try_into().unwrap()) as u128, u64::from_be_bytes(id[8..].try_into().unwrap())))
+    }
+
+    fn read_millis<R: Read>(reader: &mut R) -> std::io::Result<i64> {
+        let mut time = [0; 8];
+        reader.read_exact(&mut time)?;
+        Ok(i64::from_le_bytes(time))
+    }
+
+    // listpacks of entries, then stream metadata and consumer groups with their pending entries
+    fn read_stream<R: Read>(vtype: u8, reader: &mut R) -> std::io::Result<streams::Streams> {
+        let mut s = streams::Streams::empty();
+        for _ in 0..Self::read_plain_length(reader)? {
+            let master = Self::read_stream_id(&mut &Self::read_string(reader)?[..])?;
+            let lp = listpack_entries(&Self::read_string(reader)?)?;
+            for (id, fields) in stream_listpack_entries(master, &lp)? {
+                s.add(id.0, id.1, fields);
+            }
+        }
+        let _length = Self::read_plain_length(reader)?;
+        let last_id = (Self::read_plain_length(reader)? as u128, Self::read_plain_length(reader)? as u64);
+        let (max_deleted_id, entries_added) = match vtype {
+            TYPE_STREAM_LISTPACKS => ((0, 0), s.len() as u64),
+            _ => {
+                let _first_id = (Self::read_plain_length(reader)?, Self::read_plain_length(reader)?);
+                let max_deleted_id = (Self::read_plain_length(reader)? as u128, Self::read_plain_length(reader)? as u64);
+                (max_deleted_id, Self::read_plain_length(reader)? as u64)
+            },
+        };
+        s.set_id(last_id, Some(entries_added), Some(max_deleted_id)).map_err(invalid_data)?;
+
+        for _ in 0..Self::read_plain_length(reader)? {
+            let name = String::from_utf8_lossy(&Self::read_string(reader)?).to_string();
+            let last_delivered = (Self::read_plain_length(reader)? as u128, Self::read_plain_length(reader)? as u64);
+            // -1 when the number of entries read is not known
+            let entries_read = match vtype {
+                TYPE_STREAM_LISTPACKS => None,
+                _ => Some(Self::read_plain_length(reader)? as u64).filter(|n| *n != u64::MAX),
+            };
+            s.create_group(&name, Some(last_delivered), entries_read).map_err(invalid_data)?;
+            let group = s.groups.get_mut(&name).unwrap();
+            for _ in 0..Self::read_plain_length(reader)? {
+                let id = Self::read_stream_id(reader)?;
+                let delivery_time = Self::read_millis(reader)?.max(0) as u128;
+                let delivery_count = Self::read_plain_length(reader)? as u64;
+                group.pending.insert(id, streams::PendingEntry { consumer: String::new(), delivery_time, delivery_count });
+            }
+            // consumers list their own pending entries
+            for _ in 0..Self::read_plain_length(reader)? {
+                let consumer = String::from_utf8_lossy(&Self::read_string(reader)?).to_string();
+                let seen_time = Self::read_millis(reader)?.max(0) as u128;
+                let active_time = match vtype {
+                    TYPE_STREAM_LISTPACKS_3 => Some(Self::read_millis(reader)?).filter(|t| *t >= 0).map(|t| t as u128),
+                    _ => Some(seen_time),
+                };
+                for _ in 0..Self::read_plain_length(reader)? {
+                    let id = Self::read_stream_id(reader)?;
+                    let entry = group.pending.get_mut(&id)
+                        .ok_or_else(|| invalid_data("Consumer pending entry not in the group".to_string()))?;
+                    entry.consumer = consumer.clone();
+                }
+                group.consumers.insert(consumer, streams::Consumer { seen_time, active_time });
+            }
+            if group.pending.values().any(|p| p.consumer.is_empty()) {
+                return Err(invalid_data("Group pending entry without a consumer".to_string()));
+            }
+        }
+        Ok(s)
     }
 
     pub fn load_rdb(&self, db: &db::DB) -> std::io::Result<()> {
-        if self.directory.len() == 0 || self.rdb_file.len() == 0 {
+        let (directory, rdb_file) = (self.get_rdb_directory(), self.get_rdb_filename());
+        if directory.is_empty() || rdb_file.is_empty() {
             println!("RDB prameters are invalid - so can not parse!!");
             return Ok(());
         }
 
-        if !self.checksum() {
-            println!("invalid checksum.........");
-            return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                format!("RDB checksum does not match!!!")));
-        }
-        let filename = format!("{}/{}", self.directory, self.rdb_file);
+        let filename = format!("{}/{}", directory, rdb_file);
         println!("reading file: {}", &filename);
         // directory and filename are specified.
         let f1 = File::open(filename)?;
         let mut reader = BufReader::new(f1);
+        Self::load(&mut reader, db)
+    }
 
+    // loads RDB content from any source - file or replication stream
+    // stops right after the EOF opcode and checksum
+    pub fn load<R: Read>(reader: &mut R, db: &db::DB) -> std::io::Result<()> {
         // check the signature of this file
         let mut signature = [0; 9];
         reader.read_exact(&mut signature)?;
-        print!("Signature masmatch: ");
-        for i in 0..5 {
-            print!("{} ", signature[i]);
-        }
-        println!();
-        // if we need to check a specific signature
-        //return Err(std::io::Error::new(std::io::ErrorKind::Other,
-        //    format!("RDB signature does not match!!!")));
-
-        println!("REDIS version: {}", String::from_utf8(signature[5..].to_vec()).unwrap());
-        let mut db_num = [0; 1];
-        let mut hash_table_sz = 0;
-        let mut expire_hash_tbl_sz = 0;
+        if &signature[0..5] != b"REDIS" {
+            return Err(invalid_data("RDB signature does not match!!!".to_string()));
+        }
+        println!("REDIS version: {}", String::from_utf8_lossy(&signature[5..]));
+
+        let mut expiry_in_ms: u64 = 0;
         loop {
             //read next byte to interpret what type it is?
-            let mut opcode = [0; 1];
-            reader.read_exact(&mut opcode)?;
-            match opcode[0] {
-                0xFA => {
-                    if let Ok((key, value)) = Self::read_key_value(None, &mut reader) {
-                        println!("0xFA block, key: {:?}, value: {:?}",
-                            String::from_utf8_lossy(&key),
-                            String::from_utf8_lossy(&value));
-                    }
+            let opcode = Self::read_byte(reader)?;
+            match opcode {
+                OPCODE_AUX => {
+                    let key = Self::read_string(reader)?;
+                    let value = Self::read_string(reader)?;
+                    println!("aux field, key: {:?}, value: {:?}",
+                        String::from_utf8_lossy(&key),
+                        String::from_utf8_lossy(&value));
                 },
-                0xFB => {
-                    // read length encoded int
-                    if let Ok(len1) = Self::encoded_length(None, &mut reader) {
-                        println!("Found first integer of length: {}", len1);
-                        hash_table_sz = len1;
-                        /*let mut value1 = vec![0; len1];
-                        reader.read_exact(&mut value1)?;
-                        hash_table_sz = match len1 {
-                            1 => value1[0] as usize,
-                            2 => 110, //u16::from_ne_bytes(value1) as usize,
-                            4 => 220, //u32::from_ne_bytes(value1) as usize,
-                            _ => 0,
-                        };*/
-                        if let Ok(len2) = Self::encoded_length(None, &mut reader) {
-                            println!("Found second integer of length: {}", len2);
-                            expire_hash_tbl_sz = len2;
-                            /*let mut value2 = vec![0; len2];
-                            reader.read_exact(&mut value2)?;
-                            expire_hash_tbl_sz = match len2 {
-                                1 => value2[0] as usize,
-                                2 => 110, //u16::from_ne_bytes(value2) as usize,
-                                4 => 220, //u32::from_ne_bytes(value2) as usize,
-                                _ => 0,
-                            };*/
-
-                            println!("0xFB block, value1: {:?}, value2: {:?}", hash_table_sz, expire_hash_tbl_sz);
-                        }
-                    }
+                OPCODE_RESIZEDB => {
+                    let hash_table_sz = Self::read_plain_length(reader)?;
+                    let expire_hash_tbl_sz = Self::read_plain_length(reader)?;
+                    println!("resizedb, hash table size: {}, expiry hash table size: {}", hash_table_sz, expire_hash_tbl_sz);
                 },
-                0xFC => {
-                    println!("FC Block - please decode me!");
-                    let mut expiry_time_ms_db = [0; 8];
-                    reader.read_exact(&mut expiry_time_ms_db)?;
+                OPCODE_EXPIRETIME_MS => {
                     // RDB uses Little endian
-                    let expiry_time_ms = u64::from_le_bytes(expiry_time_ms_db);
-                    if let Ok((key, value)) = Self::read_key_value_with_type(None, &mut reader) {
-                        println!("0xFC block - key: {}, value: {}, expiry: {} ms to be added in DB",
-                        String::from_utf8_lossy(&key), String::from_utf8_lossy(&value), expiry_time_ms);
-                        if let Err(e) = Self::add_to_db(db, &key, &value, expiry_time_ms) {
-                            println!("Error adding key to the DB: {}", e);
-                        }
-                    }
+                    let mut expiry_time_ms = [0; 8];
+                    reader.read_exact(&mut expiry_time_ms)?;
+                    expiry_in_ms = u64::from_le_bytes(expiry_time_ms);
                 },
-                0xFD => {
-                    println!("FD Block - please decode me!");
-                    let mut expiry_time_sec_db = [0; 4];
-                    reader.read_exact(&mut expiry_time_sec_db)?;
-                    // RDB format uses little endian file format
-                    let expiry_time_sec = u32::from_le_bytes(expiry_time_sec_db) as u64;
-                    if let Ok((key, value)) = Self::read_key_value_with_type(None, &mut reader) {
-                        println!("0xFD block - key: {}, value: {} with expiry {} sec to be added in DB",
-                        String::from_utf8_lossy(&key), String::from_utf8_lossy(&value), expiry_time_sec);
-                        if let Err(e) = Self::add_to_db(db, &key, &value, expiry_time_sec*1000) {
-                            println!("Error adding key to the DB: {}", e);
-                        }
-                    }
+                OPCODE_EXPIRETIME => {
+                    let mut expiry_time_sec = [0; 4];
+                    reader.read_exact(&mut expiry_time_sec)?;
+                    expiry_in_ms = u32::from_le_bytes(expiry_time_sec) as u64 * 1000;
+                },
+                OPCODE_SELECTDB => {
+                    let db_num = Self::read_plain_length(reader)?;
+                    println!("select DB Number: {}", db_num);
+                },
+                OPCODE_IDLE => {
+                    let _idle = Self::read_plain_length(reader)?;
                 },
-                0xFE => {
-                    reader.read_exact(&mut db_num)?;
-                    println!("0XFE block - DB Number: {}", db_num[0]);
+                OPCODE_FREQ => {
+                    let _freq = Self::read_byte(reader)?;
                 },
-                0xFF => {
-                    println!("0xFE block - received EOF opcode!!!");
+                OPCODE_MODULE_AUX | OPCODE_FUNCTION2 => {
+                    return Err(invalid_data(format!("RDB opcode {:x} is not supported", opcode)));
+                },
+                OPCODE_EOF => {
+                    // version 5 onwards, 8 byte checksum follows
+                    let mut checksum = [0; 8];
+                    reader.read_exact(&mut checksum)?;
+                    println!("received EOF opcode!!!");
                     break;
                 },
-                _ => {
-                    println!("Invalid Opcode found, lets try reading the key and value pair: {:x}", opcode[0]);
-                    println!("hash table size: {}, expiry hash table size: {}", hash_table_sz, expire_hash_tbl_sz);
-                    let mut start_opcode = Some(opcode[0]);
-                    let mut keys_read = 0;
-                    while keys_read < hash_table_sz{
-                        if let Ok((k, v)) = Self::read_key_value_with_type(start_opcode, &mut reader) {
-                            if let Err(e) = Self::add_to_db(db, &k, &v, 0) {
-                                println!("Error adding key to the DB: {}", e);
-                            }
-                        } else { break; }
-                        start_opcode = None;
-                        keys_read += 1;
+                vtype => {
+                    let k = Self::read_string(reader)?;
+                    let v = Self::read_object(vtype, reader)?;
+                    println!("read key: {:?} of type {}", String::from_utf8_lossy(&k), v.type_name());
+                    if let Err(e) = Self::add_to_db(db, &k, v, expiry_in_ms) {
+                        println!("Error adding key to the DB: {}", e);
                     }
-                    //return Err(std::io::Error::new(std::io::ErrorKind::Other,
-                    //    format!("Invalid RDB Opcode found: {}", opcode[0])));
+                    expiry_in_ms = 0;
                 }
             }
         }
@@ -287,11 +396,438 @@ impl RDB {
         Ok(())
     }
 
-    pub fn empty() -> Result<Vec<u8>, base64::DecodeError> {
-        let mut buffer = vec![];
-        let empty_rdb = BASE64_STANDARD.decode(b"UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==")?;
-        buffer.extend_from_slice(format!("${}\r\n", empty_rdb.len()).as_bytes());
-        buffer.extend_from_slice(&empty_rdb);
-        Ok(buffer)
+    fn write_length<W: Write>(writer: &mut W, len: usize) -> std::io::Result<()> {
+        if len < 1 << 6 {
+            writer.write_all(&[len as u8])
+        } else if len < 1 << 14 {
+            writer.write_all(&[0x40 | (len >> 8) as u8, len as u8])
+        } else if len <= u32::MAX as usize {
+            writer.write_all(&[0x80])?;
+            writer.write_all(&(len as u32).to_be_bytes())
+        } else {
+            writer.write_all(&[0x81])?;
+            writer.write_all(&(len as u64).to_be_bytes())
+        }
+    }
+
+    fn write_string<W: Write>(writer: &mut W, value: &[u8]) -> std::io::Result<()> {
+        Self::write_length(writer, value.len())?;
+        writer.write_all(value)
+    }
+
+    // RDB type the value is saved as
+    fn object_type(value: &db::KeyValueType) -> u8 {
+        match value {
+            db::KeyValueType::StringType(_) | db::KeyValueType::IntegerType(_) => TYPE_STRING,
+            db::KeyValueType::SortedSetType(_) => TYPE_ZSET_2,
+            db::KeyValueType::StreamType(_) => TYPE_STREAM_LISTPACKS_3,
+        }
+    }
+
+    fn write_object<W: Write>(writer: &mut W, value: &db::KeyValueType) -> std::io::Result<()> {
+        match value {
+            // integers that fit 32 bits take the integer string encoding
+            db::KeyValueType::IntegerType(n) if *n >= i8::MIN as i64 && *n <= i8::MAX as i64 => {
+                writer.write_all(&[0xC0 | ENC_INT8, *n as i8 as u8])
+            },
+            db::KeyValueType::IntegerType(n) if *n >= i16::MIN as i64 && *n <= i16::MAX as i64 => {
+                writer.write_all(&[0xC0 | ENC_INT16])?;
+                writer.write_all(&(*n as i16).to_le_bytes())
+            },
+            db::KeyValueType::IntegerType(n) if *n >= i32::MIN as i64 && *n <= i32::MAX as i64 => {
+                writer.write_all(&[0xC0 | ENC_INT32])?;
+                writer.write_all(&(*n as i32).to_le_bytes())
+            },
+            db::KeyValueType::StringType(_) | db::KeyValueType::IntegerType(_) => {
+                Self::write_string(writer, &value.as_bytes().unwrap_or_default())
+            },
+            db::KeyValueType::SortedSetType(z) => {
+                Self::write_length(writer, z.len())?;
+                // redis saves from the highest score so that loading appends
+                for (member, score) in z.iter().collect::<Vec<_>>().into_iter().rev() {
+                    Self::write_string(writer, member.as_bytes())?;
+                    writer.write_all(&score.to_le_bytes())?;
+                }
+                Ok(())
+            },
+            db::KeyValueType::StreamType(s) => Self::write_stream(writer, s),
+        }
+    }
+
+    fn write_stream_id<W: Write>(writer: &mut W, id: streams::StreamId) -> std::io::Result<()> {
+        writer.write_all(&(id.0 as u64).to_be_bytes())?;
+        writer.write_all(&id.1.to_be_bytes())
+    }
+
+    fn write_id_lengths<W: Write>(writer: &mut W, id: streams::StreamId) -> std::io::Result<()> {
+        Self::write_length(writer, id.0 as usize)?;
+        Self::write_length(writer, id.1 as usize)
+    }
+
+    // each block becomes a listpack keyed by its master id - the layout of RDB_TYPE_STREAM_LISTPACKS_3
+    fn write_stream<W: Write>(writer: &mut W, s: &streams::Streams) -> std::io::Result<()> {
+        let blocks = s.entries.blocks().filter(|(_master, entries)| !entries.is_empty()).collect::<Vec<_>>();
+        Self::write_length(writer, blocks.len())?;
+        for (master, entries) in blocks {
+            let mut key = vec![];
+            Self::write_stream_id(&mut key, master)?;
+            Self::write_string(writer, &key)?;
+            Self::write_string(writer, &stream_listpack(master, &entries))?;
+        }
+        Self::write_length(writer, s.len())?;
+        Self::write_id_lengths(writer, s.last_entry_key())?;
+        Self::write_id_lengths(writer, s.first_id().unwrap_or((0, 0)))?;
+        Self::write_id_lengths(writer, s.max_deleted_id())?;
+        Self::write_length(writer, s.entries_added() as usize)?;
+
+        Self::write_length(writer, s.groups.len())?;
+        for (name, group) in s.groups.iter() {
+            Self::write_string(writer, name.as_bytes())?;
+            Self::write_id_lengths(writer, group.last_delivered)?;
+            Self::write_length(writer, group.entries_read.unwrap_or(u64::MAX) as usize)?;
+            Self::write_length(writer, group.pending.len())?;
+            for (id, entry) in group.pending.iter() {
+                Self::write_stream_id(writer, *id)?;
+                writer.write_all(&(entry.delivery_time as i64).to_le_bytes())?;
+                Self::write_length(writer, entry.delivery_count as usize)?;
+            }
+            Self::write_length(writer, group.consumers.len())?;
+            for (consumer, c) in group.consumers.iter() {
+                Self::write_string(writer, consumer.as_bytes())?;
+                writer.write_all(&(c.seen_time as i64).to_le_bytes())?;
+                writer.write_all(&c.active_time.map(|t| t as i64).unwrap_or(-1).to_le_bytes())?;
+                let owned = group.pending.iter().filter(|(_id, p)| &p.consumer == consumer).collect::<Vec<_>>();
+                Self::write_length(writer, owned.len())?;
+                for (id, _entry) in owned {
+                    Self::write_stream_id(writer, *id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // DUMP payload: type | value | RDB version (2 bytes LE) | CRC64 of all before (8 bytes LE)
+    pub fn dump_value(value: &db::KeyValueType) -> Result<Vec<u8>, String> {
+        let mut payload = vec![Self::object_type(value)];
+        Self::write_object(&mut payload, value).map_err(|e| format!("ERR {}", e))?;
+        payload.extend_from_slice(&DUMP_VERSION.to_le_bytes());
+        let crc = crc64(&payload);
+        payload.extend_from_slice(&crc.to_le_bytes());
+        Ok(payload)
+    }
+
+    // value of a DUMP payload - checks its version and checksum first
+    pub fn restore_value(payload: &[u8]) -> Result<db::KeyValueType, String> {
+        if payload.len() < 10 {
+            return Err("ERR DUMP payload version or checksum are wrong".to_string());
+        }
+        let (data, crc) = payload.split_at(payload.len() - 8);
+        let version = u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]]);
+        if version > DUMP_VERSION || crc64(data) != u64::from_le_bytes(crc.try_into().unwrap()) {
+            return Err("ERR DUMP payload version or checksum are wrong".to_string());
+        }
+        let mut reader = &data[1..data.len() - 2];
+        let value = Self::read_object(data[0], &mut reader).map_err(|_| "ERR Bad data format".to_string())?;
+        if !reader.is_empty() {
+            return Err("ERR Bad data format".to_string());
+        }
+        Ok(value)
+    }
+
+    // serializes the snapshot in RDB format
+    // keys are (key, value, absolute expiry in unix ms), len and expires are their counts
+    pub fn dump<'a, W: Write>(
+        writer: &mut W,
+        len: usize,
+        expires: usize,
+        entries: impl Iterator<Item = (&'a str, &'a db::KeyValueType, Option<u128>)>,
+    ) -> std::io::Result<()> {
+        writer.write_all(RDB_VERSION)?;
+        let ctime = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().to_string();
+        for (key, value) in [("redis-ver", "7.2.0"), ("redis-bits", "64"), ("ctime", ctime.as_str())] {
+            writer.write_all(&[OPCODE_AUX])?;
+            Self::write_string(writer, key.as_bytes())?;
+            Self::write_string(writer, value.as_bytes())?;
+        }
+
+        writer.write_all(&[OPCODE_SELECTDB, 0])?;
+        writer.write_all(&[OPCODE_RESIZEDB])?;
+        Self::write_length(writer, len)?;
+        Self::write_length(writer, expires)?;
+
+        for (key, value, expiry) in entries {
+            if let Some(expiry) = expiry {
+                writer.write_all(&[OPCODE_EXPIRETIME_MS])?;
+                writer.write_all(&(expiry as u64).to_le_bytes())?;
+            }
+            writer.write_all(&[Self::object_type(value)])?;
+            Self::write_string(writer, key.as_bytes())?;
+            Self::write_object(writer, value)?;
+        }
+
+        // checksum of zero means its not computed
+        writer.write_all(&[OPCODE_EOF])?;
+        writer.write_all(&[0; 8])
+    }
+
+    // saves the snapshot to the configured RDB file - write to a temp file and rename
+    pub fn save(&self, db: &db::DB) -> std::io::Result<String> {
+        let path = self.path();
+        let temp_path = format!("{}.temp-{}", path, std::process::id());
+        {
+            let mut writer = std::io::BufWriter::new(File::create(&temp_path)?);
+            db.dump(&mut writer)?;
+            writer.flush()?;
+        }
+        std::fs::rename(&temp_path, &path)?;
+        Ok(path)
+    }
+}
+
+fn parse_double(value: &[u8]) -> std::io::Result<f64> {
+    std::str::from_utf8(value).ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .ok_or(invalid_data(format!("Invalid double: {:?}", String::from_utf8_lossy(value))))
+}
+
+// CRC-64/Jones as redis uses for DUMP payloads - reflected, no final xor
+fn crc64(data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5; // 0xad93d23594c935a9 reflected
+    let mut crc = 0u64;
+    for byte in data {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+// entries of a redis listpack as strings - integers are given in decimal
+// layout: total bytes (4) | count (2) | entries | 0xFF
+// entry: encoding with data | length of both encoded backwards
+fn listpack_entries(lp: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+    let truncated = || invalid_data("Truncated listpack".to_string());
+    if lp.len() < 7 || u32::from_le_bytes(lp[0..4].try_into().unwrap()) as usize != lp.len() {
+        return Err(invalid_data("Invalid listpack header".to_string()));
+    }
+    let mut entries = vec![];
+    let mut pos = 6;
+    loop {
+        let enc = *lp.get(pos).ok_or_else(truncated)?;
+        let int = |len: usize, bits: u32| -> std::io::Result<(Vec<u8>, usize)> {
+            let data = lp.get(pos + 1..pos + 1 + len).ok_or_else(truncated)?;
+            let mut raw = [0u8; 8];
+            raw[..len].copy_from_slice(data);
+            // sign extend from the top bit
+            let value = (u64::from_le_bytes(raw) << (64 - bits)) as i64 >> (64 - bits);
+            Ok((value.to_string().into_bytes(), 1 + len))
+        };
+        let string = |header: usize, len: usize| -> std::io::Result<(Vec<u8>, usize)> {
+            let data = lp.get(pos + header..pos + header + len).ok_or_else(truncated)?;
+            Ok((data.to_vec(), header + len))
+        };
+        let (entry, size) = match enc {
+            0xFF => break,
+            e if e & 0x80 == 0 => ((e as u64).to_string().into_bytes(), 1),
+            e if e & 0xC0 == 0x80 => string(1, (e & 0x3F) as usize)?,
+            e if e & 0xE0 == 0xC0 => {
+                let next = *lp.get(pos + 1).ok_or_else(truncated)? as i64;
+                let value = (((e & 0x1F) as i64) << 8 | next) << 51 >> 51;
+                (value.to_string().into_bytes(), 2)
+            },
+            e if e & 0xF0 == 0xE0 => {
+                let next = *lp.get(pos + 1).ok_or_else(truncated)? as usize;
+                string(2, ((e & 0x0F) as usize) << 8 | next)?
+            },
+            0xF0 => {
+                let len = lp.get(pos + 1..pos + 5).ok_or_else(truncated)?;
+                string(5, u32::from_le_bytes(len.try_into().unwrap()) as usize)?
+            },
+            0xF1 => int(2, 16)?,
+            0xF2 => int(3, 24)?,
+            0xF3 => int(4, 32)?,
+            0xF4 => int(8, 64)?,
+            e => return Err(invalid_data(format!("Invalid listpack encoding: {:x}", e))),
+        };
+        entries.push(entry);
+        pos += size + backlen_size(size);
+    }
+    Ok(entries)
+}
+
+// bytes the backwards length of an entry of size takes
+fn backlen_size(size: usize) -> usize {
+    match size {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+// redis listpack of entries - the ones that are integers get an integer encoding
+fn listpack(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut lp = vec![0; 6];
+    for entry in entries {
+        let int = std::str::from_utf8(entry).ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|n| n.to_string().as_bytes() == &entry[..]);
+        let mut encoded = match int {
+            Some(n @ 0..=127) => vec![n as u8],
+            Some(n @ -4096..=4095) => vec![0xC0 | ((n >> 8) as u8 & 0x1F), n as u8],
+            Some(n @ -32768..=32767) => [&[0xF1][..], &(n as i16).to_le_bytes()].concat(),
+            Some(n @ -8388608..=8388607) => [&[0xF2][..], &(n as i32).to_le_bytes()[..3]].concat(),
+            Some(n) if n >= i32::MIN as i64 && n <= i32::MAX as i64 => [&[0xF3][..], &(n as i32).to_le_bytes()].concat(),
+            Some(n) => [&[0xF4][..], &n.to_le_bytes()].concat(),
+            None if entry.len() < 64 => vec![0x80 | entry.len() as u8],
+            None if entry.len() < 4096 => vec![0xE0 | (entry.len() >> 8) as u8, entry.len() as u8],
+            None => [&[0xF0][..], &(entry.len() as u32).to_le_bytes()].concat(),
+        };
+        if int.is_none() {
+            encoded.extend_from_slice(entry);
+        }
+        // length of the entry again, 7 bits per byte from the end
+        let size = encoded.len();
+        let backlen = backlen_size(size);
+        lp.extend_from_slice(&encoded);
+        for i in (0..backlen).rev() {
+            let byte = ((size >> (7 * i)) & 0x7F) as u8;
+            lp.push(if i == backlen - 1 { byte } else { byte | 0x80 });
+        }
+    }
+    lp.push(0xFF);
+    let total = lp.len() as u32;
+    lp[0..4].copy_from_slice(&total.to_le_bytes());
+    lp[4..6].copy_from_slice(&(entries.len().min(u16::MAX as usize) as u16).to_le_bytes());
+    lp
+}
+
+// listpack of a stream block - a master entry with the field names of the first
+// entry, then the entries with ids relative to the master id
+// master entry: count | deleted | number of fields | fields | 0
+// entry: flags | ms diff | seq diff | [number of fields | field value pairs | values] | lp count
+fn stream_listpack(master: streams::StreamId, entries: &[(streams::StreamId, Vec<String>)]) -> Vec<u8> {
+    let int = |n: i64| n.to_string().into_bytes();
+    let master_fields = entries.first().map(|(_id, f)| f.iter().step_by(2).collect::<Vec<_>>()).unwrap_or_default();
+    let mut lp = vec![int(entries.len() as i64), int(0), int(master_fields.len() as i64)];
+    lp.extend(master_fields.iter().map(|f| f.as_bytes().to_vec()));
+    lp.push(int(0));
+    for (id, fields) in entries {
+        let same = fields.len() == master_fields.len() * 2
+            && fields.iter().step_by(2).zip(master_fields.iter()).all(|(a, b)| a == *b);
+        lp.push(int(if same { STREAM_ITEM_SAME_FIELDS } else { 0 }));
+        lp.push(int((id.0 as u64).wrapping_sub(master.0 as u64) as i64));
+        lp.push(int(id.1.wrapping_sub(master.1) as i64));
+        let n = fields.len() / 2;
+        if same {
+            lp.extend(fields.iter().skip(1).step_by(2).map(|v| v.as_bytes().to_vec()));
+            lp.push(int(n as i64 + 3));
+        } else {
+            lp.push(int(n as i64));
+            lp.extend(fields.iter().map(|f| f.as_bytes().to_vec()));
+            lp.push(int(n as i64 * 2 + 4));
+        }
+    }
+    listpack(&lp)
+}
+
+// live entries of a stream block listpack - see stream_listpack for the layout
+fn stream_listpack_entries(master: streams::StreamId, lp: &[Vec<u8>]) -> std::io::Result<Vec<(streams::StreamId, Vec<String>)>> {
+    let bad = || invalid_data("Invalid stream listpack".to_string());
+    let mut pos = 0;
+    let mut next = || -> std::io::Result<&Vec<u8>> {
+        pos += 1;
+        lp.get(pos - 1).ok_or_else(bad)
+    };
+    let int = |value: &Vec<u8>| std::str::from_utf8(value).ok().and_then(|s| s.parse::<i64>().ok()).ok_or_else(bad);
+    let string = |value: &Vec<u8>| String::from_utf8_lossy(value).to_string();
+
+    let count = int(next()?)?;
+    let deleted = int(next()?)?;
+    let mut master_fields = vec![];
+    for _ in 0..int(next()?)? {
+        master_fields.push(string(next()?));
+    }
+    next()?;
+    let mut entries = vec![];
+    for _ in 0..count + deleted {
+        let flags = int(next()?)?;
+        let id = ((master.0 as u64).wrapping_add(int(next()?)? as u64) as u128, master.1.wrapping_add(int(next()?)? as u64));
+        let mut fields = vec![];
+        if flags & STREAM_ITEM_SAME_FIELDS != 0 {
+            for field in master_fields.iter() {
+                fields.push(field.clone());
+                fields.push(string(next()?));
+            }
+        } else {
+            for _ in 0..int(next()?)? * 2 {
+                fields.push(string(next()?));
+            }
+        }
+        next()?; // lp count
+        if flags & STREAM_ITEM_DELETED == 0 {
+            entries.push((id, fields));
+        }
+    }
+    Ok(entries)
+}
+
+// LZF decompression as used by redis for compressed strings
+fn lzf_decompress(input: &[u8], out_len: usize) -> std::io::Result<Vec<u8>> {
+    let mut output: Vec<u8> = Vec::with_capacity(out_len);
+    let mut idx = 0;
+    while idx < input.len() {
+        let ctrl = input[idx] as usize;
+        idx += 1;
+        if ctrl < 32 {
+            // literal run of ctrl + 1 bytes
+            let run = ctrl + 1;
+            if idx + run > input.len() {
+                return Err(invalid_data("LZF literal run past end of input".to_string()));
+            }
+            output.extend_from_slice(&input[idx..idx + run]);
+            idx += run;
+        } else {
+            // back reference
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                if idx >= input.len() {
+                    return Err(invalid_data("LZF truncated back reference".to_string()));
+                }
+                len += input[idx] as usize;
+                idx += 1;
+            }
+            if idx >= input.len() {
+                return Err(invalid_data("LZF truncated back reference".to_string()));
+            }
+            let back = ((ctrl & 0x1F) << 8) + input[idx] as usize + 1;
+            idx += 1;
+            if back > output.len() {
+                return Err(invalid_data("LZF back reference before start of output".to_string()));
+            }
+            let start = output.len() - back;
+            for i in 0..len + 2 {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+    }
+    if output.len() != out_len {
+        return Err(invalid_data(format!("LZF decompressed {} bytes, expected {}", output.len(), out_len)));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // check value of CRC-64/Jones as redis tests it in crc64.c
+    #[test]
+    fn crc64_known_vectors() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
     }
 }
@@ -1,14 +1,57 @@
 use bytes::BytesMut;
 use std::io::ErrorKind;
 use std::io::{Read, Write};
-use std::net::{Shutdown, TcpStream};
-use std::sync::mpsc::Receiver;
+use std::net::Shutdown;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::Arc;
 use std::sync::RwLock;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 
+use crate::client::connection::Connection;
 use crate::store::db;
 
+// replication ID this node hands out when it is the master
+pub const MASTER_REPLID: &str = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb";
+const SYNC_CHECK_INTERVAL_MS: u64 = 100;
+pub const EOF_MARK_LEN: usize = 40;
+
+// repl-diskless-load: how replica loads the RDB received from its master
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisklessLoad {
+    Disabled,   // save to disk first and then load it
+    OnEmptyDb,  // diskless only when there is no data to lose on failure
+    SwapDb,     // parse the stream into a fresh DB and swap it in
+}
+
+impl DisklessLoad {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "disabled" => Ok(DisklessLoad::Disabled),
+            "on-empty-db" => Ok(DisklessLoad::OnEmptyDb),
+            "swapdb" => Ok(DisklessLoad::SwapDb),
+            _ => Err(format!("invalid repl-diskless-load value: {}", value)),
+        }
+    }
+}
+
+// repl-diskless-sync / repl-diskless-sync-delay
+// master streams RDB straight to the replica sockets instead of saving it first.
+// delay (in seconds) lets more replicas join the same transfer
+#[derive(Debug, Clone)]
+pub struct DisklessSync {
+    pub enabled: bool,
+    pub delay: u64,
+}
+
+impl DisklessSync {
+    pub fn new(enabled: bool, delay: u64) -> Self {
+        Self { enabled, delay }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct ReplicationNode {
@@ -18,10 +61,15 @@ pub struct ReplicationNode {
     //capa: String,
     port: u16,
     eof: bool,
-    connection: Option<TcpStream>,
+    connection: Option<Connection>,
     ready: bool,
     repl_id: u64,
     ack_id: u64,
+    sent_offset: u64,   // bytes sent to this replica since it synced
+    ack_offset: u64,    // last offset acknowledged by the replica
+    wait_offset: u64,   // offset replica must ack for WAIT to count it
+    last_ack: Option<Instant>,
+    sync_requested: Option<Instant>, // waiting for full sync since
 }
 
 impl ReplicationNode {
@@ -36,6 +84,11 @@ impl ReplicationNode {
             ready: false,
             repl_id: 0,
             ack_id: 0,
+            sent_offset: 0,
+            ack_offset: 0,
+            wait_offset: 0,
+            last_ack: None,
+            sync_requested: None,
         }
     }
 
@@ -54,8 +107,11 @@ impl ReplicationNode {
             while self.repl_id < buffers.len() as u64 {
                 let rslt = connection.write_all(&buffers[self.repl_id as usize]);
                 if rslt.is_err() {
+                    // replica is gone - it must not hold on to the backlog
+                    self.connection = None;
                     return rslt;
                 }
+                self.sent_offset += buffers[self.repl_id as usize].len() as u64;
                 self.repl_id += 1;
             }
         }
@@ -65,15 +121,25 @@ impl ReplicationNode {
         ))
     }
 
-    fn replication_acked(&mut self, _id: u64) -> Result<(), String>{
+    fn replication_acked(&mut self, offset: u64) -> Result<(), String>{
         if !self.ready {
             println!("node not ready for replication...");
             return Ok(());
         }
         self.ack_id += 1;
+        self.ack_offset = offset;
+        self.last_ack = Some(Instant::now());
         Ok(())
     }
 
+    // replica is good if it acked within the max lag (in seconds)
+    fn good(&self, max_lag: u64) -> bool {
+        match self.last_ack {
+            Some(at) => self.ready && at.elapsed() <= Duration::from_secs(max_lag),
+            None => false,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn shutdown(&mut self) {
         if let Some(connection) = self.connection.take() {
@@ -86,11 +152,14 @@ impl ReplicationNode {
         self.ready = true;
     }
 
-    fn get_ack(&mut self, _ack: u64) -> std::io::Result<()> {
-        let cmd = b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
-        if let Some(connection) = self.connection.as_mut() {
+    // marks the offset replica has to ack for WAIT
+    // replica reports the offset before processing GETACK itself
+    fn get_ack(&mut self, buffers: &Vec<BytesMut>) -> std::io::Result<()> {
+        if self.connection.is_some() {
             println!("sending replconf getack request to slave: {}, ip: {}, port: {}", self.peer_addr, self.ip, self.port);
-            connection.write_all(cmd)?
+            let _ = self.replicate(buffers);
+            self.wait_offset = self.sent_offset;
+            return Ok(());
         }
         Err(std::io::Error::new(
             ErrorKind::Other,
@@ -99,11 +168,12 @@ impl ReplicationNode {
     }
 
     fn pending(&self) -> bool {
-        self.ack_id < self.repl_id
+        self.ack_offset < self.wait_offset
     }
 
     fn clear_pending_acks(&mut self) {
         self.ack_id = self.repl_id;
+        self.wait_offset = 0;
     }
 }
 
@@ -119,25 +189,123 @@ impl ReplicationConfigInternal {
 
 struct ReplicationCommands {
     commands: Vec<BytesMut>,
+    bytes: u64, // length of all the commands
 }
 
 impl ReplicationCommands {
     pub fn new() -> Self {
-        Self { commands: vec![] }
+        Self { commands: vec![], bytes: 0 }
+    }
+
+    fn push(&mut self, data: BytesMut) {
+        self.bytes += data.len() as u64;
+        self.commands.push(data);
+    }
+
+    fn clear(&mut self) {
+        self.commands.clear();
+        self.bytes = 0;
+    }
+
+    // drops the oldest commands while more than limit bytes are kept, but none
+    // from keep_from on - returns the number of commands and bytes dropped
+    fn trim(&mut self, keep_from: usize, limit: u64) -> (usize, u64) {
+        let (mut count, mut bytes) = (0, 0);
+        while count < keep_from && self.bytes - bytes > limit {
+            bytes += self.commands[count].len() as u64;
+            count += 1;
+        }
+        self.commands.drain(..count);
+        self.bytes -= bytes;
+        (count, bytes)
+    }
+
+    fn num_bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+// replication stream served to the replicas: its ID and offset of the first
+// buffered command. replicas inherit these from their master so that
+// sub-replicas see the exact same stream
+#[derive(Debug, Clone)]
+struct ReplicationStream {
+    replid: String,
+    base_offset: u64,
+}
+
+// min-replicas-to-write / min-replicas-max-lag
+// master refuses writes unless enough replicas acked within max lag seconds
+#[derive(Debug, Clone)]
+pub struct MinReplicas {
+    pub to_write: usize,
+    pub max_lag: u64,
+}
+
+impl MinReplicas {
+    pub fn new(to_write: usize, max_lag: u64) -> Self {
+        Self { to_write, max_lag }
+    }
+
+    // both have to be non zero for the check to be enabled
+    fn enabled(&self) -> bool {
+        self.to_write > 0 && self.max_lag > 0
     }
 }
 
 pub struct ReplicationConfig {
     replcfg: RwLock<ReplicationConfigInternal>,
     commands: RwLock<ReplicationCommands>,
+    min_replicas: RwLock<MinReplicas>,
+    stream: RwLock<Option<ReplicationStream>>,
+    role_master: bool,
+    diskless_sync: RwLock<DisklessSync>,
+    backlog_size: RwLock<u64>, // repl-backlog-size
 }
 
 impl ReplicationConfig {
-    pub fn new() -> Self {
+    pub fn new(role_master: bool, min_replicas: MinReplicas, diskless_sync: DisklessSync, backlog_size: u64) -> Self {
+        // replica learns the stream ID from its master with FULLRESYNC
+        let mut stream = None;
+        if role_master {
+            stream = Some(ReplicationStream { replid: MASTER_REPLID.to_string(), base_offset: 0 });
+        }
         Self {
             replcfg: RwLock::new(ReplicationConfigInternal::new()),
             commands: RwLock::new(ReplicationCommands::new()),
+            min_replicas: RwLock::new(min_replicas),
+            stream: RwLock::new(stream),
+            role_master,
+            diskless_sync: RwLock::new(diskless_sync),
+            backlog_size: RwLock::new(backlog_size),
+        }
+    }
+
+    // replica only proxies its master's stream, it can not inject anything into it
+    pub fn role_master(&self) -> bool {
+        self.role_master
+    }
+
+    // replication ID and current offset of the stream we serve
+    // None if replica has not synced with its master yet
+    pub fn replication_info(&self) -> Option<(String, u64)> {
+        // trimming the backlog moves bytes to the base offset under the commands lock
+        let commands = self.commands.read().unwrap();
+        let stream = self.stream.read().unwrap();
+        stream.as_ref().map(|s| (s.replid.clone(), s.base_offset + commands.num_bytes()))
+    }
+
+    // replica completed full sync with its master
+    // sub-replicas synced against the old stream are dropped and must resync
+    pub fn set_upstream(&self, replid: &str, offset: u64) {
+        let mut config = self.replcfg.write().unwrap();
+        let mut commands = self.commands.write().unwrap();
+        for node in config.nodes.iter_mut() {
+            node.shutdown();
         }
+        config.nodes.clear();
+        commands.clear();
+        *self.stream.write().unwrap() = Some(ReplicationStream { replid: replid.to_string(), base_offset: offset });
     }
 
     // finds node by its peer address -> remote IP/port where
@@ -167,41 +335,144 @@ impl ReplicationConfig {
         Ok(())
     }
 
-    pub fn _add_capabilities(&mut self, _peer_addr: &str) {
-        todo!()
+    // REPLCONF capa eof capa psync2 - eof means replica can take diskless transfers
+    pub fn add_capabilities(&self, peer_addr: &str, capabilities: &[String]) {
+        let mut replcfg = self.replcfg.write().unwrap();
+        for node in replcfg.nodes.iter_mut().filter(|node| node.peer_addr == *peer_addr) {
+            if capabilities.iter().any(|c| c == "eof") {
+                node.eof = true;
+            }
+        }
     }
 
     // when PSync command is invoked, we only know the peer address as part
-    // of the command
-    pub fn update_psync_repl_id(&self, peer_addr: &str, repl_id: i64, stream: &mut TcpStream) {
+    // of the command. Replica waits for the replicator to run the full sync
+    pub fn update_psync_repl_id(&self, peer_addr: &str, repl_id: i64, stream: &mut Connection) {
         let mut replcfg = self.replcfg.write().unwrap();
         for i in 0..replcfg.nodes.len() {
             if replcfg.nodes[i].peer_addr == *peer_addr {
-                replcfg.nodes[i].repl_id = if repl_id <= 0 { 0 } else { repl_id as u64 };
                 println!(
-                    "updated node repl id with peer_addr: {} to {}",
-                    peer_addr, replcfg.nodes[i].repl_id
+                    "full sync requested by peer_addr: {}, offset: {}",
+                    peer_addr, repl_id
                 );
-                // finally mark it ready
-                replcfg.nodes[i].ready = true;
+                replcfg.nodes[i].ready = false;
                 if let Ok(cloned_stream) = stream.try_clone() {
                     println!("able to clone connection!!!!");
                     replcfg.nodes[i].connection = Some(cloned_stream);
+                    replcfg.nodes[i].sync_requested = Some(Instant::now());
                 }
             }
         }
     }
 
-    pub fn get_acks(&self, ackid: u64)-> std::io::Result<()>{
+    // replicas due for full sync - diskless ones wait for the sync delay
+    // so that they can share the same transfer
+    fn due_for_sync(&self) -> Vec<String> {
+        let diskless = self.diskless_sync.read().unwrap().clone();
+        let replcfg = self.replcfg.read().unwrap();
+        let waiting = replcfg.nodes.iter().filter(|node| node.sync_requested.is_some());
+        let diskless_due = replcfg.nodes.iter()
+            .filter_map(|node| node.sync_requested.filter(|_| diskless.enabled && node.eof))
+            .any(|at| at.elapsed() >= Duration::from_secs(diskless.delay));
+        waiting
+            .filter(|node| !(diskless.enabled && node.eof) || diskless_due)
+            .map(|node| node.peer_addr.clone())
+            .collect()
+    }
+
+    // replicas start from the current end of the buffered stream
+    // returns connections to send the RDB to and if the transfer is diskless
+    fn begin_sync(&self, peers: &[String]) -> Vec<(String, Connection, bool)> {
+        let diskless = self.diskless_sync.read().unwrap().enabled;
+        let mut replcfg = self.replcfg.write().unwrap();
+        let commands = self.commands.read().unwrap();
+        let mut targets = vec![];
+        for node in replcfg.nodes.iter_mut().filter(|node| peers.contains(&node.peer_addr)) {
+            node.sync_requested = None;
+            node.repl_id = commands.commands.len() as u64;
+            node.sent_offset = 0;
+            node.ack_offset = 0;
+            node.wait_offset = 0;
+            if let Some(connection) = node.connection.as_ref().and_then(|c| c.try_clone().ok()) {
+                targets.push((node.peer_addr.clone(), connection, diskless && node.eof));
+            }
+        }
+        targets
+    }
+
+    // RDB transfer done - replica gets the stream buffered since the snapshot
+    fn sync_completed(&self, peer_addr: &str) {
+        let mut replcfg = self.replcfg.write().unwrap();
+        let commands = self.commands.read().unwrap();
+        for node in replcfg.nodes.iter_mut().filter(|node| node.peer_addr == *peer_addr) {
+            node.ready = true;
+            let _ = node.replicate(&commands.commands);
+        }
+        drop(commands);
+        self.trim_backlog(&mut replcfg, &mut self.commands.write().unwrap());
+    }
+
+    fn push_command(&self, data: BytesMut) {
+        let mut config = self.replcfg.write().unwrap();
+        let mut commands = self.commands.write().unwrap();
+        if !data.is_empty() {
+            commands.push(data);
+        }
+        for i in 0..config.nodes.len() {
+            let _ = config.nodes[i].replicate(&commands.commands);
+        }
+        self.trim_backlog(&mut config, &mut commands);
+    }
+
+    // keeps the backlog within repl-backlog-size - commands a replica was not
+    // sent yet stay, a replica in full sync needs all since its snapshot
+    fn trim_backlog(&self, config: &mut ReplicationConfigInternal, commands: &mut ReplicationCommands) {
+        let keep_from = config.nodes.iter()
+            .filter(|node| node.connection.is_some() && node.sync_requested.is_none())
+            .map(|node| node.repl_id as usize)
+            .min()
+            .unwrap_or(commands.commands.len());
+        let (count, bytes) = commands.trim(keep_from, *self.backlog_size.read().unwrap());
+        if count == 0 {
+            return;
+        }
+        for node in config.nodes.iter_mut() {
+            node.repl_id = node.repl_id.saturating_sub(count as u64);
+        }
+        if let Some(stream) = self.stream.write().unwrap().as_mut() {
+            stream.base_offset += bytes;
+        }
+    }
+
+    // GETACK is part of the replication stream so that offsets match
+    // across master, replicas and sub-replicas
+    pub fn get_acks(&self, _ackid: u64)-> std::io::Result<()>{
         let mut config = self.replcfg.write().unwrap();
+        let mut commands = self.commands.write().unwrap();
         for  i in 0..config.nodes.len() {
-            let _ = config.nodes[i].get_ack(ackid);
+            let _ = config.nodes[i].get_ack(&commands.commands);
         }
-        if true { return Ok(()); }
-        Err(std::io::Error::new(
-            ErrorKind::Other,
-            "No connection to the replica! we should not be here",
-        ))
+        commands.push(BytesMut::from(&b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n"[..]));
+        for  i in 0..config.nodes.len() {
+            let _ = config.nodes[i].replicate(&commands.commands);
+        }
+        self.trim_backlog(&mut config, &mut commands);
+        Ok(())
+    }
+
+    // heap held by the commands kept for replicas
+    pub fn buffered_memory(&self) -> usize {
+        let commands = self.commands.read().unwrap();
+        commands.num_bytes() as usize + commands.commands.capacity() * std::mem::size_of::<BytesMut>()
+    }
+
+    // bytes replicas still have to be sent
+    pub fn replica_output(&self) -> usize {
+        let config = self.replcfg.read().unwrap();
+        let commands = self.commands.read().unwrap();
+        config.nodes.iter()
+            .map(|node| commands.commands.iter().skip(node.repl_id as usize).map(|c| c.len()).sum::<usize>())
+            .sum()
     }
 
     pub fn num_replicas(&self) -> usize {
@@ -212,7 +483,7 @@ impl ReplicationConfig {
         self.replcfg.read().unwrap().nodes.iter().filter(|node| !node.pending()).count()
     }
 
-    pub fn replication_acked(&self, peer_addr: &str, _ack_id: u64) -> Result<(), String>{
+    pub fn replication_acked(&self, peer_addr: &str, offset: u64) -> Result<(), String>{
         let mut replcfg = self.replcfg.write().unwrap();
         for i in 0..replcfg.nodes.len() {
             if replcfg.nodes[i].peer_addr == *peer_addr {
@@ -220,12 +491,43 @@ impl ReplicationConfig {
                     "Replicatin node acked: {} to {}:{}",
                     peer_addr, replcfg.nodes[i].ip, replcfg.nodes[i].port
                 );
-                let _ = replcfg.nodes[i].replication_acked(_ack_id);
+                let _ = replcfg.nodes[i].replication_acked(offset);
             }
         }
         Ok(())
     }
 
+    pub fn min_replicas(&self) -> MinReplicas {
+        self.min_replicas.read().unwrap().clone()
+    }
+
+    pub fn set_min_replicas(&self, min_replicas: MinReplicas) {
+        *self.min_replicas.write().unwrap() = min_replicas;
+    }
+
+    pub fn set_diskless_sync(&self, diskless_sync: DisklessSync) {
+        *self.diskless_sync.write().unwrap() = diskless_sync;
+    }
+
+    pub fn set_backlog_size(&self, backlog_size: u64) {
+        *self.backlog_size.write().unwrap() = backlog_size;
+    }
+
+    // replicas that acked within min-replicas-max-lag seconds
+    pub fn good_replicas(&self) -> usize {
+        let max_lag = self.min_replicas.read().unwrap().max_lag;
+        self.replcfg.read().unwrap().nodes.iter().filter(|node| node.good(max_lag)).count()
+    }
+
+    // false when min-replicas-to-write is configured and not met
+    pub fn writes_allowed(&self) -> bool {
+        let min_replicas = self.min_replicas();
+        if !min_replicas.enabled() {
+            return true;
+        }
+        self.good_replicas() >= min_replicas.to_write
+    }
+
     pub fn clear_pending_acks(&self) {
         let mut replcfg = self.replcfg.write().unwrap();
         for i in 0..replcfg.nodes.len() {
@@ -251,24 +553,155 @@ pub fn replicator(
     db: Arc<db::DB>,
 ) {
     // replicates the commands
-    while let Ok(data) = repl_ch_rx.recv() {
-        println!("Received a command to replicate...");
-        if db.role_master() {
-            let mut config = replcfg.replcfg.write().unwrap();
-            let mut commands = replcfg.commands.write().unwrap();
-            if data.len() > 0 {
-                commands.commands.push(data);
+    // master sends its own writes, replica proxies its master's stream as is
+    loop {
+        match repl_ch_rx.recv_timeout(Duration::from_millis(SYNC_CHECK_INTERVAL_MS)) {
+            Ok(data) => {
+                println!("Received a command to replicate (master: {})...", db.role_master());
+                replcfg.push_command(data);
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let peers = replcfg.due_for_sync();
+        if !peers.is_empty() {
+            full_sync(&replcfg, &repl_ch_rx, &db, &peers);
+        }
+    }
+}
+
+// full_sync
+//
+// snapshot is taken with no command in flight and every command before it
+// buffered - so replica continues from exactly where the snapshot ends.
+// RDB is written straight from the store while commands wait, a slow
+// replica on a diskless transfer holds up the master until it is done
+fn full_sync(replcfg: &Arc<ReplicationConfig>, repl_ch_rx: &Receiver<BytesMut>, db: &Arc<db::DB>, peers: &[String]) {
+    let guard = db.exclusive_guard();
+    while let Ok(data) = repl_ch_rx.try_recv() {
+        replcfg.push_command(data);
+    }
+    let (replid, offset) = match replcfg.replication_info() {
+        Some(info) => info,
+        None => return,
+    };
+    let targets = replcfg.begin_sync(peers);
+    let header = format!("+FULLRESYNC {} {}\r\n", replid, offset);
+    let (diskless, disk): (Vec<_>, Vec<_>) = targets.into_iter().partition(|(_p, _c, d)| *d);
+
+    let mark = eof_mark();
+    let mut fanout = FanOut::new();
+    if !diskless.is_empty() {
+        for (peer_addr, mut connection, _) in diskless {
+            if connection.write_all(header.as_bytes()).is_ok()
+                && connection.write_all(format!("$EOF:{}\r\n", mark).as_bytes()).is_ok() {
+                fanout.connections.push((peer_addr, connection));
             }
-            for i in 0..config.nodes.len() {
-                let _ = config.nodes[i].replicate(&commands.commands);
+        }
+        let result = {
+            let mut writer = std::io::BufWriter::new(&mut fanout);
+            db.dump(&mut writer).and_then(|_| writer.flush())
+        };
+        println!("diskless transfer done: {:?}", result);
+    }
+    let saved = if disk.is_empty() { None } else { Some(db.rdb().save(db)) };
+    drop(guard);
+
+    fanout.write_all_ok(mark.as_bytes());
+    for (peer_addr, _) in fanout.connections.iter() {
+        replcfg.sync_completed(peer_addr);
+    }
+
+    let path = match saved {
+        Some(Ok(path)) => path,
+        Some(Err(e)) => {
+            println!("failed to save RDB for full sync: {}", e);
+            return;
+        },
+        None => return,
+    };
+    let replcfg = Arc::clone(replcfg);
+    let _ = thread::spawn(move || {
+        for (peer_addr, mut connection, _) in disk {
+            // file is sent in pieces rather than read into memory
+            let sent = std::fs::File::open(&path).and_then(|mut file| {
+                let len = file.metadata()?.len();
+                connection.write_all(header.as_bytes())?;
+                connection.write_all(format!("${}\r\n", len).as_bytes())?;
+                std::io::copy(&mut file, &mut connection)
+            });
+            if sent.is_ok() {
+                replcfg.sync_completed(&peer_addr);
             }
         }
+    });
+}
+
+// 40 random hex characters marking the end of a diskless transfer
+fn eof_mark() -> String {
+    let mut mark = String::new();
+    while mark.len() < EOF_MARK_LEN {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+        let _ = std::fmt::write(&mut mark, format_args!("{:016x}", hasher.finish()));
+    }
+    mark.truncate(EOF_MARK_LEN);
+    mark
+}
+
+// writes same RDB stream to all replicas in a diskless transfer
+// replica that fails is dropped, rest of the transfer goes on
+struct FanOut {
+    connections: Vec<(String, Connection)>,
+}
+
+impl FanOut {
+    fn new() -> Self {
+        Self { connections: vec![] }
+    }
+
+    fn write_all_ok(&mut self, buf: &[u8]) {
+        self.connections.retain_mut(|(_p, c)| c.write_all(buf).is_ok());
+    }
+}
+
+impl Write for FanOut {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_all_ok(buf);
+        if self.connections.is_empty() {
+            return Err(std::io::Error::other("all replicas dropped off the transfer"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
 #[allow(dead_code)]
-fn discard_incoming_data(stream: &mut TcpStream) {
+fn discard_incoming_data(stream: &mut Connection) {
     let mut response: [u8; 1500] = [0; 1500];
     let _ = stream.set_read_timeout(Some(Duration::from_millis(1)));
     let _ = stream.read(&mut response);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backlog_trim_keeps_unsent_commands() {
+        let mut commands = ReplicationCommands::new();
+        for _ in 0..10 {
+            commands.push(BytesMut::from(&[b'x'; 100][..]));
+        }
+        // a replica still needs commands from index 3 on
+        assert_eq!(commands.trim(3, 250), (3, 300));
+        assert_eq!(commands.num_bytes(), 700);
+        assert_eq!(commands.trim(7, 250), (5, 500));
+        assert_eq!((commands.commands.len(), commands.num_bytes()), (2, 200));
+        assert_eq!(commands.trim(2, 250), (0, 0));
+    }
+}
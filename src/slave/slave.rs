@@ -1,8 +1,13 @@
+use crate::client::client;
+use crate::client::connection::Connection;
 use crate::commands::incoming;
+use crate::commands::resp;
+use crate::rdb::rdb;
 use crate::store;
 use crate::repl;
 use bytes::BytesMut;
-use std::io::{Read, Write};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::{Shutdown, TcpStream};
 use std::sync::RwLock;
 use std::thread;
@@ -13,6 +18,10 @@ use std::sync::mpsc::Sender;
 #[allow(dead_code)]
 
 const MAX_RETRIES: u8 = 5;
+const ACK_INTERVAL_MS: u64 = 1000; // replica acks its offset every second
+// wait before setting up the link to master again - doubles up to the max
+const RECONNECT_MIN_MS: u64 = 100;
+const RECONNECT_MAX_MS: u64 = 5000;
 
 trait State {
     fn initiate(self: Box<Self>, stream: &mut TcpStream, config: &MasterNodeConfig) -> Box<dyn State>;
@@ -65,8 +74,9 @@ impl Ping {
                 buf.set_len(len);
             }
             // verify that command contains +PONG
+            // master requiring authentication refuses PING until AUTH
             let cmd = incoming::Incoming::new(&buf, true);
-            if cmd.get_command(0).to_lowercase().contains("pong") {
+            if cmd.get_command(0).to_lowercase().contains("pong") || buf.starts_with(b"-NOAUTH") {
                 return Ok(());
             }
         }
@@ -79,6 +89,67 @@ impl State for Ping {
         while self.retries < MAX_RETRIES {
             self.retries += 1;
             if let Ok(_resp) = self.initiate_internal(stream) {
+                let new_state = Box::new(Auth::new());
+                new_state.clone().initiate(stream, config);
+                return new_state;
+            } else {
+                // sleep for sometime
+                thread::sleep(time::Duration::from_millis(1000 * self.retries as u64));
+            }
+        }
+        self
+    }
+}
+
+// AUTH with masteruser/masterauth - skipped when no masterauth is set
+#[derive(Debug, Clone)]
+struct Auth {
+    retries: u8,
+}
+
+impl Auth {
+    pub fn new() -> Self {
+        Self { retries: 0 }
+    }
+
+    fn initiate_internal(&mut self, stream: &mut TcpStream, auth: &MasterAuth) -> Result<(), String> {
+        let mut args: Vec<&[u8]> = vec![b"AUTH"];
+        if let Some(user) = &auth.user {
+            args.push(user.as_bytes());
+        }
+        args.push(auth.password.as_bytes());
+        if stream.write_all(&resp::command(&args)).is_err() {
+            return Err("Error sending AUTH command".to_string());
+        }
+        let mut buf = BytesMut::with_capacity(500);
+        unsafe {
+            buf.set_len(500);
+        }
+        if let Ok(len) = stream.read(&mut buf) {
+            if len == 0 {
+                return Err("Did not receive appropriate command response (AUTH)".to_string());
+            }
+            unsafe {
+                buf.set_len(len);
+            }
+            if buf.starts_with(b"+OK") {
+                return Ok(());
+            }
+            println!("Unable to AUTH to master: {}", String::from_utf8_lossy(&buf).trim_end());
+        }
+        Err("Unable to move to next state - may be retrying!!".to_string())
+    }
+}
+
+impl State for Auth {
+    fn initiate(mut self: Box<Self>, stream: &mut TcpStream, config: &MasterNodeConfig) -> Box<dyn State> {
+        while self.retries < MAX_RETRIES {
+            self.retries += 1;
+            let result = match &config.auth {
+                Some(auth) => self.initiate_internal(stream, auth),
+                None => Ok(()),
+            };
+            if result.is_ok() {
                 let new_state = Box::new(ReplConf1::new());
                 new_state.clone().initiate(stream, config);
                 return new_state;
@@ -170,8 +241,8 @@ impl ReplConf2 {
         _config: &MasterNodeConfig,
     ) -> Result<(), String> {
         // send SYNC command
-        //*3\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n
-        let command = "*3\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n".to_string();
+        // eof - we can take diskless transfers framed with EOF marker
+        let command = "*5\r\n$8\r\nREPLCONF\r\n$4\r\ncapa\r\n$3\r\neof\r\n$4\r\ncapa\r\n$6\r\npsync2\r\n".to_string();
         let resp = stream.write(command.as_bytes());
         if resp.is_err() {
             return Err(format!("Error sending REPLCONF2 command"));
@@ -294,19 +365,34 @@ impl State for Complete {
     }
 }
 
+// credentials for a master that requires authentication
+#[derive(Debug, Clone)]
+pub struct MasterAuth {
+    user: Option<String>, // default user if not set
+    password: String,
+}
+
+impl MasterAuth {
+    pub fn new(user: Option<String>, password: String) -> Self {
+        Self { user, password }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct MasterNodeConfig {
     master_ip_addr: String,
     master_port: u16,
     my_port: u16,
+    auth: Option<MasterAuth>,
 }
 
 impl MasterNodeConfig {
-    fn new(master_ip_addr: String, master_port: u16, my_port: u16) -> Self{
+    fn new(master_ip_addr: String, master_port: u16, my_port: u16, auth: Option<MasterAuth>) -> Self{
         Self {
             master_ip_addr,
             master_port,
             my_port,
+            auth,
         }
     }
 }
@@ -315,18 +401,18 @@ pub struct Config {
     master_node: MasterNodeConfig,
     stream: Option<TcpStream>,
     state: Option<Box<dyn State>>,
-    in_sync: RwLock<bool>,  // if replica is in sync
-    offset: RwLock<u64>,
+    in_sync: Arc<RwLock<bool>>,  // if replica is in sync
+    offset: Arc<RwLock<u64>>,
 }
 
 impl Config {
-    pub fn new(master_ip_addr: String, master_port: u16, my_port: u16) -> Self {
+    pub fn new(master_ip_addr: String, master_port: u16, my_port: u16, auth: Option<MasterAuth>) -> Self {
         Self {
-            master_node: MasterNodeConfig::new(master_ip_addr, master_port, my_port),
+            master_node: MasterNodeConfig::new(master_ip_addr, master_port, my_port, auth),
             stream: None,
             state: Some(Box::new(Init::new())),
-            in_sync: RwLock::new(false),
-            offset: RwLock::new(u64::MIN),
+            in_sync: Arc::new(RwLock::new(false)),
+            offset: Arc::new(RwLock::new(u64::MIN)),
         }
     }
 
@@ -336,7 +422,7 @@ impl Config {
 
         if self.stream.is_none() {
             let stream =
-                TcpStream::connect(format!("{}:{}", self.master_node.master_ip_addr, self.master_node.master_port));
+                TcpStream::connect((self.master_node.master_ip_addr.as_str(), self.master_node.master_port));
             self.stream = stream.ok();
         }
         if let Some(mut stream) = self.stream.as_mut() {
@@ -364,6 +450,10 @@ impl Config {
         *self.in_sync.write().unwrap() = true;
     }
 
+    pub fn in_sync(&self) -> bool {
+        *self.in_sync.read().unwrap()
+    }
+
     pub fn track_offset(&self, len: u64) {
         let synced = *self.in_sync.read().unwrap();
         if synced {
@@ -377,19 +467,151 @@ impl Config {
 }
 
 
-pub fn slave_thread(
-    db: Arc<store::db::DB>,
-    replcfg: Arc<repl::repl::ReplicationConfig>,
-    repl_ch_tx: Sender<BytesMut>,
-    master_ip_addr: String,
-    master_port: u16,
-    my_port: u16
-) {
-    let mut slave = Config::new(
-        master_ip_addr,
-        master_port,
-        my_port,
-    );
+// ack_thread
+//
+// periodically acks the processed offset so that master can track replica lag
+fn ack_thread(mut stream: Connection, in_sync: Arc<RwLock<bool>>, offset: Arc<RwLock<u64>>) {
+    loop {
+        thread::sleep(time::Duration::from_millis(ACK_INTERVAL_MS));
+        if !*in_sync.read().unwrap() {
+            continue;
+        }
+        let offset_str = offset.read().unwrap().to_string();
+        let response = format!("*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{}\r\n",
+            offset_str.len(), offset_str);
+        if stream.write_all(response.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+// reads a line ending with \r\n - skips newlines master sends to keep
+// the connection alive while it prepares the RDB
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, String> {
+    loop {
+        let mut line = vec![];
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return Err("master closed the connection".to_string()),
+            Ok(_) => {},
+            Err(e) => return Err(format!("failed reading from master: {}", e)),
+        }
+        let text = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+        if !text.is_empty() {
+            return Ok(text);
+        }
+    }
+}
+
+// copies the diskless transfer until EOF marker - marker is not copied
+fn copy_until_mark<R: BufRead, W: Write>(reader: &mut R, writer: &mut W, mark: &[u8]) -> std::io::Result<()> {
+    let mut pending: Vec<u8> = vec![];
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "transfer ended before EOF marker"));
+        }
+        let (chunk_len, old_len) = (chunk.len(), pending.len());
+        pending.extend_from_slice(chunk);
+        if let Some(pos) = pending.windows(mark.len()).position(|w| w == mark) {
+            writer.write_all(&pending[..pos])?;
+            reader.consume(pos + mark.len() - old_len);
+            return Ok(());
+        }
+        reader.consume(chunk_len);
+        // marker may be split across reads
+        let keep = pending.len().min(mark.len() - 1);
+        writer.write_all(&pending[..pending.len() - keep])?;
+        pending.drain(..pending.len() - keep);
+    }
+}
+
+// receive_full_sync
+//
+// +FULLRESYNC <replid> <offset> followed by the RDB as $<len> or $EOF:<mark>
+fn receive_full_sync<R: BufRead>(
+    reader: &mut R,
+    db: &Arc<store::db::DB>,
+    replcfg: &Arc<repl::repl::ReplicationConfig>,
+) -> Result<(), String> {
+    let line = read_line(reader)?;
+    let args = line.split(' ').collect::<Vec<&str>>();
+    if args.len() != 3 || !args[0].eq_ignore_ascii_case("+fullresync") {
+        return Err(format!("unexpected response to PSYNC: {}", line));
+    }
+    let offset = args[2].parse::<u64>().map_err(|e| format!("invalid offset {}: {}", args[2], e))?;
+
+    let header = read_line(reader)?;
+    let mut eof_mark = None;
+    let mut length = 0;
+    if let Some(mark) = header.strip_prefix("$EOF:") {
+        if mark.len() != repl::repl::EOF_MARK_LEN {
+            return Err(format!("invalid EOF marker: {}", header));
+        }
+        eof_mark = Some(mark.as_bytes().to_vec());
+    } else if let Some(len) = header.strip_prefix('$') {
+        length = len.parse::<u64>().map_err(|e| format!("invalid RDB length {}: {}", len, e))?;
+    } else {
+        return Err(format!("unexpected RDB header: {}", header));
+    }
+
+    let fresh = db.fresh();
+    // repl-diskless-load is validated by the config, so it always parses
+    let diskless_load = repl::repl::DisklessLoad::parse(&db.config().string("repl-diskless-load"))
+        .unwrap_or(repl::repl::DisklessLoad::Disabled);
+    let diskless = match diskless_load {
+        repl::repl::DisklessLoad::Disabled => false,
+        repl::repl::DisklessLoad::OnEmptyDb => db.is_empty(),
+        repl::repl::DisklessLoad::SwapDb => true,
+    };
+    if diskless {
+        // parse straight from the socket
+        match eof_mark {
+            Some(mark) => {
+                rdb::RDB::load(reader, &fresh).map_err(|e| format!("failed loading RDB: {}", e))?;
+                let mut received = vec![0; mark.len()];
+                reader.read_exact(&mut received).map_err(|e| format!("failed reading EOF marker: {}", e))?;
+                if received != mark {
+                    return Err("EOF marker does not match".to_string());
+                }
+            },
+            None => {
+                let mut limited = reader.take(length);
+                rdb::RDB::load(&mut limited, &fresh).map_err(|e| format!("failed loading RDB: {}", e))?;
+                let _ = std::io::copy(&mut limited, &mut std::io::sink());
+            }
+        }
+    } else {
+        // save to disk first and load from there
+        let path = db.rdb().path();
+        let temp_path = format!("{}.temp-{}", path, std::process::id());
+        {
+            let mut file = File::create(&temp_path).map_err(|e| format!("failed creating {}: {}", temp_path, e))?;
+            let result = match eof_mark {
+                Some(mark) => copy_until_mark(reader, &mut file, &mark),
+                None => std::io::copy(&mut reader.take(length), &mut file).map(|_| ()),
+            };
+            result.map_err(|e| format!("failed receiving RDB: {}", e))?;
+        }
+        std::fs::rename(&temp_path, &path).map_err(|e| format!("failed renaming {}: {}", temp_path, e))?;
+        let file = File::open(&path).map_err(|e| format!("failed opening {}: {}", path, e))?;
+        rdb::RDB::load(&mut BufReader::new(file), &fresh).map_err(|e| format!("failed loading RDB: {}", e))?;
+    }
+    db.swap(fresh);
+    replcfg.set_upstream(args[1], offset);
+    println!("full sync done with master replid: {}, offset: {}, diskless load: {}", args[1], offset, diskless);
+    Ok(())
+}
+
+// replicate
+//
+// handshake and full sync with master, then applies the commands it streams
+// until the link is lost - Err if it did not get as far as streaming
+fn replicate(
+    mut slave: Config,
+    db: &Arc<store::db::DB>,
+    replcfg: &Arc<repl::repl::ReplicationConfig>,
+    repl_ch_tx: &Sender<BytesMut>,
+) -> Result<(), String> {
     slave.initiate(); // initiate the state machine.
 
     let mut buf = BytesMut::with_capacity(1500);
@@ -398,32 +620,82 @@ pub fn slave_thread(
     }
 
     // take out the stream from inside the config struct to be safe
-    let mut stream = slave.stream.take().unwrap();
+    let mut stream = match slave.stream.take() {
+        Some(s) => Connection::Tcp(s),
+        None => return Err("unable to connect to master".to_string()),
+    };
+    let mut reader = match stream.try_clone() {
+        Ok(s) => BufReader::new(s),
+        Err(e) => return Err(format!("unable to clone connection to master: {}", e)),
+    };
+    if let Err(e) = receive_full_sync(&mut reader, db, replcfg) {
+        let _ = stream.shutdown(Shutdown::Both);
+        return Err(format!("full sync with master failed: {}", e));
+    }
+    slave.synced_in();
+
+    if let Ok(ack_stream) = stream.try_clone() {
+        let in_sync = Arc::clone(&slave.in_sync);
+        let offset = Arc::clone(&slave.offset);
+        let _ = thread::spawn(move || ack_thread(ack_stream, in_sync, offset));
+    }
 
     let slavecfg = Some(slave);
-    // read data from socket
+    let mut client = client::Client::new();
+    // read data from socket - commands sent right after RDB may already be buffered
+    let mut input = BytesMut::new();
     loop {
-        if let Ok(len) = stream.read(&mut buf) {
-            
-            if len <= 0 {
-                //println!("read {len} bytes and hence existing...");
-                break;
-            }
-            unsafe {
-                buf.set_len(len);
-            }
-            let cmd = incoming::Incoming::new(&buf, true);
-            if let Err(e) = cmd.handle(&mut stream, &db, &replcfg, &repl_ch_tx, &slavecfg) {
-                println!("error handling incoming command on master-slave channel: {}, Error: {}", cmd, e);
-                break;
-            }
-            unsafe {
-                buf.set_len(1500);
-            }
+        let len = match reader.read(&mut buf) {
+            Ok(len) if len > 0 => len,
+            _ => break,
+        };
+        // a propagated command may span several reads
+        input.extend_from_slice(&buf[..len]);
+        let complete = resp::complete_len(&input);
+        if complete == 0 {
+            continue;
+        }
+        let commands = input.split_to(complete);
+        let cmd = incoming::Incoming::new(&commands, true);
+        if let Err(e) = cmd.handle(&mut stream, db, replcfg, repl_ch_tx, &slavecfg, &mut client) {
+            println!("error handling incoming command on master-slave channel: {}, Error: {}", cmd, e);
+            break;
         }
     }
 
     println!("replication connection (slave thread): Done with this socket - closing....");
     let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
 
+// slave_thread
+//
+// keeps replicating from master - the link is set up again whenever it fails
+// or gets lost, e.g. when master is not up yet or has no master link itself
+pub fn slave_thread(
+    db: Arc<store::db::DB>,
+    replcfg: Arc<repl::repl::ReplicationConfig>,
+    repl_ch_tx: Sender<BytesMut>,
+    master_ip_addr: String,
+    master_port: u16,
+    my_port: u16,
+    master_auth: Option<MasterAuth>,
+) {
+    let mut backoff = RECONNECT_MIN_MS;
+    loop {
+        let slave = Config::new(
+            master_ip_addr.clone(),
+            master_port,
+            my_port,
+            master_auth.clone(),
+        );
+        match replicate(slave, &db, &replcfg, &repl_ch_tx) {
+            // link was up - try again right away
+            Ok(()) => backoff = RECONNECT_MIN_MS,
+            Err(e) => println!("replication with master failed: {}", e),
+        }
+        println!("reconnecting to master in {} ms", backoff);
+        thread::sleep(time::Duration::from_millis(backoff));
+        backoff = (backoff * 2).min(RECONNECT_MAX_MS);
+    }
 }
@@ -1,28 +1,149 @@
 // maintain in memory DB
+use crate::acl::acl;
 use crate::commands::getset;
+use crate::config::config;
+use crate::pubsub::pubsub;
 use crate::rdb::rdb;
+use crate::store::evict;
 use crate::store::node_info;
+use crate::store::sortedset;
 use crate::store::streams;
-use std::collections::HashMap;
+use crate::utils::memory;
+use crate::utils::memory::MemoryUsage;
+use crate::utils::utils;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[allow(dead_code)]
 
 #[derive(Debug, Clone)]
 pub enum KeyValueType {
-    StringType(String),
+    StringType(Vec<u8>), // binary safe
+    IntegerType(i64), // strings holding an integer are kept as one
     StreamType(streams::Streams),
+    SortedSetType(sortedset::SortedSet),
+}
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+impl KeyValueType {
+    // string value - integer encoded if it reads back the same
+    pub fn string(value: Vec<u8>) -> Self {
+        match std::str::from_utf8(&value).ok().and_then(|s| s.parse::<i64>().ok()) {
+            Some(n) if n.to_string().as_bytes() == value => KeyValueType::IntegerType(n),
+            _ => KeyValueType::StringType(value),
+        }
+    }
+
+    // value of string keys, None for other types
+    pub fn as_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            KeyValueType::StringType(s) => Some(s.clone()),
+            KeyValueType::IntegerType(n) => Some(n.to_string().into_bytes()),
+            KeyValueType::StreamType(_) | KeyValueType::SortedSetType(_) => None,
+        }
+    }
+
+    // name TYPE replies with
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            KeyValueType::StringType(_) | KeyValueType::IntegerType(_) => "string",
+            KeyValueType::StreamType(_) => "stream",
+            KeyValueType::SortedSetType(_) => "zset",
+        }
+    }
+
+    // internal representation as OBJECT ENCODING names it
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            KeyValueType::IntegerType(_) => "int",
+            KeyValueType::StringType(s) if s.len() <= 44 => "embstr",
+            KeyValueType::StringType(_) => "raw",
+            KeyValueType::StreamType(_) => "stream",
+            KeyValueType::SortedSetType(z) if z.len() <= 128 && z.iter().all(|(m, _s)| m.len() <= 64) => "listpack",
+            KeyValueType::SortedSetType(_) => "skiplist",
+        }
+    }
+
+    // number of allocations to free - big values are freed in the background by UNLINK
+    pub fn effort(&self) -> usize {
+        match self {
+            KeyValueType::StringType(_) | KeyValueType::IntegerType(_) => 1,
+            KeyValueType::StreamType(s) => s.len(),
+            KeyValueType::SortedSetType(z) => z.len(),
+        }
+    }
+
+}
+
+impl memory::MemoryUsage for KeyValueType {
+    fn memory_usage(&self, samples: usize) -> usize {
+        std::mem::size_of::<Self>() + match self {
+            KeyValueType::StringType(s) => s.capacity(),
+            KeyValueType::IntegerType(_) => 0,
+            KeyValueType::StreamType(s) => s.memory_usage(samples),
+            KeyValueType::SortedSetType(z) => z.memory_usage(samples),
+        }
+    }
+}
+
+// how a command changes the key it runs on
+pub enum Change {
+    Keep,
+    Value(KeyValueType), // expiry stays as it is
+    Replace(KeyValueType, Option<u64>), // expiry in ms from now, None for no expiry
+    Expiry(Option<u64>),
+    Remove,
+}
+
+// last access in ms since the unix epoch - readers update it under the shared lock
+#[derive(Debug)]
+struct LastAccess(AtomicU64);
+
+impl LastAccess {
+    fn unix_ms() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+
+    fn now() -> Self {
+        Self::ago(0)
+    }
+
+    // accessed ms ago
+    fn ago(ms: u64) -> Self {
+        Self(AtomicU64::new(Self::unix_ms().saturating_sub(ms)))
+    }
+
+    fn update(&self) {
+        self.0.store(Self::unix_ms(), Ordering::Relaxed);
+    }
+
+    // ms since the last access
+    fn idle(&self) -> u64 {
+        Self::unix_ms().saturating_sub(self.0.load(Ordering::Relaxed))
+    }
+}
+
+impl Clone for LastAccess {
+    fn clone(&self) -> Self {
+        Self(AtomicU64::new(self.0.load(Ordering::Relaxed)))
+    }
 }
 
 #[derive(Debug, Clone)]
 struct KeyValueData {
-    key: String,
+    key: Arc<str>, // shared with the key table and the scan index
     value: KeyValueType,
     expires: bool,
     expiring_at: Instant,
+    accessed: LastAccess, // LRU clock
+    frequency: evict::Frequency, // LFU counter
 }
 
 impl KeyValueData {
@@ -33,72 +154,151 @@ impl KeyValueData {
             expires = true;
         }
         Self {
-            key,
+            key: Arc::from(key),
             value,
             expires,
             expiring_at: now + Duration::from_millis(options.expiry_in_ms),
+            accessed: LastAccess::now(),
+            frequency: evict::Frequency::new(),
+        }
+    }
+
+    fn expired(&self) -> bool {
+        self.expires && self.expiring_at < Instant::now()
+    }
+
+    fn hit(&self) {
+        self.accessed.update();
+        self.frequency.hit();
+    }
+
+    // approximate bytes the key takes along with its value - one copy of the key is
+    // shared by the table and the scan index, behind the Arc counters
+    fn memory_usage(&self, samples: usize) -> usize {
+        std::mem::size_of::<Self>() + 2 * std::mem::size_of::<usize>() + self.key.len() + self.value.memory_usage(samples)
+    }
+
+    // keys with a higher score are evicted first
+    fn eviction_score(&self, policy: evict::Policy, now: Instant) -> u64 {
+        match policy {
+            evict::Policy::AllKeysLfu | evict::Policy::VolatileLfu => 255 - self.frequency.counter() as u64,
+            evict::Policy::VolatileTtl => u64::MAX - self.expiring_at.saturating_duration_since(now).as_millis() as u64,
+            _ => self.accessed.idle(),
         }
     }
 }
 
+// version of a watched key - bumped on every change so that EXEC can tell
+// if a key changed after WATCH
+#[derive(Debug, Clone, Default)]
+struct WatchedKey {
+    version: u64,
+    watchers: usize,
+}
+
+// the key table is shrunk when less than 1/8 full, but not below this
+const MIN_TABLE_SIZE: usize = 128;
+
 struct DBInternal {
-    db: HashMap<String, KeyValueData>,
+    db: HashMap<Arc<str>, KeyValueData>,
+    // keys in SCAN order - (scan position, key)
+    scan_index: BTreeSet<(u64, Arc<str>)>,
+    watched: HashMap<String, WatchedKey>,
 }
 
 impl DBInternal {
     fn new() -> Self {
         Self {
             db: HashMap::new(),
+            scan_index: BTreeSet::new(),
+            watched: HashMap::new(),
         }
     }
 
-    // adds key into the store
-    fn add(
-        &mut self,
-        key: String,
-        value: KeyValueType,
-        options: &getset::SetOptions,
-    ) -> Result<(), String> {
-        let v = KeyValueData::new(key.clone(), value.clone(), options);
-        let _ = self
-            .db
-            .entry(key)
-            .and_modify(|val| *val = v.clone())
-            .or_insert(v);
-        //TODO: return appropriately
-        Ok(())
+    fn touch(&mut self, key: &str) {
+        if let Some(w) = self.watched.get_mut(key) {
+            w.version += 1;
+        }
+    }
+
+    // drops key if it is due to expire and marks it accessed otherwise
+    // returns true if it was dropped - it has to be notified once the lock is released
+    fn lookup(&mut self, key: &str) -> bool {
+        match self.db.get(key) {
+            Some(v) if v.expired() => self.remove(key).is_some(),
+            Some(v) => {
+                v.hit();
+                false
+            },
+            None => false,
+        }
+    }
+
+    // up to count keys from a random place in SCAN order - with volatile only keys with an expiry
+    fn sample(&self, count: usize, volatile: bool) -> Vec<&KeyValueData> {
+        let start = (utils::random(), Arc::from(""));
+        self.scan_index.range(&start..).chain(self.scan_index.range(..&start))
+            .filter_map(|(_position, key)| self.db.get(key))
+            .filter(|v| !volatile || v.expires)
+            .take(count)
+            .collect()
+    }
+
+    // next key to evict by the policy
+    fn eviction_candidate(&self, config: &evict::MaxMemory, pool: &mut evict::Pool) -> Option<String> {
+        let volatile = config.policy.volatile();
+        if config.policy.random() {
+            return self.sample(1, volatile).first().map(|v| v.key.to_string());
+        }
+        loop {
+            let sampled = self.sample(config.samples, volatile);
+            if sampled.is_empty() {
+                return None;
+            }
+            let now = Instant::now();
+            pool.populate(sampled.iter().map(|v| (v.eviction_score(config.policy, now), v.key.to_string())).collect());
+            // keys in the pool may be gone by now
+            while let Some(key) = pool.pop() {
+                if self.db.get(key.as_str()).map(|v| !volatile || v.expires).unwrap_or(false) {
+                    return Some(key);
+                }
+            }
+        }
+    }
+
+    // adds v under its key
+    fn insert(&mut self, v: KeyValueData) -> Option<KeyValueData> {
+        let key = Arc::clone(&v.key);
+        let old = self.db.insert(Arc::clone(&key), v);
+        if old.is_none() {
+            self.scan_index.insert((utils::scan_position(&key), key));
+        }
+        old
+    }
+
+    fn remove(&mut self, key: &str) -> Option<KeyValueData> {
+        let removed = self.db.remove(key);
+        if removed.is_some() {
+            self.scan_index.remove(&(utils::scan_position(key), Arc::from(key)));
+            self.touch(key);
+            // values are kept in the table - give back its room once mostly empty
+            if self.db.capacity() > MIN_TABLE_SIZE && self.db.len() * 8 < self.db.capacity() {
+                self.db.shrink_to(self.db.len() * 2);
+            }
+        }
+        removed
     }
 
-    // adds entry into stream
-    fn xadd(
+    // adds key into the store
+    fn add(
         &mut self,
         key: String,
         value: KeyValueType,
         options: &getset::SetOptions,
-        timestamp: u128,
-        seq: u64,
-        kvpairs: Vec<String>,
     ) -> Result<(), String> {
-        let _ = self
-            .db
-            .entry(key.clone())
-            .and_modify(|val| {
-                match &mut val.value {
-                    KeyValueType::StreamType(s) => {
-                        // add the key into streams
-                        s.streams.insert((timestamp, seq), kvpairs.clone());
-                    },
-                    KeyValueType::StringType(_s) => {
-                        let v = KeyValueData::new(key.clone(), value.clone(), options);
-                        *val = v.clone();
-                    }
-                }
-            })
-            .or_insert_with(||{
-                let vv: streams::Streams = streams::Streams::new(timestamp, seq, kvpairs);
-                let v = KeyValueData::new(key.clone(), KeyValueType::StreamType(vv.clone()), options);
-                v
-            });
+        self.touch(&key);
+        let v = KeyValueData::new(key, value.clone(), options);
+        self.insert(v);
         //TODO: return appropriately
         Ok(())
     }
@@ -108,14 +308,29 @@ pub struct DB {
     store: RwLock<DBInternal>,
     node_info: node_info::NodeInfo,
     rdb: rdb::RDB,
+    // commands run under shared guard, snapshots for replication
+    // take the exclusive guard to get a consistent view with the stream
+    barrier: RwLock<()>,
+    pubsub: Arc<pubsub::Hub>,
+    acl: Arc<acl::Acl>,
+    config: Arc<config::Config>,
+    maxmemory: RwLock<evict::MaxMemory>,
+    eviction_pool: Mutex<evict::Pool>,
 }
 
 impl DB {
-    pub fn new(role_master: bool, dir: Option<String>, db_filename: Option<String>) -> Self {
+    pub fn new(role_master: bool, dir: Option<String>, db_filename: Option<String>, pubsub: pubsub::Hub,
+        acl: acl::Acl, config: config::Config, maxmemory: evict::MaxMemory) -> Self {
         let instance = Self {
             store: RwLock::new(DBInternal::new()),
             node_info: node_info::NodeInfo::new(role_master),
             rdb: rdb::RDB::new(dir, db_filename),
+            barrier: RwLock::new(()),
+            pubsub: Arc::new(pubsub),
+            acl: Arc::new(acl),
+            config: Arc::new(config),
+            maxmemory: RwLock::new(maxmemory),
+            eviction_pool: Mutex::new(evict::Pool::default()),
         };
 
         // if rdb DB file has been specified, read/load the DB
@@ -125,52 +340,578 @@ impl DB {
         instance
     }
 
+    // empty DB with same settings - used to load a full sync before swapping it in
+    pub fn fresh(&self) -> Self {
+        Self {
+            store: RwLock::new(DBInternal::new()),
+            node_info: self.node_info.clone(),
+            rdb: rdb::RDB::new(Some(self.rdb_directory()), Some(self.rdb_filename())),
+            barrier: RwLock::new(()),
+            // loading a full sync does not notify subscribers
+            pubsub: Arc::new(pubsub::Hub::default()),
+            acl: Arc::clone(&self.acl),
+            config: Arc::clone(&self.config),
+            maxmemory: RwLock::new(self.maxmemory()),
+            eviction_pool: Mutex::new(evict::Pool::default()),
+        }
+    }
+
+    // replaces the whole dataset with the one loaded in other
+    pub fn swap(&self, other: DB) {
+        let mut loaded = other.store.write().unwrap();
+        let mut store = self.store.write().unwrap();
+        std::mem::swap(&mut *store, &mut *loaded);
+        // watchers stay with this DB - and every watched key has changed
+        std::mem::swap(&mut store.watched, &mut loaded.watched);
+        for w in store.watched.values_mut() {
+            w.version += 1;
+        }
+    }
+
+    // starts watching the key, returns its current version
+    pub fn watch(&self, key: &str) -> u64 {
+        let mut store = self.store.write().unwrap();
+        let w = store.watched.entry(key.to_string()).or_default();
+        w.watchers += 1;
+        w.version
+    }
+
+    pub fn unwatch(&self, key: &str) {
+        let mut store = self.store.write().unwrap();
+        if let Some(w) = store.watched.get_mut(key) {
+            w.watchers -= 1;
+            if w.watchers == 0 {
+                store.watched.remove(key);
+            }
+        }
+    }
+
+    pub fn watched_version(&self, key: &str) -> u64 {
+        self.store.read().unwrap().watched.get(key).map(|w| w.version).unwrap_or(0)
+    }
+
+    pub fn command_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.barrier.read().unwrap()
+    }
+
+    pub fn exclusive_guard(&self) -> RwLockWriteGuard<'_, ()> {
+        self.barrier.write().unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.read().unwrap().db.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.read().unwrap().db.len()
+    }
+
+    // bytes the key table and the scan index take beyond the keys and values
+    pub fn table_overhead(&self) -> usize {
+        let store = self.store.read().unwrap();
+        // a control byte per slot
+        store.db.capacity() * (std::mem::size_of::<(Arc<str>, KeyValueData)>() + 1)
+            + store.scan_index.len() * std::mem::size_of::<(u64, Arc<str>)>()
+    }
+
+    // writes all live keys as an RDB snapshot straight from the store
+    // expiry goes out as unix time in ms
+    pub fn dump<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let now = Instant::now();
+        let unix_now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let store = self.store.read().unwrap();
+        let live = |v: &&KeyValueData| !v.expires || v.expiring_at > now;
+        let len = store.db.values().filter(live).count();
+        let expires = store.db.values().filter(live).filter(|v| v.expires).count();
+        let entries = store.db.values().filter(live).map(|v| {
+            let expiry = v.expires.then(|| unix_now + (v.expiring_at - now).as_millis());
+            (v.key.as_ref(), &v.value, expiry)
+        });
+        rdb::RDB::dump(writer, len, expires, entries)
+    }
+
+    pub fn pubsub(&self) -> &pubsub::Hub {
+        &self.pubsub
+    }
+
+    pub fn acl(&self) -> &acl::Acl {
+        &self.acl
+    }
+
+    pub fn config(&self) -> &config::Config {
+        &self.config
+    }
+
+    pub fn rdb(&self) -> &rdb::RDB {
+        &self.rdb
+    }
+
     pub fn add(
         &self,
         key: String,
         value: KeyValueType,
         options: &getset::SetOptions,
     ) -> Result<(), String> {
-        println!("Adding {key} with value: {:?}", value);
         let retval;
+        let new;
+        let notify_key = key.clone();
         {
             let mut db = self.store.write().unwrap();
+            new = !db.db.contains_key(key.as_str());
             retval = db.add(key, value, options);
         }
-        println!("DB at this stage: {:?}", self.store.read().unwrap().db);
+        if retval.is_ok() {
+            if new {
+                self.pubsub.notify('n', "new", &notify_key);
+            }
+            self.pubsub.notify('$', "set", &notify_key);
+        }
         retval
     }
 
-    pub fn xadd(
+    // runs f on the stream at key to add an entry - a missing key gets a new
+    // stream only if f succeeds, without create it is left alone and None returned
+    pub fn xadd<R>(
         &self,
-        key: String,
-        value: KeyValueType,
-        options: &getset::SetOptions,
-        timestamp: u128,
-        seq: u64,
-        kvpairs: Vec<String>,
-    ) -> Result<(), String> {
-        println!("Adding {key}/{timestamp}-{seq} with value: {:?}", kvpairs);
-        let retval;
+        key: &str,
+        create: bool,
+        f: impl FnOnce(&mut streams::Streams) -> Result<R, String>,
+    ) -> Result<Option<R>, String> {
+        let result;
+        let expired;
+        let mut new = false;
         {
-            let mut db = self.store.write().unwrap();
-            retval = db.xadd(key, value, options, timestamp, seq, kvpairs);
+            let mut store = self.store.write().unwrap();
+            expired = store.lookup(key);
+            result = match store.db.get_mut(key) {
+                Some(v) => match &mut v.value {
+                    KeyValueType::StreamType(s) => f(s).map(Some),
+                    _ => Err(WRONGTYPE.to_string()),
+                },
+                None if create => {
+                    let mut s = streams::Streams::empty();
+                    f(&mut s).map(|r| {
+                        let v = KeyValueData::new(key.to_string(), KeyValueType::StreamType(s), &getset::SetOptions::new());
+                        store.insert(v);
+                        new = true;
+                        Some(r)
+                    })
+                },
+                None => Ok(None),
+            };
+            if matches!(result, Ok(Some(_))) {
+                store.touch(key);
+            }
         }
-        println!("DB at this stage: {:?}", self.store.read().unwrap().db);
-        retval
+        self.notify_expired(key, expired);
+        if matches!(result, Ok(Some(_))) {
+            if new {
+                self.pubsub.notify('n', "new", key);
+            }
+            self.pubsub.notify('t', "xadd", key);
+        }
+        result
     }
 
-    pub fn remove(&self, key: &String) -> Option<KeyValueType> {
-        if let Some(result) = self.store.write().unwrap().db.remove(key) {
-            return Some(result.value);
+    // runs f on the sorted set at key in place - a missing key gets a new set
+    // only if f succeeds and leaves members in it, without create None is returned
+    pub fn zadd<R>(
+        &self,
+        key: &str,
+        create: bool,
+        f: impl FnOnce(&mut sortedset::SortedSet) -> Result<R, String>,
+    ) -> Result<Option<R>, String> {
+        let mut new = false;
+        let expired;
+        let result;
+        {
+            let mut store = self.store.write().unwrap();
+            expired = store.lookup(key);
+            result = match store.db.get_mut(key) {
+                Some(v) => match &mut v.value {
+                    KeyValueType::SortedSetType(z) => f(z).map(Some),
+                    _ => Err(WRONGTYPE.to_string()),
+                },
+                None if create => {
+                    let mut z = sortedset::SortedSet::new();
+                    let r = f(&mut z);
+                    if r.is_ok() && !z.is_empty() {
+                        let v = KeyValueData::new(key.to_string(), KeyValueType::SortedSetType(z), &getset::SetOptions::new());
+                        store.insert(v);
+                        new = true;
+                    }
+                    r.map(Some)
+                },
+                None => Ok(None),
+            };
+            if result.is_ok() && store.db.contains_key(key) {
+                store.touch(key);
+            }
+        }
+        self.notify_expired(key, expired);
+        if new {
+            self.pubsub.notify('n', "new", key);
+        }
+        result
+    }
+
+    // runs f on the value at key under the write lock and applies the change it asks for
+    pub fn update<R>(
+        &self,
+        key: &str,
+        f: impl FnOnce(Option<&KeyValueType>) -> Result<(Change, R), String>,
+    ) -> Result<R, String> {
+        let mut new = false;
+        let expired;
+        let result;
+        {
+            let mut store = self.store.write().unwrap();
+            expired = store.lookup(key);
+            result = f(store.db.get(key).map(|v| &v.value)).map(|(change, r)| {
+                let touched = !matches!(change, Change::Keep | Change::Remove);
+                match change {
+                    Change::Keep => {},
+                    Change::Value(value) => match store.db.get_mut(key) {
+                        Some(v) => v.value = value,
+                        None => {
+                            new = true;
+                            let v = KeyValueData::new(key.to_string(), value, &getset::SetOptions::new());
+                            store.insert(v);
+                        },
+                    },
+                    Change::Replace(value, expiry) => {
+                        let mut v = KeyValueData::new(key.to_string(), value, &getset::SetOptions::new());
+                        v.expires = expiry.is_some();
+                        v.expiring_at += Duration::from_millis(expiry.unwrap_or(0));
+                        new = store.insert(v).is_none();
+                    },
+                    Change::Expiry(expiry) => {
+                        if let Some(v) = store.db.get_mut(key) {
+                            v.expires = expiry.is_some();
+                            v.expiring_at = Instant::now() + Duration::from_millis(expiry.unwrap_or(0));
+                        }
+                    },
+                    Change::Remove => {
+                        store.remove(key);
+                    },
+                }
+                if touched {
+                    store.touch(key);
+                }
+                r
+            });
+        }
+        self.notify_expired(key, expired);
+        if new {
+            self.pubsub.notify('n', "new", key);
+        }
+        result
+    }
+
+    // sets all pairs at once - with nx only if none of the keys exists
+    pub fn mset(&self, pairs: &[(String, Vec<u8>)], nx: bool) -> bool {
+        let mut created = vec![];
+        {
+            let mut store = self.store.write().unwrap();
+            let now = Instant::now();
+            let exists = |store: &DBInternal, key: &str| store.db.get(key).map(|v| !v.expires || v.expiring_at >= now).unwrap_or(false);
+            if nx && pairs.iter().any(|(k, _v)| exists(&store, k)) {
+                return false;
+            }
+            for (key, value) in pairs {
+                if !exists(&store, key) {
+                    created.push(key);
+                }
+                let v = KeyValueData::new(key.clone(), KeyValueType::string(value.clone()), &getset::SetOptions::new());
+                store.insert(v);
+                store.touch(key);
+            }
+        }
+        for key in created {
+            self.pubsub.notify('n', "new", key);
+        }
+        for (key, _value) in pairs {
+            self.pubsub.notify('$', "set", key);
+        }
+        true
+    }
+
+    // values of all keys read at once
+    pub fn mget(&self, keys: &[String]) -> Vec<Option<KeyValueType>> {
+        let now = Instant::now();
+        let store = self.store.read().unwrap();
+        keys.iter().map(|key| store.db.get(key.as_str())
+            .filter(|v| !v.expires || v.expiring_at >= now)
+            .map(|v| {
+                v.hit();
+                v.value.clone()
+            }))
+            .collect()
+    }
+
+    // removes key and returns its value - a key past its expiry counts as missing
+    pub fn remove(&self, key: &str) -> Option<KeyValueType> {
+        let removed = self.store.write().unwrap().remove(key)?;
+        if removed.expired() {
+            self.pubsub.notify('x', "expired", key);
+            return None;
+        }
+        self.pubsub.notify('g', "del", key);
+        Some(removed.value)
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.store.read().unwrap().db.get(key).map(|v| !v.expired()).unwrap_or(false)
+    }
+
+    // marks key accessed - false if there is no such key
+    pub fn access(&self, key: &str) -> bool {
+        match self.store.read().unwrap().db.get(key) {
+            Some(v) if !v.expired() => {
+                v.hit();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // moves src to dst with its expiry - with nx only if dst does not exist
+    pub fn rename(&self, src: &str, dst: &str, nx: bool) -> Result<bool, String> {
+        let mut new = None;
+        let expired;
+        let result;
+        {
+            let mut store = self.store.write().unwrap();
+            expired = [store.lookup(src), store.lookup(dst)];
+            result = if !store.db.contains_key(src) {
+                Err("ERR no such key".to_string())
+            } else if src == dst {
+                Ok(!nx)
+            } else if nx && store.db.contains_key(dst) {
+                Ok(false)
+            } else {
+                let mut v = store.remove(src).unwrap();
+                v.key = Arc::from(dst);
+                new = Some(store.insert(v).is_none());
+                store.touch(dst);
+                Ok(true)
+            };
+        }
+        self.notify_expired(src, expired[0]);
+        self.notify_expired(dst, expired[1]);
+        // None if nothing was moved
+        if let Some(new) = new {
+            self.pubsub.notify('g', "rename_from", src);
+            self.pubsub.notify('g', "rename_to", dst);
+            if new {
+                self.pubsub.notify('n', "new", dst);
+            }
+        }
+        result
+    }
+
+    // copies src to dst with its expiry - with replace an existing dst is overwritten
+    pub fn copy(&self, src: &str, dst: &str, replace: bool) -> bool {
+        let mut new = None;
+        let expired;
+        {
+            let mut store = self.store.write().unwrap();
+            expired = [store.lookup(src), store.lookup(dst)];
+            let copied = if replace || !store.db.contains_key(dst) {
+                store.db.get(src).cloned()
+            } else {
+                None
+            };
+            if let Some(mut v) = copied {
+                v.key = Arc::from(dst);
+                v.accessed = LastAccess::now();
+                v.frequency = evict::Frequency::new();
+                new = Some(store.insert(v).is_none());
+                store.touch(dst);
+            }
+        }
+        self.notify_expired(src, expired[0]);
+        self.notify_expired(dst, expired[1]);
+        let new = match new {
+            Some(new) => new,
+            None => return false,
+        };
+        self.pubsub.notify('g', "copy_to", dst);
+        if new {
+            self.pubsub.notify('n', "new", dst);
+        }
+        true
+    }
+
+    // adds a value from RESTORE - expiry in ms from now, idle in ms since last access
+    // and freq for the LFU counter
+    pub fn restore(&self, key: &str, value: KeyValueType, expiry: Option<u64>, idle: u64, freq: Option<u8>,
+        replace: bool) -> Result<(), String> {
+        let new;
+        let expired;
+        {
+            let mut store = self.store.write().unwrap();
+            expired = store.lookup(key);
+            new = !store.db.contains_key(key);
+            if replace || new {
+                let mut v = KeyValueData::new(key.to_string(), value, &getset::SetOptions::new());
+                v.expires = expiry.is_some();
+                v.expiring_at += Duration::from_millis(expiry.unwrap_or(0));
+                v.accessed = LastAccess::ago(idle);
+                if let Some(freq) = freq {
+                    v.frequency = evict::Frequency::with(freq);
+                }
+                store.insert(v);
+                store.touch(key);
+            }
+        }
+        self.notify_expired(key, expired);
+        if !replace && !new {
+            return Err("BUSYKEY Target key name already exists.".to_string());
+        }
+        self.pubsub.notify('g', "restore", key);
+        if new {
+            self.pubsub.notify('n', "new", key);
+        }
+        Ok(())
+    }
+
+    pub fn random_key(&self) -> Option<String> {
+        let store = self.store.read().unwrap();
+        let live = store.db.values().filter(|v| !v.expired()).count();
+        if live == 0 {
+            return None;
+        }
+        let pick = RandomState::new().build_hasher().finish() as usize % live;
+        store.db.values().filter(|v| !v.expired()).nth(pick).map(|v| v.key.to_string())
+    }
+
+    // value at key along with the ms it has left to live
+    pub fn get_with_ttl(&self, key: &str) -> Option<(KeyValueType, Option<u64>)> {
+        let now = Instant::now();
+        let store = self.store.read().unwrap();
+        store.db.get(key).filter(|v| !v.expired()).map(|v| {
+            v.hit();
+            let ttl = v.expires.then(|| (v.expiring_at - now).as_millis().max(1) as u64);
+            (v.value.clone(), ttl)
+        })
+    }
+
+    // looks at the value at key, its idle time in ms and LFU counter without accessing it
+    pub fn object<R>(&self, key: &str, f: impl FnOnce(&KeyValueType, u64, u8) -> R) -> Option<R> {
+        let store = self.store.read().unwrap();
+        store.db.get(key).filter(|v| !v.expired()).map(|v| f(&v.value, v.accessed.idle(), v.frequency.counter()))
+    }
+
+    // approximate bytes of key and its value, without accessing it
+    pub fn memory_usage(&self, key: &str, samples: usize) -> Option<usize> {
+        let store = self.store.read().unwrap();
+        store.db.get(key).filter(|v| !v.expired()).map(|v| v.memory_usage(samples))
+    }
+
+    pub fn maxmemory(&self) -> evict::MaxMemory {
+        self.maxmemory.read().unwrap().clone()
+    }
+
+    pub fn set_maxmemory(&self, maxmemory: evict::MaxMemory) {
+        *self.maxmemory.write().unwrap() = maxmemory;
+    }
+
+    // evicts keys by the maxmemory policy until memory use is back under the limit
+    // not_counted is memory kept for replication - it is left out like redis does
+    // returns the evicted keys, and false if memory is still over the limit
+    pub fn evict(&self, not_counted: usize) -> (Vec<String>, bool) {
+        let config = self.maxmemory();
+        let mut evicted = vec![];
+        if config.limit == 0 {
+            return (evicted, true);
+        }
+        // values are estimates - memory is checked again after freeing as much as was over
+        loop {
+            let used = memory::allocated().saturating_sub(not_counted);
+            if used <= config.limit {
+                return (evicted, true);
+            }
+            if config.policy == evict::Policy::NoEviction {
+                return (evicted, false);
+            }
+            let to_free = used - config.limit;
+            let mut freed = 0;
+            while freed < to_free {
+                let removed = {
+                    let mut store = self.store.write().unwrap();
+                    let mut pool = self.eviction_pool.lock().unwrap();
+                    match store.eviction_candidate(&config, &mut pool) {
+                        Some(key) => store.remove(&key),
+                        None => None,
+                    }
+                };
+                let v = match removed {
+                    Some(v) => v,
+                    None => return (evicted, false),
+                };
+                freed += v.memory_usage(memory::DEFAULT_SAMPLES);
+                self.pubsub.notify('e', "evicted", &v.key);
+                evicted.push(v.key.to_string());
+            }
+        }
+    }
+
+    // runs f on the stream at key in place - f gets None if there is no such key
+    // with create an empty stream is added for missing key
+    pub fn update_stream<R>(
+        &self,
+        key: &str,
+        create: bool,
+        f: impl FnOnce(Option<&mut streams::Streams>) -> Result<R, String>,
+    ) -> Result<R, String> {
+        let mut store = self.store.write().unwrap();
+        let expired = store.lookup(key);
+        if create && !store.db.contains_key(key) {
+            let v = KeyValueData::new(key.to_string(), KeyValueType::StreamType(streams::Streams::empty()),
+                &getset::SetOptions::new());
+            store.insert(v);
+        }
+        let result = match store.db.get_mut(key) {
+            Some(v) => match &mut v.value {
+                KeyValueType::StreamType(s) => f(Some(s)),
+                _ => Err(WRONGTYPE.to_string()),
+            },
+            None => f(None),
+        };
+        if result.is_ok() {
+            store.touch(key);
+        }
+        drop(store);
+        self.notify_expired(key, expired);
+        result
+    }
+
+    // keyspace notification for a command changing key
+    pub fn notify(&self, class: char, event: &str, key: &str) {
+        self.pubsub.notify(class, event, key);
+    }
+
+    // key dropped by a lookup as it was past its expiry
+    fn notify_expired(&self, key: &str, expired: bool) {
+        if expired {
+            self.pubsub.notify('x', "expired", key);
+        }
+    }
+
+    // key reached its expiry time
+    pub fn expire(&self, key: &str) {
+        let removed = self.store.write().unwrap().remove(key);
+        if removed.is_some() {
+            self.pubsub.notify('x', "expired", key);
         }
-        None
     }
 
     pub fn get(&self, key: &str) -> Option<KeyValueType> {
         let mut value = None;
         {
             if let Some(result) = self.store.read().unwrap().db.get(key) {
+                result.hit();
                 // clone so that we can release the lock
                 value = Some(result.clone());
             }
@@ -178,7 +919,7 @@ impl DB {
         if let Some(res) = value {
             // race condition - if this key is due for cleanup, check
             if res.expires && res.expiring_at < Instant::now() {
-                self.remove(&res.key);
+                self.expire(&res.key);
             } else {
                 return Some(res.value.clone());
             }
@@ -190,27 +931,41 @@ impl DB {
         self.node_info.master
     }
 
-    pub fn rdb_directory(&self) -> &str {
+    pub fn rdb_directory(&self) -> String {
         self.rdb.get_rdb_directory()
     }
 
-    pub fn rdb_filename(&self) -> &str {
+    pub fn rdb_filename(&self) -> String {
         self.rdb.get_rdb_filename()
     }
 
-    pub fn keys(&self) -> (String, u64) {
-        let mut response = String::from("");
-        let mut count: u64 = 0;
-        let db = self.store.read().unwrap();
-        for (k, _v) in db.db.iter() {
-            count += 1;
-            let _ = std::fmt::write(&mut response,
-                format_args!("${}\r\n{}\r\n", k.len(), k));
-        }
-        if count == 0 {
-            return (format!("$-1\r\n"), count);
+    // live keys matching the glob pattern
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let store = self.store.read().unwrap();
+        store.db.values()
+            .filter(|v| !v.expired() && utils::glob_match(pattern, &v.key))
+            .map(|v| v.key.to_string())
+            .collect()
+    }
+
+    // at least count keys from cursor on along with their type, and the cursor to
+    // continue from - 0 once all keys were returned. Keys sharing a position are
+    // returned together so that a cursor never points in between them.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(String, &'static str)>) {
+        let store = self.store.read().unwrap();
+        let mut keys = vec![];
+        let mut last = None;
+        for (position, key) in store.scan_index.range((cursor.reverse_bits(), Arc::from(""))..) {
+            if keys.len() >= count && last != Some(*position) {
+                return (position.reverse_bits(), keys);
+            }
+            last = Some(*position);
+            match store.db.get(key) {
+                Some(v) if !v.expired() => keys.push((key.to_string(), v.value.type_name())),
+                _ => {},
+            }
         }
-        (format!("*{}\r\n{}", count, response), count)
+        (0, keys)
     }
 
 }
@@ -224,7 +979,7 @@ pub fn key_expiry_thread(db: Arc<DB>, loop_every_in_ms: u64) {
             let store = db.store.read().unwrap();
             for (key, value) in store.db.iter() {
                 if value.expires && value.expiring_at < now {
-                    expired_keys.push(key.clone());
+                    expired_keys.push(key.to_string());
                 }
             }
         }
@@ -234,10 +989,63 @@ pub fn key_expiry_thread(db: Arc<DB>, loop_every_in_ms: u64) {
         */
         for key in expired_keys.iter() {
             println!("----- timer thread: Removing {key} -------");
-            db.remove(key);
+            db.expire(key);
         }
         expired_keys.clear();
 
         thread::sleep(sleep_duration);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::connection::Connection;
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    // db sending expired keyevent notifications to the returned socket
+    fn subscribed_db() -> (DB, UnixStream) {
+        let hub = pubsub::Hub::new(pubsub::OutputLimit::default(), pubsub::NotifyFlags::parse("Ex").unwrap());
+        let (server, client) = UnixStream::pair().unwrap();
+        let pusher = pubsub::Pusher::new(1, &Connection::Unix(server)).unwrap();
+        hub.subscribe(pubsub::Kind::Channel, "__keyevent@0__:expired", &pusher);
+        client.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let maxmemory = evict::MaxMemory::new("0", "noeviction", 5).unwrap();
+        let db = DB::new(true, None, None, hub, acl::Acl::default(), config::Config::new(), maxmemory);
+        (db, client)
+    }
+
+    fn add_expired(db: &DB, key: &str) {
+        let mut options = getset::SetOptions::new();
+        options.expiry_in_ms = 1;
+        db.add(key.to_string(), KeyValueType::string(b"v".to_vec()), &options).unwrap();
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    fn expired_message(key: &str) -> Vec<u8> {
+        format!("*3\r\n$7\r\nmessage\r\n$22\r\n__keyevent@0__:expired\r\n${}\r\n{}\r\n", key.len(), key).into_bytes()
+    }
+
+    #[test]
+    fn lazy_expiry_notifies() {
+        let (db, mut client) = subscribed_db();
+        add_expired(&db, "a");
+        let value = db.update("a", |v| Ok((Change::Keep, v.cloned()))).unwrap();
+        assert!(value.is_none());
+        add_expired(&db, "b");
+        add_expired(&db, "c");
+        assert!(db.rename("b", "c", false).is_err());
+        add_expired(&db, "d");
+        assert!(db.restore("d", KeyValueType::string(b"w".to_vec()), None, 0, None, false).is_ok());
+
+        let mut expected = vec![];
+        for key in ["a", "b", "c", "d"] {
+            expected.extend(expired_message(key));
+        }
+        let mut received = vec![0; expected.len()];
+        client.read_exact(&mut received).unwrap();
+        assert_eq!(String::from_utf8_lossy(&received), String::from_utf8_lossy(&expected));
+        assert!(db.exists("d") && !db.exists("a"));
+    }
+}
@@ -0,0 +1,156 @@
+// maxmemory and the policies picking keys to evict once it is reached
+//
+// like redis keys are not ordered by access - a few are sampled on every
+// eviction and the best candidates seen so far are kept in a small pool.
+use crate::utils::utils;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const POOL_SIZE: usize = 16;
+
+// LFU counter as redis keeps it - logarithmic, halving its growth chance every
+// LFU_LOG_FACTOR hits, and dropping by one every LFU_DECAY_MINUTES without a hit
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_MINUTES: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl Policy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.to_lowercase().as_str() {
+            "noeviction" => Ok(Policy::NoEviction),
+            "allkeys-lru" => Ok(Policy::AllKeysLru),
+            "volatile-lru" => Ok(Policy::VolatileLru),
+            "allkeys-lfu" => Ok(Policy::AllKeysLfu),
+            "volatile-lfu" => Ok(Policy::VolatileLfu),
+            "allkeys-random" => Ok(Policy::AllKeysRandom),
+            "volatile-random" => Ok(Policy::VolatileRandom),
+            "volatile-ttl" => Ok(Policy::VolatileTtl),
+            _ => Err(format!("invalid maxmemory-policy '{}'", value)),
+        }
+    }
+
+    // only keys with an expiry are evicted
+    pub fn volatile(&self) -> bool {
+        matches!(self, Policy::VolatileLru | Policy::VolatileLfu | Policy::VolatileRandom | Policy::VolatileTtl)
+    }
+
+    pub fn lfu(&self) -> bool {
+        matches!(self, Policy::AllKeysLfu | Policy::VolatileLfu)
+    }
+
+    pub fn random(&self) -> bool {
+        matches!(self, Policy::AllKeysRandom | Policy::VolatileRandom)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MaxMemory {
+    pub limit: usize, // bytes, 0 for no limit
+    pub policy: Policy,
+    pub samples: usize,
+}
+
+impl MaxMemory {
+    pub fn new(limit: &str, policy: &str, samples: usize) -> Result<Self, String> {
+        if samples == 0 {
+            return Err("maxmemory-samples must be greater than 0".to_string());
+        }
+        Ok(Self { limit: utils::parse_memory(limit)? as usize, policy: Policy::parse(policy)?, samples })
+    }
+}
+
+// minutes in 16 bits along with the counter in the low 8 bits
+#[derive(Debug)]
+pub struct Frequency(AtomicU32);
+
+impl Frequency {
+    fn minutes() -> u32 {
+        (SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 60) as u32 & 0xffff
+    }
+
+    fn pack(counter: u8) -> u32 {
+        (Self::minutes() << 8) | counter as u32
+    }
+
+    pub fn new() -> Self {
+        Self::with(LFU_INIT_VAL)
+    }
+
+    pub fn with(counter: u8) -> Self {
+        Self(AtomicU32::new(Self::pack(counter)))
+    }
+
+    // counter with the decay for the time since the last hit applied
+    pub fn counter(&self) -> u8 {
+        let packed = self.0.load(Ordering::Relaxed);
+        let last = packed >> 8;
+        let now = Self::minutes();
+        let elapsed = if now >= last { now - last } else { 0x10000 - last + now };
+        let periods = elapsed / LFU_DECAY_MINUTES;
+        (packed & 0xff).saturating_sub(periods) as u8
+    }
+
+    pub fn hit(&self) {
+        let mut counter = self.counter();
+        if counter < 255 {
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            let chance = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+            if (utils::random() as f64 / u64::MAX as f64) < chance {
+                counter += 1;
+            }
+        }
+        self.0.store(Self::pack(counter), Ordering::Relaxed);
+    }
+}
+
+impl Default for Frequency {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for Frequency {
+    fn clone(&self) -> Self {
+        Self(AtomicU32::new(self.0.load(Ordering::Relaxed)))
+    }
+}
+
+// best candidates seen by the sampling so far - higher score goes first
+#[derive(Debug, Default)]
+pub struct Pool {
+    entries: Vec<(u64, String)>, // ascending by score
+}
+
+impl Pool {
+    pub fn populate(&mut self, sampled: Vec<(u64, String)>) {
+        for (score, key) in sampled {
+            if self.entries.iter().any(|(_s, k)| *k == key) {
+                continue;
+            }
+            if self.entries.len() == POOL_SIZE {
+                if score <= self.entries[0].0 {
+                    continue;
+                }
+                self.entries.remove(0);
+            }
+            let idx = self.entries.partition_point(|(s, _k)| *s < score);
+            self.entries.insert(idx, (score, key));
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<String> {
+        self.entries.pop().map(|(_score, key)| key)
+    }
+}
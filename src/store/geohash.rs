@@ -0,0 +1,125 @@
+// geohash the way redis keeps positions in sorted sets
+//
+// score is a 52 bit hash - latitude bits on even positions, longitude bits on
+// odd ones. Latitude only goes to +-85.05112878 as in web mercator.
+pub const LON_MIN: f64 = -180.0;
+pub const LON_MAX: f64 = 180.0;
+pub const LAT_MIN: f64 = -85.05112878;
+pub const LAT_MAX: f64 = 85.05112878;
+
+const STEP: u32 = 26;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+pub fn valid(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+// spreads the 32 bits of x over the even bit positions
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+// the bits on even positions packed back together
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    ((x | (x >> 16)) & 0x0000_0000_ffff_ffff) as u32
+}
+
+fn encode_in(lon: f64, lat: f64, lat_min: f64, lat_max: f64) -> u64 {
+    let lat_offset = (lat - lat_min) / (lat_max - lat_min) * (1u64 << STEP) as f64;
+    let lon_offset = (lon - LON_MIN) / (LON_MAX - LON_MIN) * (1u64 << STEP) as f64;
+    spread(lat_offset as u32) | (spread(lon_offset as u32) << 1)
+}
+
+pub fn encode(lon: f64, lat: f64) -> u64 {
+    encode_in(lon, lat, LAT_MIN, LAT_MAX)
+}
+
+// center of the cell the hash stands for
+pub fn decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << STEP) as f64;
+    let lat_cell = squash(hash) as f64;
+    let lon_cell = squash(hash >> 1) as f64;
+    let lat_min = LAT_MIN + lat_cell / cells * (LAT_MAX - LAT_MIN);
+    let lat_max = LAT_MIN + (lat_cell + 1.0) / cells * (LAT_MAX - LAT_MIN);
+    let lon_min = LON_MIN + lon_cell / cells * (LON_MAX - LON_MIN);
+    let lon_max = LON_MIN + (lon_cell + 1.0) / cells * (LON_MAX - LON_MIN);
+    let lon = ((lon_min + lon_max) / 2.0).clamp(LON_MIN, LON_MAX);
+    let lat = ((lat_min + lat_max) / 2.0).clamp(LAT_MIN, LAT_MAX);
+    (lon, lat)
+}
+
+// standard 11 character geohash - it uses +-90 for latitude
+pub fn to_string(hash: u64) -> String {
+    let (lon, lat) = decode(hash);
+    let hash = encode_in(lon, lat, -90.0, 90.0);
+    (0..11).map(|i| match i {
+        // 52 bits only fill 10 characters
+        10 => '0',
+        _ => ALPHABET[((hash >> (52 - (i + 1) * 5)) & 0x1f) as usize] as char,
+    }).collect()
+}
+
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+
+// haversine distance in meters
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let u = ((lat2.to_radians() - lat1.to_radians()) / 2.0).sin();
+    let a = u * u + lat1.to_radians().cos() * lat2.to_radians().cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+// distance from the center if the point is within the box around it
+pub fn distance_in_box(width: f64, height: f64, center: (f64, f64), point: (f64, f64)) -> Option<f64> {
+    if lat_distance(point.1, center.1) > height / 2.0 {
+        return None;
+    }
+    if distance(point.0, point.1, center.0, point.1) > width / 2.0 {
+        return None;
+    }
+    Some(distance(center.0, center.1, point.0, point.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what redis replies for the GEOADD example in its docs
+    #[test]
+    fn known_hashes() {
+        assert_eq!(encode(13.361389, 38.115556), 3479099956230698);
+        assert_eq!(encode(15.087269, 37.502669), 3479447370796909);
+        assert_eq!(to_string(3479099956230698), "sqc8b49rny0");
+        assert_eq!(to_string(3479447370796909), "sqdtr74hyu0");
+        let (lon, lat) = decode(3479099956230698);
+        assert!((lon - 13.36138933897018433).abs() < 1e-12 && (lat - 38.11555639549629859).abs() < 1e-12);
+        // GEODIST measures between the stored cell centers
+        let (lon2, lat2) = decode(3479447370796909);
+        assert!((distance(lon, lat, lon2, lat2) - 166274.1516).abs() < 0.0001);
+    }
+
+    // a cell is below a meter wide, so a round trip stays within it
+    #[test]
+    fn round_trip() {
+        for (lon, lat) in [(0.0, 0.0), (-179.9, -85.05), (179.9, 85.05), (2.2945, 48.8584), (-122.4194, 37.7749)] {
+            let (dlon, dlat) = decode(encode(lon, lat));
+            assert!(distance(lon, lat, dlon, dlat) < 1.0, "{} {}", lon, lat);
+        }
+    }
+}
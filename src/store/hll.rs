@@ -0,0 +1,316 @@
+// HyperLogLog in the redis string format so that dumps from redis load as is
+//
+// header: "HYLL" | encoding (1 byte) | 3 unused bytes | cardinality (8 bytes LE)
+// the top bit of the last cardinality byte marks the cached value stale.
+// dense: 16384 registers of 6 bits, least significant bits first
+// sparse opcodes: ZERO 00xxxxxx - 1..64 empty registers
+//                 XZERO 01xxxxxx yyyyyyyy - 1..16384 empty registers
+//                 VAL 1vvvvvxx - 1..4 registers holding 1..32
+const MAGIC: &[u8] = b"HYLL";
+const HEADER_SIZE: usize = 16;
+const ENCODING_DENSE: u8 = 0;
+const ENCODING_SPARSE: u8 = 1;
+const CACHE_STALE: u8 = 1 << 7;
+
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+const Q: usize = 64 - P as usize;
+const REGISTER_MAX: u8 = 63;
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * 6).div_ceil(8);
+
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+// sparse form is promoted to dense past this size - hll-sparse-max-bytes
+const SPARSE_MAX_BYTES: usize = 3000;
+
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+const NOT_HLL: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+const CORRUPTED: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+// MurmurHash64A as used by redis - reads the data little endian
+fn murmurhash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        tail.iter().enumerate().for_each(|(i, b)| h ^= (*b as u64) << (8 * i));
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// register an element lands in and the length of its 000..1 pattern
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc8_3b19);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // the extra bit keeps the count at most Q + 1
+    let rest = (hash >> P) | (1 << Q);
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if prev == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if prev == z {
+            return z / 3.0;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hll {
+    registers: Vec<u8>,
+    dense: bool,
+    cached: Option<u64>,
+}
+
+impl Hll {
+    // empty sparse HLL - what PFADD creates
+    pub fn new() -> Self {
+        Self { registers: vec![0; REGISTERS], dense: false, cached: Some(0) }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC || bytes[4] > ENCODING_SPARSE {
+            return Err(NOT_HLL.to_string());
+        }
+        let cached = match bytes[15] & CACHE_STALE {
+            0 => Some(u64::from_le_bytes(bytes[8..16].try_into().unwrap())),
+            _ => None,
+        };
+        let data = &bytes[HEADER_SIZE..];
+        let registers = if bytes[4] == ENCODING_DENSE {
+            if bytes.len() != DENSE_SIZE {
+                return Err(NOT_HLL.to_string());
+            }
+            (0..REGISTERS).map(|i| Self::dense_get(data, i)).collect()
+        } else {
+            Self::sparse_registers(data)?
+        };
+        Ok(Self { registers, dense: bytes[4] == ENCODING_DENSE, cached })
+    }
+
+    fn dense_get(data: &[u8], idx: usize) -> u8 {
+        let byte = idx * 6 / 8;
+        let shift = (idx * 6) & 7;
+        let b0 = data[byte] as u16;
+        let b1 = data.get(byte + 1).copied().unwrap_or(0) as u16;
+        (((b0 >> shift) | (b1 << (8 - shift))) & REGISTER_MAX as u16) as u8
+    }
+
+    fn dense_set(data: &mut [u8], idx: usize, value: u8) {
+        let byte = idx * 6 / 8;
+        let shift = (idx * 6) & 7;
+        let value = value as u16;
+        data[byte] &= !((REGISTER_MAX as u16) << shift) as u8;
+        data[byte] |= (value << shift) as u8;
+        if byte + 1 < data.len() {
+            data[byte + 1] &= !((REGISTER_MAX as u16) >> (8 - shift)) as u8;
+            data[byte + 1] |= (value >> (8 - shift)) as u8;
+        }
+    }
+
+    fn sparse_registers(data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut registers = Vec::with_capacity(REGISTERS);
+        let mut pos = 0;
+        while pos < data.len() {
+            let op = data[pos];
+            let (value, len) = if op & 0xc0 == 0 {
+                (0, (op & 0x3f) as usize + 1)
+            } else if op & 0xc0 == 0x40 {
+                let next = *data.get(pos + 1).ok_or(CORRUPTED.to_string())?;
+                pos += 1;
+                (0, (((op & 0x3f) as usize) << 8 | next as usize) + 1)
+            } else {
+                (((op >> 2) & 0x1f) + 1, (op & 0x3) as usize + 1)
+            };
+            if registers.len() + len > REGISTERS {
+                return Err(CORRUPTED.to_string());
+            }
+            registers.resize(registers.len() + len, value);
+            pos += 1;
+        }
+        if registers.len() != REGISTERS {
+            return Err(CORRUPTED.to_string());
+        }
+        Ok(registers)
+    }
+
+    fn sparse_data(&self) -> Vec<u8> {
+        let mut data = vec![];
+        let mut idx = 0;
+        while idx < REGISTERS {
+            let value = self.registers[idx];
+            let run = self.registers[idx..].iter().take_while(|v| **v == value).count();
+            let mut left = run;
+            while left > 0 {
+                let len = match value {
+                    0 if left > SPARSE_ZERO_MAX_LEN => {
+                        let len = left.min(SPARSE_XZERO_MAX_LEN);
+                        data.push(0x40 | ((len - 1) >> 8) as u8);
+                        data.push(((len - 1) & 0xff) as u8);
+                        len
+                    },
+                    0 => {
+                        data.push((left - 1) as u8);
+                        left
+                    },
+                    _ => {
+                        let len = left.min(SPARSE_VAL_MAX_LEN);
+                        data.push(0x80 | ((value - 1) << 2) | (len - 1) as u8);
+                        len
+                    },
+                };
+                left -= len;
+            }
+            idx += run;
+        }
+        data
+    }
+
+    // redis string of this HLL - sparse ones turn dense once they outgrow the format
+    pub fn encode(&mut self) -> Vec<u8> {
+        let mut data = vec![];
+        if !self.dense {
+            if self.registers.iter().any(|v| *v > SPARSE_VAL_MAX_VALUE) {
+                self.dense = true;
+            } else {
+                data = self.sparse_data();
+                self.dense = HEADER_SIZE + data.len() > SPARSE_MAX_BYTES;
+            }
+        }
+        if self.dense {
+            data = vec![0; DENSE_SIZE - HEADER_SIZE];
+            self.registers.iter().enumerate().for_each(|(i, v)| Self::dense_set(&mut data, i, *v));
+        }
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + data.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(if self.dense { ENCODING_DENSE } else { ENCODING_SPARSE });
+        bytes.extend_from_slice(&[0; 3]);
+        match self.cached {
+            Some(card) => bytes.extend_from_slice(&card.to_le_bytes()),
+            None => bytes.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, CACHE_STALE]),
+        }
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+
+    // true if a register changed
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let (idx, count) = pattern(element);
+        if self.registers[idx] >= count {
+            return false;
+        }
+        self.registers[idx] = count;
+        self.cached = None;
+        true
+    }
+
+    // union with other - dense if either of them is
+    pub fn merge(&mut self, other: &Hll) {
+        self.registers.iter_mut().zip(other.registers.iter()).for_each(|(r, o)| *r = (*r).max(*o));
+        self.dense |= other.dense;
+        self.cached = None;
+    }
+
+    pub fn cached(&self) -> Option<u64> {
+        self.cached
+    }
+
+    // cardinality estimate - the cached one while it is still valid
+    pub fn count(&mut self) -> u64 {
+        if let Some(card) = self.cached {
+            return card;
+        }
+        let m = REGISTERS as f64;
+        let mut histogram = [0u32; 64];
+        self.registers.iter().for_each(|r| histogram[*r as usize] += 1);
+        let mut z = m * tau((m - histogram[Q + 1] as f64) / m);
+        for j in (1..=Q).rev() {
+            z += histogram[j] as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        let card = (ALPHA_INF * m * m / z).round() as u64;
+        self.cached = Some(card);
+        card
+    }
+
+    // header bytes holding the cached cardinality
+    pub fn cache_into(bytes: &mut [u8], card: u64) {
+        bytes[8..HEADER_SIZE].copy_from_slice(&card.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // standard error with 16384 registers is 0.81%, allow a few times that
+    #[test]
+    fn error_bounds() {
+        let mut hll = Hll::new();
+        assert_eq!(hll.count(), 0);
+        let mut added = 0;
+        for checkpoint in [10, 100, 1_000, 10_000, 100_000] {
+            while added < checkpoint {
+                hll.add(format!("element:{}", added).as_bytes());
+                added += 1;
+            }
+            let error = (hll.count() as f64 - checkpoint as f64).abs() / checkpoint as f64;
+            assert!(error < 0.03, "{} elements counted as {}", checkpoint, hll.count());
+        }
+        assert!(!hll.add(b"element:0"));
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let mut hll = Hll::new();
+        (0..500).for_each(|i| { hll.add(format!("{}", i).as_bytes()); });
+        let count = hll.count();
+        let mut parsed = Hll::parse(&hll.encode()).unwrap();
+        assert_eq!(parsed.count(), count);
+        assert!(Hll::parse(b"HYLL").is_err());
+    }
+}
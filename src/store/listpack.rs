@@ -0,0 +1,258 @@
+// packed blocks of stream entries - what a rax node with its listpack is in redis
+//
+// entry layout: flags (1 byte) | ms delta | seq | [field count] | strings
+// ids are varints relative to the master id of the block, seq is a delta only
+// while ms is the same as the master. Entries with the same field names as
+// the master entry only carry their values.
+use crate::store::streams::StreamId;
+
+const FLAG_DELETED: u8 = 1;
+const FLAG_SAME_FIELDS: u8 = 2;
+
+// a block is closed once it holds this many entries or bytes
+pub const NODE_MAX_ENTRIES: usize = 100;
+pub const NODE_MAX_BYTES: usize = 4096;
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u128) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn get_varint(buf: &[u8], pos: &mut usize) -> u128 {
+    let mut value = 0u128;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7f) as u128) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    put_varint(buf, s.len() as u128);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn get_string(buf: &[u8], pos: &mut usize) -> String {
+    let len = get_varint(buf, pos) as usize;
+    let s = String::from_utf8_lossy(&buf[*pos..*pos + len]).into_owned();
+    *pos += len;
+    s
+}
+
+fn skip_string(buf: &[u8], pos: &mut usize) {
+    let len = get_varint(buf, pos) as usize;
+    *pos += len;
+}
+
+// position and header of an entry in the block
+struct EntryHeader {
+    offset: usize,
+    id: StreamId,
+    flags: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    master: StreamId,
+    master_fields: Vec<String>, // field names of the first entry
+    data: Vec<u8>,
+    entries: usize, // including deleted ones
+    live: usize,
+}
+
+impl Node {
+    pub fn new(id: StreamId, fields: &[String]) -> Self {
+        let mut node = Self {
+            master: id,
+            master_fields: fields.iter().step_by(2).cloned().collect(),
+            data: vec![],
+            entries: 0,
+            live: 0,
+        };
+        node.push(id, fields);
+        node
+    }
+
+    pub fn live(&self) -> usize {
+        self.live
+    }
+
+    // approximate bytes the node takes
+    pub fn memory(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.capacity()
+            + self.master_fields.iter().map(|f| f.capacity() + std::mem::size_of::<String>()).sum::<usize>()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries >= NODE_MAX_ENTRIES || self.data.len() >= NODE_MAX_BYTES
+    }
+
+    fn same_fields(&self, fields: &[String]) -> bool {
+        fields.len() == self.master_fields.len() * 2
+            && fields.iter().step_by(2).zip(self.master_fields.iter()).all(|(a, b)| a == b)
+    }
+
+    // appends entry - id has to be greater than any in the block
+    pub fn push(&mut self, id: StreamId, fields: &[String]) {
+        let same = self.same_fields(fields);
+        self.data.push(if same { FLAG_SAME_FIELDS } else { 0 });
+        let ms_delta = id.0 - self.master.0;
+        put_varint(&mut self.data, ms_delta);
+        let seq = if ms_delta == 0 { id.1 - self.master.1 } else { id.1 };
+        put_varint(&mut self.data, seq as u128);
+        if same {
+            fields.iter().skip(1).step_by(2).for_each(|v| put_string(&mut self.data, v));
+        } else {
+            put_varint(&mut self.data, fields.len() as u128);
+            fields.iter().for_each(|f| put_string(&mut self.data, f));
+        }
+        self.entries += 1;
+        self.live += 1;
+        if self.is_full() {
+            // no more appends - give back what the buffer grew into
+            self.data.shrink_to_fit();
+        }
+    }
+
+    // reads the header at pos, leaves pos at the first string
+    fn header(&self, pos: &mut usize) -> EntryHeader {
+        let offset = *pos;
+        let flags = self.data[*pos];
+        *pos += 1;
+        let ms_delta = get_varint(&self.data, pos);
+        let seq = get_varint(&self.data, pos) as u64;
+        let id = if ms_delta == 0 {
+            (self.master.0, self.master.1 + seq)
+        } else {
+            (self.master.0 + ms_delta, seq)
+        };
+        EntryHeader { offset, id, flags }
+    }
+
+    fn skip_fields(&self, flags: u8, pos: &mut usize) {
+        let count = if flags & FLAG_SAME_FIELDS != 0 {
+            self.master_fields.len()
+        } else {
+            get_varint(&self.data, pos) as usize
+        };
+        (0..count).for_each(|_| skip_string(&self.data, pos));
+    }
+
+    fn read_fields(&self, flags: u8, pos: &mut usize) -> Vec<String> {
+        if flags & FLAG_SAME_FIELDS != 0 {
+            return self.master_fields.iter()
+                .flat_map(|name| [name.clone(), get_string(&self.data, pos)])
+                .collect();
+        }
+        let count = get_varint(&self.data, pos) as usize;
+        (0..count).map(|_| get_string(&self.data, pos)).collect()
+    }
+
+    // headers of all entries, deleted ones included
+    fn headers(&self) -> Vec<EntryHeader> {
+        let mut pos = 0;
+        let mut headers = Vec::with_capacity(self.entries);
+        while pos < self.data.len() {
+            let header = self.header(&mut pos);
+            self.skip_fields(header.flags, &mut pos);
+            headers.push(header);
+        }
+        headers
+    }
+
+    pub fn ids(&self) -> Vec<StreamId> {
+        self.headers().into_iter()
+            .filter(|h| h.flags & FLAG_DELETED == 0)
+            .map(|h| h.id)
+            .collect()
+    }
+
+    pub fn entries(&self) -> Vec<(StreamId, Vec<String>)> {
+        let mut pos = 0;
+        let mut entries = Vec::with_capacity(self.live);
+        while pos < self.data.len() {
+            let header = self.header(&mut pos);
+            if header.flags & FLAG_DELETED != 0 {
+                self.skip_fields(header.flags, &mut pos);
+                continue;
+            }
+            entries.push((header.id, self.read_fields(header.flags, &mut pos)));
+        }
+        entries
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<Vec<String>> {
+        let mut pos = 0;
+        while pos < self.data.len() {
+            let header = self.header(&mut pos);
+            if header.id == id && header.flags & FLAG_DELETED == 0 {
+                return Some(self.read_fields(header.flags, &mut pos));
+            }
+            if header.id >= id {
+                return None;
+            }
+            self.skip_fields(header.flags, &mut pos);
+        }
+        None
+    }
+
+    // flags the entry as deleted - space is only given back with the whole block
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        let found = self.headers().into_iter()
+            .find(|h| h.id == id && h.flags & FLAG_DELETED == 0);
+        match found {
+            Some(h) => {
+                self.data[h.offset] |= FLAG_DELETED;
+                self.live -= 1;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[&str]) -> Vec<String> {
+        pairs.iter().map(|s| s.to_string()).collect()
+    }
+
+    // ids before and after the ms moves on, entries with and without the master fields
+    #[test]
+    fn delta_encoding_round_trip() {
+        let master = (1_700_000_000_000, 5);
+        let entries = vec![
+            (master, fields(&["temp", "20", "hum", "40"])),
+            ((1_700_000_000_000, 6), fields(&["temp", "21", "hum", "41"])),
+            ((1_700_000_000_000, 300), fields(&["other", ""])),
+            ((1_700_000_000_001, 0), fields(&["temp", "22", "hum", "42"])),
+            ((1_800_000_000_000, u64::MAX), fields(&["temp", "23", "hum", "43", "extra", "x"])),
+        ];
+        let mut node = Node::new(entries[0].0, &entries[0].1);
+        entries[1..].iter().for_each(|(id, f)| node.push(*id, f));
+        assert_eq!(node.entries(), entries);
+        assert_eq!(node.get((1_700_000_000_000, 300)), Some(fields(&["other", ""])));
+        assert_eq!(node.get((1_700_000_000_000, 7)), None);
+
+        assert!(node.delete((1_700_000_000_001, 0)));
+        assert!(!node.delete((1_700_000_000_001, 0)));
+        assert_eq!(node.live(), 4);
+        assert_eq!(node.get((1_700_000_000_001, 0)), None);
+        assert_eq!(node.get((1_800_000_000_000, u64::MAX)), Some(entries[4].1.clone()));
+        assert_eq!(node.ids(), vec![master, (1_700_000_000_000, 6), (1_700_000_000_000, 300), (1_800_000_000_000, u64::MAX)]);
+    }
+}
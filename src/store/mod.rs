@@ -1,3 +1,8 @@
 pub mod db;
+pub mod evict;
+pub mod geohash;
+pub mod hll;
+pub mod listpack;
 mod node_info;
+pub mod sortedset;
 pub mod streams;
@@ -0,0 +1,101 @@
+// members ordered by score - ties ordered by member like redis does
+use std::cmp::Ordering;
+use crate::utils::memory;
+use crate::utils::utils;
+use std::collections::{BTreeSet, HashMap};
+
+// f64 with a total order so that it can key the index
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    index: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    // adds member or moves it to score - true if it was not there before
+    pub fn insert(&mut self, member: &str, score: f64) -> bool {
+        match self.scores.insert(member.to_string(), score) {
+            Some(old) => {
+                self.index.remove(&(Score(old), member.to_string()));
+                self.index.insert((Score(score), member.to_string()));
+                false
+            },
+            None => {
+                self.index.insert((Score(score), member.to_string()));
+                true
+            },
+        }
+    }
+
+    // members with their scores from the lowest score up
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.index.iter().map(|(score, member)| (member.as_str(), score.0))
+    }
+
+    // members from cursor on in SCAN order - see utils::scan_position
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&str, f64)>) {
+        let start = cursor.reverse_bits();
+        let mut members = self.scores.iter()
+            .map(|(member, score)| (utils::scan_position(member), member.as_str(), *score))
+            .filter(|(position, _m, _s)| *position >= start)
+            .collect::<Vec<_>>();
+        members.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(b.1)));
+        let mut found = vec![];
+        for (idx, (position, member, score)) in members.iter().enumerate() {
+            if found.len() >= count && members[idx - 1].0 != *position {
+                return (position.reverse_bits(), found);
+            }
+            found.push((*member, *score));
+        }
+        (0, found)
+    }
+}
+
+// every member is kept in the map and in the index
+impl memory::MemoryUsage for SortedSet {
+    fn memory_usage(&self, samples: usize) -> usize {
+        let per_member = 2 * (std::mem::size_of::<String>() + std::mem::size_of::<f64>());
+        let members = self.scores.keys().map(|m| 2 * m.capacity() + per_member);
+        std::mem::size_of::<Self>() + memory::sampled(members, self.len(), samples)
+    }
+}
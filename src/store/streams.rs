@@ -1,5 +1,8 @@
 // maintain in memory DB for streams
+use crate::store::listpack;
+use crate::utils::memory;
 use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[allow(dead_code)]
 /*
@@ -8,20 +11,433 @@ struct StreamData {
     data: String,
 } */
 
+// entry id - milliseconds and sequence
+pub type StreamId = (u128, u64);
+
+pub const MAX_ID: StreamId = (u128::MAX, u64::MAX);
+
+pub fn now_ms() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+// parses <ms>-<seq> or <ms>, missing sequence takes default_seq
+pub fn parse_id(value: &str, default_seq: u64) -> Result<StreamId, String> {
+    let invalid = || "ERR Invalid stream ID specified as stream command argument".to_string();
+    let (ms, seq) = match value.split_once('-') {
+        Some((ms, seq)) => (ms, Some(seq)),
+        None => (value, None),
+    };
+    let ms = ms.parse::<u128>().map_err(|_| invalid())?;
+    let seq = match seq {
+        Some(s) => s.parse::<u64>().map_err(|_| invalid())?,
+        None => default_seq,
+    };
+    Ok((ms, seq))
+}
+
+pub fn format_id(id: &StreamId) -> String {
+    format!("{}-{}", id.0, id.1)
+}
+
+pub fn next_id(id: StreamId) -> Option<StreamId> {
+    match id {
+        (ms, u64::MAX) => ms.checked_add(1).map(|ms| (ms, 0)),
+        (ms, seq) => Some((ms, seq + 1)),
+    }
+}
+
+pub fn prev_id(id: StreamId) -> Option<StreamId> {
+    match id {
+        (ms, 0) => ms.checked_sub(1).map(|ms| (ms, u64::MAX)),
+        (ms, seq) => Some((ms, seq - 1)),
+    }
+}
+
+// start/end of a range - "-", "+" or an id, "(" prefix excludes the id
+// missing sequence is 0 for start and max for end
+pub fn parse_range_id(value: &str, start: bool) -> Result<StreamId, String> {
+    let default_seq = if start { 0 } else { u64::MAX };
+    match value {
+        "-" => Ok((0, 0)),
+        "+" => Ok((u128::MAX, u64::MAX)),
+        v => match v.strip_prefix('(') {
+            Some(v) => {
+                let id = parse_id(v, default_seq)?;
+                let next = if start { next_id(id) } else { prev_id(id) };
+                next.ok_or_else(|| "ERR invalid start ID for the interval".to_string())
+            },
+            None => parse_id(v, default_seq),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+// MAXLEN|MINID [=|~] threshold [LIMIT count] of XADD and XTRIM
+#[derive(Debug, Clone, Copy)]
+pub struct Trim {
+    pub strategy: TrimStrategy,
+    pub approx: bool,
+    pub limit: Option<usize>, // 0 is no limit
+}
+
+// entry in a pending entries list - delivered but not acknowledged yet
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivery_time: u128, // ms
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    pub seen_time: u128, // last interaction in ms
+    pub active_time: Option<u128>, // last successful read or claim
+}
+
+impl Consumer {
+    fn new(now: u128) -> Self {
+        Self { seen_time: now, active_time: None }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pub entries_read: Option<u64>,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_delivered: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_delivered,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    // creates consumer if needed, returns true if it was created
+    fn touch_consumer(&mut self, name: &str, now: u128) -> bool {
+        match self.consumers.get_mut(name) {
+            Some(c) => {
+                c.seen_time = now;
+                false
+            },
+            None => {
+                self.consumers.insert(name.to_string(), Consumer::new(now));
+                true
+            }
+        }
+    }
+}
+
+// where XREADGROUP reads from - new entries or consumer history after id
+#[derive(Debug, Clone, Copy)]
+pub enum ReadFrom {
+    New,
+    History(StreamId),
+}
+
+// entry returned to a consumer - fields are None if entry got deleted
+pub type Delivered = (StreamId, Option<Vec<String>>);
+
+// options of XCLAIM
+#[derive(Debug, Clone, Default)]
+pub struct ClaimOptions {
+    pub idle: Option<u128>,
+    pub time: Option<u128>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub justid: bool,
+    pub last_id: Option<StreamId>,
+}
+
+// entries packed in blocks keyed by the master id of each block
+#[derive(Debug, Clone, Default)]
+pub struct Entries {
+    index: BTreeMap<StreamId, listpack::Node>,
+    length: usize,
+}
+
+impl Entries {
+    // id has to be greater than any in the stream
+    fn push(&mut self, id: StreamId, fields: &[String]) {
+        self.length += 1;
+        if let Some(mut last) = self.index.last_entry() {
+            if !last.get().is_full() {
+                last.get_mut().push(id, fields);
+                return;
+            }
+        }
+        self.index.insert(id, listpack::Node::new(id, fields));
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    // number of blocks
+    pub fn nodes(&self) -> usize {
+        self.index.len()
+    }
+
+    // nodes a radix tree keyed by the master ids would take, as redis counts them
+    pub fn radix_nodes(&self) -> usize {
+        let keys = self.index.keys()
+            .map(|(ms, seq)| {
+                let mut key = [0u8; 16];
+                key[..8].copy_from_slice(&(*ms as u64).to_be_bytes());
+                key[8..].copy_from_slice(&seq.to_be_bytes());
+                key
+            })
+            .collect::<Vec<_>>();
+        // an empty tree is just its root
+        if keys.is_empty() { 1 } else { rax_nodes(&keys, 0) }
+    }
+
+    // master id and live entries of each block
+    pub fn blocks(&self) -> impl Iterator<Item = (StreamId, Vec<(StreamId, Vec<String>)>)> + '_ {
+        self.index.iter().map(|(master, node)| (*master, node.entries()))
+    }
+
+    // block that would hold id
+    fn node(&self, id: StreamId) -> Option<&listpack::Node> {
+        self.index.range(..=id).next_back().map(|(_master, node)| node)
+    }
+
+    pub fn get(&self, id: &StreamId) -> Option<Vec<String>> {
+        self.node(*id).and_then(|node| node.get(*id))
+    }
+
+    pub fn contains(&self, id: &StreamId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn first_id(&self) -> Option<StreamId> {
+        self.index.values().next().and_then(|node| node.ids().first().copied())
+    }
+
+    pub fn last_id(&self) -> Option<StreamId> {
+        self.index.values().next_back().and_then(|node| node.ids().last().copied())
+    }
+
+    // entries from start to end both included, blocks are decoded as they are reached
+    pub fn range(&self, start: StreamId, end: StreamId) -> impl DoubleEndedIterator<Item = (StreamId, Vec<String>)> + '_ {
+        let from = self.index.range(..=start).next_back().map(|(master, _node)| *master).unwrap_or(start);
+        self.index.range(from..=end.max(from))
+            .flat_map(|(_master, node)| node.entries())
+            .filter(move |(id, _fields)| *id >= start && *id <= end)
+    }
+
+    fn delete(&mut self, id: StreamId) -> bool {
+        let master = match self.index.range(..=id).next_back() {
+            Some((master, _node)) => *master,
+            None => return false,
+        };
+        let node = self.index.get_mut(&master).unwrap();
+        if !node.delete(id) {
+            return false;
+        }
+        if node.live() == 0 {
+            self.index.remove(&master);
+        }
+        self.length -= 1;
+        true
+    }
+
+    // removes oldest entries - approximate trimming only drops whole blocks
+    fn trim(&mut self, trim: &Trim) -> usize {
+        let limit = match (trim.approx, trim.limit) {
+            (false, _) | (true, Some(0)) => usize::MAX,
+            (true, Some(n)) => n,
+            (true, None) => listpack::NODE_MAX_ENTRIES * 100,
+        };
+        let reached = |length: usize, first: StreamId| match trim.strategy {
+            TrimStrategy::MaxLen(max) => length <= max,
+            TrimStrategy::MinId(min) => first >= min,
+        };
+        let mut removed = 0;
+        while let Some(mut first) = self.index.first_entry() {
+            let ids = first.get().ids();
+            if reached(self.length, ids[0]) {
+                break;
+            }
+            let whole = match trim.strategy {
+                TrimStrategy::MaxLen(max) => self.length - ids.len() >= max,
+                TrimStrategy::MinId(min) => ids[ids.len() - 1] < min,
+            };
+            if whole {
+                if removed + ids.len() > limit {
+                    break;
+                }
+                removed += ids.len();
+                self.length -= ids.len();
+                first.remove();
+                continue;
+            }
+            if trim.approx {
+                break;
+            }
+            for id in ids {
+                if reached(self.length, id) {
+                    break;
+                }
+                first.get_mut().delete(id);
+                self.length -= 1;
+                removed += 1;
+            }
+            break;
+        }
+        removed
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Streams {
-    pub streams: BTreeMap<(u128, u64), Vec<String>>,
+    pub entries: Entries,
+    pub groups: BTreeMap<String, ConsumerGroup>,
+    last_id: StreamId, // stays even if entries get deleted
+    entries_added: u64, // all entries ever added
+    max_deleted_id: StreamId, // greatest id removed by XDEL - trimming only drops the head
 }
 
 impl Streams {
-   pub fn new(timestamp: u128, seq: u64, kvpairs: Vec<String>) -> Self {
-        let mut map = BTreeMap::new();
-        map.insert((timestamp, seq), kvpairs);
+    // new stream - entries come with XADD, XGROUP CREATE MKSTREAM keeps it empty
+    pub fn empty() -> Self {
         Self {
-            streams: map,
+            entries: Entries::default(),
+            groups: BTreeMap::new(),
+            last_id: (0, 0),
+            entries_added: 0,
+            max_deleted_id: (0, 0),
+        }
+    }
+
+    pub fn add(&mut self, timestamp: u128, seq: u64, kvpairs: Vec<String>) {
+        self.entries.push((timestamp, seq), &kvpairs);
+        self.entries_added += 1;
+        if (timestamp, seq) > self.last_id {
+            self.last_id = (timestamp, seq);
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+
+    pub fn first_id(&self) -> Option<StreamId> {
+        self.entries.first_id()
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    // XDEL - returns number of entries deleted
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        let mut count = 0;
+        for id in ids {
+            if self.entries.delete(*id) {
+                self.max_deleted_id = self.max_deleted_id.max(*id);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // true if entries after start were deleted - counting entries from there is not possible
+    fn has_tombstones(&self, start: StreamId) -> bool {
+        !self.entries.is_empty() && self.max_deleted_id != (0, 0) && self.max_deleted_id >= start
+    }
+
+    // number of entries added up to id, None if deletions make it unknown
+    fn entries_up_to(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if id == self.last_id || (self.entries.is_empty() && id < self.last_id) {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+        let first = self.first_id().unwrap_or(self.last_id);
+        if self.max_deleted_id == (0, 0) || self.max_deleted_id < first {
+            let before_first = self.entries_added - self.entries.len() as u64;
+            if id < first {
+                return Some(before_first);
+            }
+            if id == first {
+                return Some(before_first + 1);
+            }
+        }
+        None
+    }
+
+    // entries the group has not read yet, None if it cannot be told
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(n) if !self.has_tombstones(group.last_delivered) => Some(n),
+            _ => self.entries_up_to(group.last_delivered),
+        };
+        entries_read.map(|n| self.entries_added.saturating_sub(n))
+    }
+
+    // keys and nodes of the index holding the entry blocks
+    pub fn radix_stats(&self) -> (usize, usize) {
+        (self.entries.nodes(), self.entries.radix_nodes())
+    }
+
+    pub fn trim(&mut self, trim: &Trim) -> usize {
+        self.entries.trim(trim)
+    }
+
+    // XSETID - moves last id, optionally entries added and max deleted id
+    pub fn set_id(&mut self, id: StreamId, entries_added: Option<u64>, max_deleted_id: Option<StreamId>) -> Result<(), String> {
+        if let Some(top) = self.entries.last_id() {
+            if id < top {
+                return Err("ERR The ID specified in XSETID is smaller than the target stream top item".to_string());
+            }
+        }
+        if let Some(n) = entries_added {
+            if n < self.entries.len() as u64 {
+                return Err("ERR The entries_added specified in XSETID is smaller than the target stream length".to_string());
+            }
+        }
+        if let Some(m) = max_deleted_id {
+            if id < m {
+                return Err("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id".to_string());
+            }
+        }
+        self.last_id = id;
+        if let Some(n) = entries_added {
+            self.entries_added = n;
+        }
+        if let Some(m) = max_deleted_id {
+            self.max_deleted_id = m;
+        }
+        Ok(())
+    }
+
     #[allow(dead_code)]
     pub fn build_kvpairs(&self) -> String {
         "".to_string()
@@ -29,10 +445,314 @@ impl Streams {
 
     #[allow(dead_code)]
     pub fn number_entries(&self) -> u64 {
-        self.streams.keys().count() as u64
+        self.entries.len() as u64
     }
 
     pub fn last_entry_key(&self) -> (u128, u64) {
-        *self.streams.keys().last().unwrap()
+        self.last_id
+    }
+
+    fn nogroup(key: &str, group: &str) -> String {
+        format!("NOGROUP No such key '{}' or consumer group '{}'", key, group)
+    }
+
+    pub fn group(&self, key: &str, name: &str) -> Result<&ConsumerGroup, String> {
+        self.groups.get(name).ok_or_else(|| Self::nogroup(key, name))
+    }
+
+    fn group_mut(&mut self, key: &str, name: &str) -> Result<&mut ConsumerGroup, String> {
+        self.groups.get_mut(name).ok_or_else(|| Self::nogroup(key, name))
+    }
+
+    // id is None for "$" - last entry of the stream
+    pub fn create_group(&mut self, name: &str, id: Option<StreamId>, entries_read: Option<u64>) -> Result<(), String> {
+        if self.groups.contains_key(name) {
+            return Err("BUSYGROUP Consumer Group name already exists".to_string());
+        }
+        let entries_read = entries_read.or(if id.is_none() { Some(self.entries_added) } else { None });
+        let id = id.unwrap_or(self.last_id);
+        self.groups.insert(name.to_string(), ConsumerGroup::new(id, entries_read));
+        Ok(())
+    }
+
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    pub fn set_group_id(&mut self, key: &str, name: &str, id: Option<StreamId>, entries_read: Option<u64>) -> Result<(), String> {
+        let (last_id, entries_added) = (self.last_id, self.entries_added);
+        let group = self.group_mut(key, name)?;
+        group.last_delivered = id.unwrap_or(last_id);
+        group.entries_read = entries_read.or(if id.is_none() { Some(entries_added) } else { None });
+        Ok(())
+    }
+
+    pub fn create_consumer(&mut self, key: &str, group: &str, consumer: &str) -> Result<bool, String> {
+        let group = self.group_mut(key, group)?;
+        Ok(group.touch_consumer(consumer, now_ms()))
+    }
+
+    // returns number of pending entries the consumer had
+    pub fn delete_consumer(&mut self, key: &str, group: &str, consumer: &str) -> Result<usize, String> {
+        let group = self.group_mut(key, group)?;
+        if group.consumers.remove(consumer).is_none() {
+            return Ok(0);
+        }
+        let before = group.pending.len();
+        group.pending.retain(|_id, p| p.consumer != consumer);
+        Ok(before - group.pending.len())
+    }
+
+    // XREADGROUP - returns entries delivered to consumer and if consumer was created
+    pub fn read_group(&mut self, key: &str, group_name: &str, consumer: &str, from: ReadFrom,
+        count: Option<usize>, noack: bool) -> Result<(Vec<Delivered>, bool), String> {
+        let now = now_ms();
+        let count = count.unwrap_or(usize::MAX);
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group_name).ok_or_else(|| Self::nogroup(key, group_name))?;
+        let created = group.touch_consumer(consumer, now);
+        let mut delivered = vec![];
+        match from {
+            ReadFrom::New => {
+                let after = next_id(group.last_delivered);
+                for (id, fields) in after.into_iter().flat_map(|after| entries.range(after, MAX_ID)).take(count) {
+                    delivered.push((id, Some(fields)));
+                    group.last_delivered = id;
+                    if !noack {
+                        group.pending.insert(id, PendingEntry {
+                            consumer: consumer.to_string(),
+                            delivery_time: now,
+                            delivery_count: 1,
+                        });
+                    }
+                }
+            },
+            ReadFrom::History(start) => {
+                // entries already delivered to this consumer - delivered again
+                for (id, p) in group.pending.range_mut((std::ops::Bound::Excluded(start), std::ops::Bound::Unbounded))
+                    .filter(|(_id, p)| p.consumer == consumer)
+                    .take(count) {
+                    p.delivery_time = now;
+                    p.delivery_count += 1;
+                    delivered.push((*id, entries.get(id)));
+                }
+            }
+        }
+        if !delivered.is_empty() {
+            if let Some(c) = group.consumers.get_mut(consumer) {
+                c.active_time = Some(now);
+            }
+        }
+        if let (ReadFrom::New, Some((first, _fields))) = (from, delivered.first()) {
+            // counting on is only possible if nothing got deleted in between
+            let (entries_read, last) = (group.entries_read, group.last_delivered);
+            let entries_read = match entries_read {
+                Some(n) if !self.has_tombstones(*first) => Some(n + delivered.len() as u64),
+                _ => self.entries_up_to(last),
+            };
+            if let Some(g) = self.groups.get_mut(group_name) {
+                g.entries_read = entries_read;
+            }
+        }
+        Ok((delivered, created))
+    }
+
+    // XACK - returns number of entries acknowledged
+    pub fn ack(&mut self, group: &str, ids: &[StreamId]) -> usize {
+        match self.groups.get_mut(group) {
+            Some(g) => ids.iter().filter(|id| g.pending.remove(id).is_some()).count(),
+            None => 0,
+        }
+    }
+
+    // XCLAIM - changes ownership of pending entries, entries deleted from the stream
+    // are dropped from the PEL and not returned
+    pub fn claim(&mut self, key: &str, group: &str, consumer: &str, min_idle: u128,
+        ids: &[StreamId], options: &ClaimOptions) -> Result<Vec<Delivered>, String> {
+        let now = now_ms();
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group).ok_or_else(|| Self::nogroup(key, group))?;
+        if let Some(last_id) = options.last_id {
+            if last_id > group.last_delivered {
+                group.last_delivered = last_id;
+            }
+        }
+        group.touch_consumer(consumer, now);
+        let delivery_time = match (options.idle, options.time) {
+            (Some(idle), _) => now.saturating_sub(idle),
+            (None, Some(time)) => time,
+            (None, None) => now,
+        };
+        let mut claimed = vec![];
+        for id in ids {
+            if !entries.contains(id) {
+                group.pending.remove(id);
+                continue;
+            }
+            if !group.pending.contains_key(id) {
+                if !options.force {
+                    continue;
+                }
+                group.pending.insert(*id, PendingEntry {
+                    consumer: consumer.to_string(),
+                    delivery_time: now,
+                    delivery_count: 0,
+                });
+            }
+            let p = group.pending.get_mut(id).unwrap();
+            if min_idle > 0 && now.saturating_sub(p.delivery_time) < min_idle {
+                continue;
+            }
+            p.consumer = consumer.to_string();
+            p.delivery_time = delivery_time;
+            if let Some(n) = options.retry_count {
+                p.delivery_count = n;
+            } else if !options.justid {
+                p.delivery_count += 1;
+            }
+            claimed.push((*id, entries.get(id)));
+        }
+        if !claimed.is_empty() {
+            if let Some(c) = group.consumers.get_mut(consumer) {
+                c.active_time = Some(now);
+            }
+        }
+        Ok(claimed)
+    }
+
+    // XAUTOCLAIM - scans PEL from start, returns next cursor, claimed and deleted ids
+    #[allow(clippy::too_many_arguments)]
+    pub fn autoclaim(&mut self, key: &str, group: &str, consumer: &str, min_idle: u128,
+        start: StreamId, count: usize, justid: bool) -> Result<(StreamId, Vec<Delivered>, Vec<StreamId>), String> {
+        let now = now_ms();
+        let entries = &self.entries;
+        let group = self.groups.get_mut(group).ok_or_else(|| Self::nogroup(key, group))?;
+        group.touch_consumer(consumer, now);
+        let mut attempts = count * 10;
+        let mut next = (0, 0);
+        let mut claimed = vec![];
+        let mut deleted = vec![];
+        let ids = group.pending.range(start..).map(|(id, _p)| *id).collect::<Vec<StreamId>>();
+        let mut iter = ids.into_iter();
+        for id in iter.by_ref() {
+            if attempts == 0 || claimed.len() >= count {
+                next = id;
+                break;
+            }
+            attempts -= 1;
+            if !entries.contains(&id) {
+                group.pending.remove(&id);
+                deleted.push(id);
+                continue;
+            }
+            let p = group.pending.get_mut(&id).unwrap();
+            if now.saturating_sub(p.delivery_time) < min_idle {
+                continue;
+            }
+            p.consumer = consumer.to_string();
+            p.delivery_time = now;
+            if !justid {
+                p.delivery_count += 1;
+            }
+            claimed.push((id, entries.get(&id)));
+        }
+        if !claimed.is_empty() {
+            if let Some(c) = group.consumers.get_mut(consumer) {
+                c.active_time = Some(now);
+            }
+        }
+        Ok((next, claimed, deleted))
+    }
+}
+
+// blocks, pending entries and consumers are sampled each on their own
+impl memory::MemoryUsage for Streams {
+    fn memory_usage(&self, samples: usize) -> usize {
+        let blocks = self.entries.index.values().map(|node| std::mem::size_of::<StreamId>() + node.memory());
+        let mut size = std::mem::size_of::<Self>() + memory::sampled(blocks, self.entries.nodes(), samples);
+        for (name, group) in self.groups.iter() {
+            let pending = group.pending.values()
+                .map(|p| std::mem::size_of::<(StreamId, PendingEntry)>() + p.consumer.capacity());
+            let consumers = group.consumers.keys()
+                .map(|c| std::mem::size_of::<(String, Consumer)>() + c.capacity());
+            size += std::mem::size_of::<ConsumerGroup>() + name.capacity()
+                + memory::sampled(pending, group.pending.len(), samples)
+                + memory::sampled(consumers, group.consumers.len(), samples);
+        }
+        size
+    }
+}
+
+// nodes under sorted keys that match up to depth - shared bytes make one
+// compressed node, a branch makes one node with a child per distinct byte
+fn rax_nodes(keys: &[[u8; 16]], depth: usize) -> usize {
+    let (first, last) = (&keys[0], &keys[keys.len() - 1]);
+    let common = (depth..16).take_while(|&i| first[i] == last[i]).count();
+    if depth + common == 16 {
+        // key ends here, anything left above it is one compressed node
+        return if common > 0 { 2 } else { 1 };
+    }
+    let branch = depth + common;
+    let compressed = if common > 0 { 1 } else { 0 };
+    let children = keys.chunk_by(|a, b| a[branch] == b[branch])
+        .map(|group| rax_nodes(group, branch + 1))
+        .sum::<usize>();
+    compressed + 1 + children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // heap taken by telemetry like entries kept one per map key, the way streams
+    // used to be stored, against the same entries packed in blocks
+    // run with: cargo test stream_memory_benchmark -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn stream_memory_benchmark() {
+        let count = 100_000;
+        let fields = |i: usize| vec![
+            "sensor".to_string(), format!("sensor-{}", i % 64),
+            "temperature".to_string(), format!("{}.{}", 20 + i % 15, i % 10),
+            "humidity".to_string(), format!("{}", 40 + i % 50),
+        ];
+        // a few entries per millisecond
+        let id = |i: usize| (1_700_000_000_000 + (i / 4) as u128, (i % 4) as u64);
+
+        let before = memory::allocated();
+        let mut per_entry: BTreeMap<StreamId, Vec<String>> = BTreeMap::new();
+        (0..count).for_each(|i| { per_entry.insert(id(i), fields(i)); });
+        let per_entry_bytes = memory::allocated().saturating_sub(before);
+        drop(per_entry);
+
+        let before = memory::allocated();
+        let mut packed = Streams::empty();
+        (0..count).for_each(|i| {
+            let (ms, seq) = id(i);
+            packed.add(ms, seq, fields(i));
+        });
+        let packed_bytes = memory::allocated().saturating_sub(before);
+
+        let per = |bytes: usize| bytes as f64 / count as f64;
+        println!("entries: {}\n\
+            per entry map: {} bytes, {:.1} bytes/entry\n\
+            packed blocks: {} bytes, {:.1} bytes/entry in {} blocks\n\
+            saving: {:.1}x",
+            count, per_entry_bytes, per(per_entry_bytes), packed_bytes, per(packed_bytes),
+            packed.entries.nodes(), per_entry_bytes as f64 / packed_bytes.max(1) as f64);
+        assert!(packed_bytes < per_entry_bytes);
+    }
+
+    #[test]
+    fn radix_nodes_counted_like_redis() {
+        let mut stream = Streams::empty();
+        assert_eq!(stream.radix_stats(), (0, 1));
+        stream.add(1_700_000_000_000, 0, vec!["a".to_string(), "1".to_string()]);
+        assert_eq!(stream.radix_stats(), (1, 2));
+        // blocks 1-0 and 1-1 split on the last byte under one compressed prefix
+        let mut entries = Entries::default();
+        entries.index.insert((1, 0), listpack::Node::new((1, 0), &["a".to_string(), "1".to_string()]));
+        entries.index.insert((1, 1), listpack::Node::new((1, 1), &["a".to_string(), "1".to_string()]));
+        assert_eq!(entries.radix_nodes(), 4);
     }
 }
@@ -0,0 +1,79 @@
+// allocator that keeps count of the heap in use - redis keeps the same count in zmalloc
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static STARTUP: AtomicUsize = AtomicUsize::new(0);
+
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(new_size, Ordering::Relaxed) + new_size;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+            ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+// bytes currently allocated on the heap
+pub fn allocated() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+// most bytes ever allocated at once
+pub fn peak() -> usize {
+    PEAK.load(Ordering::Relaxed)
+}
+
+// CONFIG RESETSTAT - peak starts over from the current use
+pub fn reset_peak() {
+    PEAK.store(allocated(), Ordering::Relaxed);
+}
+
+// heap in use before any data came in
+pub fn startup() -> usize {
+    STARTUP.load(Ordering::Relaxed)
+}
+
+pub fn mark_startup() {
+    STARTUP.store(allocated(), Ordering::Relaxed);
+}
+
+// elements of a value looked at by default - MEMORY USAGE SAMPLES, keys sampled
+// for eviction are the maxmemory-samples config parameter
+pub const DEFAULT_SAMPLES: usize = 5;
+
+// approximate bytes a value takes - aggregates look at a few of their elements
+// and scale that up to all of them, 0 samples looks at every element
+pub trait MemoryUsage {
+    fn memory_usage(&self, samples: usize) -> usize;
+}
+
+// sizes of up to samples of count elements scaled up to all of them
+pub fn sampled(sizes: impl Iterator<Item = usize>, count: usize, samples: usize) -> usize {
+    let take = if samples == 0 { count } else { samples };
+    let (total, seen) = sizes.take(take).fold((0, 0), |(total, seen), size| (total + size, seen + 1));
+    match seen {
+        0 => 0,
+        seen => total * count / seen,
+    }
+}
@@ -1 +1,3 @@
+pub mod memory;
+pub mod sha256;
 pub mod utils;
@@ -0,0 +1,150 @@
+// helpers shared across modules
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hash, Hasher};
+
+// redis style glob matching - supports * ? [abc] [^a] [a-z] and \ escapes
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    glob_match_at(&p, &s)
+}
+
+fn glob_match_at(p: &[char], s: &[char]) -> bool {
+    let (mut pi, mut si) = (0, 0);
+    // position to retry from on mismatch after the last '*'
+    let mut star: Option<(usize, usize)> = None;
+    while si < s.len() {
+        if pi < p.len() {
+            match p[pi] {
+                '*' => {
+                    star = Some((pi, si));
+                    pi += 1;
+                    continue;
+                },
+                '?' => {
+                    pi += 1;
+                    si += 1;
+                    continue;
+                },
+                '[' => {
+                    if let Some((matched, next)) = match_class(p, pi, s[si]) {
+                        if matched {
+                            pi = next;
+                            si += 1;
+                            continue;
+                        }
+                    }
+                },
+                '\\' if pi + 1 < p.len() => {
+                    if p[pi + 1] == s[si] {
+                        pi += 2;
+                        si += 1;
+                        continue;
+                    }
+                },
+                c => {
+                    if c == s[si] {
+                        pi += 1;
+                        si += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+        match star {
+            Some((spi, ssi)) => {
+                pi = spi + 1;
+                si = ssi + 1;
+                star = Some((spi, ssi + 1));
+            },
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+// matches c against class starting at p[start] == '['
+// returns if it matched and index after the class
+fn match_class(p: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = i < p.len() && p[i] == '^';
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    while i < p.len() && p[i] != ']' {
+        if p[i] == '\\' && i + 1 < p.len() {
+            i += 1;
+            matched |= p[i] == c;
+        } else if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            let (lo, hi) = if p[i] <= p[i + 2] { (p[i], p[i + 2]) } else { (p[i + 2], p[i]) };
+            matched |= c >= lo && c <= hi;
+            i += 2;
+        } else {
+            matched |= p[i] == c;
+        }
+        i += 1;
+    }
+    if i >= p.len() {
+        return None; // unterminated class
+    }
+    Some((matched != negate, i + 1))
+}
+
+// place of a key in SCAN order - its hash with the bits reversed
+// a cursor is the position of the next key with its bits reversed back, the way
+// redis walks its buckets, so a cursor stays valid however the table changes
+pub fn scan_position(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish().reverse_bits()
+}
+
+// random number - every RandomState comes with new keys for the hasher
+pub fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+// parses memory sizes like redis config - 100, 1k, 1kb, 32mb, 1gb
+// k/m/g are powers of 1000, kb/mb/gb powers of 1024
+pub fn parse_memory(value: &str) -> Result<u64, String> {
+    let value = value.to_lowercase();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory unit in '{}'", value)),
+    };
+    match number.parse::<u64>() {
+        Ok(n) => Ok(n * multiplier),
+        Err(_) => Err(format!("invalid memory value '{}'", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cases from the redis keyspace and stringmatch tests
+    #[test]
+    fn glob_patterns() {
+        let cases = [
+            ("*", "", true), ("*", "anything", true), ("h?llo", "hello", true), ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true), ("h*llo", "hello world", false), ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false), ("h[^e]llo", "hallo", true), ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true), ("h[b-a]llo", "hallo", true), ("h[a-b]llo", "hcllo", false),
+            ("h\\*llo", "h*llo", true), ("h\\*llo", "hello", false), ("[\\]]", "]", true),
+            ("a*b*c", "aXbYbZc", true), ("a*b*c", "aXbYbZ", false), ("*key*", "mykey1", true),
+            ("user:*:name", "user:1000:name", true), ("[abc", "a", false),
+        ];
+        for (pattern, s, expected) in cases {
+            assert_eq!(glob_match(pattern, s), expected, "{} against {}", pattern, s);
+        }
+    }
+}
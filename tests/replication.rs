@@ -0,0 +1,94 @@
+// replication chains set up in any order - servers run as child processes
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Server {
+    port: u16,
+    dir: PathBuf,
+    child: Child,
+}
+
+impl Server {
+    fn start(port: u16, replicaof: Option<u16>) -> Self {
+        let dir = std::env::temp_dir().join(format!("redis-test-{}-{}", std::process::id(), port));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"));
+        // no wait for more replicas before a full sync starts
+        command.args(["--port", &port.to_string(), "--dir", dir.to_str().unwrap(), "--repl-diskless-sync-delay", "0"])
+            .stdout(Stdio::null());
+        if let Some(master) = replicaof {
+            command.args(["--replicaof", &format!("127.0.0.1 {}", master)]);
+        }
+        let child = command.spawn().unwrap();
+        Self { port, dir, child }
+    }
+
+    fn stop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+// sends a command and returns the reply as received, None if server is not up
+fn cmd(port: u16, args: &[&str]) -> Option<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(1))).ok()?;
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.write_all(buf.as_bytes()).ok()?;
+    let mut reply = [0; 512];
+    let len = stream.read(&mut reply).ok()?;
+    Some(String::from_utf8_lossy(&reply[..len]).to_string())
+}
+
+// polls until key has value on the server at port
+fn wait_for(port: u16, key: &str, value: &str) -> bool {
+    let expected = format!("${}\r\n{}\r\n", value.len(), value);
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        if cmd(port, &["GET", key]).as_deref() == Some(expected.as_str()) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    false
+}
+
+#[test]
+fn leaf_replica_started_before_intermediate() {
+    let (master_port, intermediate_port, leaf_port) = (free_port(), free_port(), free_port());
+    let _leaf = Server::start(leaf_port, Some(intermediate_port));
+    thread::sleep(Duration::from_millis(300));
+    let master = Server::start(master_port, None);
+    let mut intermediate = Server::start(intermediate_port, Some(master_port));
+    while cmd(master.port, &["PING"]).is_none() {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    assert_eq!(cmd(master_port, &["SET", "first", "1"]).as_deref(), Some("+OK\r\n"));
+    assert!(wait_for(leaf_port, "first", "1"));
+
+    // leaf follows again once the lost link to intermediate comes back
+    intermediate.stop();
+    assert_eq!(cmd(master_port, &["SET", "second", "2"]).as_deref(), Some("+OK\r\n"));
+    let _intermediate = Server::start(intermediate_port, Some(master_port));
+    assert!(wait_for(leaf_port, "second", "2"));
+    assert!(wait_for(leaf_port, "first", "1"));
+}